@@ -0,0 +1,82 @@
+use proc_macro::TokenStream;
+
+/// Generate the bindings of a protocol xml inline, as an alternative to generating them in a build
+/// script and `include!`ing them.
+///
+/// ```
+/// mod protocols {
+///     mod interfaces {
+///         # #![allow(unused_imports)]
+///         pub use super::tiny::*;
+///     }
+///
+///     pub use ecs_compositor_core as proto;
+///
+///     ecs_compositor_codegen_macros::protocol!(path = "tests/tiny.xml");
+/// }
+///
+/// use ecs_compositor_core::{Interface, Value};
+/// use protocols::tiny::tn_ping::{request, tn_ping};
+///
+/// assert_eq!((tn_ping::NAME, tn_ping::VERSION), ("tn_ping", 2));
+/// assert_eq!(<request::ping as Value>::FDS, 1);
+/// ```
+///
+/// Relative paths are resolved against the `CARGO_MANIFEST_DIR` of the invoking crate.
+/// Like the `include!`d bindings, the generated module expects `proto` and the `interfaces` of
+/// all protocols it references to be in scope of its parent.
+///
+/// Messages with more `fd` args than `message_header::MAX_FDS` fail to compile once their
+/// `Value::FDS` is used:
+///
+/// ```compile_fail,E0080
+/// mod protocols {
+///     mod interfaces {
+///         pub use super::too_many_fds::*;
+///     }
+///
+///     pub use ecs_compositor_core as proto;
+///
+///     ecs_compositor_codegen_macros::protocol!(path = "tests/too_many_fds.xml");
+/// }
+///
+/// use ecs_compositor_core::Value;
+///
+/// const _: usize = <protocols::too_many_fds::tm_pass::request::pass as Value>::FDS;
+/// ```
+#[proc_macro]
+pub fn protocol(input: TokenStream) -> TokenStream {
+    ecs_compositor_codegen::protocol_macro(input.into()).into()
+}
+
+/// Implement `Value` for a struct by writing and reading its fields one after another, like the
+/// generated messages do.
+///
+/// ```
+/// use ecs_compositor_core::{Value, object, string, uint};
+/// use std::num::NonZero;
+///
+/// #[derive(ecs_compositor_codegen_macros::Value)]
+/// struct Announce<'a> {
+///     id: object,
+///     name: string<'a>,
+///     version: uint,
+/// }
+///
+/// let announce = Announce {
+///     id: object::from_id(NonZero::new(3).unwrap()),
+///     name: string::from_slice(b"wl_seat\0"),
+///     version: uint(7),
+/// };
+/// assert_eq!(<Announce as Value>::FDS, 0);
+/// assert_eq!(announce.len(), 4 + 4 + 8 + 4);
+/// ```
+///
+/// Every field has to implement `Value` itself, borrowing the message content for the first
+/// lifetime of the struct.
+/// The impl refers to `::ecs_compositor_core`, which therefore has to be a dependency of the
+/// invoking crate.
+#[proc_macro_derive(Value)]
+pub fn value(input: TokenStream) -> TokenStream {
+    ecs_compositor_codegen::derive_value(input.into()).into()
+}
@@ -0,0 +1,24 @@
+mod protocols {
+    mod interfaces {
+        // Only used by protocols referencing interfaces.
+        #[allow(unused_imports)]
+        pub use super::tiny::*;
+    }
+
+    pub use ecs_compositor_core as proto;
+
+    ecs_compositor_codegen_macros::protocol!(path = "tests/tiny.xml");
+}
+
+use ecs_compositor_core::{Interface, Message, Value};
+use protocols::tiny::tn_ping::{event, request, tn_ping};
+
+#[test]
+fn tiny_protocol() {
+    assert_eq!(protocols::tiny::INTERFACES, [("tn_ping", 2)]);
+    assert_eq!((tn_ping::NAME, tn_ping::VERSION), ("tn_ping", 2));
+
+    assert_eq!(<request::ping as Message>::OP, 0);
+    assert_eq!(<request::ping as Value>::FDS, 1);
+    assert_eq!(<event::pong as Message>::VERSION, 2);
+}
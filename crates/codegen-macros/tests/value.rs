@@ -0,0 +1,64 @@
+use ecs_compositor_codegen_macros::Value;
+use ecs_compositor_core::{Value, object, string, uint};
+use std::{num::NonZero, os::fd::RawFd, ptr};
+
+#[derive(Value)]
+struct Announce<'a> {
+    id: object,
+    name: string<'a>,
+    version: uint,
+}
+
+#[derive(Value)]
+struct Serial(uint);
+
+#[test]
+fn derive_value_round_trip() {
+    let announce = Announce {
+        id: object::from_id(NonZero::new(3).unwrap()),
+        name: string::from_slice(b"wl_seat\0"),
+        version: uint(7),
+    };
+    assert_eq!(<Announce as Value>::FDS, 0);
+    assert_eq!(announce.len(), 4 + 4 + 8 + 4);
+
+    let mut buf = [0u32; 5];
+    unsafe {
+        let (mut data, mut fds): (*mut [u8], *mut [RawFd]) = (
+            ptr::slice_from_raw_parts_mut(buf.as_mut_ptr().cast::<u8>(), 20),
+            &mut [],
+        );
+        announce.write(&mut data, &mut fds).ok().unwrap();
+        assert_eq!(data.len(), 0);
+    }
+    let mut expected = vec![3, 8];
+    expected.extend(
+        b"wl_seat\0"
+            .chunks(4)
+            .map(|chunk| u32::from_ne_bytes(chunk.try_into().unwrap())),
+    );
+    expected.push(7);
+    assert_eq!(buf.as_slice(), expected);
+
+    unsafe {
+        let (mut data, mut fds): (*const [u8], *const [RawFd]) = (
+            ptr::slice_from_raw_parts(buf.as_ptr().cast::<u8>(), 20),
+            &[],
+        );
+        let Announce { id, name, version } = Announce::read(&mut data, &mut fds).ok().unwrap();
+        assert_eq!(
+            (
+                id.id.get(),
+                name.as_slice_without_trailing_null(),
+                version.0
+            ),
+            (3, &b"wl_seat"[..], 7)
+        );
+        assert_eq!(data.len(), 0);
+
+        let (mut data, mut fds): (*const [u8], *const [RawFd]) =
+            (ptr::slice_from_raw_parts(buf.as_ptr().cast::<u8>(), 4), &[]);
+        let Serial(serial) = Serial::read(&mut data, &mut fds).ok().unwrap();
+        assert_eq!(serial.0, 3);
+    }
+}
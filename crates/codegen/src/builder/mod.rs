@@ -1,3 +1,4 @@
+use proc_macro2::Span;
 use std::path::{Path, PathBuf};
 
 pub use self::dir::Dir;
@@ -8,7 +9,8 @@ mod dir;
 pub struct Wayland {}
 
 impl Wayland {
-    pub fn protocols<'a, Iter: IntoIterator<Item = Event<'a>>>(iter: Iter) {
+    /// Generate all protocols in `iter`, stopping at the first one that fails.
+    pub fn protocols<'a, Iter: IntoIterator<Item = Event<'a>>>(iter: Iter) -> syn::Result<()> {
         let mut context = Context::default();
 
         for event in iter {
@@ -35,12 +37,20 @@ impl Wayland {
                         context.out_buf.extend(&context.out_dir);
                         context.out_buf.extend(out_file.parent());
 
-                        std::fs::create_dir_all(&context.out_buf).unwrap();
+                        std::fs::create_dir_all(&context.out_buf).map_err(|err| {
+                            syn::Error::new(
+                                Span::call_site(),
+                                format!(
+                                    "failed to create dir {path} with {err}",
+                                    path = context.out_buf.display()
+                                ),
+                            )
+                        })?;
                         context.out_buf.push(out_file.file_name().unwrap());
                     }
 
                     println!("cargo::rerun-if-changed={}", &context.in_buf.display());
-                    crate::protocol(&context.in_buf, &context.out_buf, formatted);
+                    crate::protocol_to_file(&context.in_buf, &context.out_buf, formatted)?;
                 }
                 Event::ExitDir { in_dir, out_dir } => {
                     if in_dir {
@@ -53,7 +63,56 @@ impl Wayland {
                 }
             }
         }
+
+        Ok(())
+    }
+
+    /// Generate every `*.xml` protocol found in `in_dir` and its subdirectories.
+    ///
+    /// The bindings are written to the same relative path in `out_dir`, with the extension
+    /// replaced by `.rs`. Use [`Wayland::protocols()`] to pick the files and their output names by
+    /// hand.
+    ///
+    /// Cargo reruns the build script when anything in `in_dir` changes, so added protocols get
+    /// picked up as well.
+    pub fn scan_dir(in_dir: impl AsRef<Path>, out_dir: impl AsRef<Path>) -> syn::Result<()> {
+        let in_dir = in_dir.as_ref();
+        println!("cargo::rerun-if-changed={}", in_dir.display());
+        let mut files = Vec::new();
+        scan_xml(in_dir, Path::new(""), &mut files)?;
+        files.sort();
+
+        let paths: Vec<_> = files.iter().map(|in_file| (in_file, in_file.with_extension("rs"))).collect();
+        Self::protocols(
+            Dir::with(in_dir, out_dir.as_ref()).protocols(paths.iter().map(|(in_file, out_file)| (*in_file, out_file))),
+        )
+    }
+}
+
+/// Collect the paths of all xml files below `base.join(rel)`, relative to `base`.
+fn scan_xml(base: &Path, rel: &Path, files: &mut Vec<PathBuf>) -> syn::Result<()> {
+    let io_err = |path: &Path, err: std::io::Error| {
+        syn::Error::new(
+            Span::call_site(),
+            format!(
+                "failed to read dir {path} with {err}",
+                path = path.display()
+            ),
+        )
+    };
+
+    let dir = base.join(rel);
+    for entry in std::fs::read_dir(&dir).map_err(|err| io_err(&dir, err))? {
+        let entry = entry.map_err(|err| io_err(&dir, err))?;
+        let path = rel.join(entry.file_name());
+        if entry.file_type().map_err(|err| io_err(&dir, err))?.is_dir() {
+            scan_xml(base, &path, files)?;
+        } else if path.extension().is_some_and(|ext| ext == "xml") {
+            files.push(path);
+        }
     }
+
+    Ok(())
 }
 
 #[derive(Debug, Default, Clone)]
@@ -71,3 +130,29 @@ pub enum Event<'a> {
     Protocol { in_file: &'a Path, out_file: &'a Path, formatted: bool },
     ExitDir { in_dir: bool, out_dir: bool },
 }
+
+#[test]
+fn test_scan_dir() {
+    let protocol = |name: &str| {
+        format!(
+            r#"<protocol name="{name}"><interface name="{name}_object" version="1"><request name="destroy" type="destructor"/></interface></protocol>"#
+        )
+    };
+    let base = std::env::temp_dir().join(format!("codegen-scan-{}", std::process::id()));
+    let (in_dir, out_dir) = (base.join("in"), base.join("out"));
+    std::fs::create_dir_all(in_dir.join("nested")).unwrap();
+    std::fs::write(in_dir.join("first.xml"), protocol("first")).unwrap();
+    std::fs::write(in_dir.join("nested/second.xml"), protocol("second")).unwrap();
+    std::fs::write(in_dir.join("README"), "not a protocol").unwrap();
+
+    let res = Wayland::scan_dir(&in_dir, &out_dir);
+    let first = std::fs::read_to_string(out_dir.join("first.rs"));
+    let second = std::fs::read_to_string(out_dir.join("nested/second.rs"));
+    let readme = out_dir.join("README.rs").exists();
+    std::fs::remove_dir_all(&base).unwrap();
+
+    res.unwrap();
+    assert!(first.unwrap().contains("first_object"));
+    assert!(second.unwrap().contains("second_object"));
+    assert!(!readme);
+}
@@ -5,13 +5,43 @@ use proc_macro2::{Span, TokenStream};
 use quote::{ToTokens, TokenStreamExt, quote};
 use std::{
     fs::{File, read_to_string},
-    io::{self, Write},
+    io::Write,
     path::{Path, PathBuf},
 };
 use syn::{LitStr, parse::Parse};
 use verbs::Verb;
 use wayland_scanner_lib::protocol::Protocol;
 
+/// Options for the generated bindings, see [`protocol_to_tokens_with()`](crate::protocol_to_tokens_with).
+#[derive(Debug, Default, Clone, Copy)]
+#[non_exhaustive]
+pub struct Config {
+    /// Turn the names of interfaces and messages of the protocol, like `wl_surface` or
+    /// `wl_surface.attach`, in its descriptions into intra-doc links.
+    ///
+    /// Off by default, which escapes all brackets so the descriptions can't produce broken links.
+    pub doc_links: bool,
+    /// `(interface, version)` pairs setting the `Interface::MIN_VERSION` of the named interfaces,
+    /// the oldest version the code using the bindings can work with.
+    ///
+    /// Interfaces not listed get `1`, which accepts every version.
+    pub min_versions: &'static [(&'static str, u32)],
+}
+
+impl Config {
+    /// See [`Self::doc_links`].
+    pub fn doc_links(mut self, doc_links: bool) -> Self {
+        self.doc_links = doc_links;
+        self
+    }
+
+    /// See [`Self::min_versions`].
+    pub fn min_versions(mut self, min_versions: &'static [(&'static str, u32)]) -> Self {
+        self.min_versions = min_versions;
+        self
+    }
+}
+
 pub enum GenerateConfig {
     Include { path: PathBuf, token: LitStr },
     Inline { protocol: Protocol },
@@ -70,7 +100,11 @@ impl Parse for GenerateConfig {
         match verb {
             Verb::Include { xml, out } => {
                 let protocol = read_xml_to_protocol(Path::new(xml.value().as_str()))?;
-                write_tokens_to_file(protocol, Path::new(out.value().as_str()), true)?;
+                write_tokens_to_file(
+                    generate_protocol(&protocol),
+                    Path::new(out.value().as_str()),
+                    true,
+                )?;
 
                 Ok(Self::Include {
                     path: PathBuf::new(), // TODO
@@ -82,7 +116,11 @@ impl Parse for GenerateConfig {
                 match out {
                     None => Ok(Self::Inline { protocol }),
                     Some(out) => {
-                        write_tokens_to_file(protocol, Path::new(out.value().as_str()), false)?;
+                        write_tokens_to_file(
+                            generate_protocol(&protocol),
+                            Path::new(out.value().as_str()),
+                            false,
+                        )?;
                         Ok(Self::None)
                     }
                 }
@@ -143,16 +181,8 @@ pub(crate) fn read_xml_to_protocol(path: &Path) -> syn::Result<Protocol> {
     })
 }
 
-pub(crate) fn write_tokens_to_file(
-    protocol: Protocol,
-    path: &Path,
-    formatted: bool,
-) -> syn::Result<()> {
-    let mut content = {
-        let mut tokens = TokenStream::new();
-        tokens.append_all(generate_protocol(&protocol));
-        tokens.to_string()
-    };
+pub(crate) fn write_tokens_to_file(tokens: TokenStream, path: &Path, formatted: bool) -> syn::Result<()> {
+    let mut content = tokens.to_string();
     let mut res = Ok(());
 
     if formatted {
@@ -169,10 +199,16 @@ pub(crate) fn write_tokens_to_file(
     }
 
     File::create(path)
-        .map_err(|err| io::Error::other(format!("{path}: {err}", path = path.display())))
-        .unwrap()
-        .write_all(content.as_bytes())
-        .unwrap();
+        .and_then(|mut file| file.write_all(content.as_bytes()))
+        .map_err(|err| {
+            syn::Error::new(
+                Span::call_site(),
+                format!(
+                    "failed to write file {path} with {err}",
+                    path = path.display()
+                ),
+            )
+        })?;
 
     res
 }
@@ -0,0 +1,118 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{Data, DeriveInput, Fields, GenericParam, Index, Lifetime, LifetimeParam, Member, parse_quote};
+
+/// Expand `#[derive(Value)]` to an impl of `Value` serializing the fields of a struct one after
+/// another, the same way the generated messages do.
+///
+/// The first lifetime of the struct is used as the `'data` lifetime of `Value<'data>`, so fields
+/// like `string<'a>` can borrow the message content.
+/// Errors are turned into a `compile_error!` pointing at the offending item.
+pub fn derive_value(input: TokenStream) -> TokenStream {
+    syn::parse2::<DeriveInput>(input)
+        .and_then(|input| expand(&input))
+        .unwrap_or_else(syn::Error::into_compile_error)
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream> {
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "`Value` can only be derived for structs",
+        ));
+    };
+
+    let name = &input.ident;
+    let (_, generic_args, _) = input.generics.split_for_impl();
+
+    let mut generics = input.generics.clone();
+    let data_lt = match generics.lifetimes().next() {
+        Some(param) => param.lifetime.clone(),
+        None => {
+            let lt = Lifetime::new("'data", proc_macro2::Span::call_site());
+            generics
+                .params
+                .insert(0, GenericParam::Lifetime(LifetimeParam::new(lt.clone())));
+            lt
+        }
+    };
+
+    let members: Vec<Member> = match &data.fields {
+        Fields::Named(fields) => fields
+            .named
+            .iter()
+            .map(|field| Member::Named(field.ident.clone().unwrap()))
+            .collect(),
+        Fields::Unnamed(fields) => (0..fields.unnamed.len()).map(|i| Member::Unnamed(Index::from(i))).collect(),
+        Fields::Unit => Vec::new(),
+    };
+    let types: Vec<_> = data.fields.iter().map(|field| &field.ty).collect();
+
+    let where_clause = generics.make_where_clause();
+    for typ in &types {
+        where_clause
+            .predicates
+            .push(parse_quote! { #typ: ::ecs_compositor_core::Value<#data_lt> });
+    }
+    let (impl_generics, _, where_clause) = generics.split_for_impl();
+
+    let value = quote! { ::ecs_compositor_core::Value<#data_lt> };
+
+    Ok(quote! {
+        impl #impl_generics #value for #name #generic_args #where_clause {
+            const FDS: usize = 0 #(+ <#types as #value>::FDS)*;
+
+            fn len(&self) -> u32 {
+                0 #(+ <#types as #value>::len(&self.#members))*
+            }
+
+            unsafe fn read(
+                data: &mut *const [u8],
+                fds: &mut *const [::std::os::fd::RawFd],
+            ) -> ::ecs_compositor_core::primitives::Result<Self> {
+                unsafe {
+                    Ok(Self { #(#members: <#types as #value>::read(data, fds)?,)* })
+                }
+            }
+
+            unsafe fn write(
+                &self,
+                data: &mut *mut [u8],
+                fds: &mut *mut [::std::os::fd::RawFd],
+            ) -> ::ecs_compositor_core::primitives::Result<()> {
+                unsafe {
+                    #(<#types as #value>::write(&self.#members, data, fds)?;)*
+                    Ok(())
+                }
+            }
+        }
+    })
+}
+
+#[test]
+fn test_derive_value() {
+    use quote::ToTokens;
+
+    let expanded = derive_value(quote! {
+        struct Header<'a> {
+            id: object,
+            name: string<'a>,
+        }
+    });
+    let item: syn::ItemImpl = syn::parse2(expanded).unwrap();
+    assert_eq!(
+        quote! { #item }.to_string().split(" for ").next().unwrap(),
+        quote! { impl<'a> ::ecs_compositor_core::Value<'a> }.to_string()
+    );
+
+    // Structs without a lifetime get a fresh one.
+    let expanded = derive_value(quote! { struct Serial(uint); });
+    let item: syn::ItemImpl = syn::parse2(expanded).unwrap();
+    assert_eq!(
+        item.generics.params.to_token_stream().to_string(),
+        quote! { 'data }.to_string()
+    );
+
+    let err = derive_value(quote! { enum Serial { A } }).to_string();
+    assert!(err.contains("compile_error"), "{err}");
+}
@@ -1,4 +1,4 @@
-use crate::generate::flat_map_fn::IteratorExt;
+use crate::{Config, generate::flat_map_fn::IteratorExt};
 use proc_macro2::{Literal, Span, TokenStream};
 use quote::{ToTokens, format_ident, quote};
 use std::fmt::Write;
@@ -11,22 +11,42 @@ use wayland_scanner_lib::protocol::{Arg, Entry, Enum, Interface, Message, Protoc
 mod flat_map_fn;
 
 pub fn generate_protocol(protocol: &Protocol) -> TokenStream {
+    generate_protocol_with(protocol, &Config::default())
+}
+
+pub fn generate_protocol_with(protocol: &Protocol, config: &Config) -> TokenStream {
     let Protocol { name, description, interfaces, .. } = protocol;
+    let links = DocLinks(config.doc_links.then_some(protocol));
 
-    let docs = Docs::Global.description(description);
+    let docs = links.protocol().description(description);
     let name = mod_name(name);
-    let interfaces = interfaces.iter().map(generate_interface);
+    let registry = interfaces.iter().map(|Interface { name, version, .. }| {
+        let version = Literal::u32_unsuffixed(*version);
+        quote! { (#name, #version), }
+    });
+    let interfaces = interfaces.iter().map(|interface| {
+        let min_version = config
+            .min_versions
+            .iter()
+            .find_map(|&(name, version)| (name == interface.name).then_some(version))
+            .unwrap_or(1);
+        generate_interface(interface, min_version, links)
+    });
     quote! {
         #[allow(unused_variables,unused_mut,unused_imports, dead_code, non_camel_case_types, unused_unsafe)]
         #[allow(clippy::doc_lazy_continuation,clippy::identity_op, clippy::match_single_binding, clippy::tabs_in_doc_comments)]
         pub mod #name {
             #docs
+
+            /// `(NAME, VERSION)` of every interface in this protocol.
+            pub static INTERFACES: &[(&str, u32)] = &[#(#registry)*];
+
             #(#interfaces)*
         }
     }
 }
 
-fn generate_interface(interface: &Interface) -> TokenStream {
+fn generate_interface(interface: &Interface, min_version: u32, links: DocLinks<'_>) -> TokenStream {
     let Interface { name, version, description, requests, events, enums } = interface;
 
     let error = if let Some(error) = enums.iter().find(|e| e.name == "error") {
@@ -39,10 +59,18 @@ fn generate_interface(interface: &Interface) -> TokenStream {
     let typ_name = typ_name(name);
     let mod_name = mod_name(name);
 
-    let docs = Docs::Global.description(description);
+    let docs = links.interface().description(description);
 
     let iface_name = {
         let version = Literal::u32_unsuffixed(*version);
+        let min_version = Literal::u32_unsuffixed(min_version);
+        let destructor_op = match requests.iter().position(|msg| msg.typ == Some(Type::Destructor)) {
+            Some(op) => {
+                let op = Literal::u16_unsuffixed(op as u16);
+                quote! { Some(#op) }
+            }
+            None => quote! { None },
+        };
 
         quote! {
             use {
@@ -54,6 +82,13 @@ fn generate_interface(interface: &Interface) -> TokenStream {
             impl proto::Interface for #typ_name {
                 const NAME:   &str = #name;
                 const VERSION: u32 = #version;
+                const MIN_VERSION: u32 = {
+                    assert!(#min_version <= Self::VERSION, "`MIN_VERSION` newer than the protocol");
+                    #min_version
+                };
+                const DESTRUCTOR_OP: Option<u16> = #destructor_op;
+                const REQUEST_SIGNATURE: &[&[proto::ArgKind]] = request::SIGNATURE;
+                const EVENT_SIGNATURE: &[&[proto::ArgKind]] = event::SIGNATURE;
 
                 type Request = request::Opcodes;
                 type Event   = event::Opcodes;
@@ -65,7 +100,7 @@ fn generate_interface(interface: &Interface) -> TokenStream {
 
     let requests = {
         let opcodes = gen_message_opcodes(requests);
-        let requests = requests.iter().map(|msg| generate_message(msg, interface, &typ_name));
+        let requests = requests.iter().map(|msg| generate_message(msg, interface, &typ_name, links));
 
         quote! {
             pub mod request {
@@ -78,7 +113,7 @@ fn generate_interface(interface: &Interface) -> TokenStream {
     };
     let events = {
         let opcodes = gen_message_opcodes(events);
-        let events = events.iter().map(|msg| generate_message(msg, interface, &typ_name));
+        let events = events.iter().map(|msg| generate_message(msg, interface, &typ_name, links));
 
         quote! {
             pub mod event {
@@ -90,7 +125,7 @@ fn generate_interface(interface: &Interface) -> TokenStream {
         }
     };
     let enumerations = {
-        let enums = enums.iter().map(generate_enum);
+        let enums = enums.iter().map(|enum_| generate_enum(enum_, links));
         quote! {
             pub mod enumeration {
                 use super::{*, proto::enumeration};
@@ -127,6 +162,11 @@ fn gen_message_opcodes(messages: &[Message]) -> TokenStream {
 
     let fields_ident = messages.iter().map(|msg| self::typ_name(&msg.name));
     let fields_str = messages.iter().map(|msg| &msg.name);
+    let name = quote! {
+        match self {
+            #(Self::#fields_ident => #fields_str,)*
+        }
+    };
 
     let fd_count = {
         if !messages.is_empty() {
@@ -151,13 +191,96 @@ fn gen_message_opcodes(messages: &[Message]) -> TokenStream {
         }
     };
 
+    let signature = messages.iter().map(|msg| {
+        let kinds = msg.args.iter().map(|arg| match arg.typ {
+            Type::Int => quote! { Int },
+            Type::Uint => quote! { Uint },
+            Type::Fixed => quote! { Fixed },
+            Type::String => quote! { String },
+            Type::Object => quote! { Object },
+            Type::NewId => quote! { NewId },
+            Type::Array => quote! { Array },
+            Type::Fd => quote! { Fd },
+            Type::Destructor => unreachable!("`destructor` is not an argument type"),
+        });
+        quote! { &[#(proto::ArgKind::#kinds),*] }
+    });
+
+    // Received messages are decoded with the interface of created objects, see `<name>_dyn`.
+    let decoded_typ = |msg: &Message| {
+        let name = typ_name(&msg.name);
+        match msg.args.iter().any(is_dyn_new_id) {
+            true => {
+                let name = format_ident!("{name}_dyn");
+                quote! { #name<'data> }
+            }
+            false if msg.args.iter().any(|arg| matches!(arg.typ, Type::Array | Type::String)) => {
+                quote! { #name<'data> }
+            }
+            false => quote! { #name },
+        }
+    };
+    let decoded_lifetime = messages
+        .iter()
+        .any(|msg| {
+            msg.args
+                .iter()
+                .any(|arg| is_dyn_new_id(arg) || matches!(arg.typ, Type::Array | Type::String))
+        })
+        .then(|| quote! { <'data> });
+    let decoded_variants = messages.iter().map(|msg| {
+        let name = typ_name(&msg.name);
+        let typ = decoded_typ(msg);
+        quote! { #name(#typ), }
+    });
+    let decoded_display = messages.iter().map(|msg| {
+        let name = typ_name(&msg.name);
+        quote! { Self::#name(ref msg) => msg.fmt(f), }
+    });
+    let decode = messages.iter().enumerate().map(|(i, msg)| {
+        let name = typ_name(&msg.name);
+        let typ = decoded_typ(msg);
+        let i = Literal::u16_unsuffixed(i.try_into().expect("requests overflowing u16"));
+        quote! { #i => Ok(Decoded::#name(<#typ as Value<'data>>::read(data, fds)?)), }
+    });
+
     quote! {
+        /// Argument kinds of every message, indexed by opcode.
+        pub const SIGNATURE: &[&[proto::ArgKind]] = &[#(#signature),*];
+
+        /// Every message decoded, see [`proto::Opcode::decode()`].
+        pub enum Decoded #decoded_lifetime {
+            #(#decoded_variants)*
+        }
+
+        impl #decoded_lifetime std::fmt::Display for Decoded #decoded_lifetime {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match *self {
+                    #(#decoded_display)*
+                }
+            }
+        }
+
         #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
         pub enum Opcodes {
             #(#entry)*
         }
 
+        impl Opcodes {
+            /// Name of the message as in the protocol xml.
+            pub fn name(self) -> &'static str {
+                #name
+            }
+        }
+
+        /// Name of the message with opcode `i`, if there is one.
+        pub fn name_of(i: u16) -> Option<&'static str> {
+            <Opcodes as proto::Opcode>::from_u16(i).ok().map(|op| op.name())
+        }
+
         impl proto::Opcode for Opcodes {
+            type Decoded<'data> = Decoded #decoded_lifetime;
+
             fn from_u16(i: u16) -> std::result::Result<Self, u16> {
                 match i {
                     #(#from_u16)*
@@ -165,6 +288,10 @@ fn gen_message_opcodes(messages: &[Message]) -> TokenStream {
                 }
             }
 
+            fn name_of(i: u16) -> Option<&'static str> {
+                name_of(i)
+            }
+
             fn to_u16(self) -> u16 {
                 self as u16
             }
@@ -172,41 +299,103 @@ fn gen_message_opcodes(messages: &[Message]) -> TokenStream {
             fn fd_count(&self) -> usize {
                 #fd_count
             }
+
+            unsafe fn decode<'data>(
+                opcode: u16,
+                data: &mut *const [u8],
+                fds: &mut *const [RawFd],
+            ) -> primitives::Result<Self::Decoded<'data>> {
+                unsafe {
+                    match opcode {
+                        #(#decode)*
+                        _ => Err(proto::wl_display::enumeration::error::invalid_method.msg("invalid opcode")),
+                    }
+                }
+            }
+
+            fn shorten<'short, 'data: 'short>(decoded: &'short Self::Decoded<'data>) -> &'short Self::Decoded<'short> {
+                decoded
+            }
         }
 
         impl std::fmt::Display for Opcodes {
             fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-                match *self {
-                    #(Self::#fields_ident => f.write_str(#fields_str),)*
-                }
+                f.write_str(self.name())
             }
         }
     }
 }
 
-fn generate_message(message: &Message, interface: &Interface, iface_name: &syn::Ident) -> TokenStream {
+fn generate_message(
+    message: &Message,
+    interface: &Interface,
+    iface_name: &syn::Ident,
+    links: DocLinks<'_>,
+) -> TokenStream {
+    let typed = generate_message_struct(message, interface, iface_name, false, links);
+    // The receiver doesn't know the interface of the created object in advance, so it decodes the
+    // message as `<name>_dyn` with the interface name and version instead.
+    let dyn_ = message
+        .args
+        .iter()
+        .any(is_dyn_new_id)
+        .then(|| generate_message_struct(message, interface, iface_name, true, links));
+
+    quote! {
+        #typed
+        #dyn_
+    }
+}
+
+/// With `dyn_decode` the interface-less `new_id` args are generated as [`new_id_dyn`], which
+/// allows decoding them without knowing the interface up front.
+fn generate_message_struct(
+    message: &Message,
+    interface: &Interface,
+    iface_name: &syn::Ident,
+    dyn_decode: bool,
+    links: DocLinks<'_>,
+) -> TokenStream {
     let Message { name, typ: _, since, description, args } = message;
 
     let str_name = Literal::string(name);
-    let name = typ_name(name);
+    let opcode = typ_name(name);
+    let name = match dyn_decode {
+        true => format_ident!("{opcode}_dyn"),
+        false => opcode.clone(),
+    };
 
-    let lifetime = if message
-        .args
-        .iter()
-        .any(|arg| matches!(arg.typ, Type::Array | Type::String | Type::NewId if arg.interface.is_none()))
-    {
-        quote! {<'data>}
-    } else {
-        quote! {}
+    let lifetime = dyn_decode || message.args.iter().any(|arg| matches!(arg.typ, Type::Array | Type::String));
+    // `new_id` args without an interface are generic over the interface of the created object.
+    let dyn_new_id = !dyn_decode && message.args.iter().any(is_dyn_new_id);
+    let generic_new_id = |arg: &Arg| !dyn_decode && is_dyn_new_id(arg);
+
+    let (generics, generic_args) = match (lifetime, dyn_new_id) {
+        (false, false) => (quote! {}, quote! {}),
+        (true, false) => (quote! {<'data>}, quote! {<'data>}),
+        (false, true) => (quote! {<I: proto::Interface>}, quote! {<I>}),
+        (true, true) => (quote! {<'data, I: proto::Interface>}, quote! {<'data, I>}),
+    };
+    let impl_generics = match dyn_new_id {
+        true => quote! {<'data, I: proto::Interface>},
+        false => quote! {<'data>},
     };
 
     let item = {
-        let docs = Docs::Local.description(description);
-        let fields = args.iter().map(|arg| GenArg::new(interface, arg).gen_field());
+        let docs = match dyn_decode {
+            true => {
+                let docs = format!("Like [`{opcode}`], but with the interface name and version of the created object.");
+                quote! { #[doc = #docs] }
+            }
+            false => links.item().description(description),
+        };
+        let fields = args
+            .iter()
+            .map(|arg| GenArg::new(interface, arg, dyn_decode, links).gen_field());
 
         quote! {
             #docs
-            pub struct #name #lifetime {
+            pub struct #name #generics {
                 #(#fields)*
             }
         }
@@ -218,25 +407,48 @@ fn generate_message(message: &Message, interface: &Interface, iface_name: &syn::
         let fd_count = Literal::usize_unsuffixed(args.iter().filter(|arg| matches!(arg.typ, Type::Fd)).count());
 
         let fields_read = args.iter().map(|arg| {
-            let arg = GenArg::new(interface, arg);
+            let dyn_new_id = generic_new_id(arg);
+            let arg = GenArg::new(interface, arg, dyn_decode, links);
             let name = &arg.name;
             let typ = &arg.typ;
+            match dyn_new_id {
+                true => quote! {
+                    #name: <#typ>::read_dyn(data, fds)?,
+                },
+                false => quote! {
+                    #name: <#typ>::read(data, fds)?,
+                },
+            }
+        });
+
+        let fields_fds = args.iter().map(|arg| {
+            let typ = GenArg::new(interface, arg, dyn_decode, links).typ;
             quote! {
-                #name: <#typ>::read(data, fds)?,
+                + <#typ as Value<'data>>::FDS
             }
         });
 
         let fields_write_len = args.iter().map(|arg| {
             let name = mod_name(&arg.name);
-            quote! {
-                + self.#name.len()
+            match generic_new_id(arg) {
+                true => quote! {
+                    + self.#name.dyn_len()
+                },
+                false => quote! {
+                    + self.#name.len()
+                },
             }
         });
 
         let fields_write = args.iter().map(|arg| {
             let name = mod_name(&arg.name);
-            quote! {
-                self.#name.write(data,fds)?;
+            match generic_new_id(arg) {
+                true => quote! {
+                    self.#name.write_dyn(data,fds)?;
+                },
+                false => quote! {
+                    self.#name.write(data,fds)?;
+                },
             }
         });
 
@@ -247,6 +459,7 @@ fn generate_message(message: &Message, interface: &Interface, iface_name: &syn::
                 let typ = match arg.typ {
                     Type::String => format_ident!("string"),
                     Type::Object => format_ident!("object"),
+                    Type::NewId => format_ident!("new_id"),
                     _ => unreachable!(),
                 };
 
@@ -269,18 +482,26 @@ fn generate_message(message: &Message, interface: &Interface, iface_name: &syn::
         });
 
         quote! {
-            impl<'data> Message<'data> for #name #lifetime {
+            impl #impl_generics Message<'data> for #name #generic_args {
                 type Interface = #iface_name;
                 const VERSION: u32 = #version;
+                const MIN_VERSION: u32 = #version;
                 const NAME: &'static str = #str_name;
 
                 type Opcode = Opcodes;
-                const OPCODE: Self::Opcode = Self::Opcode::#name;
+                const OPCODE: Self::Opcode = Self::Opcode::#opcode;
                 const OP: u16 = Self::OPCODE as u16;
             }
 
-            impl<'data> Value<'data> for #name #lifetime {
-                const FDS: usize = #fd_count;
+            impl #impl_generics Value<'data> for #name #generic_args {
+                // Counted from the `fd` args above, so a miscount fails to compile at the first use
+                // instead of mis-sizing the cmsg buffers.
+                const FDS: usize = {
+                    let fds = #fd_count;
+                    assert!(fds == 0 #(#fields_fds)*, "`FDS` doesn't match the `fd` args");
+                    assert!(fds <= proto::message_header::MAX_FDS, "more `fd` args than a message can carry");
+                    fds
+                };
                 unsafe fn read(
                     data: &mut *const [u8],
                     fds: &mut *const [RawFd],
@@ -309,9 +530,9 @@ fn generate_message(message: &Message, interface: &Interface, iface_name: &syn::
 
             }
 
-            impl #lifetime std::fmt::Display for #name #lifetime {
+            impl #generics std::fmt::Display for #name #generic_args {
                 fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-                    write!(f, "{iface}.{msg}", iface = #iface_name::NAME, msg = #name::NAME)?;
+                    write!(f, "{iface}.{msg}", iface = #iface_name::NAME, msg = Self::NAME)?;
                     write!(f, "( ")?;
                     #(#fields_debug)*
                     write!(f, ")")?;
@@ -334,7 +555,37 @@ struct GenArg {
 }
 
 impl GenArg {
-    fn new(interface: &Interface, arg: &Arg) -> Self {
+    /// With `dyn_decode` an interface-less `new_id` is typed as [`new_id_dyn`].
+    fn new(interface: &Interface, arg: &Arg, dyn_decode: bool, links: DocLinks<'_>) -> Self {
+        if dyn_decode && is_dyn_new_id(arg) {
+            return Self {
+                name: mod_name(&arg.name),
+                docs: links.item().summary(&arg.summary, &arg.description),
+                typ: syn::parse_quote!(new_id_dyn<'data>),
+            };
+        }
+
+        // `enum="iface.name"` references an enum of another interface, `enum="name"` one of our own.
+        let enum_ = arg.enum_.as_ref().map(|enum_| {
+            let (iface, name) = match enum_.split_once('.') {
+                Some((iface, name)) if iface != interface.name => (Some(iface), name),
+                Some((_, name)) => (None, name),
+                None => (None, enum_.as_str()),
+            };
+            syn::Path {
+                leading_colon: None,
+                segments: Punctuated::from_iter(
+                    iface
+                        .map(|iface| PathSegment { ident: mod_name(iface), arguments: PathArguments::None })
+                        .into_iter()
+                        .chain([
+                            PathSegment { ident: format_ident!("enumeration"), arguments: PathArguments::None },
+                            PathSegment { ident: typ_name(name), arguments: PathArguments::None },
+                        ]),
+                ),
+            }
+        });
+
         let interface = arg.interface.as_ref().map(|iface| syn::Path {
             leading_colon: None,
             segments: Punctuated::from_iter(
@@ -371,7 +622,7 @@ impl GenArg {
             })
         }
 
-        let typ = syn::Path {
+        let typ = enum_.unwrap_or_else(|| syn::Path {
             leading_colon: None,
             segments: {
                 let mut punctuated = Punctuated::new();
@@ -384,10 +635,7 @@ impl GenArg {
                         Type::Array => ident("array"),
                         Type::String => ident("string"),
 
-                        Type::NewId => match arg.interface.is_some() {
-                            true => ident("new_id"),
-                            false => ident("new_id_dyn"),
-                        },
+                        Type::NewId => ident("new_id"),
                         Type::Object => ident("object"),
 
                         Type::Fd => ident("fd"),
@@ -396,8 +644,12 @@ impl GenArg {
                     arguments: {
                         use Type::{Array, NewId, Object, String};
                         match (arg.typ, interface) {
-                            (String | Array, _) | (NewId, None) => generic_arg(GenericArgument::Lifetime(
-                                Lifetime::new("'data", Span::call_site()),
+                            (String | Array, _) => generic_arg(GenericArgument::Lifetime(Lifetime::new(
+                                "'data",
+                                Span::call_site(),
+                            ))),
+                            (NewId, None) => generic_arg(GenericArgument::Type(
+                                TypePath { qself: None, path: ident("I").into() }.into(),
                             )),
                             (NewId | Object, Some(path)) => {
                                 generic_arg(GenericArgument::Type(TypePath { qself: None, path }.into()))
@@ -420,9 +672,9 @@ impl GenArg {
                     punctuated
                 }
             },
-        };
+        });
 
-        Self { name: mod_name(&arg.name), docs: Docs::Local.summary(&arg.summary, &arg.description), typ }
+        Self { name: mod_name(&arg.name), docs: links.item().summary(&arg.summary, &arg.description), typ }
     }
 
     fn gen_field(&self) -> TokenStream {
@@ -437,16 +689,22 @@ impl GenArg {
     }
 }
 
-fn generate_enum(enum_: &Enum) -> TokenStream {
+/// `new_id` without a specified interface, which on the wire is preceded by the interface name and
+/// version.
+fn is_dyn_new_id(arg: &Arg) -> bool {
+    matches!(arg.typ, Type::NewId) && arg.interface.is_none()
+}
+
+fn generate_enum(enum_: &Enum, links: DocLinks<'_>) -> TokenStream {
     let Enum { name, since: _, description, entries, bitfield } = enum_;
 
     let name = typ_name(name);
-    let docs = Docs::Local.description(description);
+    let docs = links.item().description(description);
     let typ = match *bitfield {
         true => {
             let entries = entries.iter().map(|Entry { name, value, since: _, summary, description }| {
                 let name = typ_name(name);
-                let docs = Docs::Local.summary(summary, description);
+                let docs = links.item().summary(summary, description);
                 let value = Literal::u32_unsuffixed(*value);
                 quote! {
                     #docs
@@ -468,13 +726,12 @@ fn generate_enum(enum_: &Enum) -> TokenStream {
             }
         }
         false => {
-            let entries = entries.iter().map(|Entry { name, value, since: _, summary, description }| {
+            let entries = entries.iter().map(|Entry { name, value: _, since: _, summary, description }| {
                 let name = typ_name(name);
-                let docs = Docs::Local.summary(summary, description);
-                let value = Literal::u32_unsuffixed(*value);
+                let docs = links.item().summary(summary, description);
                 quote! {
                     #docs
-                    #name = #value,
+                    #name,
                 }
             });
             quote! {
@@ -482,6 +739,9 @@ fn generate_enum(enum_: &Enum) -> TokenStream {
                 #[derive(Debug, Clone, Copy)]
                 pub enum #name {
                     #(#entries)*
+                    /// Value unknown to these bindings, e.g. one added by a newer version of the
+                    /// protocol.
+                    Unknown(u32),
                 }
             }
         }
@@ -506,13 +766,29 @@ fn impl_enum(enum_: &Enum) -> TokenStream {
             #value => Some(Self::#name),
         }
     });
+    let values = enum_.entries.iter().map(|entry| {
+        let value = Literal::u32_unsuffixed(entry.value);
+        let name = typ_name(&entry.name);
+        quote! { Self::#name => #value, }
+    });
     let versions = enum_.entries.iter().map(|entry| {
         let name = typ_name(&entry.name);
         let version = Literal::u32_unsuffixed(entry.since as u32);
         quote! { Self::#name => #version, }
     });
+    let names = enum_.entries.iter().map(|entry| {
+        let name = typ_name(&entry.name);
+        let str_name = Literal::string(&entry.name);
+        quote! { Self::#name => f.write_str(#str_name), }
+    });
+    let all = enum_.entries.iter().map(|entry| typ_name(&entry.name));
 
     quote! {
+        impl #name {
+            /// All variants, in the order they are declared in the protocol.
+            pub const ALL: &[Self] = &[#(Self::#all),*];
+        }
+
         impl proto::enumeration for #name {
             fn from_u32(i: u32) -> Option<Self> {
                 match i {
@@ -522,23 +798,44 @@ fn impl_enum(enum_: &Enum) -> TokenStream {
             }
 
             fn to_u32(&self) -> u32 {
-                *self as u32
+                match *self {
+                    #(#values)*
+                    Self::Unknown(i) => i,
+                }
             }
 
             fn since_version(&self) -> u32 {
                 match self {
                     #(#versions)*
+                    // Newer than any version the bindings know of.
+                    Self::Unknown(_) => u32::MAX,
                 }
             }
         }
 
+        impl TryFrom<u32> for #name {
+            type Error = u32;
+
+            fn try_from(i: u32) -> std::result::Result<Self, u32> {
+                Self::from_u32(i).ok_or(i)
+            }
+        }
+
+        impl From<#name> for u32 {
+            fn from(val: #name) -> u32 {
+                val.to_u32()
+            }
+        }
+
         impl Value<'_> for #name {
             const FDS: usize = 0;
             unsafe fn read(
                 data: &mut *const [u8],
                 fds: &mut *const [RawFd],
             ) -> primitives::Result<Self> {
-                todo!()
+                // Unknown values are kept, so a newer peer doesn't fail the whole message.
+                let i = unsafe { uint::read(data, fds)?.0 };
+                Ok(Self::from_u32(i).unwrap_or(Self::Unknown(i)))
             }
 
             fn len(&self) -> u32 {
@@ -550,7 +847,16 @@ fn impl_enum(enum_: &Enum) -> TokenStream {
                 data: &mut *mut [u8],
                 fds: &mut *mut [RawFd],
             ) -> primitives::Result<()> {
-                todo!()
+                unsafe { uint(self.to_u32()).write(data, fds) }
+            }
+        }
+
+        impl std::fmt::Display for #name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match self {
+                    #(#names)*
+                    Self::Unknown(i) => write!(f, "unknown({i})"),
+                }
             }
         }
     }
@@ -579,7 +885,7 @@ fn impl_bitfield(enum_: &Enum) -> TokenStream {
                 data: &mut *const [u8],
                 fds: &mut *const [RawFd],
             ) -> primitives::Result<Self> {
-                todo!()
+                unsafe { Ok(Self::from_bits_retain(uint::read(data, fds)?.0)) }
             }
 
             fn len(&self) -> u32 {
@@ -591,25 +897,81 @@ fn impl_bitfield(enum_: &Enum) -> TokenStream {
                 data: &mut *mut [u8],
                 fds: &mut *mut [RawFd],
             ) -> primitives::Result<()> {
-                todo!()
+                unsafe { uint(self.to_u32()).write(data, fds) }
+            }
+        }
+
+        impl std::fmt::Display for #name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                ::bitflags::parser::to_writer(self, f)
             }
         }
     }
 }
 
+/// Protocol whose interface and message names get turned into intra-doc links in the docs, see
+/// [`Config::doc_links`].
+///
+/// With [`None`] brackets in the docs are escaped instead, so none are mistaken for broken links.
+#[derive(Clone, Copy, Default)]
+struct DocLinks<'a>(Option<&'a Protocol>);
+
+impl<'a> DocLinks<'a> {
+    /// Docs of the protocol module.
+    fn protocol(self) -> Docs<'a> {
+        Docs { inner: true, links: self, to_protocol: "" }
+    }
+
+    /// Docs of an interface module.
+    fn interface(self) -> Docs<'a> {
+        Docs { inner: true, links: self, to_protocol: "super::" }
+    }
+
+    /// Docs of the items in the `request`, `event` and `enumeration` modules of an interface.
+    fn item(self) -> Docs<'a> {
+        Docs { inner: false, links: self, to_protocol: "super::super::" }
+    }
+
+    /// Path relative to the protocol module of what `name` refers to, if it is the name of an
+    /// interface like `wl_surface` or of a message like `wl_surface.attach`.
+    fn resolve(self, name: &str) -> Option<String> {
+        let (interface, message) = match name.split_once('.') {
+            Some((interface, message)) => (interface, Some(message)),
+            None => (name, None),
+        };
+        let interface = self.0?.interfaces.iter().find(|iface| iface.name == interface)?;
+        let (mod_name, typ) = (mod_name(&interface.name), typ_name(&interface.name));
+
+        let Some(message) = message else {
+            return Some(format!("{mod_name}::{typ}"));
+        };
+        let kind = [("request", &interface.requests), ("event", &interface.events)]
+            .into_iter()
+            .find_map(|(kind, messages)| messages.iter().any(|msg| msg.name == message).then_some(kind))?;
+        Some(format!(
+            "{mod_name}::{kind}::{message}",
+            message = typ_name(message)
+        ))
+    }
+}
+
+/// Where generated docs end up, as intra-doc links are resolved relative to it.
 #[derive(Clone, Copy)]
-enum Docs {
-    Global,
-    Local,
+struct Docs<'a> {
+    /// `#![doc]` of the surrounding module instead of `#[doc]` of the next item.
+    inner: bool,
+    links: DocLinks<'a>,
+    /// Path from the scope of the docs to the protocol module.
+    to_protocol: &'static str,
 }
 
-impl Docs {
+impl Docs<'_> {
     fn to_attr<T: ToTokens>(self, msg: T) -> TokenStream {
-        match self {
-            Docs::Global => {
+        match self.inner {
+            true => {
                 quote! { #![doc = #msg] }
             }
-            Docs::Local => {
+            false => {
                 quote! { #[doc = #msg] }
             }
         }
@@ -625,23 +987,7 @@ impl Docs {
                         buf.reserve(str.len() + 1);
 
                         buf += " ";
-
-                        const PATTERN: &[char] = &['[', ']'];
-                        for next in str.split_inclusive(PATTERN) {
-                            let mut segment = next.chars();
-                            match segment.next_back() {
-                                Some(char) => {
-                                    if PATTERN.contains(&char) {
-                                        buf += segment.as_str();
-                                        buf += "\\";
-                                        buf.write_char(char).unwrap();
-                                    } else {
-                                        buf += next;
-                                    }
-                                }
-                                None => todo!(),
-                            }
-                        }
+                        self.push_line(&mut buf, str);
 
                         self.to_attr(&buf)
                     }
@@ -660,6 +1006,41 @@ impl Docs {
             .collect()
     }
 
+    /// Append `line`, linking the names [`DocLinks::resolve()`] knows outside of code spans.
+    fn push_line(self, buf: &mut String, mut line: &str) {
+        if self.links.0.is_none() {
+            return escape_brackets(buf, line);
+        }
+
+        let mut code = false;
+        while let Some(char) = line.chars().next() {
+            if !(char.is_ascii_alphabetic() || char == '_') {
+                code ^= char == '`';
+                escape_brackets(buf, &line[..char.len_utf8()]);
+                line = &line[char.len_utf8()..];
+                continue;
+            }
+
+            let end = line
+                .find(|char: char| !(char.is_ascii_alphanumeric() || char == '_' || char == '.'))
+                .unwrap_or(line.len());
+            let word = line[..end].trim_end_matches('.');
+            // `wl_surface.unknown` still links the interface.
+            let (word, path) = match self.links.resolve(word) {
+                Some(path) => (word, Some(path)),
+                None => {
+                    let interface = word.split('.').next().unwrap_or(word);
+                    (interface, self.links.resolve(interface))
+                }
+            };
+            match path {
+                Some(path) if !code => write!(buf, "[`{word}`]({}{path})", self.to_protocol).unwrap(),
+                _ => *buf += word,
+            }
+            line = &line[word.len()..];
+        }
+    }
+
     fn description(self, description: &Option<(String, String)>) -> TokenStream {
         self.with_iter(
             description
@@ -683,6 +1064,16 @@ impl Docs {
     }
 }
 
+/// Append `str` with `[` and `]` escaped, so they aren't mistaken for broken intra-doc links.
+fn escape_brackets(buf: &mut String, str: &str) {
+    for char in str.chars() {
+        if matches!(char, '[' | ']') {
+            buf.push('\\');
+        }
+        buf.push(char);
+    }
+}
+
 fn mod_name(name: &str) -> syn::Ident {
     format_ident!("{name}")
 }
@@ -705,3 +1096,511 @@ fn is_numeric(str: &str) -> bool {
 fn is_keyword(str: &str) -> bool {
     matches!(str, "move")
 }
+
+/// Items of all impls in `items`, to pick out single generated consts and fns.
+#[cfg(test)]
+fn impl_items(items: &[syn::Item]) -> impl Iterator<Item = &syn::ImplItem> {
+    items.iter().flat_map(|item| match item {
+        syn::Item::Impl(item) => &item.items[..],
+        _ => &[],
+    })
+}
+
+/// Value of the first associated const `name` in the impls of `items`, as a token string.
+#[cfg(test)]
+fn impl_const(items: &[syn::Item], name: &str) -> Option<String> {
+    impl_items(items).find_map(|item| match item {
+        syn::ImplItem::Const(item) if item.ident == name => Some(item.expr.to_token_stream().to_string()),
+        _ => None,
+    })
+}
+
+#[test]
+fn test_protocol_interfaces() {
+    let interface = |name: &str, version| Interface { name: name.to_owned(), version, ..Interface::new() };
+    let mut protocol = Protocol::new("test".to_owned());
+    protocol.interfaces = vec![interface("test_manager", 3), interface("test_object", 1), interface("test_child", 5)];
+
+    let module: syn::ItemMod = syn::parse2(generate_protocol(&protocol)).unwrap();
+    let (_, items) = module.content.unwrap();
+    let interfaces = items
+        .iter()
+        .find_map(|item| match item {
+            syn::Item::Static(item) if item.ident == "INTERFACES" => Some(&item.expr),
+            _ => None,
+        })
+        .expect("missing `INTERFACES`");
+
+    let expected = quote! { &[("test_manager", 3), ("test_object", 1), ("test_child", 5),] };
+    assert_eq!(
+        interfaces.to_token_stream().to_string(),
+        expected.to_string()
+    );
+}
+
+#[test]
+fn test_interface_min_version() {
+    let interface = |name: &str, version| Interface { name: name.to_owned(), version, ..Interface::new() };
+    let mut protocol = Protocol::new("test".to_owned());
+    protocol.interfaces = vec![interface("test_manager", 3), interface("test_object", 2)];
+
+    let module: syn::ItemMod = syn::parse2(generate_protocol_with(
+        &protocol,
+        &Config::default().min_versions(&[("test_manager", 2), ("other_manager", 3)]),
+    ))
+    .unwrap();
+    let (_, items) = module.content.unwrap();
+    let min_versions = ["test_manager", "test_object"].map(|name| {
+        let items = items
+            .iter()
+            .find_map(|item| match item {
+                syn::Item::Mod(item) if item.ident == name => Some(&item.content.as_ref().unwrap().1),
+                _ => None,
+            })
+            .unwrap_or_else(|| panic!("missing `{name}`"));
+        impl_const(items, "MIN_VERSION").expect("missing `MIN_VERSION`")
+    });
+
+    let min_version = |version: u32| {
+        let version = Literal::u32_unsuffixed(version);
+        quote! {{
+            assert!(#version <= Self::VERSION, "`MIN_VERSION` newer than the protocol");
+            #version
+        }}
+        .to_string()
+    };
+    assert_eq!(min_versions, [min_version(2), min_version(1)]);
+}
+
+#[test]
+fn test_interface_destructor_op() {
+    fn destructor_op(interface: &Interface) -> String {
+        let module: syn::ItemMod = syn::parse2(generate_interface(interface, 1, DocLinks::default())).unwrap();
+        let (_, items) = module.content.unwrap();
+        impl_const(&items, "DESTRUCTOR_OP").expect("missing `DESTRUCTOR_OP`")
+    }
+
+    let message = |name: &str, typ, args: &[&str]| Message {
+        name: name.to_owned(),
+        typ,
+        args: args
+            .iter()
+            .map(|name| Arg { name: (*name).to_owned(), typ: Type::Uint, ..Arg::new() })
+            .collect(),
+        ..Message::new()
+    };
+
+    let wl_shm_pool = Interface {
+        name: "wl_shm_pool".to_owned(),
+        requests: vec![
+            message(
+                "create_buffer",
+                None,
+                &["offset", "width", "height", "stride", "format"],
+            ),
+            message("destroy", Some(Type::Destructor), &[]),
+            message("resize", None, &["size"]),
+        ],
+        ..Interface::new()
+    };
+    assert_eq!(destructor_op(&wl_shm_pool), quote! { Some(1) }.to_string());
+
+    // Still a destructor, even though it can't be sent on drop.
+    let zwp_tablet_pad_group = Interface {
+        name: "zwp_tablet_pad_group".to_owned(),
+        requests: vec![
+            message("set_feedback", None, &["serial"]),
+            message("release", Some(Type::Destructor), &["serial"]),
+        ],
+        ..Interface::new()
+    };
+    assert_eq!(
+        destructor_op(&zwp_tablet_pad_group),
+        quote! { Some(1) }.to_string()
+    );
+
+    let wl_registry = Interface {
+        name: "wl_registry".to_owned(),
+        requests: vec![message("bind", None, &["name"])],
+        ..Interface::new()
+    };
+    assert_eq!(destructor_op(&wl_registry), quote! { None }.to_string());
+}
+
+#[test]
+fn test_message_enum_arg() {
+    let arg = |name: &str, enum_: Option<&str>| Arg {
+        name: name.to_owned(),
+        typ: Type::Uint,
+        enum_: enum_.map(str::to_owned),
+        ..Arg::new()
+    };
+    let interface = Interface { name: "wl_shm_pool".to_owned(), ..Interface::new() };
+    let message = Message {
+        name: "create_buffer".to_owned(),
+        args: vec![
+            arg("offset", None),
+            arg("format", Some("wl_shm.format")),
+            arg("own", Some("wl_shm_pool.mode")),
+            arg("unqualified", Some("mode")),
+        ],
+        ..Message::new()
+    };
+
+    let file: syn::File = syn::parse2(generate_message(
+        &message,
+        &interface,
+        &format_ident!("wl_shm_pool"),
+        DocLinks::default(),
+    ))
+    .unwrap();
+    let fields = file
+        .items
+        .iter()
+        .find_map(|item| match item {
+            syn::Item::Struct(item) => Some(&item.fields),
+            _ => None,
+        })
+        .expect("missing message struct")
+        .iter()
+        .map(|field| {
+            (
+                field.ident.as_ref().unwrap().to_string(),
+                field.ty.to_token_stream().to_string(),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let expected = [
+        ("offset", quote! { uint }),
+        ("format", quote! { wl_shm::enumeration::format }),
+        ("own", quote! { enumeration::mode }),
+        ("unqualified", quote! { enumeration::mode }),
+    ]
+    .map(|(name, typ)| (name.to_owned(), typ.to_string()));
+    assert_eq!(fields, expected);
+}
+
+#[test]
+fn test_opcode_name() {
+    let message = |name: &str| Message { name: name.to_owned(), ..Message::new() };
+    let messages = [message("global"), message("global_remove")];
+    let file: syn::File = syn::parse2(gen_message_opcodes(&messages)).unwrap();
+
+    let name = impl_items(&file.items)
+        .find_map(|item| match item {
+            syn::ImplItem::Fn(item) if item.sig.ident == "name" => Some(&item.block),
+            _ => None,
+        })
+        .expect("missing `Opcodes::name()`");
+    assert_eq!(
+        name.to_token_stream().to_string(),
+        quote! {{
+            match self {
+                Self::global => "global",
+                Self::global_remove => "global_remove",
+            }
+        }}
+        .to_string()
+    );
+    let name_of = file
+        .items
+        .iter()
+        .any(|item| matches!(item, syn::Item::Fn(item) if item.sig.ident == "name_of"));
+    assert!(name_of, "missing `name_of()`");
+}
+
+#[test]
+fn test_message_decoded() {
+    let arg = |name: &str, typ: Type| Arg { name: name.to_owned(), typ, ..Arg::new() };
+    let global = Message {
+        name: "global".to_owned(),
+        args: vec![arg("name", Type::Uint), arg("interface", Type::String), arg("version", Type::Uint)],
+        ..Message::new()
+    };
+    let global_remove =
+        Message { name: "global_remove".to_owned(), args: vec![arg("name", Type::Uint)], ..Message::new() };
+    let file: syn::File = syn::parse2(gen_message_opcodes(&[global, global_remove])).unwrap();
+
+    let decoded = file
+        .items
+        .iter()
+        .find_map(|item| match item {
+            syn::Item::Enum(item) if item.ident == "Decoded" => Some(item),
+            _ => None,
+        })
+        .expect("missing `Decoded`");
+    assert_eq!(
+        decoded.generics.to_token_stream().to_string(),
+        quote! { <'data> }.to_string()
+    );
+    let variants: Vec<_> = decoded
+        .variants
+        .iter()
+        .map(|variant| {
+            (
+                variant.ident.to_string(),
+                variant.fields.to_token_stream().to_string(),
+            )
+        })
+        .collect();
+    let expected = [("global", quote! { (global<'data>) }), ("global_remove", quote! { (global_remove) })]
+        .map(|(name, fields)| (name.to_owned(), fields.to_string()));
+    assert_eq!(variants, expected);
+}
+
+#[test]
+fn test_dyn_new_id_message() {
+    let interface = Interface { name: "wl_registry".to_owned(), ..Interface::new() };
+    let message = Message {
+        name: "bind".to_owned(),
+        args: vec![
+            Arg { name: "name".to_owned(), typ: Type::Uint, ..Arg::new() },
+            Arg { name: "id".to_owned(), typ: Type::NewId, ..Arg::new() },
+        ],
+        ..Message::new()
+    };
+
+    let file: syn::File = syn::parse2(generate_message(
+        &message,
+        &interface,
+        &format_ident!("wl_registry"),
+        DocLinks::default(),
+    ))
+    .unwrap();
+    let structs = file
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            syn::Item::Struct(item) => Some((
+                item.ident.to_string(),
+                item.generics.to_token_stream().to_string(),
+                item.fields
+                    .iter()
+                    .map(|field| field.ty.to_token_stream().to_string())
+                    .collect::<Vec<_>>(),
+            )),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    let expected = [
+        (
+            "bind",
+            quote! { <I: proto::Interface> },
+            [quote! { uint }, quote! { new_id<I> }],
+        ),
+        (
+            "bind_dyn",
+            quote! { <'data> },
+            [quote! { uint }, quote! { new_id_dyn<'data> }],
+        ),
+    ]
+    .map(|(name, generics, fields)| {
+        (
+            name.to_owned(),
+            generics.to_string(),
+            fields.map(|typ| typ.to_string()).to_vec(),
+        )
+    });
+    assert_eq!(structs, expected);
+
+    // Both decode the same opcode.
+    let opcodes = impl_items(&file.items)
+        .filter_map(|item| match item {
+            syn::ImplItem::Const(item) if item.ident == "OPCODE" => Some(item.expr.to_token_stream().to_string()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let opcode = quote! { Self::Opcode::bind }.to_string();
+    assert_eq!(opcodes, [opcode.clone(), opcode]);
+}
+
+#[test]
+fn test_message_signature() {
+    let arg = |name: &str, typ| Arg { name: name.to_owned(), typ, ..Arg::new() };
+    let messages = [
+        Message {
+            name: "global".to_owned(),
+            args: vec![arg("name", Type::Uint), arg("interface", Type::String), arg("version", Type::Uint)],
+            ..Message::new()
+        },
+        Message { name: "global_remove".to_owned(), args: vec![arg("name", Type::Uint)], ..Message::new() },
+    ];
+    let file: syn::File = syn::parse2(gen_message_opcodes(&messages)).unwrap();
+
+    let signature = file
+        .items
+        .iter()
+        .find_map(|item| match item {
+            syn::Item::Const(item) if item.ident == "SIGNATURE" => Some(&item.expr),
+            _ => None,
+        })
+        .expect("missing `SIGNATURE`");
+    assert_eq!(
+        signature.to_token_stream().to_string(),
+        quote! {
+            &[
+                &[proto::ArgKind::Uint, proto::ArgKind::String, proto::ArgKind::Uint],
+                &[proto::ArgKind::Uint]
+            ]
+        }
+        .to_string()
+    );
+}
+
+#[test]
+fn test_enum_all() {
+    let entry = |name: &str, value| Entry { name: name.to_owned(), value, ..Entry::new() };
+    let format = Enum {
+        name: "format".to_owned(),
+        entries: vec![entry("argb8888", 0), entry("xrgb8888", 1), entry("c8", 0x20203843)],
+        ..Enum::new()
+    };
+    let file: syn::File = syn::parse2(generate_enum(&format, DocLinks::default())).unwrap();
+
+    let all = impl_items(&file.items)
+        .find_map(|item| match item {
+            syn::ImplItem::Const(item) if item.ident == "ALL" => Some(&item.expr),
+            _ => None,
+        })
+        .expect("missing `ALL`");
+    let syn::Expr::Reference(syn::ExprReference { expr, .. }) = all else { panic!("`ALL` is not a reference") };
+    let syn::Expr::Array(array) = &**expr else { panic!("`ALL` is not an array") };
+    let variants = array
+        .elems
+        .iter()
+        .map(|elem| elem.to_token_stream().to_string())
+        .collect::<Vec<_>>();
+    for variant in [format_ident!("argb8888"), format_ident!("xrgb8888")] {
+        assert!(
+            variants.contains(&quote! { Self::#variant }.to_string()),
+            "missing `{variant}`"
+        );
+    }
+    assert_eq!(variants.len(), 3);
+}
+
+#[test]
+fn test_enum_conversions() {
+    let entry = |name: &str, value| Entry { name: name.to_owned(), value, ..Entry::new() };
+    let format =
+        Enum { name: "format".to_owned(), entries: vec![entry("argb8888", 0), entry("xrgb8888", 1)], ..Enum::new() };
+    let file: syn::File = syn::parse2(generate_enum(&format, DocLinks::default())).unwrap();
+
+    let impls = file
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            syn::Item::Impl(item) => {
+                let (_, path, _) = item.trait_.as_ref()?;
+                Some((
+                    path.to_token_stream().to_string(),
+                    item.self_ty.to_token_stream().to_string(),
+                ))
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    for expected in [(quote! { TryFrom<u32> }, quote! { format }), (quote! { From<format> }, quote! { u32 })] {
+        assert!(impls.contains(&(expected.0.to_string(), expected.1.to_string())));
+    }
+}
+
+#[test]
+fn test_message_min_version() {
+    let interface = Interface { name: "wl_surface".to_owned(), ..Interface::new() };
+    let message = Message {
+        name: "set_buffer_transform".to_owned(),
+        since: 2,
+        args: vec![Arg { name: "transform".to_owned(), typ: Type::Int, ..Arg::new() }],
+        ..Message::new()
+    };
+
+    let file: syn::File = syn::parse2(generate_message(
+        &message,
+        &interface,
+        &format_ident!("wl_surface"),
+        DocLinks::default(),
+    ))
+    .unwrap();
+    let versions = impl_items(&file.items)
+        .filter_map(|item| match item {
+            syn::ImplItem::Const(item) if item.ident.to_string().ends_with("VERSION") => Some((
+                item.ident.to_string(),
+                item.expr.to_token_stream().to_string(),
+            )),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        versions,
+        [("VERSION".to_owned(), "2".to_owned()), ("MIN_VERSION".to_owned(), "2".to_owned())]
+    );
+}
+
+#[test]
+fn test_doc_links() {
+    let mut surface = Interface { name: "wl_surface".to_owned(), ..Interface::new() };
+    surface.requests = vec![Message { name: "attach".to_owned(), ..Message::new() }];
+    let mut callback = Interface { name: "wl_callback".to_owned(), ..Interface::new() };
+    callback.events = vec![Message {
+        name: "done".to_owned(),
+        description: Some((
+            "sent after wl_surface.attach, see wl_surface and `wl_surface`, but not wl_output [1]".to_owned(),
+            String::new(),
+        )),
+        ..Message::new()
+    }];
+    let mut protocol = Protocol::new("test".to_owned());
+    protocol.interfaces = vec![surface, callback];
+
+    let docs = |config: &Config| {
+        let module: syn::ItemMod = syn::parse2(generate_protocol_with(&protocol, config)).unwrap();
+        let event = module
+            .content
+            .unwrap()
+            .1
+            .into_iter()
+            .find_map(|item| match item {
+                syn::Item::Mod(item) if item.ident == "wl_callback" => Some(item.content.unwrap().1),
+                _ => None,
+            })
+            .and_then(|items| {
+                items.into_iter().find_map(|item| match item {
+                    syn::Item::Mod(item) if item.ident == "event" => Some(item.content.unwrap().1),
+                    _ => None,
+                })
+            })
+            .expect("missing `wl_callback::event`");
+        let done = event
+            .iter()
+            .find_map(|item| match item {
+                syn::Item::Struct(item) if item.ident == "done" => Some(&item.attrs),
+                _ => None,
+            })
+            .expect("missing `done`");
+        done.iter()
+            .filter_map(|attr| match &attr.meta {
+                syn::Meta::NameValue(syn::MetaNameValue {
+                    value: syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Str(str), .. }),
+                    ..
+                }) => Some(str.value()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        docs(&Config::default()),
+        [r" sent after wl_surface.attach, see wl_surface and `wl_surface`, but not wl_output \[1\]"]
+    );
+    assert_eq!(
+        docs(&Config::default().doc_links(true)),
+        [concat!(
+            " sent after [`wl_surface.attach`](super::super::wl_surface::request::attach),",
+            " see [`wl_surface`](super::super::wl_surface::wl_surface) and `wl_surface`,",
+            r" but not wl_output \[1\]"
+        )]
+    );
+}
@@ -1,27 +1,45 @@
 use crate::config::{read_xml_to_protocol, write_tokens_to_file};
+use proc_macro2::TokenStream;
 use std::path::Path;
 
-pub use self::builder::Wayland;
+pub use self::{builder::Wayland, config::Config, derive_value::derive_value, protocol_macro::protocol_macro};
 
 pub mod builder;
 mod config;
+mod derive_value;
 mod generate;
+mod protocol_macro;
 
-// pub fn protocol(stream: TokenStream) -> TokenStream {
-//     parse_macro_input!(stream as GenerateConfig).into_token_stream()
-// }
+/// Parse the protocol xml at `infile` and generate its bindings.
+pub fn protocol_to_tokens(infile: impl AsRef<Path>) -> syn::Result<TokenStream> {
+    protocol_to_tokens_with(infile, &Config::default())
+}
 
-pub fn protocol(protocol: impl AsRef<Path>, outfile: impl AsRef<Path>, formatted: bool) {
-    fn inner(infile: &Path, outfile: &Path, formatted: bool) -> syn::Result<()> {
-        write_tokens_to_file(read_xml_to_protocol(infile)?, outfile, formatted)?;
+/// Like [`protocol_to_tokens()`], but with the options in `config`.
+pub fn protocol_to_tokens_with(infile: impl AsRef<Path>, config: &Config) -> syn::Result<TokenStream> {
+    let protocol = read_xml_to_protocol(infile.as_ref())?;
+    Ok(generate::generate_protocol_with(&protocol, config))
+}
 
-        Ok(())
-    }
+/// Generate the bindings for the protocol xml at `infile` and write them to `outfile`.
+pub fn protocol_to_file(infile: impl AsRef<Path>, outfile: impl AsRef<Path>, formatted: bool) -> syn::Result<()> {
+    write_tokens_to_file(protocol_to_tokens(infile)?, outfile.as_ref(), formatted)
+}
 
-    match inner(protocol.as_ref(), outfile.as_ref(), formatted) {
-        Ok(()) => {}
-        Err(err) => {
-            println!("cargo::error={err}")
-        }
+/// Like [`protocol_to_file`], but reports errors to cargo, for use in build scripts.
+pub fn protocol(protocol: impl AsRef<Path>, outfile: impl AsRef<Path>, formatted: bool) {
+    if let Err(err) = protocol_to_file(protocol, outfile, formatted) {
+        println!("cargo::error={err}")
     }
 }
+
+#[test]
+fn test_protocol_malformed_xml() {
+    let xml = r#"<protocol name="broken"><interface name="wl_broken" version="x">"#;
+    let infile = std::env::temp_dir().join(format!("codegen-malformed-{}.xml", std::process::id()));
+    std::fs::write(&infile, xml).unwrap();
+
+    let res = protocol_to_tokens(&infile);
+    std::fs::remove_file(&infile).unwrap();
+    assert!(res.is_err());
+}
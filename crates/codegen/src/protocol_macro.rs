@@ -0,0 +1,107 @@
+use crate::{config::read_xml_to_protocol, generate::generate_protocol};
+use proc_macro2::TokenStream;
+use quote::quote;
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+use syn::{
+    LitStr, Token, custom_keyword,
+    parse::{Parse, ParseStream},
+};
+
+custom_keyword!(path);
+
+/// Input of `protocol!(path = "wayland.xml")`.
+struct ProtocolInput {
+    path: LitStr,
+}
+
+impl Parse for ProtocolInput {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        input.parse::<path>()?;
+        input.parse::<Token![=]>()?;
+        let path = input.parse()?;
+        input.parse::<Option<Token![,]>>()?;
+        Ok(Self { path })
+    }
+}
+
+impl ProtocolInput {
+    /// The xml path, relative ones being resolved against the `CARGO_MANIFEST_DIR` of the crate
+    /// invoking the macro, like `include!` does against the invoking file.
+    fn resolve(&self) -> PathBuf {
+        let path = PathBuf::from(self.path.value());
+        match env::var_os("CARGO_MANIFEST_DIR") {
+            Some(manifest_dir) if path.is_relative() => Path::new(&manifest_dir).join(path),
+            _ => path,
+        }
+    }
+
+    fn expand(&self) -> syn::Result<TokenStream> {
+        let path = self.resolve();
+        let protocol = read_xml_to_protocol(&path).map_err(|err| syn::Error::new(self.path.span(), err))?;
+        let bindings = generate_protocol(&protocol);
+
+        // Depending on the file makes cargo rebuild the invoking crate when it changes.
+        let Some(path) = path.to_str() else {
+            return Err(syn::Error::new(
+                self.path.span(),
+                format!(
+                    "non-UTF-8 path `{path}` isn't supported",
+                    path = path.display()
+                ),
+            ));
+        };
+        Ok(quote! {
+            const _: &[u8] = include_bytes!(#path);
+            #bindings
+        })
+    }
+}
+
+/// Expand `protocol!(path = "wayland.xml")` to the bindings of the protocol at `path`, see
+/// [`protocol_to_tokens()`](crate::protocol_to_tokens).
+///
+/// Errors are turned into a `compile_error!` pointing at the path.
+pub fn protocol_macro(input: TokenStream) -> TokenStream {
+    syn::parse2::<ProtocolInput>(input)
+        .and_then(|input| input.expand())
+        .unwrap_or_else(syn::Error::into_compile_error)
+}
+
+#[test]
+fn test_protocol_macro() {
+    let xml =
+        r#"<protocol name="tiny"><interface name="tn_ping" version="1"><request name="ping"/></interface></protocol>"#;
+    let infile = env::temp_dir().join(format!("codegen-macro-{}.xml", std::process::id()));
+    std::fs::write(&infile, xml).unwrap();
+
+    let path = infile.to_str().unwrap();
+    let expanded = protocol_macro(quote! { path = #path, });
+    std::fs::remove_file(&infile).unwrap();
+
+    let file: syn::File = syn::parse2(expanded).unwrap();
+    let items: Vec<_> = file
+        .items
+        .iter()
+        .map(|item| match item {
+            syn::Item::Const(item) => quote! { #item }.to_string(),
+            syn::Item::Mod(item) => item.ident.to_string(),
+            _ => panic!("unexpected item"),
+        })
+        .collect();
+    assert_eq!(items[1], "tiny");
+    assert!(items[0].contains(path), "{}", items[0]);
+
+    // Relative paths are resolved against the manifest dir, which doesn't have this file.
+    let err = protocol_macro(quote! { path = "missing.xml" }).to_string();
+    assert!(err.contains("compile_error"), "{err}");
+    assert!(err.contains(env!("CARGO_MANIFEST_DIR")), "{err}");
+
+    assert!(
+        protocol_macro(quote! { file = "wayland.xml" })
+            .to_string()
+            .contains("compile_error")
+    );
+}
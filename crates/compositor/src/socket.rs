@@ -5,6 +5,9 @@ use std::{collections::VecDeque, os::fd::OwnedFd};
 use tokio::net::UnixStream;
 
 pub mod buffer;
+mod listener;
+
+pub use self::listener::Listener;
 
 /// Maximum number of FD that can be sent in a single socket message
 pub const MAX_FDS_COUNT: usize = 28;
@@ -1,12 +1,14 @@
 use std::{
-    alloc::Layout,
     cmp,
+    mem::MaybeUninit,
+    pin::pin,
     os::fd::RawFd,
     ptr,
     sync::atomic::{AtomicBool, AtomicUsize, Ordering},
 };
+use tokio::sync::Notify;
 
-struct MessageQueue {
+pub(crate) struct MessageQueue {
     buf: *mut Message,
     capacity: usize,
 
@@ -21,31 +23,72 @@ struct MessageQueue {
     /// Index of the first active message, until which new messages can be written
     write_until: AtomicUsize,
 
+    /// Index of the next message handed out by [`MessageQueue::next_message()`]
+    ///
+    /// There is only supposed to be a single reader.
+    read_next: AtomicUsize,
+
     data: Subqueue<u8>,
     fds: Subqueue<RawFd>,
+
+    /// Notified whenever the reader is done with a message, see
+    /// [`MessageQueue::allocate_message_wait()`].
+    freed: Notify,
 }
 
 const PROCESSING: usize = usize::MAX;
 
 impl MessageQueue {
-    fn new(msgs: usize, data: usize, fds: usize) -> Self {
-        use std::alloc;
-
-        #[allow(unused)]
-        unsafe {
-            let buf: *mut Message =
-                alloc::alloc(Layout::array::<Message>(msgs).expect("invalid amount of messages"))
-                    as _;
-            let data: *mut u8 =
-                alloc::alloc(Layout::array::<u8>(data).expect("invalid amount of data bytes")) as _;
-            let fds: *mut RawFd =
-                alloc::alloc(Layout::array::<RawFd>(fds).expect("invalid amount of fds")) as _;
-
-            todo!()
+    pub(crate) fn with_capacity(msgs: usize, data: usize, fds: usize) -> Self {
+        let buf: Box<[Message]> = (0..msgs).map(|_| Message::INIT).collect();
+
+        Self {
+            buf: Box::into_raw(buf).cast(),
+            capacity: msgs,
+            write_next: AtomicUsize::new(0),
+            write_until: AtomicUsize::new(0),
+            read_next: AtomicUsize::new(0),
+            data: Subqueue::with_capacity(data),
+            fds: Subqueue::with_capacity(fds),
+            freed: Notify::new(),
+        }
+    }
+
+    /// Take the next message written by [`MessageQueue::allocate_message()`], which is
+    /// deallocated again when the returned [`MessageRef`] is dropped.
+    ///
+    /// Returns [`None`] if the next message wasn't allocated yet, or is still being written.
+    pub(crate) fn next_message(&self) -> Option<MessageRef<'_>> {
+        let index = self.read_next.load(Ordering::Acquire);
+        if index >= self.capacity {
+            return None;
+        }
+
+        // SAFETY: Messages are only ever accessed immutably, the fields written by
+        // `allocate_message()` are published by the `Ordering::AcqRel` swap of `is_ready`.
+        let message = unsafe { &*self.buf.add(index) };
+        if !message.is_ready.swap(false, Ordering::AcqRel) {
+            return None;
         }
+
+        let next = if index + 1 < self.capacity { index + 1 } else { 0 };
+        self.read_next.store(next, Ordering::Release);
+
+        Some(MessageRef {
+            queue: self,
+            index,
+            data: ptr::slice_from_raw_parts(
+                unsafe { self.data.buf.add(message.data_start.load(Ordering::Relaxed)) },
+                message.data_len.load(Ordering::Relaxed),
+            ),
+            fds: ptr::slice_from_raw_parts(
+                unsafe { self.fds.buf.add(message.fds_start.load(Ordering::Relaxed)) },
+                message.fds_len.load(Ordering::Relaxed),
+            ),
+        })
     }
 
-    fn allocate_message(&self, data: usize, fds: usize) -> Option<MessageHandle<'_>> {
+    pub(crate) fn allocate_message(&self, data: usize, fds: usize) -> Option<MessageHandle<'_>> {
         let mut write_next = self.write_next.load(Ordering::Acquire);
 
         loop {
@@ -74,12 +117,31 @@ impl MessageQueue {
         }
 
         let index = write_next;
-        let data_handle = self.data.allocate(data)?;
-        let fds_handle = self.fds.allocate(fds)?;
+        let (data_handle, fds_handle) = match (self.data.allocate(data), self.fds.allocate(fds)) {
+            (Some(data_handle), Some(fds_handle)) => (data_handle, fds_handle),
+            (data_handle, fds_handle) => {
+                // Give back whatever space was allocated, before releasing the lock.
+                if let Some(handle) = data_handle {
+                    handle.free();
+                }
+                if let Some(handle) = fds_handle {
+                    handle.free();
+                }
+                self.write_next.store(index, Ordering::Release);
+                return None;
+            }
+        };
 
-        // SAFETY: We have immutable access to `self`, and have just allocated the new message,
-        // so handing out an immutable reference to it is fine.
-        let message = unsafe { &*self.buf.add(write_next) };
+        // SAFETY: `index < self.capacity`.
+        let message = unsafe { &*self.buf.add(index) };
+        // Nobody reads these before `is_ready` is set, which publishes them with
+        // `Ordering::Release`, so `Ordering::Relaxed` is enough here.
+        message.data_start.store(data_handle.index, Ordering::Relaxed);
+        message.data_len.store(data, Ordering::Relaxed);
+        message.fds_start.store(fds_handle.index, Ordering::Relaxed);
+        message.fds_len.store(fds, Ordering::Relaxed);
+        message.is_active.store(true, Ordering::Release);
+        message.is_ready.store(false, Ordering::Release);
 
         write_next = if write_next + 1 < self.capacity { write_next + 1 } else { 0 };
 
@@ -101,6 +163,21 @@ impl MessageQueue {
         })
     }
 
+    /// Like [`MessageQueue::allocate_message()`], but waits for the reader to make room while the
+    /// queue is full.
+    pub(crate) async fn allocate_message_wait(&self, data: usize, fds: usize) -> MessageHandle<'_> {
+        let mut freed = pin!(self.freed.notified());
+        loop {
+            // Register before trying, so a message freed in-between isn't missed.
+            freed.as_mut().enable();
+            if let Some(handle) = self.allocate_message(data, fds) {
+                return handle;
+            }
+            freed.as_mut().await;
+            freed.set(self.freed.notified());
+        }
+    }
+
     fn deallocate(&self, index: usize) {
         if self.write_until.load(Ordering::Acquire) != index {
             // Mark message as tombstone and exit
@@ -167,6 +244,9 @@ impl MessageQueue {
 
                 self.write_until.store(0, Ordering::Release);
 
+                // All messages have been deallocated, so there is nothing left for the reader.
+                self.read_next.store(0, Ordering::Release);
+
                 // Release the lock
                 self.write_next.store(0, Ordering::Release);
 
@@ -178,22 +258,31 @@ impl MessageQueue {
             if is_active.load(Ordering::Acquire) {
                 // We arrived at an active message
 
-                // SAFETY:
-                // The message is active, and we have control over the queue, so getting a
-                // immutable reference to it is fine.
+                // SAFETY: Messages are only ever accessed immutably.
                 let Message { data_start, fds_start, .. } = unsafe { &*message };
+                let data_start = data_start.load(Ordering::Acquire);
+                let fds_start = fds_start.load(Ordering::Acquire);
 
                 // Mark `self.data` and `self.fds` as free until the contents of the message at
                 // `cleanup_until`.
                 //
                 // This is safe because all messages in-between are marked as not active,
                 // so there are no references to it anymore and the space is safe to be re-used.
-                self.data.write_until.store(*data_start, Ordering::Release);
-                self.fds.write_until.store(*fds_start, Ordering::Release);
+                self.data.write_until.store(data_start, Ordering::Release);
+                self.fds.write_until.store(fds_start, Ordering::Release);
 
                 // Give over control to the message
                 self.write_until.store(cleanup_until, Ordering::Release);
 
+                // If the queue was marked as full, the writer stopped right at `index`, from
+                // where on the space is free again.
+                let _ = self.write_next.compare_exchange(
+                    self.capacity,
+                    index,
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                );
+
                 // Make sure the message we handed control is still active,
                 // since us checking `message.is_active` above.
                 if is_active.load(Ordering::Acquire) {
@@ -222,7 +311,16 @@ impl MessageQueue {
     }
 }
 
-struct MessageHandle<'a> {
+impl Drop for MessageQueue {
+    fn drop(&mut self) {
+        // SAFETY: `self.buf` was allocated in `MessageQueue::with_capacity()` with `self.capacity`
+        // messages.
+        drop(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(self.buf, self.capacity)) });
+    }
+}
+
+/// Writer side of a message, which is handed to the reader once dropped.
+pub(crate) struct MessageHandle<'a> {
     queue: &'a MessageQueue,
     index: usize,
 
@@ -231,17 +329,76 @@ struct MessageHandle<'a> {
     fds: *mut [RawFd],
 }
 
+impl MessageHandle<'_> {
+    pub(crate) fn data(&mut self) -> &mut [u8] {
+        // SAFETY: The space was allocated exclusively for this handle.
+        unsafe { &mut *self.data }
+    }
+
+    pub(crate) fn fds(&mut self) -> &mut [RawFd] {
+        // SAFETY: The space was allocated exclusively for this handle.
+        unsafe { &mut *self.fds }
+    }
+}
+
+impl Drop for MessageHandle<'_> {
+    fn drop(&mut self) {
+        self.message.is_ready.store(true, Ordering::Release);
+    }
+}
+
+/// Reader side of a message, see [`MessageQueue::next_message()`].
+pub(crate) struct MessageRef<'a> {
+    queue: &'a MessageQueue,
+    index: usize,
+
+    data: *const [u8],
+    fds: *const [RawFd],
+}
+
+impl MessageRef<'_> {
+    pub(crate) fn data(&self) -> &[u8] {
+        // SAFETY: The message stays allocated until `self` is dropped.
+        unsafe { &*self.data }
+    }
+
+    pub(crate) fn fds(&self) -> &[RawFd] {
+        // SAFETY: The message stays allocated until `self` is dropped.
+        unsafe { &*self.fds }
+    }
+}
+
+impl Drop for MessageRef<'_> {
+    fn drop(&mut self) {
+        self.queue.deallocate(self.index);
+        self.queue.freed.notify_waiters();
+    }
+}
+
 #[derive(Debug)]
 struct Message {
     is_active: AtomicBool,
-
-    data_start: usize,
-    fds_start: usize,
+    /// Set once the writer is done with the message, and cleared again by the reader.
+    is_ready: AtomicBool,
+
+    /// Only written by [`MessageQueue::allocate_message()`] while holding the lock on
+    /// `write_next`, but atomic since the reader and the cleanup share the [`Message`].
+    data_start: AtomicUsize,
+    data_len: AtomicUsize,
+    fds_start: AtomicUsize,
+    fds_len: AtomicUsize,
 }
 
 impl Message {
     #[allow(clippy::declare_interior_mutable_const)]
-    const INIT: Self = Self { is_active: AtomicBool::new(false), data_start: 0, fds_start: 0 };
+    const INIT: Self = Self {
+        is_active: AtomicBool::new(false),
+        is_ready: AtomicBool::new(false),
+        data_start: AtomicUsize::new(0),
+        data_len: AtomicUsize::new(0),
+        fds_start: AtomicUsize::new(0),
+        fds_len: AtomicUsize::new(0),
+    };
 }
 
 struct Subqueue<T> {
@@ -255,17 +412,32 @@ struct Subqueue<T> {
 struct SubqueueHandle<'a, T> {
     queue: &'a Subqueue<T>,
     index: usize,
+    /// `queue.write_next` before this allocation.
+    prev_write_next: usize,
 
     data: *mut [T],
 }
 
 impl<T> Subqueue<T> {
+    fn with_capacity(capacity: usize) -> Self {
+        let buf = Box::<[T]>::new_uninit_slice(capacity);
+
+        Self {
+            buf: Box::into_raw(buf).cast(),
+            capacity,
+            write_next: AtomicUsize::new(0),
+            write_until: AtomicUsize::new(0),
+        }
+    }
+
     fn allocate(&self, len: usize) -> Option<SubqueueHandle<'_, T>> {
         let mut write_next = self.write_next.load(Ordering::Acquire);
         let mut write_until = self.write_until.load(Ordering::Acquire);
+        let mut prev_write_next;
         let mut new_write_next;
 
         loop {
+            prev_write_next = write_next;
             'enough_space: {
                 if write_until <= write_next {
                     let available_space = self.capacity - write_next;
@@ -294,7 +466,7 @@ impl<T> Subqueue<T> {
 
             // Actually allocate our new data
             match self.write_next.compare_exchange_weak(
-                write_next,
+                prev_write_next,
                 new_write_next,
                 Ordering::AcqRel,
                 Ordering::Acquire,
@@ -313,13 +485,107 @@ impl<T> Subqueue<T> {
         // be exclusively be used by holders of the handle, is fine.
         let data = unsafe { ptr::slice_from_raw_parts_mut(self.buf.add(write_next), len) };
 
-        Some(SubqueueHandle { queue: self, index: write_next, data })
+        Some(SubqueueHandle { queue: self, index: write_next, prev_write_next, data })
+    }
+}
+
+impl<T> SubqueueHandle<'_, T> {
+    /// Give the space back to the queue.
+    ///
+    /// Only sound while holding the lock on [`MessageQueue::write_next`], with this being the
+    /// latest allocation in the queue.
+    fn free(self) {
+        self.queue.write_next.store(self.prev_write_next, Ordering::Release);
+    }
+}
+
+impl<T> Drop for Subqueue<T> {
+    fn drop(&mut self) {
+        // SAFETY: `self.buf` was allocated in `Subqueue::with_capacity()` with `self.capacity`
+        // elements, which don't need to be dropped.
+        drop(unsafe {
+            Box::from_raw(ptr::slice_from_raw_parts_mut(
+                self.buf.cast::<MaybeUninit<T>>(),
+                self.capacity,
+            ))
+        });
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::socket::buffer::MessageQueue;
+    use std::{pin::pin, sync::atomic::Ordering, time::Duration};
+    use tokio::time;
 
     #[test]
-    fn simple_alloc_dealloc() {}
+    fn simple_alloc_dealloc() {
+        let queue = MessageQueue::with_capacity(4, 64, 8);
+        assert!(queue.next_message().is_none());
+
+        let mut first = queue.allocate_message(8, 2).unwrap();
+        first.data().copy_from_slice(b"wl_shm\0\0");
+        first.fds().copy_from_slice(&[3, 4]);
+
+        let mut second = queue.allocate_message(4, 0).unwrap();
+        second.data().copy_from_slice(&7u32.to_ne_bytes());
+
+        // Still being written.
+        assert!(queue.next_message().is_none());
+        drop(first);
+        drop(second);
+
+        let first = queue.next_message().unwrap();
+        assert_eq!(first.data(), b"wl_shm\0\0");
+        assert_eq!(first.fds(), [3, 4]);
+
+        let second = queue.next_message().unwrap();
+        assert_eq!(second.data(), 7u32.to_ne_bytes());
+        assert!(second.fds().is_empty());
+        assert!(queue.next_message().is_none());
+
+        drop(first);
+        assert_eq!(queue.write_until.load(Ordering::Acquire), 1);
+        drop(second);
+
+        // Deallocating the last message resets the queue.
+        assert_eq!(queue.write_next.load(Ordering::Acquire), 0);
+        assert_eq!(queue.write_until.load(Ordering::Acquire), 0);
+        assert_eq!(queue.data.write_next.load(Ordering::Acquire), 0);
+
+        let mut msg = queue.allocate_message(4, 1).unwrap();
+        msg.data().copy_from_slice(b"next");
+        drop(msg);
+        assert_eq!(queue.next_message().unwrap().data(), b"next");
+    }
+
+    #[test]
+    fn failed_alloc_frees_space() {
+        let queue = MessageQueue::with_capacity(4, 16, 2);
+
+        // Whichever part did fit has to be given back.
+        assert!(queue.allocate_message(8, 3).is_none());
+        assert_eq!(queue.data.write_next.load(Ordering::Acquire), 0);
+        assert!(queue.allocate_message(16, 1).is_none());
+        assert_eq!(queue.fds.write_next.load(Ordering::Acquire), 0);
+
+        let mut msg = queue.allocate_message(8, 1).unwrap();
+        msg.data().copy_from_slice(b"wl_seat\0");
+        drop(msg);
+        assert_eq!(queue.next_message().unwrap().data(), b"wl_seat\0");
+    }
+
+    #[tokio::test]
+    async fn alloc_waits_for_reader() {
+        let queue = MessageQueue::with_capacity(2, 64, 8);
+        drop(queue.allocate_message(4, 0).unwrap());
+        drop(queue.allocate_message(4, 0).unwrap());
+        assert!(queue.allocate_message(4, 0).is_none());
+
+        let mut wait = pin!(queue.allocate_message_wait(4, 0));
+        assert!(time::timeout(Duration::from_millis(10), wait.as_mut()).await.is_err());
+
+        drop(queue.next_message().unwrap());
+        time::timeout(Duration::from_secs(5), wait).await.unwrap();
+    }
 }
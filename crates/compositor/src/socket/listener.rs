@@ -0,0 +1,321 @@
+use crate::socket::{MAX_BYTES, MAX_FDS_COUNT, buffer::MessageQueue};
+use ecs_compositor_core::Interface;
+use ecs_compositor_tokio::{
+    connection::{Connection, Object},
+    handle::{ConnectionHandle, InterfaceDir, Server},
+};
+use std::{
+    env,
+    ffi::OsStr,
+    fmt::Display,
+    fs::{self, File, TryLockError},
+    io,
+    os::fd::{BorrowedFd, IntoRawFd, OwnedFd},
+    path::{Path, PathBuf},
+};
+use tokio::net::UnixListener;
+
+/// Highest display number [`Listener::bind()`] tries, the same limit libwayland uses.
+const MAX_DISPLAYS: u32 = 32;
+
+/// Number of requests the [`MessageQueue`] of a [`Listener`] holds before
+/// [`Listener::forward()`] has to wait for the reader.
+const QUEUE_MESSAGES: usize = 256;
+
+/// Display socket clients connect to, yielding a [`Connection<Server>`] per client.
+///
+/// Requests of all clients are forwarded into a single [`MessageQueue`], from which the ECS picks
+/// them up.
+///
+/// Next to the socket a `<socket>.lock` file is held locked while the listener lives, so other
+/// compositors skip the display and a socket left behind by a crashed one can be told apart from
+/// one that is in use. Both get removed again on drop.
+pub struct Listener {
+    listener: UnixListener,
+    queue: MessageQueue,
+    path: PathBuf,
+    lock_path: PathBuf,
+    _lock: File,
+}
+
+impl Listener {
+    /// Bind the first free `$XDG_RUNTIME_DIR/wayland-N`.
+    pub fn bind() -> io::Result<Self> {
+        let runtime_dir = env::var_os("XDG_RUNTIME_DIR")
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "`XDG_RUNTIME_DIR` not set"))?;
+        Self::bind_in(Path::new(&runtime_dir))
+    }
+
+    /// Bind the first free `wayland-N` in `dir`.
+    pub fn bind_in(dir: &Path) -> io::Result<Self> {
+        for n in 0..MAX_DISPLAYS {
+            match Self::bind_to(dir.join(format!("wayland-{n}"))) {
+                Err(err) if err.kind() == io::ErrorKind::AddrInUse => continue,
+                res => return res,
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            "no free wayland display",
+        ))
+    }
+
+    /// Bind the socket at `path`, failing with [`io::ErrorKind::AddrInUse`] if another listener
+    /// holds its lock.
+    pub fn bind_to(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let mut lock_path = path.clone().into_os_string();
+        lock_path.push(".lock");
+        let lock_path = PathBuf::from(lock_path);
+
+        let lock = File::options().create(true).truncate(false).write(true).open(&lock_path)?;
+        match lock.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => return Err(io::ErrorKind::AddrInUse.into()),
+            Err(TryLockError::Error(err)) => return Err(err),
+        }
+
+        // Holding the lock means whoever created a leftover socket is gone.
+        match fs::remove_file(&path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+        let listener = UnixListener::bind(&path)?;
+
+        let queue = MessageQueue::with_capacity(QUEUE_MESSAGES, MAX_BYTES * 16, MAX_FDS_COUNT * 4);
+
+        Ok(Self { listener, queue, path, lock_path, _lock: lock })
+    }
+
+    /// Path of the socket.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Name of the socket clients expect in `$WAYLAND_DISPLAY`.
+    pub fn name(&self) -> &OsStr {
+        self.path.file_name().unwrap_or_default()
+    }
+
+    /// Queue the requests passed to [`Listener::forward()`] end up in.
+    pub(crate) fn queue(&self) -> &MessageQueue {
+        &self.queue
+    }
+
+    /// Wait for the next client and set up its connection.
+    ///
+    /// Requests to its objects reach the [queue](Self::queue()) through [`Listener::forward()`].
+    pub async fn accept(&self) -> io::Result<Connection<Server>> {
+        let (sock, _) = self.listener.accept().await?;
+        Connection::from_stream(sock.into_std()?)
+    }
+
+    /// Receive the next request to `obj` and copy it into the [queue](Self::queue()).
+    ///
+    /// The request is stored as it was on the wire, header included. Its fds are duplicated, so
+    /// the reader of the queue owns them. While the queue is full this waits for the reader to
+    /// make room.
+    pub async fn forward<Conn, I>(&self, obj: &Object<Conn, I>) -> io::Result<()>
+    where
+        Conn: ConnectionHandle<Dir = Server>,
+        Server: InterfaceDir<I, Recv: Display>,
+        I: Interface,
+    {
+        let msg = obj.recv().await?;
+        // Copied out so the rx buffer isn't held while waiting on the queue.
+        let (hdr, data) = (msg.hdr(), msg.raw_data().to_vec());
+
+        // Kept owned until they are in the queue, so they are closed if this future is dropped.
+        let fds = msg
+            .raw_fds()
+            .iter()
+            .map(|&fd| match fd {
+                -1 => Ok(None),
+                // SAFETY: The fd is owned by `msg`, which is alive for the duration of the borrow.
+                fd => Ok(Some(
+                    unsafe { BorrowedFd::borrow_raw(fd) }.try_clone_to_owned()?,
+                )),
+            })
+            .collect::<io::Result<Vec<Option<OwnedFd>>>>()?;
+        drop(msg);
+
+        let mut handle = self.queue.allocate_message_wait(8 + data.len(), fds.len()).await;
+        let (header, content) = handle.data().split_at_mut(8);
+        header[..4].copy_from_slice(&hdr.object_id.id().get().to_ne_bytes());
+        header[4..].copy_from_slice(&((hdr.datalen as u32) << 16 | hdr.opcode as u32).to_ne_bytes());
+        content.copy_from_slice(&data);
+        for (slot, fd) in handle.fds().iter_mut().zip(fds) {
+            *slot = fd.map_or(-1, IntoRawFd::into_raw_fd);
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+        let _ = fs::remove_file(&self.lock_path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Listener;
+    use ecs_compositor_core::{Message, Value, message_header, new_id, object, string, uint};
+    use ecs_compositor_tokio::{
+        connection::ServerHandle,
+        protocols::wayland::{wl_display, wl_registry},
+    };
+    use std::{
+        fs,
+        io::{self, Read, Write},
+        marker::PhantomData,
+        num::NonZero,
+        os::{fd::RawFd, unix::net::UnixStream},
+        ptr,
+    };
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        dir
+    }
+
+    /// Encode `msg` to `id` the way `examples/wayland-raw` does, header included.
+    fn encode<'a, M: Message<'a>>(id: u32, msg: &M) -> Vec<u32> {
+        let hdr = message_header::with_content_len(
+            object::from_id(NonZero::new(id).unwrap()),
+            M::OP,
+            msg.len() as usize,
+        )
+        .unwrap();
+        let mut buf = vec![0u32; hdr.datalen as usize / 4];
+        let mut data = ptr::slice_from_raw_parts_mut(buf.as_mut_ptr().cast::<u8>(), hdr.datalen as usize);
+        let mut fds = ptr::slice_from_raw_parts_mut(ptr::null_mut::<RawFd>(), 0);
+        unsafe {
+            hdr.write(&mut data, &mut fds).ok().unwrap();
+            msg.write(&mut data, &mut fds).ok().unwrap();
+        }
+        buf
+    }
+
+    fn as_bytes(words: &[u32]) -> &[u8] {
+        unsafe { &*ptr::slice_from_raw_parts(words.as_ptr().cast::<u8>(), words.len() * 4) }
+    }
+
+    /// Decode a message encoded by [`encode()`] from `words`.
+    fn decode<'a, M: Message<'a>>(words: &'a [u32]) -> (message_header, M) {
+        let mut data = ptr::slice_from_raw_parts(words.as_ptr().cast::<u8>(), words.len() * 4);
+        let mut fds = ptr::slice_from_raw_parts(ptr::null::<RawFd>(), 0);
+        unsafe {
+            let hdr = message_header::read(&mut data, &mut fds).ok().unwrap();
+            (hdr, M::read(&mut data, &mut fds).ok().unwrap())
+        }
+    }
+
+    /// Copy `bytes` into 4 byte aligned words for [`decode()`].
+    fn words(bytes: &[u8]) -> Vec<u32> {
+        bytes
+            .chunks(4)
+            .map(|word| u32::from_ne_bytes(word.try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn bind_skips_locked_displays() {
+        let rt = tokio::runtime::Builder::new_current_thread().enable_io().build().unwrap();
+        let _guard = rt.enter();
+        let dir = temp_dir("wayland-listener-bind");
+
+        let first = Listener::bind_in(&dir).unwrap();
+        let second = Listener::bind_in(&dir).unwrap();
+        assert_eq!(
+            (first.name(), second.name()),
+            ("wayland-0".as_ref(), "wayland-1".as_ref())
+        );
+        assert_eq!(
+            Listener::bind_to(first.path()).err().map(|err| err.kind()),
+            Some(io::ErrorKind::AddrInUse)
+        );
+
+        // A socket without a lock is stale and gets replaced.
+        let path = first.path().to_owned();
+        drop(first);
+        assert!(!path.exists());
+        std::os::unix::net::UnixListener::bind(&path).unwrap();
+        assert_eq!(Listener::bind_in(&dir).unwrap().path(), path);
+
+        drop(second);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn get_registry_handshake() {
+        let dir = temp_dir("wayland-listener-accept");
+        let listener = Listener::bind_in(&dir).unwrap();
+
+        let mut client = UnixStream::connect(listener.path()).unwrap();
+        let server = &listener.accept().await.unwrap();
+
+        const WL_REGISTRY: u32 = 2;
+        let get_registry = wl_display::request::get_registry {
+            registry: new_id { id: NonZero::new(WL_REGISTRY).unwrap(), _marker: PhantomData },
+        };
+        client.write_all(as_bytes(&encode(1, &get_registry))).unwrap();
+
+        // The request reaches the ECS side through the queue.
+        let server_display = server.register_client_object::<wl_display::wl_display>(1).unwrap();
+        listener.forward(&server_display).await.unwrap();
+        let msg = {
+            let msg = listener.queue().next_message().unwrap();
+            assert!(msg.fds().is_empty());
+            words(msg.data())
+        };
+        let (hdr, wl_display::request::get_registry { registry }) = decode(&msg);
+        assert_eq!(
+            (hdr.object_id.id().get(), hdr.opcode, registry.id.get()),
+            (1, wl_display::request::get_registry::OP, WL_REGISTRY)
+        );
+        assert!(listener.queue().next_message().is_none());
+
+        let server_registry = server
+            .register_client_object::<wl_registry::wl_registry>(registry.id.get())
+            .unwrap();
+        server_registry
+            .send_and_flush(&wl_registry::event::global {
+                name: uint(1),
+                interface: string::from_slice(b"wl_compositor\0"),
+                version: uint(6),
+            })
+            .await
+            .unwrap();
+
+        let mut header = [0u32; 2];
+        client
+            .read_exact(unsafe { &mut *ptr::slice_from_raw_parts_mut(header.as_mut_ptr().cast::<u8>(), 8) })
+            .unwrap();
+        let datalen = (header[1] >> 16) as usize;
+        let mut reply = vec![0u32; datalen / 4];
+        reply[..2].copy_from_slice(&header);
+        client
+            .read_exact(unsafe {
+                &mut *ptr::slice_from_raw_parts_mut(reply[2..].as_mut_ptr().cast::<u8>(), datalen - 8)
+            })
+            .unwrap();
+
+        let (hdr, wl_registry::event::global { name, interface, version }) = decode(&reply);
+        assert_eq!(
+            (hdr.object_id.id().get(), hdr.opcode),
+            (WL_REGISTRY, wl_registry::event::global::OP)
+        );
+        assert_eq!(
+            (name, interface.as_slice_without_trailing_null(), version),
+            (uint(1), &b"wl_compositor"[..], uint(6))
+        );
+
+        drop(listener);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
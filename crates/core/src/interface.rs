@@ -1,22 +1,74 @@
-use crate::{primitives::enumeration, uint};
+use crate::{
+    primitives::{self, enumeration},
+    uint,
+};
+use std::os::fd::RawFd;
 
 pub trait Interface {
     const NAME: &str;
     const VERSION: u32;
+    /// Oldest version of the interface the bindings can still work with.
+    ///
+    /// Globals advertised below this version can't be bound, see `ClientHandle::bind_checked()`.
+    const MIN_VERSION: u32 = 1;
+    /// Opcode of the destructor request, the one with `type="destructor"`, if the interface has
+    /// one.
+    ///
+    /// Used to destroy objects on drop if it takes no arguments, see `Object::auto_destroy()`.
+    const DESTRUCTOR_OP: Option<u16> = None;
+    /// Argument kinds of every request, indexed by opcode.
+    const REQUEST_SIGNATURE: &[&[ArgKind]] = &[];
+    /// Argument kinds of every event, indexed by opcode.
+    const EVENT_SIGNATURE: &[&[ArgKind]] = &[];
 
     type Error: enumeration;
 
     type Request: Opcode;
     type Event: Opcode;
+
+    /// Argument kinds of the request with opcode `op`, if there is one.
+    fn request_signature(op: u16) -> Option<&'static [ArgKind]> {
+        Self::REQUEST_SIGNATURE.get(op as usize).copied()
+    }
+
+    /// Argument kinds of the event with opcode `op`, if there is one.
+    fn event_signature(op: u16) -> Option<&'static [ArgKind]> {
+        Self::EVENT_SIGNATURE.get(op as usize).copied()
+    }
+}
+
+/// Wire type of a message argument, as listed in the `SIGNATURE` of a message module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ArgKind {
+    Int,
+    Uint,
+    Fixed,
+    String,
+    Object,
+    NewId,
+    Array,
+    Fd,
 }
 
-/// Interface for [`new_id`]/[`object`] without a specific interface set.
+/// Interface of type-erased objects, whose actual interface isn't known at compile time.
+///
+/// This is the default interface of [`new_id`]/[`object`], so `object` is the same as
+/// `object<Unknown>`, and [`object::cast()`] turns it into or back from a concrete interface.
 ///
 /// [`new_id`]: crate::primitives::new_id
 /// [`object`]: crate::primitives::object
+/// [`object::cast()`]: crate::primitives::object::cast
+pub type Unknown = ();
+
+/// Sentinel for [`Unknown`] objects.
+///
+/// The empty [`Interface::NAME`] marks the interface as unknown, so casts from or to it are
+/// always allowed and the objects are displayed as just `#id`. Messages are only known by their
+/// opcode, and the version is `0` as there is nothing to negotiate.
 impl Interface for () {
     const NAME: &str = "";
     const VERSION: u32 = 0;
+    const MIN_VERSION: u32 = 0;
 
     type Error = uint;
 
@@ -25,13 +77,42 @@ impl Interface for () {
 }
 
 pub trait Opcode: Sized {
+    /// The decoded messages, usually an enum with a variant per opcode.
+    type Decoded<'data>;
+
     fn from_u16(i: u16) -> Result<Self, u16>;
     fn to_u16(self) -> u16;
 
     fn fd_count(&self) -> usize;
+
+    /// Decode the content of the message with `opcode`.
+    ///
+    /// # Safety
+    ///
+    /// Same as for [`Value::read()`](crate::Value::read), with the message content living for
+    /// `'data`.
+    unsafe fn decode<'data>(
+        opcode: u16,
+        data: &mut *const [u8],
+        fds: &mut *const [RawFd],
+    ) -> primitives::Result<Self::Decoded<'data>>;
+
+    /// Shorten the lifetime of a decoded message, which the compiler can't do on its own for the
+    /// generic associated type.
+    fn shorten<'short, 'data: 'short>(decoded: &'short Self::Decoded<'data>) -> &'short Self::Decoded<'short>;
+
+    /// Name of the message with opcode `i`, if it is known.
+    fn name_of(i: u16) -> Option<&'static str> {
+        let _ = i;
+        None
+    }
 }
 
+/// Opcodes of objects without a known interface, whose messages can't be decoded beyond the
+/// opcode.
 impl Opcode for u16 {
+    type Decoded<'data> = u16;
+
     fn from_u16(i: u16) -> Result<Self, u16> {
         Ok(i)
     }
@@ -43,4 +124,16 @@ impl Opcode for u16 {
     fn fd_count(&self) -> usize {
         0
     }
+
+    unsafe fn decode<'data>(
+        opcode: u16,
+        _: &mut *const [u8],
+        _: &mut *const [RawFd],
+    ) -> primitives::Result<Self::Decoded<'data>> {
+        Ok(opcode)
+    }
+
+    fn shorten<'short, 'data: 'short>(decoded: &'short Self::Decoded<'data>) -> &'short Self::Decoded<'short> {
+        decoded
+    }
 }
@@ -1,9 +1,11 @@
 pub use self::{
     error::*,
-    interface::{Interface, Opcode},
+    interface::{ArgKind, Interface, Opcode, Unknown},
     message::{Message, message_header},
     primitives::Value,
-    primitives::{array, enumeration, fd, fixed, int, new_id, new_id_dyn, object, string, uint},
+    primitives::{
+        ArrayElement, array, enumeration, fd, fd_owned, fixed, int, new_id, new_id_dyn, object, string, uint,
+    },
     raw_slice::RawSliceExt,
 };
 
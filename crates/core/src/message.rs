@@ -3,7 +3,10 @@ use std::os::unix::prelude::RawFd;
 
 pub trait Message<'data>: Value<'data> {
     type Interface: Interface;
+    /// Interface version the message was introduced in, its `since` attribute.
     const VERSION: u32;
+    /// Lowest version an object has to be created with to send or receive the message.
+    const MIN_VERSION: u32 = Self::VERSION;
     const NAME: &'static str;
 
     type Opcode: Opcode;
@@ -59,6 +62,20 @@ impl message_header {
 
     pub const COMBINED_LEN: (u16, usize) = (Self::DATA_LEN, Self::CTRL_LEN);
 
+    /// Largest message, header included, whose length still fits into `datalen`.
+    pub const MAX_LEN: usize = u16::MAX as usize;
+
+    /// Most fds a single message can carry, as all of them have to be passed along with a single
+    /// `sendmsg()`.
+    pub const MAX_FDS: usize = 252;
+
+    /// Header of a message with `content_len` bytes following it, or [`None`] if the message
+    /// would exceed [`Self::MAX_LEN`].
+    pub fn with_content_len(object_id: object, opcode: u16, content_len: usize) -> Option<Self> {
+        let datalen = content_len.checked_add(Self::DATA_LEN as usize)?.try_into().ok()?;
+        Some(Self { object_id, datalen, opcode })
+    }
+
     pub fn content_len(&self) -> u16 {
         self.datalen.wrapping_sub(self.len() as u16)
     }
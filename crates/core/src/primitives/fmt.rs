@@ -1,4 +1,4 @@
-use crate::{Interface, array, fd, fixed, int, new_id, new_id_dyn, object, string, uint};
+use crate::{Interface, array, fd, fd_owned, fixed, int, new_id, new_id_dyn, object, string, uint};
 use bstr::ByteSlice;
 use std::{
     fmt::{self, Debug, Display, Formatter},
@@ -39,9 +39,16 @@ impl Display for fd {
     }
 }
 
+impl Display for fd_owned {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Display::fmt(&self.as_raw(), f)
+    }
+}
+
+/// The decimal value, not the raw 24.8 representation.
 impl Display for fixed {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        Display::fmt(&self.0, f)
+        Display::fmt(&self.to_f64(), f)
     }
 }
 
@@ -58,39 +65,29 @@ impl Display for int {
     }
 }
 
+/// `interface#id` like `WAYLAND_DEBUG` does, or just `#id` for untyped objects.
 impl<I: Interface> Display for object<I> {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "object")?;
-        if !I::NAME.is_empty() {
-            write!(f, "<{NAME}>", NAME = I::NAME)?;
-        }
-        write!(f, "({id})", id = self.id)?;
-
-        Ok(())
+        write!(f, "{NAME}#{id}", NAME = I::NAME, id = self.id)
     }
 }
 
 impl<I: Interface> object<I> {
     pub fn fmt_none(f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "object")?;
-        if !I::NAME.is_empty() {
-            write!(f, "<{NAME}>", NAME = I::NAME)?;
-        }
-        write!(f, "(Null)")?;
-
-        Ok(())
+        f.write_str("nil")
     }
 }
 
+/// `new id interface#id` like `WAYLAND_DEBUG` does, or `new id #id` for untyped objects.
 impl<I: Interface> Display for new_id<I> {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "new_id")?;
-        if !I::NAME.is_empty() {
-            write!(f, "<{NAME}>", NAME = I::NAME)?;
-        }
-        write!(f, "({id})", id = self.id)?;
+        write!(f, "new id {NAME}#{id}", NAME = I::NAME, id = self.id)
+    }
+}
 
-        Ok(())
+impl<I: Interface> new_id<I> {
+    pub fn fmt_none(f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("new id nil")
     }
 }
 
@@ -100,3 +97,30 @@ impl Display for new_id_dyn<'_> {
         write!(f, "new_id {{ name: {name}, version: {version}, id: {id} }}")
     }
 }
+
+#[test]
+fn test_display() {
+    use crate::wl_display::wl_display;
+    use std::{marker::PhantomData, num::NonZero};
+
+    assert_eq!(int(-42).to_string(), "-42");
+    assert_eq!(uint(u32::MAX).to_string(), "4294967295");
+    assert_eq!(fixed::from_f64(-1.5).to_string(), "-1.5");
+    assert_eq!(fixed::from_i32(3).to_string(), "3");
+    assert_eq!(format!("{:.3}", fixed(1)), "0.004");
+
+    let id = NonZero::new(7).unwrap();
+    assert_eq!(
+        object::<wl_display>::from_id(id).to_string(),
+        "wl_display#7"
+    );
+    assert_eq!(object::<()>::from_id(id).to_string(), "#7");
+    assert_eq!(
+        new_id::<wl_display> { id, _marker: PhantomData }.to_string(),
+        "new id wl_display#7"
+    );
+    assert_eq!(
+        new_id::<()> { id, _marker: PhantomData }.to_string(),
+        "new id #7"
+    );
+}
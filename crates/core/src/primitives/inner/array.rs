@@ -1,9 +1,10 @@
 use crate::{
     RawSliceExt,
-    primitives::{Result, Value, align},
+    primitives::{Result, Value, align, checked_align, fixed, int, uint},
     wl_display::enumeration::error,
 };
 use std::{
+    ffi::{CStr, FromBytesWithNulError},
     marker::PhantomData,
     num::NonZero,
     os::unix::prelude::RawFd,
@@ -16,7 +17,7 @@ use std::{
 #[derive(Debug)]
 pub struct array<'a> {
     /// If this is set to [`None`], this implies that the data has already been written to the
-    /// buffer, which means only the header has to be set.
+    /// buffer, which means only the header has to be set, see [`write()`].
     pub ptr: Option<NonNull<u8>>,
     /// Note that this length isn't the size of the allocation, but the size if the *data*, which
     /// means after `ptr + len` there might be `0..=3` bytes of padding.
@@ -27,6 +28,58 @@ pub struct array<'a> {
 unsafe impl<'a> Send for array<'a> {}
 unsafe impl<'a> Sync for array<'a> {}
 
+impl<'a> array<'a> {
+    pub fn as_slice(&self) -> &[u8] {
+        match self.ptr {
+            None => &[],
+            Some(ptr) => unsafe { &*slice_from_raw_parts(ptr.as_ptr(), self.len as usize) },
+        }
+    }
+
+    /// View the content as a slice of `T`, e.g. the `u32` keycodes of `wl_keyboard.enter`.
+    ///
+    /// Fails if the length isn't a multiple of the size of `T`, or the content isn't aligned for
+    /// `T`, which can't happen for arrays read from a message.
+    pub fn as_slice_of<T: ArrayElement>(&self) -> Result<&[T]> {
+        let Some(ptr) = self.ptr else { return Ok(&[]) };
+        if !(self.len as usize).is_multiple_of(size_of::<T>()) {
+            return Err(error::invalid_method.msg("array length isn't a multiple of the element size"));
+        }
+        if !ptr.cast::<T>().is_aligned() {
+            return Err(error::implementation.msg("array content isn't aligned for the element type"));
+        }
+
+        Ok(unsafe { &*slice_from_raw_parts(ptr.cast::<T>().as_ptr(), self.len as usize / size_of::<T>()) })
+    }
+}
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// Types that can be read straight out of an [`array`], see [`array::as_slice_of()`].
+///
+/// This is sealed, as any bit pattern has to be a valid value of the type, so e.g. reading `bool`s
+/// is rejected:
+///
+/// ```compile_fail
+/// # use ecs_compositor_core::array;
+/// # fn read(keys: &array<'_>) {
+/// keys.as_slice_of::<bool>();
+/// # }
+/// ```
+pub trait ArrayElement: sealed::Sealed + Copy {}
+
+macro_rules! array_element {
+    ($($typ:ty),*) => {$(
+        impl sealed::Sealed for $typ {}
+        impl ArrayElement for $typ {}
+    )*};
+}
+
+// `int`, `uint` and `fixed` are `#[repr(transparent)]` over their integer.
+array_element!(u32, i32, uint, int, fixed);
+
 impl<'data> Value<'data> for array<'data> {
     const FDS: usize = 0;
     #[inline]
@@ -75,6 +128,12 @@ impl<'a> string<'a> {
     pub fn as_utf8(&self) -> std::result::Result<&str, Utf8Error> {
         std::str::from_utf8(self.as_slice_without_trailing_null())
     }
+    /// Borrow the string as a [`CStr`], e.g. to pass it on to libc.
+    ///
+    /// Fails if the trailing null byte is missing or the string contains another one.
+    pub fn as_c_str(&self) -> std::result::Result<&CStr, FromBytesWithNulError> {
+        CStr::from_bytes_with_nul(self.as_slice())
+    }
     pub fn from_slice<'data>(slice: &'data [u8]) -> string<'data> {
         string {
             ptr: NonNull::new(slice.as_ptr().cast_mut()),
@@ -112,7 +171,8 @@ impl<'data> Value<'data> for Option<string<'data>> {
     const FDS: usize = 0;
     #[inline]
     fn len(&self) -> u32 {
-        4 + self.as_ref().map(string::len).unwrap_or(0)
+        // A null string is just the zero length header.
+        self.as_ref().map(string::len).unwrap_or(4)
     }
 
     #[inline]
@@ -148,9 +208,10 @@ pub unsafe fn read(data: &mut *const [u8]) -> Result<(NonNull<u8>, u32)> {
             len.read()
         };
 
-        let content = data
-            .split_at(align::<4>(len) as usize)
-            .ok_or_else(|| error::implementation.msg("reading buffer too short for message content"))?;
+        // Checked, as `len` comes straight from the wire and might be anything up to `u32::MAX`.
+        let content = checked_align::<4>(len)
+            .and_then(|padded_len| data.split_at(padded_len as usize))
+            .ok_or_else(|| error::invalid_method.msg("array/string length exceeds message content"))?;
 
         // Safety: `data` is guarantied by caller to point to a valid buffer.
         Ok((NonNull::new_unchecked(content as *mut u8), len))
@@ -166,6 +227,7 @@ pub unsafe fn read(data: &mut *const [u8]) -> Result<(NonNull<u8>, u32)> {
 /// If there is not enough room on the buffer, throws an error.
 /// If `ptr` is `None`, only writes the header and assumes the user has already written the actual
 /// content (including the padding bytes to the 4 byte boundary).
+/// Either way `data` is advanced past the header, content and padding.
 ///
 /// # Safety
 ///
@@ -187,10 +249,7 @@ pub unsafe fn write(data: &mut *mut [u8], ptr: Option<NonNull<u8>>, len: u32) ->
 
         let (content, padding) = {
             let mut content = data.split_at_unchecked(align::<4>(len) as usize);
-            (
-                content.split_at_unchecked(align::<4>(len) as usize),
-                content,
-            )
+            (content.split_at_unchecked(len as usize), content)
         };
         if let Some(ptr) = ptr {
             content
@@ -203,3 +262,203 @@ pub unsafe fn write(data: &mut *mut [u8], ptr: Option<NonNull<u8>>, len: u32) ->
         Ok(())
     }
 }
+
+/// Write `str` as [`string`], appending the null terminator `str` lacks.
+///
+/// Writes the length (including the null terminator), the content, the null terminator and the
+/// zeroed padding to the 4 byte boundary. Fails without writing anything if `data` is too short.
+///
+/// # Safety
+///
+/// - `data` has to point to a valid buffer **and** has to be aligned to a 4 byte boundary.
+///   See [`crate::primitives::align()`] as an helper.
+#[inline]
+pub unsafe fn write_wayland_string(data: &mut *mut [u8], str: &[u8]) -> Result<()> {
+    unsafe {
+        let len = str.len() as u32 + 1;
+        if data.len() < 4 + align::<4>(len) as usize {
+            return Err(error::implementation.msg("not enough buffer provided"));
+        }
+
+        let len_hdr = data.split_at_unchecked(4).cast::<u32>();
+        debug_assert!(len_hdr.is_aligned());
+        len_hdr.write(len);
+
+        // Zero everything after the content, which covers both the null terminator and the
+        // padding.
+        let content = data.split_at_unchecked(align::<4>(len) as usize);
+        content.start().write_bytes(0, content.len());
+        content.start().copy_from_nonoverlapping(str.as_ptr(), str.len());
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_read_length_overflow() {
+    use std::ptr;
+
+    unsafe fn read_words(words: &[u32]) -> Result<(NonNull<u8>, u32)> {
+        let mut data = ptr::slice_from_raw_parts(words.as_ptr().cast::<u8>(), size_of_val(words));
+        let res = unsafe { read(&mut data) };
+        if res.is_err() {
+            assert_eq!(
+                data.len(),
+                size_of_val(words),
+                "failed read has to restore `data`"
+            );
+        }
+        res
+    }
+
+    let hello = u32::from_ne_bytes(*b"hell");
+    let o = u32::from_ne_bytes(*b"o\0\0\0");
+    unsafe {
+        // Sanity check, `"hello\0"` padded to 8 bytes.
+        let (_, len) = read_words(&[6, hello, o]).ok().unwrap();
+        assert_eq!(len, 6);
+
+        // Truncated buffer
+        let err = read_words(&[6, hello]).err().unwrap();
+        assert!(matches!(err.err, error::invalid_method));
+        let err = read_words(&[12, hello, o]).err().unwrap();
+        assert!(matches!(err.err, error::invalid_method));
+
+        // Absurd length prefixes, which would overflow when aligned
+        for len in [u32::MAX, u32::MAX - 2, u32::MAX - 3] {
+            let err = read_words(&[len, hello, o]).err().unwrap();
+            assert!(matches!(err.err, error::invalid_method));
+        }
+
+        // Same for strings
+        let words = [u32::MAX, hello];
+        let mut data = ptr::slice_from_raw_parts(words.as_ptr().cast::<u8>(), 8);
+        assert!(string::read(&mut data, &mut ptr::slice_from_raw_parts(ptr::null(), 0)).is_err());
+    }
+}
+
+#[test]
+fn test_array_as_slice_of() {
+    use std::ptr;
+
+    // `wl_keyboard.enter` keys, followed by the next argument.
+    let words = [12u32, 30, 48, 46, 7];
+    let mut data = ptr::slice_from_raw_parts(words.as_ptr().cast::<u8>(), size_of_val(&words));
+    let keys = unsafe { array::read(&mut data, &mut ptr::slice_from_raw_parts(ptr::null(), 0)) }
+        .ok()
+        .unwrap();
+    assert_eq!(data.len(), 4);
+
+    assert_eq!(keys.as_slice_of::<u32>().ok().unwrap(), [30, 48, 46]);
+    assert_eq!(keys.as_slice_of::<i32>().ok().unwrap(), [30, 48, 46]);
+    assert_eq!(
+        keys.as_slice_of::<uint>().ok().unwrap(),
+        [uint(30), uint(48), uint(46)]
+    );
+    assert_eq!(
+        keys.as_slice_of::<int>().ok().unwrap(),
+        [int(30), int(48), int(46)]
+    );
+    assert_eq!(
+        keys.as_slice_of::<fixed>().ok().unwrap(),
+        [fixed(30), fixed(48), fixed(46)]
+    );
+    assert_eq!(keys.as_slice().len(), 12);
+
+    let odd = array { len: 6, ..keys };
+    assert!(matches!(
+        odd.as_slice_of::<u32>().err().unwrap().err,
+        error::invalid_method
+    ));
+
+    let empty = array { ptr: None, len: 0, _marker: PhantomData };
+    assert_eq!(empty.as_slice_of::<u32>().ok().unwrap(), []);
+}
+
+#[test]
+fn test_write_wayland_string() {
+    use std::ptr;
+
+    fn write_words(str: &[u8]) -> Vec<u8> {
+        let mut words = [u32::MAX; 4];
+        let mut data = ptr::slice_from_raw_parts_mut(words.as_mut_ptr().cast::<u8>(), size_of_val(&words));
+        unsafe { write_wayland_string(&mut data, str) }.ok().unwrap();
+
+        let written = size_of_val(&words) - data.len();
+        words.iter().flat_map(|word| word.to_ne_bytes()).take(written).collect()
+    }
+
+    let len = |len: u32| len.to_ne_bytes();
+    assert_eq!(write_words(b""), [&len(1)[..], b"\0\0\0\0"].concat());
+    assert_eq!(write_words(b"abc"), [&len(4)[..], b"abc\0"].concat());
+    assert_eq!(
+        write_words(b"abcd"),
+        [&len(5)[..], b"abcd\0\0\0\0"].concat()
+    );
+
+    // Doesn't touch the buffer when the string doesn't fit.
+    let mut word = u32::MAX;
+    let mut data = ptr::slice_from_raw_parts_mut((&raw mut word).cast::<u8>(), 4);
+    assert!(unsafe { write_wayland_string(&mut data, b"") }.is_err());
+    assert_eq!((data.len(), word), (4, u32::MAX));
+}
+
+#[test]
+fn test_string_as_c_str() {
+    assert_eq!(
+        string::from_slice(b"wl_seat\0").as_c_str().ok(),
+        Some(c"wl_seat")
+    );
+    assert_eq!(string::from_slice(b"\0").as_c_str().ok(), Some(c""));
+    assert!(matches!(
+        string::from_slice(b"wl_seat").as_c_str(),
+        Err(FromBytesWithNulError::NotNulTerminated)
+    ));
+    assert!(matches!(
+        string::from_slice(b"wl\0seat\0").as_c_str(),
+        Err(FromBytesWithNulError::InteriorNul { position: 2 })
+    ));
+}
+
+#[test]
+fn test_write_prewritten() {
+    use std::ptr;
+
+    /// Write `value` into a buffer holding `prefill` and return the written bytes.
+    fn write_words<'a>(value: &impl Value<'a>, prefill: &[u8]) -> Vec<u8> {
+        let mut words = [u32::MAX; 4];
+        let dst = words.as_mut_ptr().cast::<u8>();
+        unsafe { dst.copy_from_nonoverlapping(prefill.as_ptr(), prefill.len()) };
+        let mut data = ptr::slice_from_raw_parts_mut(words.as_mut_ptr().cast::<u8>(), size_of_val(&words));
+        let mut fds = ptr::slice_from_raw_parts_mut(ptr::null_mut(), 0);
+        unsafe { value.write(&mut data, &mut fds) }.ok().unwrap();
+
+        let written = size_of_val(&words) - data.len();
+        assert_eq!(written, value.len() as usize);
+        words.iter().flat_map(|word| word.to_ne_bytes()).take(written).collect()
+    }
+
+    // Content and padding are already in place behind a stale length.
+    let prefill = |content: &[u8]| [&u32::MAX.to_ne_bytes()[..], content].concat();
+
+    let content = b"hello\0\0\0";
+    let normal = array { ptr: NonNull::new(content.as_ptr().cast_mut()), len: 5, _marker: PhantomData };
+    let prewritten = array { ptr: None, len: 5, _marker: PhantomData };
+    let bytes = write_words(&normal, &[]);
+    assert_eq!(bytes, [&5u32.to_ne_bytes()[..], content].concat());
+    assert_eq!(write_words(&prewritten, &prefill(content)), bytes);
+
+    let normal = string::from_slice(b"hello\0");
+    let prewritten = string { ptr: None, len: normal.len, _marker: PhantomData };
+    let bytes = write_words(&normal, &[]);
+    assert_eq!(bytes, [&6u32.to_ne_bytes()[..], b"hello\0\0\0"].concat());
+    assert_eq!(write_words(&prewritten, &prefill(b"hello\0\0\0")), bytes);
+    assert_eq!(
+        write_words(&Some(prewritten), &prefill(b"hello\0\0\0")),
+        bytes
+    );
+
+    // An empty array doesn't advance over any content.
+    let empty = array { ptr: None, len: 0, _marker: PhantomData };
+    assert_eq!(write_words(&empty, &prefill(&[])), 0u32.to_ne_bytes());
+}
@@ -3,12 +3,70 @@ use crate::{
     primitives::{Result, Value},
     wl_display::enumeration::error,
 };
-use std::os::fd::RawFd;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
 
 /// The file descriptor is not stored in the message buffer, but in the ancillary data of the UNIX
 /// domain socket message (msg_control).
+///
+/// Doesn't track ownership, see [`fd_owned`] for a variant that does.
+/// When receiving, the fd is taken out of the fds buffer by overwriting it with `-1` like
+/// [`fd_owned`] does, so the buffer has to be writable and each received fd can only be read once.
+/// Whoever read it owns it and has to close it, e.g. by turning it into an [`fd_owned`] with
+/// [`fd::into_owned()`].
 pub struct fd(pub RawFd);
 
+impl fd {
+    /// Take ownership of the fd, e.g. one that was just received.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`OwnedFd::from_raw_fd()`], so the fd has to be open and not owned by anything else.
+    pub unsafe fn into_owned(self) -> fd_owned {
+        fd_owned(unsafe { OwnedFd::from_raw_fd(self.0) })
+    }
+}
+
+/// [`fd`] that owns the file descriptor and closes it on drop.
+///
+/// When sending, only the raw fd is written to the fds buffer, so whoever sends the buffer has
+/// to duplicate it if the message outlives the value.
+/// When receiving, the fd is taken out of the fds buffer by overwriting it with `-1`, so the
+/// buffer has to be writable and each received fd can only be taken once.
+pub struct fd_owned(pub OwnedFd);
+
+impl fd_owned {
+    /// Borrow as a raw [`fd`], to be used in messages, which don't take ownership of their fds.
+    pub fn as_raw(&self) -> fd {
+        fd(self.0.as_raw_fd())
+    }
+}
+
+impl Value<'_> for fd_owned {
+    const FDS: usize = 1;
+    fn len(&self) -> u32 {
+        0
+    }
+
+    unsafe fn read(_: &mut *const [u8], fds: &mut *const [RawFd]) -> Result<Self> {
+        unsafe {
+            let raw = fds
+                .split_at(1)
+                .ok_or(error::implementation.msg("not enough fds in read buffer"))?
+                .cast::<RawFd>()
+                .cast_mut()
+                .replace(-1);
+            if raw < 0 {
+                return Err(error::implementation.msg("fd was already taken"));
+            }
+            Ok(fd_owned(OwnedFd::from_raw_fd(raw)))
+        }
+    }
+
+    unsafe fn write(&self, data: &mut *mut [u8], fds: &mut *mut [RawFd]) -> Result<()> {
+        unsafe { self.as_raw().write(data, fds) }
+    }
+}
+
 impl Value<'_> for fd {
     const FDS: usize = 1;
     fn len(&self) -> u32 {
@@ -17,11 +75,16 @@ impl Value<'_> for fd {
 
     unsafe fn read(_: &mut *const [u8], fds: &mut *const [RawFd]) -> Result<Self> {
         unsafe {
-            Ok(fd(fds
+            let raw = fds
                 .split_at(1)
                 .ok_or(error::implementation.msg("not enough fds in read buffer"))?
                 .cast::<RawFd>()
-                .read()))
+                .cast_mut()
+                .replace(-1);
+            if raw < 0 {
+                return Err(error::implementation.msg("fd was already taken"));
+            }
+            Ok(fd(raw))
         }
     }
 
@@ -36,3 +99,49 @@ impl Value<'_> for fd {
         Ok(())
     }
 }
+
+#[test]
+fn test_fd_owned_closes_received() {
+    use std::{
+        io::{ErrorKind, Write},
+        os::fd::IntoRawFd,
+    };
+
+    let (reader, mut writer) = std::io::pipe().unwrap();
+    let mut fds = [OwnedFd::from(reader).into_raw_fd()];
+
+    let received = unsafe {
+        let (mut data, mut fds): (*const [u8], *const [RawFd]) = (&[], &raw mut fds);
+        let received = fd_owned::read(&mut data, &mut fds).ok().unwrap();
+        assert_eq!(fds.len(), 0);
+        received
+    };
+    // Taking the fd a second time has to fail instead of closing it twice.
+    assert_eq!(fds, [-1]);
+    unsafe {
+        let (mut data, mut fds): (*const [u8], *const [RawFd]) = (&[], &raw mut fds);
+        assert!(fd_owned::read(&mut data, &mut fds).is_err());
+    }
+    writer.write_all(b"open").unwrap();
+
+    drop(received);
+    assert_eq!(
+        writer.write(b"closed").unwrap_err().kind(),
+        ErrorKind::BrokenPipe
+    );
+}
+
+#[test]
+fn test_fd_takes_received() {
+    let mut fds = [7];
+    unsafe {
+        let (mut data, mut fds): (*const [u8], *const [RawFd]) = (&[], &raw mut fds);
+        assert_eq!(fd::read(&mut data, &mut fds).ok().unwrap().0, 7);
+    }
+    // Whoever read it owns it now, so nothing else may close it.
+    assert_eq!(fds, [-1]);
+    unsafe {
+        let (mut data, mut fds): (*const [u8], *const [RawFd]) = (&[], &raw mut fds);
+        assert!(fd::read(&mut data, &mut fds).is_err());
+    }
+}
@@ -11,8 +11,11 @@ use std::os::unix::prelude::RawFd;
 /// of integer precision and 8 bits of decimal precision. Consider [`fixed`]
 /// as an opaque struct with methods that facilitate conversion to and from
 /// [`f64`] and [`i32`] types.
+///
+/// Comparing two [`fixed`] compares their fixed-point values.
 #[allow(non_camel_case_types)]
-#[derive(Debug, Clone, Copy)]
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct fixed(pub i32);
 
 impl fixed {
@@ -22,9 +25,10 @@ impl fixed {
         f64::from(self.0) / 256.0
     }
 
+    /// Rounds to the nearest representable value, saturating at the bounds of [`fixed`].
     #[inline]
     pub fn from_f64(d: f64) -> Self {
-        fixed(d as i32)
+        fixed((d * 256.0).round() as i32)
     }
 
     #[inline]
@@ -65,3 +69,44 @@ impl Value<'_> for fixed {
         Ok(())
     }
 }
+
+#[test]
+fn test_fixed_ord() {
+    assert!(fixed::from_i32(-1) < fixed(1));
+    assert!(fixed(1) < fixed::from_i32(1));
+    assert_eq!(fixed::from_i32(2).max(fixed(300)), fixed::from_i32(2));
+}
+
+#[test]
+fn test_fixed_round_trip() {
+    use std::ptr;
+
+    let cases =
+        [(-1.5, 0xFFFF_FE80u32), (-0.00390625, 0xFFFF_FFFF), (-256.0, 0xFFFF_0000), (255.99609375, 0x0000_FFFF)];
+    for (value, wire) in cases {
+        let val = fixed::from_f64(value);
+        assert_eq!(val.0 as u32, wire, "{value}");
+
+        let mut word = 0u32;
+        let mut data = ptr::slice_from_raw_parts_mut((&raw mut word).cast::<u8>(), 4);
+        unsafe {
+            val.write(
+                &mut data,
+                &mut ptr::slice_from_raw_parts_mut(ptr::null_mut(), 0),
+            )
+        }
+        .ok()
+        .unwrap();
+        assert_eq!(word, wire);
+
+        let mut data = ptr::slice_from_raw_parts((&raw const word).cast::<u8>(), 4);
+        let read = unsafe { fixed::read(&mut data, &mut ptr::slice_from_raw_parts(ptr::null(), 0)) }
+            .ok()
+            .unwrap();
+        assert_eq!(read, val);
+        assert_eq!(read.to_f64(), value);
+    }
+
+    assert_eq!(fixed::from_f64(-1.5).to_i32(), -1);
+    assert_eq!(fixed::from_i32(-256).to_f64(), -256.0);
+}
@@ -6,13 +6,39 @@ use crate::{
 use std::os::unix::prelude::RawFd;
 
 /// The value is the 32-bit value of the signed int.
-#[derive(Debug, Clone, Copy)]
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct int(pub i32);
 
 /// The value is the 32-bit value of the unsigned int.
-#[derive(Debug, Clone, Copy)]
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct uint(pub u32);
 
+impl From<i32> for int {
+    fn from(value: i32) -> Self {
+        Self(value)
+    }
+}
+
+impl From<int> for i32 {
+    fn from(value: int) -> Self {
+        value.0
+    }
+}
+
+impl From<u32> for uint {
+    fn from(value: u32) -> Self {
+        Self(value)
+    }
+}
+
+impl From<uint> for u32 {
+    fn from(value: uint) -> Self {
+        value.0
+    }
+}
+
 impl<'data> Value<'data> for int {
     const FDS: usize = 0;
     fn len(&self) -> u32 {
@@ -68,3 +94,19 @@ impl<'data> Value<'data> for uint {
         Ok(())
     }
 }
+
+#[test]
+fn test_uint_map_key() {
+    use std::collections::{BTreeMap, HashSet};
+
+    let map = BTreeMap::from([(uint(3), "c"), (uint(1), "a"), (uint::from(2), "b")]);
+    assert_eq!(map.get(&uint(2)), Some(&"b"));
+    assert_eq!(
+        map.keys().copied().map(u32::from).collect::<Vec<_>>(),
+        [1, 2, 3]
+    );
+
+    let set = HashSet::from([int(-1), int::from(1), int(-1)]);
+    assert_eq!(set.len(), 2);
+    assert!(int(-1) < int(1));
+}
@@ -1,6 +1,6 @@
 use crate::{
     Interface, RawSliceExt,
-    primitives::{Result, Value},
+    primitives::{Result, Value, align, write_wayland_string},
     string, uint,
     wl_display::{self, enumeration::error},
 };
@@ -36,6 +36,17 @@ impl<I: Interface> object<I> {
         object { id, _marker: PhantomData }
     }
 
+    /// Like [`Self::cast()`], but asserts in debug builds that the interface stays the same.
+    ///
+    /// Casting from or to the type-erased `object<()>` is always allowed, as the interface is
+    /// unknown on one side. Connections that know which interface every id was created with can
+    /// check those casts as well, like `ecs_compositor_tokio`'s `Connection::cast_to()`.
+    #[track_caller]
+    pub fn cast_to<J: Interface>(self) -> object<J> {
+        debug_assert_cast::<I, J>(self.id);
+        self.cast()
+    }
+
     pub fn id(&self) -> NonZero<u32> {
         self.id
     }
@@ -115,6 +126,14 @@ impl<I: Interface> new_id<I> {
         new_id { id, _marker: PhantomData }
     }
 
+    /// Like [`Self::cast()`], but asserts in debug builds that the interface stays the same, see
+    /// [`object::cast_to()`].
+    #[track_caller]
+    pub fn cast_to<J: Interface>(&self) -> new_id<J> {
+        debug_assert_cast::<I, J>(self.id);
+        self.cast()
+    }
+
     pub fn id(&self) -> NonZero<u32> {
         self.id
     }
@@ -126,6 +145,46 @@ impl<I: Interface> new_id<I> {
     pub fn err(self, err: I::Error, msg: &'static str) -> wl_display::event::error<I> {
         wl_display::event::error::new(self.to_object(), err, msg)
     }
+
+    /// Length of the id when sent as a [`new_id_dyn`], which means preceded by the interface name
+    /// and version.
+    pub fn dyn_len(&self) -> u32 {
+        4 + align::<4>(I::NAME.len() as u32 + 1) // Interface::NAME
+        + 4 // Interface::VERSION
+        + 4 // self.id
+    }
+
+    /// Write the id as a [`new_id_dyn`] with [`Interface::NAME`] and [`Interface::VERSION`] of `I`.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Value::write()`].
+    pub unsafe fn write_dyn(&self, data: &mut *mut [u8], fds: &mut *mut [RawFd]) -> Result<()> {
+        unsafe {
+            if data.len() < self.dyn_len() as usize {
+                return Err(error::implementation.msg("not enough write buffer space"));
+            }
+
+            write_wayland_string(data, I::NAME.as_bytes())?;
+
+            uint(I::VERSION).write(data, fds)?;
+            self.write(data, fds)
+        }
+    }
+
+    /// Read a [`new_id_dyn`], failing if the interface name doesn't match `I`.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Value::read()`].
+    pub unsafe fn read_dyn(data: &mut *const [u8], fds: &mut *const [RawFd]) -> Result<Self> {
+        let new_id_dyn { name, version: _, id } = unsafe { new_id_dyn::read(data, fds)? };
+        if name.as_slice_without_trailing_null() != I::NAME.as_bytes() {
+            return Err(error::invalid_method.msg("new_id has unexpected interface"));
+        }
+
+        Ok(id.cast())
+    }
 }
 
 impl<I: Interface> Value<'_> for new_id<I> {
@@ -150,12 +209,43 @@ impl<I: Interface> Value<'_> for new_id<I> {
     }
 }
 
+impl<I: Interface> Value<'_> for Option<new_id<I>> {
+    const FDS: usize = 0;
+    fn len(&self) -> u32 {
+        4
+    }
+
+    unsafe fn read(data: &mut *const [u8], _: &mut *const [RawFd]) -> Result<Self> {
+        match unsafe { read_id(data)? } {
+            None => Ok(None),
+            Some(id) => Ok(Some(new_id { id, _marker: PhantomData })),
+        }
+    }
+
+    unsafe fn write<'a>(&self, data: &mut *mut [u8], _: &mut *mut [RawFd]) -> Result<()> {
+        unsafe {
+            write_id(
+                data,
+                self.as_ref().map(|new_id| new_id.id.get()).unwrap_or(0),
+            )?;
+        }
+        Ok(())
+    }
+}
+
 pub struct new_id_dyn<'data> {
     pub name: string<'data>,
     pub version: uint,
     pub id: new_id,
 }
 
+impl<'data> new_id_dyn<'data> {
+    /// Interface name the id is created with, without the null terminator.
+    pub fn interface_name(&self) -> &[u8] {
+        self.name.as_slice_without_trailing_null()
+    }
+}
+
 impl<'data> Value<'data> for new_id_dyn<'data> {
     const FDS: usize = 0;
     fn len(&self) -> u32 {
@@ -182,6 +272,16 @@ impl<'data> Value<'data> for new_id_dyn<'data> {
     }
 }
 
+#[track_caller]
+fn debug_assert_cast<From: Interface, To: Interface>(id: NonZero<u32>) {
+    debug_assert!(
+        From::NAME.is_empty() || To::NAME.is_empty() || From::NAME == To::NAME,
+        "cast of object {id} from `{from}` to `{to}`",
+        from = From::NAME,
+        to = To::NAME,
+    );
+}
+
 unsafe fn read_id(data: &mut *const [u8]) -> Result<Option<NonZero<u32>>> {
     let u32 = unsafe {
         data.split_at(4)
@@ -202,3 +302,96 @@ unsafe fn write_id(data: &mut *mut [u8], id: u32) -> Result<()> {
     }
     Ok(())
 }
+
+#[test]
+fn test_option_id_round_trip() {
+    use std::ptr;
+
+    unsafe fn round_trip<'a, T: Value<'a>>(value: T) -> (u32, T) {
+        let mut word = 0u32;
+        unsafe {
+            let mut data = ptr::slice_from_raw_parts_mut((&raw mut word).cast::<u8>(), 4);
+            value
+                .write(
+                    &mut data,
+                    &mut ptr::slice_from_raw_parts_mut(ptr::null_mut(), 0),
+                )
+                .ok()
+                .unwrap();
+            assert_eq!(data.len(), 0);
+
+            let mut data = ptr::slice_from_raw_parts((&raw const word).cast::<u8>(), 4);
+            let read = T::read(&mut data, &mut ptr::slice_from_raw_parts(ptr::null(), 0)).ok().unwrap();
+            (word, read)
+        }
+    }
+
+    let id = NonZero::new(7).unwrap();
+    unsafe {
+        assert_eq!(round_trip(None::<object>), (0, None));
+        assert_eq!(
+            round_trip(Some(object::<()>::from_id(id))),
+            (7, Some(object::from_id(id)))
+        );
+
+        assert_eq!(round_trip(None::<new_id>), (0, None));
+        assert_eq!(
+            round_trip(Some(new_id::<()> { id, _marker: PhantomData })),
+            (7, Some(new_id { id, _marker: PhantomData }))
+        );
+    }
+}
+
+#[cfg(test)]
+enum test_surface {}
+
+#[cfg(test)]
+impl Interface for test_surface {
+    const NAME: &str = "test_surface";
+    const VERSION: u32 = 1;
+
+    type Error = uint;
+
+    type Request = u16;
+    type Event = u16;
+}
+
+#[test]
+fn test_cast_to() {
+    let surface = object::<test_surface>::from_id(NonZero::new(3).unwrap());
+    let erased = surface.cast_to::<()>();
+    assert_eq!(erased.cast_to::<test_surface>().id, surface.id);
+    assert_eq!(
+        surface.to_new_id().cast_to::<()>().cast_to::<test_surface>().id,
+        surface.id
+    );
+}
+
+#[cfg(debug_assertions)]
+#[test]
+#[should_panic = "cast of object 3 from `test_surface` to `wl_display`"]
+fn test_cast_to_mismatch() {
+    let surface = object::<test_surface>::from_id(NonZero::new(3).unwrap());
+    let _ = surface.cast_to::<wl_display::wl_display>();
+}
+
+#[test]
+fn test_unknown() {
+    use crate::Unknown;
+
+    fn signature<I: Interface>(op: u16) -> Option<&'static [crate::ArgKind]> {
+        I::request_signature(op)
+    }
+
+    let unknown = object::<Unknown>::from_id(NonZero::new(3).unwrap());
+    assert_eq!(signature::<Unknown>(0), None);
+    assert_eq!(unknown.to_string(), "#3");
+
+    let surface = unknown.cast_to::<test_surface>();
+    assert_eq!(surface.to_string(), "test_surface#3");
+    assert_eq!(surface.cast_to::<Unknown>(), unknown);
+    assert_eq!(
+        surface.to_new_id().cast_to::<Unknown>().to_object(),
+        unknown
+    );
+}
@@ -16,9 +16,9 @@ mod inner {
 }
 
 pub use self::inner::{
-    array::{array, string},
+    array::{ArrayElement, array, string, write_wayland_string},
     enumeration::enumeration,
-    fd::fd,
+    fd::{fd, fd_owned},
     fixed::fixed,
     int::{int, uint},
     object::{new_id, new_id_dyn, object},
@@ -79,6 +79,115 @@ impl From<Error> for crate::wl_display::event::error {
     }
 }
 
+/// Round `len` up to the next multiple of `ALIGN`, which has to be a power of two.
+///
+/// Used for the padding of [`string`]/[`array`] content, which always ends on a 4 byte boundary.
+/// Saturates to [`u32::MAX`] if the padded length doesn't fit, so length checks against a buffer
+/// still fail instead of passing with a wrapped around length.
+/// See [`checked_align()`] for telling that case apart.
 pub const fn align<const ALIGN: u32>(len: u32) -> u32 {
-    (len + ALIGN - 1) & !(ALIGN - 1)
+    match checked_align::<ALIGN>(len) {
+        Some(padded) => padded,
+        None => u32::MAX,
+    }
+}
+
+/// Like [`align()`], but returning [`None`] if the padded length doesn't fit in a [`u32`].
+pub const fn checked_align<const ALIGN: u32>(len: u32) -> Option<u32> {
+    const { assert!(ALIGN.is_power_of_two()) };
+    match len.checked_add(ALIGN - 1) {
+        Some(len) => Some(len & !(ALIGN - 1)),
+        None => None,
+    }
+}
+
+/// [`Value`] for tuples, which are read/written element by element in declaration order.
+macro_rules! impl_value_for_tuple {
+    ($($T:ident),+) => {
+        #[allow(non_snake_case)]
+        impl<'data, $($T: Value<'data>),+> Value<'data> for ($($T,)+) {
+            const FDS: usize = 0 $(+ $T::FDS)+;
+            fn len(&self) -> u32 {
+                let ($($T,)+) = self;
+                0 $(+ $T.len())+
+            }
+
+            unsafe fn read(data: &mut *const [u8], fds: &mut *const [RawFd]) -> Result<Self> {
+                let (old_data, old_fds) = (*data, *fds);
+                (|| unsafe { Ok(($($T::read(data, fds)?,)+)) })().inspect_err(|_| {
+                    *data = old_data;
+                    *fds = old_fds;
+                })
+            }
+
+            unsafe fn write(&self, data: &mut *mut [u8], fds: &mut *mut [RawFd]) -> Result<()> {
+                let ($($T,)+) = self;
+                unsafe { $($T.write(data, fds)?;)+ }
+                Ok(())
+            }
+        }
+    };
+}
+
+impl_value_for_tuple!(A);
+impl_value_for_tuple!(A, B);
+impl_value_for_tuple!(A, B, C);
+impl_value_for_tuple!(A, B, C, D);
+impl_value_for_tuple!(A, B, C, D, E);
+impl_value_for_tuple!(A, B, C, D, E, F);
+
+#[test]
+fn test_align() {
+    assert_eq!(align::<4>(0), 0);
+    assert_eq!(align::<4>(1), 4);
+    assert_eq!(align::<4>(4), 4);
+    assert_eq!(align::<4>(5), 8);
+    assert_eq!(align::<4>(u32::MAX - 3), u32::MAX - 3);
+    assert_eq!(align::<4>(u32::MAX - 2), u32::MAX);
+
+    assert_eq!(checked_align::<4>(u32::MAX - 3), Some(u32::MAX - 3));
+    assert_eq!(checked_align::<4>(u32::MAX - 2), None);
+    assert_eq!(checked_align::<1>(u32::MAX), Some(u32::MAX));
+}
+
+#[test]
+fn test_tuple_round_trip() {
+    use std::{num::NonZero, ptr};
+
+    let value = (
+        uint(42),
+        object::<()>::from_id(NonZero::new(7).unwrap()),
+        string::from_slice(b"tuple\0"),
+    );
+    assert_eq!(value.len(), 4 + 4 + 4 + 8);
+    assert_eq!(<(uint, object, string)>::FDS, 0);
+    assert_eq!(<(fd, uint, fd)>::FDS, 2);
+
+    let mut buf = [0u32; 5];
+    unsafe {
+        let mut data = ptr::slice_from_raw_parts_mut(buf.as_mut_ptr().cast::<u8>(), 20);
+        value
+            .write(
+                &mut data,
+                &mut ptr::slice_from_raw_parts_mut(ptr::null_mut(), 0),
+            )
+            .ok()
+            .unwrap();
+        assert!(data.is_empty());
+
+        let mut data = ptr::slice_from_raw_parts(buf.as_ptr().cast::<u8>(), 20);
+        let (a, b, c) = <(uint, object, string)>::read(&mut data, &mut ptr::slice_from_raw_parts(ptr::null(), 0))
+            .ok()
+            .unwrap();
+        assert!(data.is_empty());
+        assert_eq!(
+            (a.0, b.id.get(), c.as_slice()),
+            (42, 7, b"tuple\0".as_slice())
+        );
+
+        // A failing element rolls the whole tuple back.
+        let mut data = ptr::slice_from_raw_parts(buf.as_ptr().cast::<u8>(), 16);
+        assert!(<(uint, object, string)>::read(&mut data, &mut ptr::slice_from_raw_parts(ptr::null(), 0)).is_err());
+        assert_eq!(data.len(), 16);
+    }
 }
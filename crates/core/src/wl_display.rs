@@ -1,7 +1,7 @@
 //! Stripped down impl of [`wl_display`] for error reporting
 
-use crate::{Interface, interface::Opcode, object};
-use std::num::NonZero;
+use crate::{ArgKind, Interface, Value, interface::Opcode, object, primitives, string, uint};
+use std::{num::NonZero, os::fd::RawFd};
 
 #[allow(non_camel_case_types)]
 pub enum wl_display {}
@@ -12,6 +12,7 @@ pub const OBJECT: object = object::from_id(NonZero::new(1).unwrap());
 impl Interface for wl_display {
     const NAME: &str = "wl_display";
     const VERSION: u32 = 1;
+    const EVENT_SIGNATURE: &[&[ArgKind]] = &[&[ArgKind::Object, ArgKind::Uint, ArgKind::String]];
 
     type Error = self::enumeration::error;
 
@@ -21,6 +22,8 @@ impl Interface for wl_display {
 
 pub enum Request {}
 impl Opcode for Request {
+    type Decoded<'data> = Request;
+
     fn from_u16(i: u16) -> Result<Self, u16> {
         Err(i)
     }
@@ -32,6 +35,18 @@ impl Opcode for Request {
     fn fd_count(&self) -> usize {
         unreachable!()
     }
+
+    unsafe fn decode<'data>(
+        _: u16,
+        _: &mut *const [u8],
+        _: &mut *const [RawFd],
+    ) -> primitives::Result<Self::Decoded<'data>> {
+        Err(enumeration::error::invalid_method.msg("`wl_display` has no requests here"))
+    }
+
+    fn shorten<'short, 'data: 'short>(decoded: &'short Self::Decoded<'data>) -> &'short Self::Decoded<'short> {
+        decoded
+    }
 }
 
 #[repr(u16)]
@@ -41,6 +56,9 @@ pub enum Event {
 }
 
 impl Opcode for Event {
+    /// The `object_id`, `code` and `message` of `wl_display.error`.
+    type Decoded<'data> = (object, uint, string<'data>);
+
     fn from_u16(i: u16) -> Result<Self, u16> {
         match i {
             0 => Ok(Self::error),
@@ -57,6 +75,25 @@ impl Opcode for Event {
             Event::error => 0,
         }
     }
+
+    fn name_of(i: u16) -> Option<&'static str> {
+        Self::from_u16(i).ok().map(|Event::error| "error")
+    }
+
+    unsafe fn decode<'data>(
+        opcode: u16,
+        data: &mut *const [u8],
+        fds: &mut *const [RawFd],
+    ) -> primitives::Result<Self::Decoded<'data>> {
+        match Self::from_u16(opcode) {
+            Ok(Event::error) => unsafe { Value::read(data, fds) },
+            Err(_) => Err(enumeration::error::invalid_method.msg("invalid `wl_display` event opcode")),
+        }
+    }
+
+    fn shorten<'short, 'data: 'short>(decoded: &'short Self::Decoded<'data>) -> &'short Self::Decoded<'short> {
+        decoded
+    }
 }
 
 pub mod enumeration {
@@ -68,7 +105,7 @@ pub mod enumeration {
     ///
     /// These errors are global and can be emitted in response to any
     /// server request.
-    #[derive(Debug, Clone, Copy)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     #[repr(u32)]
     #[allow(non_camel_case_types)]
     pub enum error {
@@ -83,6 +120,11 @@ pub mod enumeration {
     }
 
     impl error {
+        /// Every error code defined by the protocol.
+        pub const ALL: [error; 4] =
+            [error::invalid_object, error::invalid_method, error::no_memory, error::implementation];
+
+        /// Build a [`primitives::Error`] with this code, for example `error::no_memory.msg("...")`.
         pub fn msg(self, msg: &'static str) -> primitives::Error {
             primitives::Error { err: self, msg }
         }
@@ -135,11 +177,11 @@ pub mod enumeration {
                 (error::invalid_method, false) => "invalid_method",
                 (error::invalid_method, true) => "method doesn't exist on the specified interface or malformed request",
 
-                (error::no_memory, true) => "no_memory",
-                (error::no_memory, false) => "server is out of memory",
+                (error::no_memory, false) => "no_memory",
+                (error::no_memory, true) => "server is out of memory",
 
-                (error::implementation, true) => "implementation",
-                (error::implementation, false) => "implementation error in compositor",
+                (error::implementation, false) => "implementation",
+                (error::implementation, true) => "implementation error in compositor",
             })
         }
     }
@@ -212,3 +254,22 @@ pub mod event {
 }
 
 pub mod request {}
+
+#[test]
+fn test_error_codes() {
+    use self::enumeration::error;
+    use crate::{enumeration as _, primitives};
+
+    for (code, err) in error::ALL.into_iter().enumerate() {
+        assert_eq!(error::from_u32(code as u32), Some(err));
+
+        let primitives::Error { err: built, msg } = err.msg("test message");
+        assert_eq!((built, msg), (err, "test message"));
+
+        let event: event::error = err.msg("test message").into();
+        assert_eq!(event.object.id, OBJECT.id);
+        assert_eq!((event.err.0, event.msg), (code as u32, "test message"));
+        assert_eq!(format!("{err}"), format!("{err:?}"));
+    }
+    assert_eq!(error::from_u32(error::ALL.len() as u32), None);
+}
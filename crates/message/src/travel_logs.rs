@@ -1,6 +1,6 @@
 use std::{
     alloc::Layout,
-    fmt::Debug,
+    fmt::{self, Debug, Display, Formatter},
     ops::Bound,
     sync::atomic::{AtomicUsize, Ordering},
 };
@@ -27,6 +27,7 @@ impl<T: Metadata + Debug> Debug for Buffer<T> {
         f.debug_struct("Buffer")
             .field("buf", &self.buf)
             .field("metadata", &self.metadata)
+            .field("allocated", &self.allocated_range().display(self.metadata.capacity()))
             .field("slot_next", &self.slot_next)
             .field("slot_free", &self.slot_free)
             .field("data_next", &self.data_next)
@@ -73,13 +74,25 @@ pub unsafe trait Metadata {
     unsafe fn dealloc(&self, free: PointRange) -> Point;
 }
 
-#[derive(Debug)]
 pub struct Handle<'a, T: Metadata> {
     buf: &'a Buffer<T>,
     range: PointRange,
     handle: T::Handle,
 }
 
+/// Shows the range with the capacity of the buffer instead of the whole buffer.
+impl<T: Metadata> Debug for Handle<'_, T>
+where
+    T::Handle: Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Handle")
+            .field("range", &self.range.display(self.buf.metadata.capacity()))
+            .field("handle", &self.handle)
+            .finish()
+    }
+}
+
 impl<'a, T: Metadata> Handle<'a, T> {
     pub fn into_raw(self) -> PointRange {
         let range = self.range;
@@ -111,6 +124,27 @@ impl PointRange {
 
         Point { slot: *slot, data: *data }
     }
+
+    /// Render the ranges for the slot and data `capacity`, see [`Range::display()`].
+    pub fn display(self, capacity: Point) -> PointRangeDisplay {
+        PointRangeDisplay { range: self, capacity }
+    }
+}
+
+/// [`Debug`] of a [`PointRange`] that knows the capacity of the buffer, see
+/// [`PointRange::display()`].
+pub struct PointRangeDisplay {
+    range: PointRange,
+    capacity: Point,
+}
+
+impl Debug for PointRangeDisplay {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PointRange")
+            .field("slot", &self.range.slot.display(self.capacity.slot))
+            .field("data", &self.range.data.display(self.capacity.data))
+            .finish()
+    }
 }
 
 /// Range of values. Might wrap.
@@ -131,6 +165,14 @@ impl Range {
         Self { from: self.upto, upto: self.from.checked_sub(1).unwrap_or(capacity - 1) }
     }
 
+    /// Render the range for a ring of `capacity`, like `[3..10]`, or `[14..2 (wrapped)]` if it
+    /// wraps around the end.
+    ///
+    /// Ranges reaching beyond `capacity` are marked as well, as they are a bug in the ring math.
+    pub fn display(self, capacity: usize) -> RangeDisplay {
+        RangeDisplay { range: self, capacity }
+    }
+
     pub const fn into_ring_bounds(
         self,
         capacity: usize,
@@ -143,6 +185,31 @@ impl Range {
     }
 }
 
+/// [`Range`] rendered for a ring of known capacity, see [`Range::display()`].
+pub struct RangeDisplay {
+    range: Range,
+    capacity: usize,
+}
+
+impl Display for RangeDisplay {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let Range { from, upto } = self.range;
+        write!(f, "[{from}..{upto}")?;
+        if self.capacity < from || self.capacity < upto {
+            write!(f, " (exceeds {})", self.capacity)?;
+        } else if upto < from {
+            f.write_str(" (wrapped)")?;
+        }
+        f.write_str("]")
+    }
+}
+
+impl Debug for RangeDisplay {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
 impl<T: Metadata> Buffer<T> {
     pub fn new(message: T) -> Self {
         let Point { data: len, .. } = message.capacity();
@@ -165,7 +232,6 @@ impl<T: Metadata> Buffer<T> {
         Handle { buf: self, range, handle }
     }
 
-    #[allow(dead_code)]
     fn allocated_range(&self) -> PointRange {
         PointRange {
             slot: Range {
@@ -224,3 +224,26 @@ fn out_of_order() {
     b.join().unwrap();
     c.join().unwrap();
 }
+
+#[test]
+fn range_display() {
+    assert_eq!(Range { from: 3, upto: 10 }.display(16).to_string(), "[3..10]");
+    assert_eq!(Range { from: 14, upto: 2 }.display(16).to_string(), "[14..2 (wrapped)]");
+    assert_eq!(Range { from: 14, upto: 20 }.display(16).to_string(), "[14..20 (exceeds 16)]");
+
+    let buf = Buffer::new(Bytes::new(16));
+    let handle = buf.alloc_n(3).unwrap();
+    assert_eq!(
+        format!("{handle:?}"),
+        "Handle { range: PointRange { slot: [0..1], data: [0..3] }, handle: () }"
+    );
+    assert_eq!(
+        format!(
+            "{:?}",
+            PointRange { slot: Range { from: 7, upto: 1 }, data: Range { from: 14, upto: 2 } }
+                .display(buf.metadata.capacity())
+        ),
+        "PointRange { slot: [7..1 (wrapped)], data: [14..2 (wrapped)] }"
+    );
+    handle.dealloc();
+}
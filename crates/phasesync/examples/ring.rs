@@ -0,0 +1,57 @@
+//! Multiple producers fill the slots of a ring and hand them back in any order, while the ring
+//! only gets reclaimed in order.
+//!
+//! The slot that is responsible for the phase learns through `commit` up to where the ring can be
+//! reclaimed, whenever it gets freed and hands the responsibility on to the next active slot.
+
+use phasesync::{FreeReturn, Phasesync, Pos};
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering::*},
+    },
+    thread,
+};
+
+type Ring = Phasesync<1, 2>;
+
+const PRODUCERS: usize = 4;
+const SLOTS: usize = 100;
+
+fn main() {
+    let ring = Arc::new(Ring::new());
+    // slot 0 starts out responsible for the phase
+    ring.chunks[0].fetch_and(!1, Relaxed);
+    let last = Pos::from_flat(SLOTS - 1).unwrap();
+
+    // every slot before this one can be reused
+    let reclaimed = Arc::new(AtomicUsize::new(0));
+    let phase_ends = Arc::new(AtomicUsize::new(0));
+
+    let producers: Vec<_> = (0..PRODUCERS)
+        .map(|producer| {
+            let (ring, reclaimed, phase_ends) =
+                (ring.clone(), reclaimed.clone(), phase_ends.clone());
+            thread::spawn(move || {
+                // each producer got handed every `PRODUCERS`th slot
+                for slot in (producer..SLOTS).step_by(PRODUCERS) {
+                    let slot = Pos::from_flat(slot).unwrap();
+                    let ret = ring.free_slots(slot..=slot, last, |next| {
+                        reclaimed.fetch_max(next.to_flat(), Release);
+                    });
+                    if let FreeReturn::AllSlotsDead = ret {
+                        reclaimed.store(SLOTS, Release);
+                        phase_ends.fetch_add(1, Relaxed);
+                    }
+                }
+            })
+        })
+        .collect();
+    for producer in producers {
+        producer.join().unwrap();
+    }
+
+    assert_eq!(phase_ends.load(Relaxed), 1);
+    assert_eq!(reclaimed.load(Acquire), SLOTS);
+    println!("reclaimed all {SLOTS} slots");
+}
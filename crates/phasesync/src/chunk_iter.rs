@@ -2,95 +2,130 @@ use crate::{
     helpers::bitmask_range,
     position::{CarryingAdd, Pos, WrappingU6, WrappingUsize},
 };
-use std::ops::RangeInclusive;
+use std::{iter::FusedIterator, ops::RangeInclusive};
 
+/// Iterator over the chunks touched by a [`RangeInclusive<Pos>`], yielding the range of bits
+/// covered in each of them.
+///
+/// Wraps around after the chunk [`MAX`](WrappingUsize), so for [`Phasesync<MAX, LEN>`] where
+/// `MAX == LEN - 1` a range with `end.chunk < start.chunk` continues at the first chunk.
+///
+/// [`Phasesync<MAX, LEN>`]: crate::Phasesync
+#[derive(Debug, Clone)]
 pub struct ChunkIter<const MAX: usize> {
-    state: State,
     start: Pos<MAX>,
     end: Pos<MAX>,
+    /// Offset of `end.chunk` relative to `start.chunk`.
+    last: usize,
+    /// Offset of the next chunk yielded from the front, relative to `start.chunk`.
+    front: usize,
+    /// Offset one past the next chunk yielded from the back, relative to `start.chunk`.
+    back: usize,
 }
 
 impl<const MAX: usize> ChunkIter<MAX> {
     pub fn new(range: RangeInclusive<Pos<MAX>>) -> Self {
         let (start, end) = range.into_inner();
-        Self { state: State::Start, start, end }
+        let (last, _) = end.chunk.borrowing_sub(start.chunk, false);
+        Self { start, end, last: *last, front: 0, back: *last + 1 }
     }
 
-    fn wrapping_add(&self, lhs: usize, rhs: usize) -> usize {
-        *(WrappingUsize::<MAX>::new(lhs) + WrappingUsize::<MAX>::new(rhs))
+    fn info(&self, offset: usize) -> ChunkInfo<MAX> {
+        let step = WrappingUsize::new(offset);
+        let (chunk, _) = self.start.chunk.carrying_add(step, false);
+        let lower = match offset == 0 {
+            true => self.start.index,
+            false => WrappingU6::ZERO,
+        };
+        let upper = match offset == self.last {
+            true => self.end.index,
+            false => WrappingU6::MAX,
+        };
+        ChunkInfo { chunk, lower, upper }
     }
 }
 
-enum State {
-    Start,
-    Middle { next_chunk: usize },
-    End,
-    None,
-}
-
-// TODO: reimplement this based on the new [`Pos::carrying_add()`] impl
 impl<const MAX: usize> Iterator for ChunkIter<MAX> {
     type Item = ChunkInfo<MAX>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.state {
-            State::Start => {
-                let Pos { chunk, index: lower } = self.start;
-                match chunk == self.end.chunk {
-                    true => {
-                        self.state = State::None;
-                        Some(ChunkInfo { chunk, lower, upper: self.end.index })
-                    }
-                    false => {
-                        let next_chunk = self.wrapping_add(*chunk, 1);
-                        self.state = match next_chunk == *self.end.chunk {
-                            true => State::Middle { next_chunk },
-                            false => State::End,
-                        };
-
-                        Some(ChunkInfo { chunk, lower, upper: WrappingU6::MAX })
-                    }
-                }
-            }
-            State::Middle { next_chunk } => {
-                let chunk = next_chunk;
-
-                let next_chunk = self.wrapping_add(chunk, 1);
-                self.state = match next_chunk == *self.end.chunk {
-                    true => State::Middle { next_chunk },
-                    false => State::End,
-                };
-
-                Some(ChunkInfo {
-                    chunk: WrappingUsize::new(chunk),
-                    lower: WrappingU6::ZERO,
-                    upper: WrappingU6::MAX,
-                })
-            }
-            State::End => {
-                let Pos { chunk, index: upper } = self.end;
-                self.state = State::None;
-
-                Some(ChunkInfo { chunk, lower: WrappingU6::ZERO, upper })
-            }
-            State::None => None,
+        if self.front == self.back {
+            return None;
+        }
+
+        let info = self.info(self.front);
+        self.front += 1;
+        Some(info)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.back - self.front;
+        (len, Some(len))
+    }
+}
+
+impl<const MAX: usize> DoubleEndedIterator for ChunkIter<MAX> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.front == self.back {
+            return None;
         }
+
+        self.back -= 1;
+        Some(self.info(self.back))
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+impl<const MAX: usize> ExactSizeIterator for ChunkIter<MAX> {}
+
+impl<const MAX: usize> FusedIterator for ChunkIter<MAX> {}
+
+/// Bits `lower..=upper` of the chunk at index `chunk`, as yielded by [`ChunkIter`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct ChunkInfo<const MAX: usize> {
+    /// Index into [`Phasesync::chunks`](crate::Phasesync::chunks).
     pub chunk: WrappingUsize<MAX>,
+    /// First bit of the chunk that is part of the range.
     pub lower: WrappingU6,
+    /// Last bit of the chunk that is part of the range, inclusive.
     pub upper: WrappingU6,
 }
 
 impl<const MAX: usize> ChunkInfo<MAX> {
+    /// Bitmask selecting the bits `lower..=upper`.
     pub fn mask(&self) -> u64 {
         bitmask_range(self.lower.inner(), self.upper.inner())
     }
 
+    /// Indices of the bits `lower..=upper`.
     pub fn range(&self) -> RangeInclusive<u8> {
         *self.lower..=*self.upper
     }
 }
+
+#[test]
+fn test_chunk_iter_wrapping() {
+    fn pos(chunk: usize, index: u8) -> Pos<3> {
+        Pos { chunk: WrappingUsize::new(chunk), index: WrappingU6::new(index) }
+    }
+    fn tuples(iter: impl Iterator<Item = ChunkInfo<3>>) -> Vec<(usize, u8, u8)> {
+        let tuple = |info: ChunkInfo<3>| (*info.chunk, *info.lower, *info.upper);
+        iter.map(tuple).collect()
+    }
+
+    let expected = [(2, 10, 63), (3, 0, 63), (0, 0, 63), (1, 0, 5)];
+    let iter = ChunkIter::new(pos(2, 10)..=pos(1, 5));
+    assert_eq!(iter.len(), 4);
+    assert_eq!(tuples(iter.clone()), expected);
+    assert_eq!(tuples(iter.clone().rev()), expected.into_iter().rev().collect::<Vec<_>>());
+
+    let mut iter = iter;
+    assert_eq!(tuples((&mut iter).take(1)), [(2, 10, 63)]);
+    assert_eq!(tuples(iter.next_back().into_iter()), [(1, 0, 5)]);
+    assert_eq!(iter.len(), 2);
+    assert_eq!(tuples(iter.by_ref()), [(3, 0, 63), (0, 0, 63)]);
+    assert_eq!(iter.next(), None);
+    assert_eq!(iter.next_back(), None);
+
+    assert_eq!(tuples(ChunkIter::new(pos(1, 3)..=pos(1, 7))), [(1, 3, 7)]);
+    assert_eq!(tuples(ChunkIter::new(pos(3, 60)..=pos(0, 1))), [(3, 60, 63), (0, 0, 1)]);
+}
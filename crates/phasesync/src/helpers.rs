@@ -1,9 +1,7 @@
+use crate::sync::AtomicU64;
 use std::{
     num::NonZero,
-    sync::atomic::{
-        AtomicU64,
-        Ordering::{Acquire, Release},
-    },
+    sync::atomic::Ordering::{Acquire, Release},
 };
 
 /// Create a bitmask that selects the `lower..=upper` bits of an [`u64`].
@@ -39,7 +37,7 @@ pub const fn lowest_one(val: u64) -> Option<u8> {
         return None;
     };
 
-    Some((u64::BITS - 1 - val.leading_zeros()) as u8)
+    Some(val.trailing_zeros() as u8)
 }
 
 /// Loop until `cond(val)` is false, or `val` is successfully updated to `f(val)`.
@@ -82,3 +80,10 @@ pub struct WrapArgs<Lhs, Rhs, Lower, Upper, Diff> {
 
     pub diff: Diff,
 }
+
+#[test]
+fn test_lowest_one() {
+    assert_eq!(lowest_one(0), None);
+    assert_eq!(lowest_one(0b1010_0000), Some(5));
+    assert_eq!(lowest_one(1 << 63 | 1 << 2), Some(2));
+}
@@ -1,30 +1,47 @@
 use crate::{
     chunk_iter::{ChunkInfo, ChunkIter},
     helpers::{bitmask_range, lowest_one, try_while, try_while_mut},
+    sync::AtomicU64,
 };
-use std::{
-    ops::RangeInclusive,
-    sync::atomic::{AtomicU64, Ordering::*},
-};
+use std::{ops::RangeInclusive, sync::atomic::Ordering::*};
 
 pub use crate::position::{CarryingAdd, Pos, WrappingU6, WrappingUsize};
 
 pub mod chunk_iter;
 pub mod helpers;
 mod position;
+pub mod sync;
+
+#[cfg(all(test, loom))]
+mod loom_tests;
 
 /// Synchronization primitive to allow the coordination of ring-buffer shaped resources between
 /// multiple threads.
 ///
 /// See [`examples/`](./examples/) for examples on how to use this.
-#[repr(transparent)]
 pub struct Phasesync<const MAX: usize, const LEN: usize> {
     pub chunks: [AtomicU64; LEN],
+    /// Flat position of the slot that revived the current phase, or [`DEAD`] once it ended with
+    /// [`FreeReturn::AllSlotsDead`], see [`Phasesync::reinit_phase()`].
+    revived: AtomicU64,
 }
 
+/// Marker of [`Phasesync::revived`] for a phase nobody is responsible for.
+const DEAD: u64 = u64::MAX;
+
 impl<const MAX: usize, const LEN: usize> Phasesync<MAX, LEN> {
+    #[cfg(not(loom))]
     pub fn new() -> Self {
-        Self { chunks: [const { AtomicU64::new(u64::MAX) }; _] }
+        Self { chunks: [const { AtomicU64::new(u64::MAX) }; _], revived: AtomicU64::new(DEAD) }
+    }
+
+    /// `loom`s atomics can't be created in a const context.
+    #[cfg(loom)]
+    pub fn new() -> Self {
+        Self {
+            chunks: std::array::from_fn(|_| AtomicU64::new(u64::MAX)),
+            revived: AtomicU64::new(DEAD),
+        }
     }
 }
 
@@ -40,6 +57,18 @@ impl<const MAX: usize, const LEN: usize> Phasesync<MAX, LEN> {
     /// set by `until`.
     ///
     /// See [`FreeReturn`] and more importantly [`FreeReturn::AllSlotsDead`] for details.
+    ///
+    /// # `commit`
+    ///
+    /// `commit(slot)` tells the caller that the resources of every slot before `slot` can be
+    /// freed. It only gets called if `slots` were responsible for the phase, and always before
+    /// `slot` is handed that responsibility, so the owner of `slot` can't free it any earlier.
+    ///
+    /// If `slot` got freed concurrently before the handoff went through, `commit` gets called
+    /// again with the next active slot. So the slots passed to `commit` are strictly ascending,
+    /// with the last one being the slot returned in [`FreeReturn::Selected`].
+    /// It is never called for [`FreeReturn::Successful`], and for [`FreeReturn::AllSlotsDead`]
+    /// only with slots that died before they could take over.
     pub fn free_slots(
         &self,
         slots: RangeInclusive<Pos<MAX>>,
@@ -61,7 +90,11 @@ impl<const MAX: usize, const LEN: usize> Phasesync<MAX, LEN> {
         )
     }
 
-    // TODO: actually document the steps involved
+    /// `slots` were responsible for the phase, so hand the responsibility over to the next
+    /// active slot in `slots.end + 1..=until`.
+    ///
+    /// The bits of `slots` are set again first, resetting them for when the ring wraps around to
+    /// them.
     fn slow_path(
         &self,
         slots: RangeInclusive<Pos<MAX>>,
@@ -83,6 +116,12 @@ impl<const MAX: usize, const LEN: usize> Phasesync<MAX, LEN> {
         self.set_in_search_range(search_range, commit)
     }
 
+    /// Hand the responsibility for the phase over to the first active slot in `search_range`.
+    ///
+    /// The bits of the freed slots in front of the candidate are reset as well, then `commit` is
+    /// called with the candidate and only afterwards its bit is cleared to make it responsible.
+    /// If the candidate got freed in between, the search continues behind it, see
+    /// [`Self::free_slots()`] for the guarantees this gives `commit`.
     pub fn set_in_search_range(
         &self,
         search_range: RangeInclusive<Pos<MAX>>,
@@ -97,15 +136,15 @@ impl<const MAX: usize, const LEN: usize> Phasesync<MAX, LEN> {
 
                     if let Some(prev_index) = (*index)
                         .checked_sub(1)
-                        .filter(|&prev_index| *lower < prev_index)
+                        .filter(|&prev_index| *lower <= prev_index)
                     {
                         match try_while_mut(
                             chunk,
                             &mut val,
-                            |val| val & (1 << *index) == 1,
+                            |val| (val >> *index) & 1 == 1,
                             |val| val | bitmask_range(*lower, prev_index),
                         ) {
-                            true => mask = bitmask_range(prev_index, *info.upper),
+                            true => mask = bitmask_range(*index, *info.upper),
                             false => continue,
                         }
                     }
@@ -115,7 +154,7 @@ impl<const MAX: usize, const LEN: usize> Phasesync<MAX, LEN> {
                     match try_while_mut(
                         chunk,
                         &mut val,
-                        |val| val & (1 << *index) >> *index == 1,
+                        |val| (val >> *index) & 1 == 1,
                         |val| val & !(1 << *index),
                     ) {
                         true => return Some(FreeReturn::Selected { slot }),
@@ -124,12 +163,39 @@ impl<const MAX: usize, const LEN: usize> Phasesync<MAX, LEN> {
                 }
                 None
             })
-            .unwrap_or(FreeReturn::AllSlotsDead)
+            .unwrap_or_else(|| {
+                self.revived.store(DEAD, Release);
+                FreeReturn::AllSlotsDead
+            })
+    }
+
+    /// Make `first` responsible for freeing a phase that ended with [`FreeReturn::AllSlotsDead`].
+    ///
+    /// The phase is marked as revived by `first` in a single atomic operation, so if several
+    /// threads race to revive it, even with different slots, exactly one of them gets
+    /// [`ReinitResult::Revived`] and every other one [`ReinitResult::AlreadyRevived`]. Only then
+    /// the bit of `first` gets cleared.
+    /// The marker is reset by [`Self::free_slots()`] before it returns
+    /// [`FreeReturn::AllSlotsDead`], so the revival has to be started after learning about it.
+    ///
+    /// `first` has to be active, as a freed slot looks just like a responsible one.
+    pub fn reinit_phase(&self, first: Pos<MAX>) -> ReinitResult {
+        let slot = first.to_flat() as u64;
+        if self
+            .revived
+            .compare_exchange(DEAD, slot, AcqRel, Acquire)
+            .is_err()
+        {
+            return ReinitResult::AlreadyRevived;
+        }
+
+        self.chunks[*first.chunk].fetch_and(!(1 << *first.index), AcqRel);
+        ReinitResult::Revived
     }
 
     /// Iterator over the range of bits of each chunk described by `slots`.
-    /// Note if `end.chunk < start.chunk`, this *will* correctly wrap around `const LEN`
-    fn chunk_iter(slots: RangeInclusive<Pos<MAX>>) -> ChunkIter<MAX> {
+    /// Note if `end.chunk < start.chunk`, this *will* correctly wrap around after the chunk `MAX`.
+    pub fn chunk_iter(slots: RangeInclusive<Pos<MAX>>) -> ChunkIter<MAX> {
         ChunkIter::new(slots)
     }
 
@@ -151,6 +217,23 @@ impl<const MAX: usize, const LEN: usize> Phasesync<MAX, LEN> {
     ) -> impl FnMut(ChunkInfo<MAX>) -> LoadedChunk<'chunk, MAX> {
         move |info| self.load_chunk(info)
     }
+
+    /// Positions of all slots whose bit is set, in ascending order, e.g. to dump the state when
+    /// debugging leaked slots.
+    ///
+    /// This is only a best-effort snapshot, each chunk is loaded on its own when the iterator gets
+    /// to it, so it isn't atomic across chunks.
+    pub fn active_slots(&self) -> impl Iterator<Item = Pos<MAX>> + '_ {
+        self.chunks.iter().enumerate().flat_map(|(chunk, val)| {
+            let mut val = val.load(Acquire);
+            std::iter::from_fn(move || {
+                let index = lowest_one(val)?;
+                val &= val - 1;
+                Some(index)
+            })
+            .map_while(move |index| Pos::from_flat((chunk << 6) | index as usize))
+        })
+    }
 }
 
 pub struct LoadedChunk<'chunk, const MAX: usize> {
@@ -178,6 +261,151 @@ pub enum FreeReturn<const MAX: usize> {
     ///
     /// That also means the caller of [`Phasesync::free_slots()`] is now responsible to in some way make
     /// sure the next time a slot is created in this phase, it knows, it is responsible for doing
-    /// the resource freeing when it is destroyed again.
+    /// the resource freeing when it is destroyed again, see [`Phasesync::reinit_phase()`].
     AllSlotsDead,
 }
+
+/// Outcome of [`Phasesync::reinit_phase()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[must_use = "Only the caller getting [`Self::Revived`] is responsible for the phase"]
+pub enum ReinitResult {
+    /// The slot is now responsible for the phase.
+    Revived,
+    /// Another thread revived the phase first, possibly with a different slot.
+    AlreadyRevived,
+}
+
+#[cfg(not(loom))]
+#[test]
+fn test_free_slots_commit() {
+    let pos = |index| Pos::<1>::from_flat(index).unwrap();
+
+    let sync = Phasesync::<1, 2>::new();
+    // slot 0 is responsible for the phase
+    sync.chunks[0].fetch_and(!1, Relaxed);
+
+    // free `slots` and return the slots passed to `commit`
+    let free = |slots: RangeInclusive<usize>, until| {
+        let mut commits = Vec::new();
+        let ret = sync.free_slots(pos(*slots.start())..=pos(*slots.end()), pos(until), |slot| {
+            commits.push(slot.to_flat())
+        });
+        (ret, commits)
+    };
+
+    let (ret, commits) = free(1..=2, 7);
+    assert!(matches!(ret, FreeReturn::Successful));
+    assert_eq!(commits, []);
+
+    // 1 and 2 are already freed, so 3 takes over
+    let (ret, commits) = free(0..=0, 7);
+    assert!(matches!(ret, FreeReturn::Selected { slot } if slot == pos(3)));
+    assert_eq!(commits, [3]);
+
+    assert!(matches!(free(4..=5, 7).0, FreeReturn::Successful));
+    let (ret, commits) = free(3..=3, 7);
+    assert!(matches!(ret, FreeReturn::Selected { slot } if slot == pos(6)));
+    assert_eq!(commits, [6]);
+
+    assert!(matches!(free(7..=7, 7).0, FreeReturn::Successful));
+    let (ret, commits) = free(6..=6, 7);
+    assert!(matches!(ret, FreeReturn::AllSlotsDead));
+    assert_eq!(commits, []);
+}
+
+#[cfg(not(loom))]
+#[test]
+fn test_reinit_phase_race() {
+    use std::{sync::Barrier, thread};
+
+    let pos = |index| Pos::<1>::from_flat(index).unwrap();
+    // Both threads revive with the same slot, or each with its own one.
+    for slots in [[3, 3], [3, 5], [5, 3]] {
+        for _ in 0..100 {
+            let sync = Phasesync::<1, 2>::new();
+            let barrier = Barrier::new(2);
+
+            let results: Vec<_> = thread::scope(|scope| {
+                let threads: Vec<_> = slots
+                    .map(|slot| {
+                        let (sync, barrier) = (&sync, &barrier);
+                        scope.spawn(move || {
+                            barrier.wait();
+                            (slot, sync.reinit_phase(pos(slot)))
+                        })
+                    })
+                    .into_iter()
+                    .collect();
+                threads
+                    .into_iter()
+                    .map(|thread| thread.join().unwrap())
+                    .collect()
+            });
+
+            let revived: Vec<_> = results
+                .iter()
+                .filter(|&&(_, ret)| ret == ReinitResult::Revived)
+                .map(|&(slot, _)| slot)
+                .collect();
+            let [first] = revived[..] else { panic!("{slots:?}: {results:?}") };
+            assert_eq!(sync.chunks[0].load(Relaxed), !(1 << first));
+
+            // The revived slot takes over the phase like any other responsible slot.
+            let ret = sync.free_slots(pos(first)..=pos(first), pos(6), |_| {});
+            assert!(matches!(ret, FreeReturn::Selected { slot } if slot == pos(first + 1)));
+        }
+    }
+}
+
+#[cfg(not(loom))]
+#[test]
+fn test_reinit_after_phase_end() {
+    let pos = |index| Pos::<1>::from_flat(index).unwrap();
+    let sync = Phasesync::<1, 2>::new();
+    assert_eq!(sync.reinit_phase(pos(3)), ReinitResult::Revived);
+    assert_eq!(sync.reinit_phase(pos(4)), ReinitResult::AlreadyRevived);
+
+    assert!(matches!(
+        sync.free_slots(pos(4)..=pos(4), pos(4), |_| {}),
+        FreeReturn::Successful
+    ));
+    let ret = sync.free_slots(pos(3)..=pos(3), pos(4), |_| {});
+    assert!(matches!(ret, FreeReturn::AllSlotsDead), "{ret:?}");
+
+    // The phase ended, so the next slot created in it revives it again.
+    assert_eq!(sync.reinit_phase(pos(5)), ReinitResult::Revived);
+    assert_eq!(sync.chunks[0].load(Relaxed) & (1 << 5), 0);
+}
+
+#[cfg(not(loom))]
+#[test]
+fn test_active_slots() {
+    let sync = Phasesync::<1, 2>::new();
+    assert_eq!(sync.active_slots().count(), 128);
+
+    sync.chunks[0].store(0b1010_0001, Relaxed);
+    sync.chunks[1].store(1 << 63 | 1 << 2, Relaxed);
+    let active: Vec<_> = sync.active_slots().map(|slot| slot.to_flat()).collect();
+    assert_eq!(active, [0, 5, 7, 66, 127]);
+
+    sync.chunks[0].store(0, Relaxed);
+    sync.chunks[1].store(0, Relaxed);
+    assert_eq!(sync.active_slots().next(), None);
+}
+
+#[cfg(not(loom))]
+#[test]
+fn test_free_slots_hands_over_to_oldest() {
+    let pos = |index| Pos::<1> { chunk: WrappingUsize::new(0), index: WrappingU6::new(index) };
+    let sync = Phasesync::<1, 2>::new();
+    sync.chunks[0].fetch_and(!1, Relaxed);
+
+    assert!(matches!(
+        sync.free_slots(pos(1)..=pos(2), pos(7), |_| {}),
+        FreeReturn::Successful
+    ));
+    // The slots freed in the meantime are skipped, and the oldest active slot takes over.
+    let ret = sync.free_slots(pos(0)..=pos(0), pos(7), |_| {});
+    assert!(matches!(ret, FreeReturn::Selected { slot } if slot == pos(3)), "{ret:?}");
+    assert_eq!(sync.chunks[0].load(Relaxed), !(1 << 3));
+}
@@ -0,0 +1,127 @@
+//! Model checks of the phase handoff, run with
+//! `RUSTFLAGS="--cfg loom" cargo test -p phasesync --release`.
+
+use crate::{FreeReturn, Phasesync, Pos, ReinitResult, WrappingU6, WrappingUsize};
+use loom::{
+    sync::{Arc, atomic::AtomicU8},
+    thread,
+};
+use std::sync::atomic::Ordering::*;
+
+type Phase = Phasesync<{ usize::MAX }, 1>;
+
+fn pos(index: u8) -> Pos<{ usize::MAX }> {
+    Pos { chunk: WrappingUsize::new(0), index: WrappingU6::new(index) }
+}
+
+/// Fresh phase with slot `0` being responsible for freeing it.
+fn phase() -> Arc<Phase> {
+    let sync = Phase::new();
+    sync.chunks[0].fetch_and(!1, Relaxed);
+    Arc::new(sync)
+}
+
+/// Free every slot of a `len` long phase on its own thread.
+///
+/// Whoever gets [`FreeReturn::Selected`] frees the selected slot, so exactly one thread has to
+/// end up with [`FreeReturn::AllSlotsDead`], and only once every slot started being freed.
+fn free_concurrently(len: u8) {
+    let mut model = loom::model::Builder::new();
+    // Unbounded the `compare_exchange` loops make the three thread case intractable.
+    model.preemption_bound = Some(2);
+    model.check(move || {
+        let sync = phase();
+        let until = pos(len - 1);
+        let started = Arc::new(AtomicU8::new(0));
+
+        let threads: Vec<_> = (0..len)
+            .map(|index| {
+                let (sync, started) = (sync.clone(), started.clone());
+                thread::spawn(move || {
+                    started.fetch_add(1, SeqCst);
+                    let mut commits = Vec::new();
+                    let ret =
+                        sync.free_slots(pos(index)..=pos(index), until, |slot| commits.push(slot));
+                    assert!(commits.is_sorted_by(|a, b| a < b), "unordered commits {commits:?}");
+                    if let FreeReturn::Selected { slot } = ret {
+                        assert_eq!(commits.last(), Some(&slot), "selected slot wasn't committed");
+                    }
+                    if let FreeReturn::AllSlotsDead = ret {
+                        assert_eq!(
+                            started.load(SeqCst),
+                            len,
+                            "phase ended while slots were active"
+                        );
+                    }
+                    ret
+                })
+            })
+            .collect();
+        let results: Vec<_> = threads
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .collect();
+
+        let dead = results
+            .iter()
+            .filter(|ret| matches!(ret, FreeReturn::AllSlotsDead))
+            .count();
+        assert_eq!(dead, 1, "expected exactly one handoff to end the phase: {results:?}");
+
+        for ret in &results {
+            if let FreeReturn::Selected { slot } = ret {
+                let selected = &results[*slot.index as usize];
+                assert!(
+                    !matches!(selected, FreeReturn::Successful),
+                    "{slot:?} was selected, but didn't take over the phase: {results:?}"
+                );
+            }
+        }
+    });
+}
+
+#[test]
+fn two_threads() {
+    free_concurrently(2);
+}
+
+#[test]
+fn three_threads() {
+    free_concurrently(3);
+}
+
+/// Two threads saw the phase end and race to revive it with `slots`, which only one of them may
+/// win.
+fn reinit_race(slots: [u8; 2]) {
+    loom::model(move || {
+        let sync = Arc::new(Phase::new());
+
+        let threads: Vec<_> = slots
+            .map(|slot| {
+                let sync = sync.clone();
+                thread::spawn(move || sync.reinit_phase(pos(slot)))
+            })
+            .into_iter()
+            .collect();
+        let results: Vec<_> = threads
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .collect();
+
+        let revived = results
+            .iter()
+            .filter(|&&ret| ret == ReinitResult::Revived)
+            .count();
+        assert_eq!(revived, 1, "{results:?}");
+    });
+}
+
+#[test]
+fn reinit_race_same_slot() {
+    reinit_race([0, 0]);
+}
+
+#[test]
+fn reinit_race_different_slots() {
+    reinit_race([0, 1]);
+}
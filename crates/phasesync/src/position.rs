@@ -31,7 +31,20 @@ impl<const MAX: usize> Pos<MAX> {
     }
 }
 
-impl<const MAX: usize> Pos<MAX> {}
+impl<const MAX: usize> Pos<MAX> {
+    /// Address the slot `index` of a [`Phasesync<MAX, LEN>`](crate::Phasesync) by a single
+    /// integer, counting from bit 0 of chunk 0.
+    ///
+    /// Returns [`None`] if `index` is out of range, so not below `LEN * 64` with `LEN = MAX + 1`.
+    pub fn from_flat(index: usize) -> Option<Self> {
+        (index >> 6 <= MAX).then(|| Self::from_flat_index(index))
+    }
+
+    /// Inverse of [`Self::from_flat()`].
+    pub fn to_flat(&self) -> usize {
+        (*self.chunk << 6) | *self.index as usize
+    }
+}
 
 impl<const MAX: usize> CarryingAdd for Pos<MAX> {
     const ZERO: Self = Self { chunk: WrappingUsize::<MAX>::ZERO, index: WrappingU6::ZERO };
@@ -150,3 +163,16 @@ impl<const MAX: usize> Sub<Pos<MAX>> for WrappingUsize<MAX> {
         Pos::from_flat_index(*self).borrowing_sub(rhs, false).0
     }
 }
+
+#[test]
+fn test_pos_flat() {
+    type Pos = self::Pos<3>;
+
+    for (flat, chunk, index) in [(0, 0, 0), (63, 0, 63), (64, 1, 0), (4 * 64 - 1, 3, 63)] {
+        let pos = Pos::from_flat(flat).unwrap();
+        assert_eq!((*pos.chunk, *pos.index), (chunk, index));
+        assert_eq!(pos.to_flat(), flat);
+    }
+    assert_eq!(Pos::from_flat(4 * 64), None);
+    assert_eq!(Pos::from_flat(usize::MAX), None);
+}
@@ -0,0 +1,9 @@
+//! Atomics used by [`Phasesync`](crate::Phasesync).
+//!
+//! Swapped for the [`loom`](https://docs.rs/loom) versions when building with `--cfg loom`, so the
+//! handoff logic can be model checked.
+
+#[cfg(loom)]
+pub use loom::sync::atomic::AtomicU64;
+#[cfg(not(loom))]
+pub use std::sync::atomic::AtomicU64;
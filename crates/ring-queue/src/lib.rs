@@ -3,8 +3,13 @@ mod helpers;
 pub mod reader;
 mod sync_point;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-struct WaylandPos {
+use std::cmp::Ordering;
+
+/// Position in the ring buffer, packed into a single [`u64`] to be updated atomically.
+///
+/// Positions are ordered by their packed value, so by `data`, then `ctrl`, then `slot`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WaylandPos {
     // 18 bits
     // maximum wayland length frame length is `1 << 16`
     // we fit at most `1 << 16 * 4` in data -> `16 + 2` bits
@@ -18,19 +23,82 @@ struct WaylandPos {
     slot: u16,
 }
 
-#[allow(dead_code)]
 impl WaylandPos {
-    const fn from_u64(val: u64) -> Self {
+    pub const DATA_CAP: u32 = 1 << 18;
+    pub const CTRL_CAP: u16 = 1 << 10;
+    pub const SLOT_CAP: u16 = 1 << 15;
+
+    /// Returns [`None`] if any field doesn't fit into its bits.
+    pub const fn new(data: u32, ctrl: u16, slot: u16) -> Option<Self> {
+        if Self::DATA_CAP <= data || Self::CTRL_CAP <= ctrl || Self::SLOT_CAP <= slot {
+            return None;
+        }
+        Some(Self { data, ctrl, slot })
+    }
+
+    pub const fn data(self) -> u32 {
+        self.data
+    }
+    pub const fn ctrl(self) -> u16 {
+        self.ctrl
+    }
+    pub const fn slot(self) -> u16 {
+        self.slot
+    }
+
+    /// Advance `data` by `len`, wrapping around at [`Self::DATA_CAP`].
+    ///
+    /// Returns [`None`] if `len` doesn't fit into the field, as that would lap the buffer.
+    pub const fn checked_add_data(self, len: u32) -> Option<Self> {
+        if Self::DATA_CAP <= len {
+            return None;
+        }
+        Some(Self { data: (self.data + len) % Self::DATA_CAP, ..self })
+    }
+
+    /// Advance `ctrl` by `len`, wrapping around at [`Self::CTRL_CAP`].
+    ///
+    /// Returns [`None`] if `len` doesn't fit into the field, as that would lap the buffer.
+    pub const fn checked_add_ctrl(self, len: u16) -> Option<Self> {
+        if Self::CTRL_CAP <= len {
+            return None;
+        }
+        Some(Self { ctrl: (self.ctrl + len) % Self::CTRL_CAP, ..self })
+    }
+
+    /// Advance `slot` by `len`, wrapping around at [`Self::SLOT_CAP`].
+    ///
+    /// Returns [`None`] if `len` doesn't fit into the field, as that would lap the buffer.
+    pub const fn checked_add_slot(self, len: u16) -> Option<Self> {
+        if Self::SLOT_CAP <= len {
+            return None;
+        }
+        Some(Self { slot: (self.slot + len) % Self::SLOT_CAP, ..self })
+    }
+
+    pub const fn from_u64(val: u64) -> Self {
         Self {
-            data: ((val >> 32) & ((1 << 18) - 1)) as u32,
-            ctrl: ((val >> 16) & ((1 << 10) - 1)) as u16,
-            slot: (val & ((1 << 15) - 1)) as u16,
+            data: ((val >> 32) & (Self::DATA_CAP as u64 - 1)) as u32,
+            ctrl: ((val >> 16) & (Self::CTRL_CAP as u64 - 1)) as u16,
+            slot: (val & (Self::SLOT_CAP as u64 - 1)) as u16,
         }
     }
-    const fn into_64(self) -> u64 {
-        (((self.data & ((1 << 18) - 1)) as u64) << 32)
-            | (((self.ctrl & ((1 << 10) - 1)) as u64) << 16)
-            | (self.slot & ((1 << 15) - 1)) as u64
+    pub const fn into_64(self) -> u64 {
+        (((self.data & (Self::DATA_CAP - 1)) as u64) << 32)
+            | (((self.ctrl & (Self::CTRL_CAP - 1)) as u64) << 16)
+            | (self.slot & (Self::SLOT_CAP - 1)) as u64
+    }
+}
+
+impl PartialOrd for WaylandPos {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for WaylandPos {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.into_64().cmp(&other.into_64())
     }
 }
 
@@ -43,3 +111,48 @@ fn t() {
 
     assert_eq!(foo, WaylandPos::from_u64(val))
 }
+
+#[test]
+fn test_new_rejects_overflow() {
+    assert!(WaylandPos::new((1 << 18) - 1, (1 << 10) - 1, (1 << 15) - 1).is_some());
+    assert_eq!(WaylandPos::new(1 << 18, 0, 0), None);
+    assert_eq!(WaylandPos::new(0, 1 << 10, 0), None);
+    assert_eq!(WaylandPos::new(0, 0, 1 << 15), None);
+}
+
+#[test]
+fn test_checked_add_wraps() {
+    let pos = WaylandPos::new((1 << 18) - 8, (1 << 10) - 1, (1 << 15) - 1).unwrap();
+
+    assert_eq!(
+        pos.checked_add_data(12),
+        WaylandPos::new(4, (1 << 10) - 1, (1 << 15) - 1)
+    );
+    assert_eq!(
+        pos.checked_add_ctrl(3),
+        WaylandPos::new((1 << 18) - 8, 2, (1 << 15) - 1)
+    );
+    assert_eq!(
+        pos.checked_add_slot(1),
+        WaylandPos::new((1 << 18) - 8, (1 << 10) - 1, 0)
+    );
+    assert_eq!(
+        pos.checked_add_data(4),
+        WaylandPos::new((1 << 18) - 4, (1 << 10) - 1, (1 << 15) - 1)
+    );
+
+    assert_eq!(pos.checked_add_data(1 << 18), None);
+    assert_eq!(pos.checked_add_ctrl(1 << 10), None);
+    assert_eq!(pos.checked_add_slot(1 << 15), None);
+}
+
+#[test]
+fn test_ordered_by_packed_value() {
+    let pos = |data, ctrl, slot| WaylandPos::new(data, ctrl, slot).unwrap();
+    assert!(pos(0, 0, 1) < pos(0, 1, 0));
+    assert!(pos(0, 1023, 32767) < pos(1, 0, 0));
+
+    let mut sorted = [pos(2, 0, 0), pos(0, 0, 5), pos(1, 3, 0), pos(1, 2, 7)];
+    sorted.sort();
+    assert!(sorted.is_sorted_by_key(|pos| pos.into_64()));
+}
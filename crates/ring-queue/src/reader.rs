@@ -1,20 +1,99 @@
 use crate::{WaylandPos, bitfield::BitField};
 use std::{
-    num::NonZero,
+    collections::VecDeque,
     ops::{
+        BitAnd, BitOr,
         Bound::{self, *},
-        RangeBounds,
+        Not, RangeBounds,
     },
     os::fd::RawFd,
     ptr::NonNull,
     sync::{
         Mutex,
-        atomic::{AtomicU64, Ordering::*},
+        atomic::{AtomicU32, AtomicU64, Ordering, Ordering::*},
     },
 };
 
-pub struct Buffer {
-    slot: NonNull<[AtomicU64; slot::UPPER_CAP as usize]>,
+/// Atomic word making up one chunk of the slot bitmap.
+///
+/// Defaults to [`AtomicU64`], [`AtomicU32`] halves the chunk size for memory-constrained setups.
+pub trait ChunkWord {
+    type Value: Copy + Eq + BitAnd<Output = Self::Value> + BitOr<Output = Self::Value> + Not<Output = Self::Value>;
+
+    /// `log2(BITS)`, the number of lower slot bits indexing into a single chunk.
+    const SHIFT: u8;
+    const BITS: u32 = 1 << Self::SHIFT;
+    const ZERO: Self::Value;
+
+    fn new(val: Self::Value) -> Self;
+
+    /// `1 << index`
+    fn bit(index: u32) -> Self::Value;
+    /// `(1 << len) - 1`, saturating to all ones for `BITS <= len`.
+    fn low_mask(len: u32) -> Self::Value;
+    fn trailing_zeros(val: Self::Value) -> u32;
+
+    fn load(&self, order: Ordering) -> Self::Value;
+    fn compare_exchange(
+        &self,
+        current: Self::Value,
+        new: Self::Value,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<Self::Value, Self::Value>;
+}
+
+macro_rules! impl_chunk_word {
+    ($atomic:ty, $val:ty, $shift:literal) => {
+        impl ChunkWord for $atomic {
+            type Value = $val;
+
+            const SHIFT: u8 = $shift;
+            const ZERO: $val = 0;
+
+            fn new(val: $val) -> Self {
+                <$atomic>::new(val)
+            }
+
+            fn bit(index: u32) -> $val {
+                1 << index
+            }
+            fn low_mask(len: u32) -> $val {
+                match len {
+                    ..<$val>::BITS => (1 << len) - 1,
+                    _ => <$val>::MAX,
+                }
+            }
+            fn trailing_zeros(val: $val) -> u32 {
+                val.trailing_zeros()
+            }
+
+            fn load(&self, order: Ordering) -> $val {
+                self.load(order)
+            }
+            fn compare_exchange(
+                &self,
+                current: $val,
+                new: $val,
+                success: Ordering,
+                failure: Ordering,
+            ) -> Result<$val, $val> {
+                self.compare_exchange(current, new, success, failure)
+            }
+        }
+    };
+}
+
+impl_chunk_word!(AtomicU64, u64, 6);
+impl_chunk_word!(AtomicU32, u32, 5);
+
+pub struct Buffer<W: ChunkWord = AtomicU64> {
+    /// `slot::upper_cap::<W>()` chunks
+    ///
+    /// The bit of a slot is cleared once its frame was freed, or while it is the oldest slot in
+    /// use and therefore responsible for reclaiming the frames after it. While the buffer is
+    /// empty, the slot allocated next carries that mark, so allocating never has to touch it.
+    slot: NonNull<[W]>,
     data: NonNull<[u8; data::CAP as usize]>,
     ctrl: NonNull<[RawFd; ctrl::CAP as usize]>,
 
@@ -24,16 +103,73 @@ pub struct Buffer {
     reader_state: Mutex<State>,
 }
 
-impl Buffer {
-    fn slot_chunk(&self, index: u16) -> &AtomicU64 {
-        debug_assert!(index <= slot::UPPER_CAP);
-        unsafe { self.slot.cast::<AtomicU64>().add(index.into()).as_ref() }
+impl<W: ChunkWord> Buffer<W> {
+    pub fn new() -> Self {
+        // The first allocated slot is the oldest one.
+        let slots: Box<[W]> = (0..slot::upper_cap::<W>())
+            .map(|index| W::new(if index == 0 { !W::bit(0) } else { !W::ZERO }))
+            .collect();
+        let data_buf: Box<[u8; data::CAP as usize]> = vec![0; data::CAP as usize]
+            .into_boxed_slice()
+            .try_into()
+            .expect("length is `data::CAP`");
+        let ctrl_buf = Box::new([0; ctrl::CAP as usize]);
+
+        // The slot before the first one counts as the last one allocated and reclaimed.
+        let start = WaylandPos { data: 0, ctrl: 0, slot: slot::MASK }.into_64();
+        Self {
+            slot: NonNull::from(Box::leak(slots)),
+            data: NonNull::from(Box::leak(data_buf)),
+            ctrl: NonNull::from(Box::leak(ctrl_buf)),
+            free: AtomicU64::new(start),
+            next: AtomicU64::new(start),
+            reader_state: Mutex::new(State {
+                data: Range { next: 0, free: 0 },
+                ctrl: Range { next: 0, free: 0 },
+                slot: 0,
+                frames: VecDeque::new(),
+            }),
+        }
+    }
+
+    fn slot_chunk(&self, index: u16) -> &W {
+        debug_assert!(index <= slot::upper_cap::<W>());
+        unsafe { self.slot.cast::<W>().add(index.into()).as_ref() }
     }
 }
 
+// SAFETY: The slot bitmap is only accessed atomically and the reader state behind its mutex, while
+// `data` and `ctrl` are split into frames that are only handed out once.
+unsafe impl<W: ChunkWord + Send + Sync> Send for Buffer<W> {}
+unsafe impl<W: ChunkWord + Send + Sync> Sync for Buffer<W> {}
+
+impl<W: ChunkWord> Default for Buffer<W> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<W: ChunkWord> Drop for Buffer<W> {
+    fn drop(&mut self) {
+        unsafe {
+            drop(Box::from_raw(self.slot.as_ptr()));
+            drop(Box::from_raw(self.data.as_ptr()));
+            drop(Box::from_raw(self.ctrl.as_ptr()));
+        }
+    }
+}
+
+/// The readers view of the buffer.
+///
+/// `free..next` is the section holding frames that were allocated but not yet handed out by
+/// [`Buffer::drain()`].
 struct State {
     data: Range<u32>,
     ctrl: Range<u16>,
+    /// Slot of the next allocated frame.
+    slot: u16,
+    /// [`WaylandPos`] right after each of those frames, oldest first.
+    frames: VecDeque<WaylandPos>,
 }
 
 #[allow(non_camel_case_types)]
@@ -52,25 +188,28 @@ struct ctrl(u16);
 impl BitField<10, u16> for ctrl {}
 
 impl slot {
-    const UPPER_CAP: u16 = 1 << (Self::WIDTH - 6);
+    /// Number of chunks needed to fit all slots.
+    const fn upper_cap<W: ChunkWord>() -> u16 {
+        1 << (Self::WIDTH - W::SHIFT)
+    }
 
-    const fn new(upper: u16, lower: u16) -> Self {
-        let lower_mask = (1 << 6) - 1;
-        let upper_mask = (1 << (Self::WIDTH - 6)) - 1;
-        slot(((upper & upper_mask) << 6) | (lower & lower_mask))
+    fn new<W: ChunkWord>(upper: u16, lower: u16) -> Self {
+        let lower_mask = (1 << W::SHIFT) - 1;
+        let upper_mask = (1 << (Self::WIDTH - W::SHIFT)) - 1;
+        slot(((upper & upper_mask) << W::SHIFT) | (lower & lower_mask))
     }
 
-    const fn get(self) -> (u16, u32) {
-        (self.upper(), self.lower() as u32)
+    fn get<W: ChunkWord>(self) -> (u16, u32) {
+        (self.upper::<W>(), self.lower::<W>() as u32)
     }
 
-    // index into the u64 atomic
-    const fn lower(self) -> u16 {
-        self.0 & ((1 << 6) - 1)
+    // index into the atomic
+    fn lower<W: ChunkWord>(self) -> u16 {
+        self.0 & ((1 << W::SHIFT) - 1)
     }
-    // index into the u64 array
-    const fn upper(self) -> u16 {
-        self.0 >> 6
+    // index into the atomic array
+    fn upper<W: ChunkWord>(self) -> u16 {
+        self.0 >> W::SHIFT
     }
 }
 
@@ -91,17 +230,31 @@ impl Handle {
     }
 }
 
-const fn find_first_one(val: u64) -> Option<u32> {
-    let Some(val) = NonZero::new(val) else {
+/// What [`Buffer::free_handle()`] did with the freed frame, mirroring `phasesync::FreeReturn`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FreeOutcome {
+    /// An older frame is still in use and reclaims this one once it is freed itself.
+    MadeSomeoneElsesProblem,
+    /// The frame was the oldest one in use, so everything up to `upto` can be deallocated.
+    ///
+    /// The rest of the freed frames are reclaimed by the next frame still in use.
+    Reclaimed { upto: WaylandPos },
+    /// No frame is in use anymore, so everything allocated so far can be deallocated.
+    EmptiedBuffer,
+}
+
+/// Index of the lowest set bit, so the first of those slots in allocation order.
+fn find_first_one<W: ChunkWord>(val: W::Value) -> Option<u32> {
+    if val == W::ZERO {
         return None;
-    };
+    }
 
-    Some(u64::BITS - 1 - val.leading_zeros())
+    Some(W::trailing_zeros(val))
 }
 
 /// Calculates `(1 << end) - (1 << start)` while also handling all the possible edge_cases.
-fn bit_mask_range(bound: impl RangeBounds<u32>) -> u64 {
-    const fn inner((start_bound, end_bound): (Bound<u32>, Bound<u32>)) -> u64 {
+fn bit_mask_range<W: ChunkWord>(bound: impl RangeBounds<u32>) -> W::Value {
+    fn inner<W: ChunkWord>((start_bound, end_bound): (Bound<u32>, Bound<u32>)) -> W::Value {
         let lower = match start_bound {
             Bound::Included(val) => val,
             Bound::Excluded(val) => val + 1,
@@ -111,37 +264,84 @@ fn bit_mask_range(bound: impl RangeBounds<u32>) -> u64 {
         let upper = match end_bound {
             Bound::Excluded(val) => val,
             Bound::Included(val) => val + 1,
-            Bound::Unbounded => 64,
+            Bound::Unbounded => W::BITS,
         };
 
         match (lower, upper) {
-            (l, u) if u <= l => 0,
-            (64.., _) => 0,
-            (l, 64..) => u64::MAX - ((1 << l) - 1),
-            (l, u) => (1 << u) - (1 << l),
+            (l, u) if u <= l => W::ZERO,
+            (l, u) => W::low_mask(u) & !W::low_mask(l),
         }
     }
 
-    inner((
+    inner::<W>((
         bound.start_bound().map(|val| *val),
         bound.end_bound().map(|val| *val),
     ))
 }
 
-impl Buffer {
-    pub fn alloc_handle(&self) -> Handle {
-        todo!()
+impl<W: ChunkWord> Buffer<W> {
+    /// Allocate the frame of `data_len` bytes and `ctrl_len` file descriptors directly after the
+    /// previously allocated one.
+    ///
+    /// The frame is handed out as [`Handle`] by the next [`Self::drain()`].
+    ///
+    /// Returns [`None`] if the frame doesn't fit into the space that wasn't reclaimed yet. One slot,
+    /// byte and file descriptor always stay unused, so a full buffer can't be mistaken for an empty
+    /// one.
+    pub fn alloc_handle(&self, data_len: u32, ctrl_len: u16) -> Option<WaylandPos> {
+        let mut state = self.reader_state.lock().unwrap();
+        let index = state.slot;
+
+        let free = WaylandPos::from_u64(self.free.load(Acquire));
+        let slots_used = index.wrapping_sub(1).wrapping_sub(free.slot) & slot::MASK;
+        let data_used = state.data.next.wrapping_sub(free.data) & data::MASK;
+        let ctrl_used = state.ctrl.next.wrapping_sub(free.ctrl) & ctrl::MASK;
+        if slot::MASK <= slots_used || data::MASK - data_used < data_len || ctrl::MASK - ctrl_used < ctrl_len {
+            return None;
+        }
+
+        state.slot = (index + 1) & slot::MASK;
+        state.data.next = (state.data.next + data_len) & data::MASK;
+        state.ctrl.next = (state.ctrl.next + ctrl_len) & ctrl::MASK;
+
+        let next = WaylandPos { data: state.data.next, ctrl: state.ctrl.next, slot: index };
+        state.frames.push_back(next);
+        self.next.store(next.into_64(), Release);
+        Some(next)
     }
 
-    pub fn free_handle(&self, handle: Handle) {
-        let (upper, lower) = handle.slot.get();
+    /// Hand out the allocated frames between `free` and `next` in the order they were allocated.
+    ///
+    /// The reader state is advanced past each frame as it is yielded, so frames the iterator
+    /// wasn't polled for are handed out by the next call instead. The reader state is only locked
+    /// while yielding a frame, so frames can be allocated while the iterator is alive.
+    pub fn drain(&self) -> impl Iterator<Item = Handle> {
+        std::iter::from_fn(move || {
+            let mut state = self.reader_state.lock().unwrap();
+            let next = state.frames.pop_front()?;
+            let handle = Handle {
+                slot: slot(next.slot),
+                data: Range { next: data(next.data), free: data(state.data.free) },
+                ctrl: Range { next: ctrl(next.ctrl), free: ctrl(state.ctrl.free) },
+            };
+
+            state.data.free = next.data;
+            state.ctrl.free = next.ctrl;
+            Some(handle)
+        })
+    }
+
+    /// Free the frame of `handle`, reclaiming it and the freed frames after it if it was the
+    /// oldest frame still in use.
+    pub fn free_handle(&self, handle: Handle) -> FreeOutcome {
+        let (upper, lower) = handle.slot.get::<W>();
         let mut chunk = self.slot_chunk(upper);
 
-        let mask = 1 << lower;
+        let mask = W::bit(lower);
 
         let mut val = chunk.load(Acquire);
         loop {
-            if (val & mask) == 0 {
+            if (val & mask) == W::ZERO {
                 // The handles bit was set to 0 from the outside and is now responsible
                 // for freeing the section.
 
@@ -155,7 +355,7 @@ impl Buffer {
                 Ok(_) => {
                     // Handle was freed sucessfully.
                     // No further action is required from us
-                    return;
+                    return FreeOutcome::MadeSomeoneElsesProblem;
                 }
                 Err(v) => {
                     val = v;
@@ -164,37 +364,54 @@ impl Buffer {
             }
         }
 
-        if self.handle_chunk(chunk, val, (Excluded(lower), Unbounded)) {
-            return;
+        let upto = handle.next();
+        if let Some(first_one) = self.handle_chunk(chunk, val, (Excluded(lower), Unbounded)) {
+            return self.handed_over(slot::new::<W>(upper, first_one as u16), upto);
         }
         let mut chunk_index = upper;
 
         loop {
-            assign_add_wrap::<{ slot::UPPER_CAP }>(&mut chunk_index, 1);
+            assign_add_wrap(&mut chunk_index, 1, slot::upper_cap::<W>());
             chunk = self.slot_chunk(chunk_index);
             val = chunk.load(Acquire);
 
             if chunk_index == upper {
-                if self.handle_chunk(chunk, val, ..lower) {
-                    return;
-                } else {
-                    // Not yet sure how to handle this case
-                    todo!("buffer was completely full and is now empty again")
+                if let Some(first_one) = self.handle_chunk(chunk, val, ..lower) {
+                    return self.handed_over(slot::new::<W>(upper, first_one as u16), upto);
                 }
+
+                // The buffer was completely full and is now empty again.
+                // Our own slot gets allocated next, so it becomes the oldest one.
+                self.free.store(self.next.load(Acquire), Release);
+                loop_until_success(chunk, &mut val, |val| val & !mask, |_| true);
+                return FreeOutcome::EmptiedBuffer;
             }
 
-            if self.handle_chunk(chunk, val, ..) {
-                return;
+            if let Some(first_one) = self.handle_chunk(chunk, val, ..) {
+                return self.handed_over(slot::new::<W>(chunk_index, first_one as u16), upto);
             }
         }
     }
+
+    /// The responsibility for reclaiming frames was passed on to `oldest`.
+    fn handed_over(&self, oldest: slot, upto: WaylandPos) -> FreeOutcome {
+        let next = WaylandPos::from_u64(self.next.load(Acquire));
+        if oldest.0 != (next.slot + 1) & slot::MASK {
+            return FreeOutcome::Reclaimed { upto };
+        }
+
+        // `oldest` gets allocated next, so all frames allocated so far were freed.
+        // A frame allocated in the meantime starts at `next`, so it isn't affected.
+        self.free.store(next.into_64(), Release);
+        FreeOutcome::EmptiedBuffer
+    }
 }
 
-fn loop_until_success(
-    chunk: &AtomicU64,
-    val: &mut u64,
-    mut f: impl FnMut(u64) -> u64,
-    mut should_continue: impl FnMut(u64) -> bool,
+fn loop_until_success<W: ChunkWord>(
+    chunk: &W,
+    val: &mut W::Value,
+    mut f: impl FnMut(W::Value) -> W::Value,
+    mut should_continue: impl FnMut(W::Value) -> bool,
 ) -> bool {
     loop {
         let Err(actual) = chunk.compare_exchange(*val, f(*val), Release, Acquire) else {
@@ -208,45 +425,48 @@ fn loop_until_success(
     }
 }
 
-impl Buffer {
-    fn handle_chunk(&self, chunk: &AtomicU64, mut val: u64, range: impl RangeBounds<u32>) -> bool {
+impl<W: ChunkWord> Buffer<W> {
+    /// Reset the freed slots in `range` up to the first one still in use and make that one
+    /// responsible for reclaiming, returning its index in `chunk`.
+    fn handle_chunk(&self, chunk: &W, mut val: W::Value, range: impl RangeBounds<u32>) -> Option<u32> {
         let (start, end) = (
             range.start_bound().map(|b| *b),
             range.end_bound().map(|b| *b),
         );
 
         loop {
-            match find_first_one(val & bit_mask_range((start, end))) {
+            match find_first_one::<W>(val & bit_mask_range::<W>((start, end))) {
                 Some(first_one) => {
                     let range = (start, Excluded(first_one));
+                    // Stop trying once the slot freed itself, the next one in use takes over then.
                     let was_success = loop_until_success(
                         chunk,
                         &mut val,
-                        |val| val | bit_mask_range(range) & !(1 << first_one),
-                        |val| val & (1 << first_one) == 1,
+                        |val| (val | bit_mask_range::<W>(range)) & !W::bit(first_one),
+                        |val| val & W::bit(first_one) != W::ZERO,
                     );
 
                     if was_success {
-                        break true;
+                        break Some(first_one);
                     }
                 }
                 None => {
                     loop_until_success(
                         chunk,
                         &mut val,
-                        |val| val | bit_mask_range((start, end)),
+                        |val| val | bit_mask_range::<W>((start, end)),
                         |_| true,
                     );
-                    break false;
+                    break None;
                 }
             }
         }
     }
 }
 
-const fn assign_add_wrap<const WRAP: u16>(s: &mut u16, add: u16) {
-    debug_assert!(*s < WRAP);
-    let diff = WRAP - *s;
+const fn assign_add_wrap(s: &mut u16, add: u16, wrap: u16) {
+    debug_assert!(*s < wrap);
+    let diff = wrap - *s;
     if add < diff {
         *s += add;
     } else {
@@ -257,13 +477,238 @@ const fn assign_add_wrap<const WRAP: u16>(s: &mut u16, add: u16) {
 #[test]
 fn test_assing_add_wrap_normal_case() {
     let mut s = 16;
-    assign_add_wrap::<32>(&mut s, 15);
+    assign_add_wrap(&mut s, 15, 32);
     assert_eq!(s, 31);
 }
 
 #[test]
 fn test_assing_add_wrap_wrapping_case() {
     let mut s = 16;
-    assign_add_wrap::<32>(&mut s, 17);
+    assign_add_wrap(&mut s, 17, 32);
     assert_eq!(s, 1);
 }
+
+#[test]
+fn test_assing_add_wrap_normal_case_u32_chunks() {
+    let cap = slot::upper_cap::<AtomicU32>();
+    assert_eq!(cap, 2 * slot::upper_cap::<AtomicU64>());
+
+    let mut s = cap / 2;
+    assign_add_wrap(&mut s, cap / 2 - 1, cap);
+    assert_eq!(s, cap - 1);
+}
+
+#[test]
+fn test_assing_add_wrap_wrapping_case_u32_chunks() {
+    let cap = slot::upper_cap::<AtomicU32>();
+
+    let mut s = cap / 2;
+    assign_add_wrap(&mut s, cap / 2 + 1, cap);
+    assert_eq!(s, 1);
+
+    let mut s = cap - 1;
+    assign_add_wrap(&mut s, 1, cap);
+    assert_eq!(s, 0);
+}
+
+#[test]
+fn test_drain() {
+    let buffer = Buffer::<AtomicU64>::new();
+    assert!(buffer.drain().next().is_none());
+
+    for (data_len, ctrl_len) in [(8, 0), (16, 1), (12, 0)] {
+        buffer.alloc_handle(data_len, ctrl_len).unwrap();
+    }
+    let handles: Vec<_> = buffer
+        .drain()
+        .map(|h| {
+            (
+                h.slot.0,
+                h.data.free.0..h.data.next.0,
+                h.ctrl.free.0..h.ctrl.next.0,
+            )
+        })
+        .collect();
+    assert_eq!(
+        handles,
+        [(0, 0..8, 0..0), (1, 8..24, 0..1), (2, 24..36, 1..1)]
+    );
+    assert!(buffer.drain().next().is_none());
+
+    buffer.alloc_handle(8, 2).unwrap();
+    let handle = buffer.drain().next().unwrap();
+    assert_eq!(
+        (handle.slot.0, handle.data.free.0, handle.ctrl.free.0),
+        (3, 36, 1)
+    );
+    assert_eq!(
+        WaylandPos::from_u64(buffer.next.load(Relaxed)),
+        handle.next()
+    );
+}
+
+#[test]
+fn test_chunk_bits_u32() {
+    let s = slot::new::<AtomicU32>(3, 31);
+    assert_eq!(s.get::<AtomicU32>(), (3, 31));
+    assert_eq!(s.get::<AtomicU64>(), (1, 63));
+
+    assert_eq!(bit_mask_range::<AtomicU32>(..), u32::MAX);
+    assert_eq!(bit_mask_range::<AtomicU32>(4..8), 0xf0);
+    assert_eq!(
+        bit_mask_range::<AtomicU32>((Excluded(30), Unbounded)),
+        1 << 31
+    );
+    assert_eq!(bit_mask_range::<AtomicU32>(32..), 0);
+    assert_eq!(bit_mask_range::<AtomicU64>(32..), 0xffff_ffff_0000_0000);
+
+    assert_eq!(find_first_one::<AtomicU32>(0), None);
+    assert_eq!(find_first_one::<AtomicU32>(1 << 31 | 1 << 3), Some(3));
+    assert_eq!(find_first_one::<AtomicU64>(1 << 40), Some(40));
+}
+
+#[test]
+fn test_free_outcome() {
+    let buffer = Buffer::<AtomicU64>::new();
+    for (data_len, ctrl_len) in [(8, 0), (16, 1), (12, 0)] {
+        buffer.alloc_handle(data_len, ctrl_len).unwrap();
+    }
+    let [first, second, third] = buffer.drain().collect::<Vec<_>>().try_into().ok().unwrap();
+    let upto = first.next();
+
+    assert_eq!(
+        buffer.free_handle(second),
+        FreeOutcome::MadeSomeoneElsesProblem
+    );
+    assert_eq!(buffer.free_handle(first), FreeOutcome::Reclaimed { upto });
+    assert_eq!(WaylandPos::from_u64(buffer.free.load(Relaxed)), upto);
+
+    assert_eq!(buffer.free_handle(third), FreeOutcome::EmptiedBuffer);
+    assert_eq!(buffer.free.load(Relaxed), buffer.next.load(Relaxed));
+}
+
+#[test]
+fn test_free_full_then_empty() {
+    let buffer = Buffer::<AtomicU32>::new();
+    for _ in 0..slot::MASK {
+        buffer.alloc_handle(8, 0).unwrap();
+    }
+    assert_eq!(buffer.alloc_handle(8, 0), None);
+    let mut handles = buffer.drain().collect::<Vec<_>>();
+    assert_eq!(handles.len(), slot::MASK as usize);
+
+    // Everything but the oldest frame gets freed first, leaving it to reclaim the whole buffer.
+    let oldest = handles.remove(0);
+    for handle in handles.into_iter().rev() {
+        assert_eq!(
+            buffer.free_handle(handle),
+            FreeOutcome::MadeSomeoneElsesProblem
+        );
+    }
+    assert_eq!(buffer.free_handle(oldest), FreeOutcome::EmptiedBuffer);
+    assert_eq!(buffer.free.load(Relaxed), buffer.next.load(Relaxed));
+
+    // The buffer is usable again, starting at the slot that was left unused.
+    for _ in 0..2 {
+        buffer.alloc_handle(8, 0).unwrap();
+    }
+    let [first, second] = buffer.drain().collect::<Vec<_>>().try_into().ok().unwrap();
+    assert_eq!((first.slot.0, second.slot.0), (slot::MASK, 0));
+    let upto = first.next();
+    assert_eq!(buffer.free_handle(first), FreeOutcome::Reclaimed { upto });
+    assert_eq!(buffer.free_handle(second), FreeOutcome::EmptiedBuffer);
+}
+
+/// Meant for `cargo miri test`, which reports the arrays as leaked if `Drop` misses one of them.
+#[test]
+fn test_new_drop() {
+    let buffer = Buffer::<AtomicU64>::new();
+    // The first slot is marked as the oldest one before anything is allocated.
+    assert_eq!(buffer.slot_chunk(0).load(Relaxed), !1);
+    for index in 1..slot::upper_cap::<AtomicU64>() {
+        assert_eq!(buffer.slot_chunk(index).load(Relaxed), u64::MAX);
+    }
+    drop(buffer);
+
+    // Frames that were never freed don't keep the arrays alive.
+    let buffer = Buffer::<AtomicU32>::new();
+    buffer.alloc_handle(8, 1).unwrap();
+    // The first frame is the oldest one in use.
+    assert_eq!(buffer.slot_chunk(0).load(Relaxed), !1);
+    drop(buffer);
+}
+
+#[test]
+fn test_alloc_full() {
+    let buffer = Buffer::<AtomicU64>::new();
+    assert_eq!(buffer.alloc_handle(data::CAP, 0), None);
+    assert_eq!(buffer.alloc_handle(0, ctrl::CAP), None);
+
+    let first = buffer.alloc_handle(data::MASK - 8, ctrl::MASK).unwrap();
+    assert_eq!(buffer.alloc_handle(9, 0), None);
+    assert_eq!(buffer.alloc_handle(0, 1), None);
+    let second = buffer.alloc_handle(8, 0).unwrap();
+    assert_eq!(buffer.alloc_handle(1, 0), None);
+
+    // Reclaiming the first frame makes its space available again.
+    let mut handles = buffer.drain();
+    let handle = handles.next().unwrap();
+    assert_eq!(handle.next(), first);
+    assert_eq!(
+        buffer.free_handle(handle),
+        FreeOutcome::Reclaimed { upto: first }
+    );
+
+    // Allocating while the drain is alive doesn't deadlock.
+    let third = buffer.alloc_handle(16, 1).unwrap();
+    assert_eq!(handles.next().unwrap().next(), second);
+    assert_eq!(handles.next().unwrap().next(), third);
+    assert!(handles.next().is_none());
+}
+
+#[test]
+fn test_concurrent_alloc_free() {
+    use std::{sync::mpsc, thread};
+
+    const FRAMES: usize = 100_000;
+
+    let buffer = Buffer::<AtomicU32>::new();
+    thread::scope(|scope| {
+        let (tx, rx) = mpsc::channel::<Handle>();
+        // Frames are freed while the next ones are allocated, which keeps emptying the buffer
+        // right as it gets filled again.
+        let freer = scope.spawn(|| {
+            let mut emptied = 0;
+            for handle in rx {
+                if buffer.free_handle(handle) == FreeOutcome::EmptiedBuffer {
+                    emptied += 1;
+                }
+            }
+            emptied
+        });
+
+        let mut allocated = 0;
+        while allocated < FRAMES {
+            if buffer.alloc_handle(8, (allocated % 2) as u16).is_none() {
+                thread::yield_now();
+            } else {
+                allocated += 1;
+            }
+            for handle in buffer.drain() {
+                tx.send(handle).unwrap();
+            }
+        }
+        drop(tx);
+        assert!(freer.join().unwrap() > 0);
+    });
+
+    assert_eq!(buffer.free.load(Relaxed), buffer.next.load(Relaxed));
+    // Exactly one slot, the next one to be allocated, is marked as the oldest one.
+    let marked: u32 = (0..slot::upper_cap::<AtomicU32>())
+        .map(|index| buffer.slot_chunk(index).load(Relaxed).count_zeros())
+        .sum();
+    assert_eq!(marked, 1);
+    let next = WaylandPos::from_u64(buffer.next.load(Relaxed));
+    let (upper, lower) = slot((next.slot + 1) & slot::MASK).get::<AtomicU32>();
+    assert_eq!(buffer.slot_chunk(upper).load(Relaxed) & (1 << lower), 0);
+}
@@ -0,0 +1,160 @@
+use crate::error::WaylandError;
+use ecs_compositor_core::Interface;
+
+/// Highest version of `I` supported by both the bindings and the server, which advertised
+/// `server_version`.
+///
+/// Fails if that is older than [`Interface::MIN_VERSION`], or `0`, which no object can have.
+pub(crate) fn negotiate_version<I: Interface>(server_version: u32) -> Result<u32, WaylandError> {
+    let required = I::MIN_VERSION.max(1);
+    if server_version < required {
+        return Err(WaylandError::UnsupportedVersion { interface: I::NAME, required, advertised: server_version });
+    }
+    Ok(I::VERSION.min(server_version))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        connection::{ClientHandle, Connection, ServerHandle},
+        error::WaylandError,
+        handle::{Client, Server},
+        protocols::wayland::{wl_compositor::wl_compositor, wl_registry},
+        test_util,
+    };
+    use ecs_compositor_core::{Interface, Message, uint};
+    use std::{
+        io::{ErrorKind, Read},
+        os::unix::net::UnixStream,
+    };
+
+    /// `wl_compositor` bindings that refuse to work with servers older than v4.
+    #[allow(non_camel_case_types)]
+    enum wl_compositor_v4 {}
+
+    impl Interface for wl_compositor_v4 {
+        const NAME: &str = wl_compositor::NAME;
+        const VERSION: u32 = wl_compositor::VERSION;
+        const MIN_VERSION: u32 = 4;
+
+        type Error = uint;
+
+        type Request = u16;
+        type Event = u16;
+    }
+
+    #[tokio::test]
+    async fn bind_checked_downgrades() {
+        let (conn, mut server) = test_util::pair();
+        let conn = &conn;
+        let registry = conn.new_object_with_id::<wl_registry::wl_registry>(2);
+
+        let compositor = conn.bind_checked::<wl_compositor>(&registry, uint(7), uint(3)).await.unwrap();
+        const { assert!(wl_compositor::VERSION > 3) };
+        assert_eq!(compositor.version(), 3);
+        conn.flush().await.unwrap();
+
+        let (hdr, content, _) = test_util::read_msg(&mut server);
+        assert_eq!(
+            (hdr.object_id.id().get(), hdr.opcode),
+            (2, wl_registry::request::bind::<wl_compositor>::OP)
+        );
+        // name, interface name (length + 16 bytes), version, id
+        assert_eq!(content.len(), 8);
+        assert_eq!(content[0], 7);
+        assert_eq!(content[6..], [3, compositor.id().id.get()]);
+    }
+
+    #[tokio::test]
+    async fn bind_checked_too_old() {
+        let (conn, mut server) = test_util::pair();
+        let conn = &conn;
+        let registry = conn.new_object_with_id::<wl_registry::wl_registry>(2);
+
+        let Err(err) = conn.bind_checked::<wl_compositor_v4>(&registry, uint(7), uint(3)).await else {
+            panic!("binding a too old global succeeded");
+        };
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(
+            err.downcast::<WaylandError>().unwrap(),
+            WaylandError::UnsupportedVersion { interface: "wl_compositor", required: 4, advertised: 3 }
+        );
+
+        conn.flush().await.unwrap();
+        server.set_nonblocking(true).unwrap();
+        assert_eq!(
+            server.read(&mut [0; 4]).unwrap_err().kind(),
+            ErrorKind::WouldBlock,
+            "nothing should have been sent"
+        );
+    }
+
+    #[tokio::test]
+    async fn new_object_dyn_versioned() {
+        let (conn, mut server) = test_util::pair();
+        let conn = &conn;
+        let registry = conn.new_object_with_id::<wl_registry::wl_registry>(2);
+
+        let (id, compositor) = conn.new_object_dyn_versioned::<wl_compositor>(3).unwrap();
+        assert_eq!(compositor.version(), 3);
+        registry
+            .send_and_flush(&wl_registry::request::bind_dyn { name: uint(7), id })
+            .await
+            .unwrap();
+
+        let (_, content, _) = test_util::read_msg(&mut server);
+        assert_eq!(content[6..], [3, compositor.id().id.get()]);
+
+        // Servers newer than the bindings get the version of the bindings.
+        let (id, compositor) = conn.new_object_dyn_versioned::<wl_compositor>(u32::MAX).unwrap();
+        assert_eq!(
+            (id.version, compositor.version()),
+            (uint(wl_compositor::VERSION), wl_compositor::VERSION)
+        );
+
+        // Like `bind_checked()`, servers older than the bindings support are refused.
+        assert_eq!(
+            conn.new_object_dyn_versioned::<wl_compositor_v4>(3).err(),
+            Some(WaylandError::UnsupportedVersion { interface: "wl_compositor", required: 4, advertised: 3 })
+        );
+        assert_eq!(
+            conn.new_object_dyn_versioned::<wl_compositor>(0).err(),
+            Some(WaylandError::UnsupportedVersion { interface: "wl_compositor", required: 1, advertised: 0 })
+        );
+    }
+
+    #[tokio::test]
+    async fn server_decodes_bind() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let client = &Connection::<Client>::from_stream(client).unwrap();
+        let server = &Connection::<Server>::from_stream(server).unwrap();
+
+        // Allocated through the client, so both ids are distinct.
+        let (_, registry) = client.new_object::<wl_registry::wl_registry>();
+        let compositor;
+        registry
+            .send_and_flush(&wl_registry::request::bind::<wl_compositor> {
+                name: uint(7),
+                id: crate::new_id!(client, compositor),
+            })
+            .await
+            .unwrap();
+
+        let registry = server
+            .register_client_object::<wl_registry::wl_registry>(registry.id().id.get())
+            .unwrap();
+        let msg = registry.recv().await.unwrap();
+        assert!(matches!(
+            msg.decode_opcode(),
+            wl_registry::request::Opcodes::bind
+        ));
+        let bind = msg.decode_msg::<wl_registry::request::bind_dyn>().ok().unwrap();
+        assert_eq!(bind.name, uint(7));
+        assert_eq!(bind.id.interface_name(), wl_compositor::NAME.as_bytes());
+        assert_eq!(bind.id.version, uint(wl_compositor::VERSION));
+        assert_eq!(bind.id.id.id, compositor.id().id);
+
+        let bound = server.register_client_object::<wl_compositor>(bind.id.id.id.get()).unwrap();
+        assert_eq!(bound.id().id, compositor.id().id);
+    }
+}
@@ -0,0 +1,79 @@
+use crate::{
+    connection::{ClientHandle, Object},
+    protocols::wayland::wl_callback,
+};
+use std::io;
+
+/// A `wl_callback` the server answers exactly once with `done`, as created by
+/// `wl_display.sync` or `wl_surface.frame`.
+pub struct Callback<Conn>
+where
+    Conn: ClientHandle,
+{
+    obj: Object<Conn, wl_callback::wl_callback>,
+}
+
+impl<Conn> Callback<Conn>
+where
+    Conn: ClientHandle,
+{
+    pub fn new(obj: Object<Conn, wl_callback::wl_callback>) -> Self {
+        Self { obj }
+    }
+
+    pub fn object(&self) -> &Object<Conn, wl_callback::wl_callback> {
+        &self.obj
+    }
+
+    pub fn into_object(self) -> Object<Conn, wl_callback::wl_callback> {
+        self.obj
+    }
+
+    /// Wait for the `done` event and return its `callback_data`.
+    ///
+    /// The server destroys the object right after, so it is consumed.
+    pub async fn done(self) -> io::Result<u32> {
+        let msg = self.obj.recv().await?;
+        let wl_callback::event::done { callback_data } = msg.decode_msg()?;
+        Ok(callback_data.0)
+    }
+}
+
+impl<Conn> From<Object<Conn, wl_callback::wl_callback>> for Callback<Conn>
+where
+    Conn: ClientHandle,
+{
+    fn from(obj: Object<Conn, wl_callback::wl_callback>) -> Self {
+        Self::new(obj)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        connection::{Callback, ClientHandle},
+        protocols::wayland::{wl_callback, wl_display},
+        test_util,
+    };
+    use ecs_compositor_core::uint;
+
+    #[tokio::test]
+    async fn done() {
+        let (conn, mut server) = test_util::MockServer::pair();
+        let conn = &conn;
+        let display = conn.new_object_with_id::<wl_display::wl_display>(1);
+
+        let callback = Callback::new(display.sync().await.unwrap());
+        conn.flush().await.unwrap();
+        let (content, _) = server.expect::<wl_display::request::sync>(1);
+        assert_eq!(content[0], callback.object().id().id.get());
+        server
+            .event(
+                content[0],
+                &wl_callback::event::done { callback_data: uint(42) },
+            )
+            .play();
+
+        assert_eq!(callback.done().await.unwrap(), 42);
+    }
+}
@@ -0,0 +1,612 @@
+use crate::{
+    connection::{
+        Connection, Direction, RateLimit, Registry,
+        recv::{next_hdr, take_msg},
+    },
+    drive_io::{Interest, IoBuf, IoHalf, RxIo},
+    error::WaylandError,
+    handle::InterfaceDir,
+    protocols::wayland::wl_display,
+};
+use ecs_compositor_core::{Interface, Unknown, Value, message_header, object};
+use std::{
+    cell::Cell,
+    collections::{BTreeMap, VecDeque},
+    future::poll_fn,
+    io,
+    os::fd::{AsRawFd, RawFd},
+    pin::Pin,
+    ptr,
+    sync::Arc,
+    task::{Context, Poll, Waker, ready},
+};
+use tokio::{task::JoinHandle, time::Sleep};
+use tracing::{debug, instrument, trace};
+
+/// Per-object message queues filled by the task started with [`Connection::spawn_driver()`].
+#[derive(Default)]
+pub(crate) struct Demux {
+    state: State,
+    queues: BTreeMap<object, VecDeque<QueuedMsg>>,
+    /// Woken when a new receiver registers, as the driver might wait for it.
+    pub(crate) waker: Option<Waker>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum State {
+    #[default]
+    Off,
+    Running,
+    /// The peer closed the connection.
+    Closed,
+    Failed(io::ErrorKind),
+}
+
+impl Demux {
+    /// Pop the next message queued for `id`.
+    ///
+    /// Returns [`None`] if no driver was started and nothing was queued by
+    /// [`Connection::dispatch_pending()`], in which case the caller has to drive io itself, and
+    /// [`Poll::Pending`] if the queue is empty.
+    pub(crate) fn poll_pop(&mut self, id: object) -> Option<Poll<io::Result<QueuedMsg>>> {
+        if self.state == State::Off {
+            return self
+                .queues
+                .get_mut(&id)
+                .and_then(VecDeque::pop_front)
+                .map(|msg| Poll::Ready(Ok(msg)));
+        }
+
+        match self.queues.get_mut(&id).and_then(VecDeque::pop_front) {
+            Some(msg) => Some(Poll::Ready(Ok(msg))),
+            None => match self.state {
+                State::Closed => Some(Poll::Ready(Err(WaylandError::Closed.into()))),
+                State::Failed(kind) => Some(Poll::Ready(Err(io::Error::new(
+                    kind,
+                    "connection driver failed",
+                )))),
+                _ => Some(Poll::Pending),
+            },
+        }
+    }
+}
+
+/// Message copied out of the rx buffer, so the rx lock doesn't have to be held until it is
+/// decoded.
+pub(crate) struct QueuedMsg {
+    pub(crate) hdr: message_header,
+    // `u32` backing storage to keep the content 4 byte aligned.
+    da: Box<[u32]>,
+    /// Owned by the message, decoding takes them out by overwriting them with `-1`, which needs
+    /// the interior mutability.
+    fd: Box<[Cell<RawFd>]>,
+}
+
+impl QueuedMsg {
+    /// # Safety
+    ///
+    /// `buf` has to point to the received content of the message `hdr`, whose fds are handed over
+    /// to the returned message.
+    pub(super) unsafe fn new(hdr: message_header, buf: IoBuf) -> Self {
+        unsafe {
+            let mut da = vec![0u32; buf.da.len().div_ceil(4)].into_boxed_slice();
+            ptr::copy_nonoverlapping(
+                buf.da.cast::<u8>(),
+                da.as_mut_ptr().cast::<u8>(),
+                buf.da.len(),
+            );
+
+            Self { hdr, da, fd: (*buf.fd).iter().copied().map(Cell::new).collect() }
+        }
+    }
+
+    pub(crate) fn data(&self) -> (*const [u8], *const [RawFd]) {
+        (
+            ptr::slice_from_raw_parts(
+                self.da.as_ptr().cast::<u8>(),
+                self.hdr.content_len() as usize,
+            ),
+            ptr::slice_from_raw_parts(self.fd.as_ptr().cast::<RawFd>(), self.fd.len()),
+        )
+    }
+}
+
+/// Close the fds nobody took out of the message.
+impl Drop for QueuedMsg {
+    fn drop(&mut self) {
+        unsafe {
+            close_untaken(ptr::slice_from_raw_parts(
+                self.fd.as_ptr().cast(),
+                self.fd.len(),
+            ))
+        }
+    }
+}
+
+/// Close the fds of a received message that weren't taken out by decoding it, which marks them
+/// with `-1`.
+///
+/// # Safety
+///
+/// `fds` has to be valid for reads and owned by the caller.
+pub(super) unsafe fn close_untaken(fds: *const [RawFd]) {
+    for &fd in unsafe { &*fds } {
+        if fd >= 0 {
+            unsafe { libc::close(fd) };
+        }
+    }
+}
+
+impl<Dir> Connection<Dir>
+where
+    Dir: InterfaceDir<wl_display::wl_display> + std::marker::Send + Sync + 'static,
+{
+    /// Spawn a task reading all incoming messages and routing them to the object they are
+    /// addressed to.
+    ///
+    /// Without it, whichever [`Object::recv()`](crate::connection::Object::recv) gets hold of the
+    /// rx lock reads the socket and has to wake the addressee of every message that isn't its
+    /// own, so with many objects waiting most wakeups are spurious.
+    /// With the driver running, receiving just pops the object's queue.
+    ///
+    /// The task finishes once the server closes the connection. Afterwards, and if it fails,
+    /// receiving returns an error as soon as the queue of the object is empty.
+    pub fn spawn_driver(self: &Arc<Self>) -> JoinHandle<io::Result<()>> {
+        self.registry().demux.state = State::Running;
+
+        let conn = self.clone();
+        tokio::spawn(async move {
+            let mut throttle = None;
+            let res = poll_fn(|cx| conn.poll_demux(&mut throttle, cx)).await;
+
+            let mut registry = conn.registry();
+            registry.demux.state = match &res {
+                Ok(()) => State::Closed,
+                Err(err) => State::Failed(err.kind()),
+            };
+            for entry in registry.receiver_map.values() {
+                entry.waker.wake_by_ref();
+            }
+            res
+        })
+    }
+
+    /// Route every message that can be received without waiting to the object it is addressed
+    /// to, returning how many were routed.
+    ///
+    /// Reads the socket at most once and never waits for it, so synchronous code can pump the
+    /// connection without a task driving it, like `wl_display_dispatch_pending()` of libwayland.
+    /// The routed messages are returned by [`Object::recv()`](crate::connection::Object::recv)
+    /// before anything still in the rx buffer.
+    ///
+    /// Stops at the first message for an object nobody receives on yet if fds are buffered, as it
+    /// can't tell which of them belong to the message, and returns `0` if the rx lock is held
+    /// elsewhere.
+    pub fn dispatch_pending(&self) -> io::Result<usize> {
+        let Some(mut io) = self.try_lock_rx() else {
+            trace!("rx lock is held elsewhere");
+            return Ok(0);
+        };
+        let mut routed = 0;
+        for read in [false, true] {
+            if read && io.interest.contains(Interest::RECV) {
+                io.recv_nonblocking(self.fd.as_raw_fd())?;
+            }
+            // The driver might be waiting for a receiver, so its waker must not be replaced.
+            match unsafe { route(&mut io, &mut self.registry(), &mut routed, None) } {
+                Poll::Ready(res) => res?,
+                Poll::Pending => break,
+            }
+        }
+        Ok(routed)
+    }
+
+    #[instrument(name = "poll_demux", level = "trace", ret, skip_all)]
+    fn poll_demux(&self, throttle: &mut Option<Pin<Box<Sleep>>>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        loop {
+            let throttled = {
+                let mut io = ready!(self.poll_lock_rx(cx));
+                ready!(unsafe { route(&mut io, &mut self.registry(), &mut 0, Some(cx.waker())) })?;
+
+                if io.interest.contains(Interest::RECV_CLOSED) {
+                    debug!("connection closed, stopping driver");
+                    return Poll::Ready(Ok(()));
+                }
+                // Others reading from the socket, like `Connection::disconnect()`, wake us.
+                io.rx_waker = Some(cx.waker().clone());
+                io.throttled_until()
+            };
+
+            if let Some(until) = throttled {
+                ready!(RateLimit::poll_throttle(throttle, until, cx));
+                continue;
+            }
+
+            let mut guard = ready!(self.fd.poll_read_ready(cx))?;
+            ready!(self.poll_lock_rx(cx)).drive_io(&mut guard)?;
+        }
+    }
+}
+
+/// Move every complete message in the rx buffer to the queue of the object it is addressed to,
+/// counting them in `routed`.
+///
+/// Messages addressed to objects nobody receives on yet are queued as well, so they don't hold up
+/// the ones behind them, as long as no fds are buffered. Which fds belong to a message depends on
+/// the interface of its addressee, so otherwise this returns [`Poll::Pending`] until a receiver
+/// registers for it, and `waker` gets woken once one does. Without a waker the one registered
+/// before is kept.
+///
+/// `wl_display.delete_id` events free their id right here, so ids get reused even if nobody
+/// receives on the `wl_display`. They are only queued if somebody does.
+///
+/// # Safety
+///
+/// `io.rx_hdr` has to be the header of the next message in the rx buffer if set.
+unsafe fn route<Dir>(
+    io: &mut RxIo,
+    registry: &mut Registry<Dir>,
+    routed: &mut usize,
+    waker: Option<&Waker>,
+) -> Poll<io::Result<()>>
+where
+    Dir: InterfaceDir<wl_display::wl_display>,
+{
+    unsafe {
+        loop {
+            let Some(hdr) = next_hdr(io)? else {
+                return Poll::Ready(Ok(()));
+            };
+
+            let delete_id = hdr.object_id.id().get() == 1 && Dir::is_delete_id(hdr.opcode);
+            let fd_count = match registry.receiver_map.get(&hdr.object_id) {
+                Some(entry) => (entry.fd_count)(hdr.opcode),
+                None if delete_id => Some(0),
+                // Fds arrive with the first byte of the `sendmsg` they were sent with, so none
+                // can belong to the message anymore.
+                None if io.rx.fd.data.is_empty() => Some(0),
+                None => {
+                    trace!(id = hdr.object_id.id().get(), "waiting for receiver");
+                    if let Some(waker) = waker {
+                        registry.demux.waker = Some(waker.clone());
+                    }
+                    return Poll::Pending;
+                }
+            };
+            let Some((_, buf)) = take_msg(io, hdr, fd_count)? else {
+                return Poll::Ready(Ok(()));
+            };
+            if delete_id {
+                let (mut da, mut fd) = (buf.da as *const [u8], buf.fd as *const [RawFd]);
+                if let Ok(wl_display::event::delete_id { id }) = wl_display::event::delete_id::read(&mut da, &mut fd) {
+                    registry.free_id(id.0);
+                }
+            }
+
+            let entry = registry.receiver_map.get(&hdr.object_id);
+            let interface = match entry {
+                Some(entry) => entry.interface,
+                None if delete_id => wl_display::wl_display::NAME,
+                None => <Unknown as Interface>::NAME,
+            };
+            io.logger.log_msg(Direction::Rx, interface, hdr.object_id, hdr.opcode, buf.da);
+            if entry.is_none() && delete_id {
+                continue;
+            }
+
+            trace!(id = hdr.object_id.id().get(), opcode = hdr.opcode, "route");
+            registry
+                .demux
+                .queues
+                .entry(hdr.object_id)
+                .or_default()
+                .push_back(QueuedMsg::new(hdr, buf));
+            *routed += 1;
+            if let Some(entry) = entry {
+                entry.waker.wake_by_ref();
+            }
+            registry.wake_readiness(&io.stats);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        connection::{BufConfig, ClientHandle, Connection, ServerHandle},
+        drive_io::MAX_FDS,
+        error::WaylandError,
+        handle::{Client, Server},
+        msg_io::{
+            Msg,
+            cmsg_cursor::{CmsgBuf, CmsgCursor},
+        },
+        protocols::wayland::{wl_callback, wl_display, wl_keyboard, wl_shm, wl_surface},
+        test_util,
+    };
+    use ecs_compositor_core::{Message, fd, int, uint};
+    use libc::{CMSG_SPACE, SCM_RIGHTS, SOL_SOCKET};
+    use std::{
+        fs::File,
+        future::poll_fn,
+        io::{ErrorKind, Read},
+        os::{
+            fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+            unix::net::UnixStream,
+        },
+        pin::pin,
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering::Relaxed},
+        },
+        time::Duration,
+    };
+
+    const OBJECTS: u32 = 100;
+
+    /// Number of times the `wl_callback.done` receivers of `OBJECTS` objects get polled when all
+    /// events arrive in reverse order.
+    async fn recv_polls(driven: bool) -> usize {
+        let (conn, mut server) = test_util::pair();
+        let conn = Arc::new(conn);
+        if driven {
+            conn.spawn_driver();
+        }
+
+        for id in (2..OBJECTS + 2).rev() {
+            test_util::write_msg(
+                &mut server,
+                id,
+                &wl_callback::event::done { callback_data: uint(id) },
+            );
+        }
+
+        let polls = Arc::new(AtomicUsize::new(0));
+        let tasks: Vec<_> = (2..OBJECTS + 2)
+            .map(|id| {
+                let callback = conn.new_object_with_id::<wl_callback::wl_callback>(id);
+                let polls = polls.clone();
+                tokio::spawn(async move {
+                    let mut recv = pin!(callback.recv());
+                    let msg = poll_fn(|cx| {
+                        polls.fetch_add(1, Relaxed);
+                        recv.as_mut().poll(cx)
+                    })
+                    .await
+                    .unwrap();
+                    let wl_callback::event::done { callback_data } = msg.decode_msg().ok().unwrap();
+                    assert_eq!(callback_data.0, id);
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        polls.load(Relaxed)
+    }
+
+    #[tokio::test]
+    async fn dispatch_pending() {
+        let (sock, mut client) = UnixStream::pair().unwrap();
+        let conn = &Connection::<Server>::from_stream(sock).unwrap();
+        let surfaces: Vec<_> = (3..6)
+            .map(|id| conn.register_client_object::<wl_surface::wl_surface>(id).unwrap())
+            .collect();
+        assert_eq!(conn.dispatch_pending().unwrap(), 0);
+
+        for id in (3..6).rev() {
+            test_util::write_msg(&mut client, id, &wl_surface::request::commit {});
+        }
+        assert_eq!(conn.dispatch_pending().unwrap(), 3);
+        assert_eq!(conn.dispatch_pending().unwrap(), 0);
+
+        for surface in &surfaces {
+            let msg = surface.try_recv().unwrap().expect("message wasn't routed");
+            assert_eq!(msg.opcode(), wl_surface::request::commit::OP);
+        }
+    }
+
+    #[tokio::test]
+    async fn dispatch_pending_keeps_driver_waker() {
+        let (conn, mut server) = test_util::pair();
+        let conn = Arc::new(conn);
+        conn.spawn_driver();
+
+        // Carries an fd, so the driver can't route it before it knows the interface of `2`.
+        let (_read, write) = test_util::pipe();
+        test_util::write_msg(
+            &mut server,
+            2,
+            &wl_keyboard::event::keymap {
+                format: wl_keyboard::enumeration::keymap_format::no_keymap,
+                fd: fd(write.as_raw_fd()),
+                size: uint(7),
+            },
+        );
+        // Let the driver stop at the message, waiting for a receiver.
+        while conn.registry().demux.waker.is_none() {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(conn.dispatch_pending().unwrap(), 0);
+
+        let keyboard = conn.new_object_with_id::<wl_keyboard::wl_keyboard>(2);
+        let msg = tokio::time::timeout(Duration::from_secs(5), keyboard.recv())
+            .await
+            .expect("driver wasn't woken by the new receiver")
+            .unwrap();
+        let wl_keyboard::event::keymap { size, .. } = msg.decode_msg().ok().unwrap();
+        assert_eq!(size.0, 7);
+    }
+
+    #[tokio::test]
+    async fn unknown_object_queued() {
+        let (conn, mut server) = test_util::pair();
+        let conn = Arc::new(conn);
+        conn.spawn_driver();
+
+        for id in [3, 2] {
+            test_util::write_msg(
+                &mut server,
+                id,
+                &wl_callback::event::done { callback_data: uint(id) },
+            );
+        }
+
+        // Nobody receives on `3` yet, which doesn't hold up the message for `2`.
+        for id in [2, 3] {
+            let callback = conn.new_object_with_id::<wl_callback::wl_callback>(id);
+            let msg = tokio::time::timeout(Duration::from_secs(5), callback.recv())
+                .await
+                .expect("message wasn't routed")
+                .unwrap();
+            let wl_callback::event::done { callback_data } = msg.decode_msg().ok().unwrap();
+            assert_eq!(callback_data.0, id);
+        }
+    }
+
+    /// On a single thread, a driver blocking on the rx lock would never let the test release it.
+    #[test]
+    fn driver_waits_for_rx_lock() {
+        let (done_tx, done) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+            rt.block_on(async {
+                let (conn, mut server) = test_util::pair();
+                let conn = Arc::new(conn);
+                let callback = conn.new_object_with_id::<wl_callback::wl_callback>(2);
+                conn.spawn_driver();
+
+                let io = conn.lock_rx();
+                test_util::write_msg(
+                    &mut server,
+                    2,
+                    &wl_callback::event::done { callback_data: uint(7) },
+                );
+                for _ in 0..10 {
+                    tokio::task::yield_now().await;
+                }
+                drop(io);
+
+                let msg = callback.recv().await.unwrap();
+                let wl_callback::event::done { callback_data } = msg.decode_msg().ok().unwrap();
+                done_tx.send(callback_data.0).unwrap();
+            });
+        });
+
+        let callback_data = done
+            .recv_timeout(Duration::from_secs(5))
+            .expect("the driver blocked on the rx lock");
+        assert_eq!(callback_data, 7);
+    }
+
+    #[tokio::test]
+    async fn routed_delete_id_frees_id() {
+        let (sock, mut server) = UnixStream::pair().unwrap();
+        let conn = &Connection::<Client>::from_stream(sock).unwrap();
+        let (_, surface) = conn.new_object::<wl_surface::wl_surface>();
+        let id = surface.id().id.get();
+
+        // Nobody receives on the `wl_display`, so the event is dropped after freeing the id.
+        test_util::write_msg(
+            &mut server,
+            1,
+            &wl_display::event::delete_id { id: uint(id) },
+        );
+        assert_eq!(conn.dispatch_pending().unwrap(), 0);
+
+        let (_, reused) = conn.new_object::<wl_surface::wl_surface>();
+        assert_eq!(reused.id().id.get(), id);
+        let err = surface.send(&wl_surface::request::commit {}).await.unwrap_err();
+        assert_eq!(
+            err.downcast::<WaylandError>().unwrap(),
+            WaylandError::StaleObject { id }
+        );
+        reused.send_and_flush(&wl_surface::request::commit {}).await.unwrap();
+        let (hdr, _, _) = test_util::read_msg(&mut server);
+        assert_eq!(
+            (hdr.object_id.id().get(), hdr.opcode),
+            (id, wl_surface::request::commit::OP)
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_object_fds_closed() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let client = &Connection::<Client>::from_stream(client).unwrap();
+        let server = Connection::<Server>::from_stream(server).unwrap();
+
+        let (read, write) = test_util::pipe();
+
+        // The server never registers the `wl_shm`, so the message stays in the rx buffer.
+        let shm = client.new_object_with_id::<wl_shm::wl_shm>(7);
+        let _pool;
+        shm.send(&wl_shm::request::create_pool {
+            id: crate::new_id!(client, _pool),
+            fd: fd(write.as_raw_fd()),
+            size: int(4096),
+        })
+        .await
+        .unwrap();
+        drop(write);
+        assert_eq!(server.dispatch_pending().unwrap(), 0);
+        assert_eq!(server.stats().fds_recv, 1);
+
+        // Only the write end the server received is still open.
+        let Err(err) = (&read).read(&mut [0]) else { panic!("pipe was closed") };
+        assert_eq!(err.kind(), ErrorKind::WouldBlock);
+
+        drop(server);
+        assert_eq!(
+            (&read).read(&mut [0]).unwrap(),
+            0,
+            "fd of the dropped message leaked"
+        );
+    }
+
+    #[tokio::test]
+    async fn fd_limit_exceeded() {
+        const FDS: usize = 200;
+
+        let (client, server) = UnixStream::pair().unwrap();
+        let config = BufConfig { fd_capacity: MAX_FDS as usize, ..BufConfig::default() };
+        let server = &Connection::<Server>::from_stream_with_config(server, config).unwrap();
+
+        // Two `sendmsg`s whose fds don't fit into the rx buffer together, with too little data for
+        // a message header, so the first batch of fds stays buffered.
+        let file = File::from(unsafe { OwnedFd::from_raw_fd(libc::memfd_create(c"test".as_ptr(), 0)) });
+        for _ in 0..2 {
+            let mut ctrl_buf = CmsgBuf::<{ unsafe { CMSG_SPACE((FDS * size_of::<RawFd>()) as u32) as usize } }>::new();
+            let mut cursor = unsafe { CmsgCursor::from_ctrl_buf(&mut ctrl_buf.0) };
+            cursor
+                .write_cursor::<RawFd>(SOL_SOCKET, SCM_RIGHTS)
+                .unwrap()
+                .write_slice(&[file.as_raw_fd(); FDS])
+                .commit()
+                .unwrap();
+            let mut msg = Msg { data: &mut [0u8; 2], ctrl: cursor.as_slice(), flags: 0 };
+            msg.send(client.as_raw_fd(), 0).unwrap().unwrap();
+        }
+
+        assert_eq!(server.dispatch_pending().unwrap(), 0);
+        let err = server.dispatch_pending().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(
+            err.downcast::<WaylandError>().unwrap(),
+            WaylandError::FdLimitExceeded { limit: MAX_FDS as usize }
+        );
+    }
+
+    #[tokio::test]
+    async fn driver_wakeups() {
+        let undriven = recv_polls(false).await;
+        let driven = recv_polls(true).await;
+
+        // Every receiver is polled once to register and once more after its message got routed.
+        assert!(driven <= 2 * OBJECTS as usize, "{driven} polls with driver");
+        assert!(
+            driven < undriven,
+            "{driven} polls with driver, {undriven} without"
+        );
+    }
+}
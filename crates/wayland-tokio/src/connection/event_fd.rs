@@ -0,0 +1,203 @@
+use crate::connection::Connection;
+use std::{
+    io,
+    os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+    sync::Arc,
+    task::{Wake, Waker},
+};
+use tracing::debug;
+
+/// `eventfd` that becomes readable whenever it gets woken, for notifying event loops that poll
+/// fds instead of [`Waker`]s, see [`Connection::event_fd()`].
+#[derive(Debug)]
+pub struct EventFd(OwnedFd);
+
+impl EventFd {
+    pub fn new() -> io::Result<Self> {
+        let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self(unsafe { OwnedFd::from_raw_fd(fd) }))
+    }
+
+    /// Make the fd readable until the next [`Self::reset()`].
+    pub fn notify(&self) -> io::Result<()> {
+        let val = 1u64.to_ne_bytes();
+        if unsafe { libc::write(self.0.as_raw_fd(), val.as_ptr().cast(), val.len()) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Clear the readiness, returning whether it got notified since the last reset.
+    pub fn reset(&self) -> io::Result<bool> {
+        let mut val = [0u8; 8];
+        if unsafe { libc::read(self.0.as_raw_fd(), val.as_mut_ptr().cast(), val.len()) } < 0 {
+            let err = io::Error::last_os_error();
+            return match err.kind() {
+                io::ErrorKind::WouldBlock => Ok(false),
+                _ => Err(err),
+            };
+        }
+        Ok(true)
+    }
+}
+
+impl Wake for EventFd {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if let Err(err) = self.notify() {
+            debug!(%err, "failed to notify eventfd");
+        }
+    }
+}
+
+impl AsRawFd for EventFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl AsFd for EventFd {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+impl<Dir> Connection<Dir> {
+    /// Register `waker` to be woken whenever receiving leaves messages for other objects in the rx
+    /// buffer or the driver queues them, replacing the previous one.
+    ///
+    /// It is woken once per read from the socket, so objects that aren't received on don't wake
+    /// it over and over again.
+    ///
+    /// Polling the socket only reports data that wasn't read yet, so event loops not driven by
+    /// tokio have to be woken for buffered messages as well, by this or [`Self::event_fd()`].
+    pub fn readiness_waker(&self, waker: Waker) {
+        self.registry().readiness = Some(waker);
+    }
+
+    /// Create an [`EventFd`] and register it as [`Self::readiness_waker()`].
+    ///
+    /// A foreign event loop polls it together with the socket for readability and then calls
+    /// [`Object::try_recv()`](crate::connection::Object::try_recv) on its objects until they return
+    /// [`None`].
+    pub fn event_fd(&self) -> io::Result<Arc<EventFd>> {
+        let event_fd = Arc::new(EventFd::new()?);
+        self.readiness_waker(Waker::from(event_fd.clone()));
+        Ok(event_fd)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        connection::{ClientHandle, Connection, ServerHandle},
+        handle::{Client, Server},
+        protocols::wayland::{wl_callback, wl_seat, wl_surface},
+        test_util,
+    };
+    use ecs_compositor_core::{string, uint};
+    use std::os::{
+        fd::{AsRawFd, RawFd},
+        unix::net::UnixStream,
+    };
+
+    /// Wait until any of `fds` is readable.
+    fn poll_readable(fds: [RawFd; 2]) {
+        let mut pollfds = fds.map(|fd| libc::pollfd { fd, events: libc::POLLIN, revents: 0 });
+        assert!(
+            unsafe { libc::poll(pollfds.as_mut_ptr(), pollfds.len() as _, 1000) } > 0,
+            "timed out"
+        );
+    }
+
+    #[test]
+    fn foreign_event_loop() {
+        // The reactor is only needed to register the socket, it never gets driven.
+        let rt = tokio::runtime::Builder::new_current_thread().enable_io().build().unwrap();
+        let _guard = rt.enter();
+
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let conn = &Connection::<Client>::from_stream(client).unwrap();
+        let seat = conn.new_object_with_id::<wl_seat::wl_seat>(2);
+        let callback = conn.new_object_with_id::<wl_callback::wl_callback>(3);
+        let event_fd = conn.event_fd().unwrap();
+
+        test_util::write_msg(
+            &mut server,
+            2,
+            &wl_seat::event::name { name: string::from_slice(b"seat0\0") },
+        );
+        test_util::write_msg(
+            &mut server,
+            3,
+            &wl_callback::event::done { callback_data: uint(42) },
+        );
+
+        let (mut name, mut done) = (None, None);
+        while name.is_none() || done.is_none() {
+            poll_readable([conn.as_raw_fd(), event_fd.as_raw_fd()]);
+            event_fd.reset().unwrap();
+
+            while let Some(msg) = seat.try_recv().unwrap() {
+                let wl_seat::event::name { name: seat_name } = msg.decode_msg().ok().unwrap();
+                name = Some(seat_name.as_slice_without_trailing_null().to_vec());
+            }
+            while let Some(msg) = callback.try_recv().unwrap() {
+                let wl_callback::event::done { callback_data } = msg.decode_msg().ok().unwrap();
+                done = Some(callback_data);
+            }
+        }
+        assert_eq!(name.as_deref(), Some(&b"seat0"[..]));
+        assert_eq!(done, Some(uint(42)));
+
+        // Taking the seat's message left the callback's buffered, which got reported.
+        assert!(event_fd.reset().unwrap());
+        assert!(seat.try_recv().unwrap().is_none());
+        assert!(callback.try_recv().unwrap().is_none());
+        assert!(!event_fd.reset().unwrap());
+    }
+
+    #[test]
+    fn notified_once_per_read() {
+        let rt = tokio::runtime::Builder::new_current_thread().enable_io().build().unwrap();
+        let _guard = rt.enter();
+
+        // Receiving on an object other than the addressee reports the message only once.
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let conn = &Connection::<Client>::from_stream(client).unwrap();
+        let seat = conn.new_object_with_id::<wl_seat::wl_seat>(2);
+        let event_fd = conn.event_fd().unwrap();
+
+        test_util::write_msg(
+            &mut server,
+            3,
+            &wl_callback::event::done { callback_data: uint(42) },
+        );
+        poll_readable([conn.as_raw_fd(), event_fd.as_raw_fd()]);
+        for notified in [true, false, false] {
+            assert!(seat.try_recv().unwrap().is_none());
+            assert_eq!(event_fd.reset().unwrap(), notified);
+        }
+
+        // So do messages routed to the queues of their receivers.
+        let (sock, mut client) = UnixStream::pair().unwrap();
+        let conn = &Connection::<Server>::from_stream(sock).unwrap();
+        let surfaces = [3, 4].map(|id| conn.register_client_object::<wl_surface::wl_surface>(id).unwrap());
+        let event_fd = conn.event_fd().unwrap();
+
+        for id in [3, 4] {
+            test_util::write_msg(&mut client, id, &wl_surface::request::commit {});
+        }
+        assert_eq!(conn.dispatch_pending().unwrap(), 2);
+        assert!(event_fd.reset().unwrap());
+        assert_eq!(conn.dispatch_pending().unwrap(), 0);
+        assert!(surfaces[0].try_recv().unwrap().is_some());
+        assert!(!event_fd.reset().unwrap());
+    }
+}
@@ -0,0 +1,169 @@
+use crate::{
+    connection::{ClientHandle, Connection, Object},
+    handle::Client,
+    protocols::wayland::{wl_display, wl_registry},
+};
+use ecs_compositor_core::uint;
+use futures::Stream;
+use std::{
+    io,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tokio::{sync::mpsc, task::JoinHandle};
+use tracing::trace;
+
+/// Change to the set of globals the server advertises, see [`Connection::globals()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GlobalEvent {
+    Added { name: uint, interface: String, version: uint },
+    Removed { name: uint },
+}
+
+impl Connection<Client> {
+    /// Create a `wl_registry` and stream every global the server announces or removes on it.
+    ///
+    /// The server first announces all globals it already has, afterwards the stream reports the
+    /// globals that get added or removed later on, e.g. outputs getting hotplugged.
+    pub async fn globals(self: &Arc<Self>) -> io::Result<Globals> {
+        let display = self.new_object_with_id::<wl_display::wl_display>(1);
+        let registry;
+        display
+            .send(&wl_display::request::get_registry { registry: new_id!(self, registry) })
+            .await?;
+
+        let (tx, events) = mpsc::unbounded_channel();
+        let task = tokio::spawn({
+            let registry = registry.clone();
+            async move {
+                loop {
+                    let event = recv_global(&registry).await;
+                    let failed = event.is_err();
+                    if tx.send(event).is_err() || failed {
+                        break;
+                    }
+                }
+            }
+        });
+
+        Ok(Globals { registry, events, task })
+    }
+}
+
+async fn recv_global(registry: &Object<Arc<Connection<Client>>, wl_registry::wl_registry>) -> io::Result<GlobalEvent> {
+    let msg = registry.recv().await?;
+    match msg.decode_opcode() {
+        wl_registry::event::Opcodes::global => {
+            let wl_registry::event::global { name, interface, version } = msg.decode_msg()?;
+            let interface = interface
+                .as_utf8()
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            trace!(
+                name = name.0,
+                interface,
+                version = version.0,
+                "global added"
+            );
+            Ok(GlobalEvent::Added { name, interface: interface.to_owned(), version })
+        }
+        wl_registry::event::Opcodes::global_remove => {
+            let wl_registry::event::global_remove { name } = msg.decode_msg()?;
+            trace!(name = name.0, "global removed");
+            Ok(GlobalEvent::Removed { name })
+        }
+    }
+}
+
+/// Stream of the [`GlobalEvent`]s of a `wl_registry`, see [`Connection::globals()`].
+///
+/// Ends after yielding the first error, e.g. once the connection got closed.
+pub struct Globals {
+    registry: Object<Arc<Connection<Client>>, wl_registry::wl_registry>,
+    events: mpsc::UnboundedReceiver<io::Result<GlobalEvent>>,
+    task: JoinHandle<()>,
+}
+
+impl Globals {
+    /// The registry to bind the announced globals on, e.g. with
+    /// [`ClientHandle::bind_checked()`].
+    pub fn registry(&self) -> &Object<Arc<Connection<Client>>, wl_registry::wl_registry> {
+        &self.registry
+    }
+}
+
+impl Stream for Globals {
+    type Item = io::Result<GlobalEvent>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().events.poll_recv(cx)
+    }
+}
+
+impl Drop for Globals {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        connection::GlobalEvent,
+        protocols::wayland::{wl_display, wl_registry},
+        test_util,
+    };
+    use ecs_compositor_core::{Message, string, uint};
+    use futures::StreamExt;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn globals() {
+        let (conn, mut server) = test_util::pair();
+        let conn = Arc::new(conn);
+
+        let mut globals = conn.globals().await.unwrap();
+        conn.flush().await.unwrap();
+        let (hdr, content, _) = test_util::read_msg(&mut server);
+        assert_eq!(
+            (hdr.object_id.id().get(), hdr.opcode),
+            (1, wl_display::request::get_registry::OP)
+        );
+        assert_eq!(content, [globals.registry().id().id.get()]);
+
+        let registry = content[0];
+        for (name, interface) in [(1, "wl_output\0"), (2, "wl_seat\0")] {
+            test_util::write_msg(
+                &mut server,
+                registry,
+                &wl_registry::event::global {
+                    name: uint(name),
+                    interface: string::from_slice(interface.as_bytes()),
+                    version: uint(4),
+                },
+            );
+        }
+        test_util::write_msg(
+            &mut server,
+            registry,
+            &wl_registry::event::global_remove { name: uint(1) },
+        );
+
+        let mut events = Vec::new();
+        for _ in 0..3 {
+            events.push(globals.next().await.unwrap().unwrap());
+        }
+        assert_eq!(
+            events,
+            [
+                GlobalEvent::Added { name: uint(1), interface: "wl_output".to_owned(), version: uint(4) },
+                GlobalEvent::Added { name: uint(2), interface: "wl_seat".to_owned(), version: uint(4) },
+                GlobalEvent::Removed { name: uint(1) },
+            ]
+        );
+
+        drop(server);
+        assert!(globals.next().await.unwrap().is_err());
+        assert!(globals.next().await.is_none());
+    }
+}
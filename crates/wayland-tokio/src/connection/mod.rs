@@ -1,67 +1,486 @@
 use crate::{
-    drive_io::Io,
-    handle::{Client, ConnectionHandle},
+    connection::bind::negotiate_version,
+    drive_io::{
+        CloseNotify, Interest, IoHalf, IoStats, Logger, MAX_DATA, MAX_FDS, RxIo, TxIo, WAYLAND_MAX_MESSAGE_LEN,
+    },
+    error::WaylandError,
+    handle::{Client, ConnectionHandle, Server},
+    protocols::wayland::wl_registry,
 };
 use ecs_compositor_core::{Interface, new_id, new_id_dyn, object, string, uint};
+use futures::task::AtomicWaker;
 use std::{
-    env, io,
+    env,
+    future::Future,
+    io,
     marker::PhantomData,
+    mem::ManuallyDrop,
+    net::Shutdown,
     num::{NonZero, NonZeroU32},
+    ops::{Deref, DerefMut},
     os::{
-        fd::{AsRawFd, RawFd},
+        fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, RawFd},
         unix::net::UnixStream,
     },
     path::PathBuf,
     ptr::NonNull,
-    sync::{Mutex, MutexGuard, TryLockError},
+    sync::{
+        Arc, Mutex, MutexGuard, TryLockError,
+        atomic::{AtomicBool, Ordering},
+    },
+    task::{Context, Poll},
+    time::Duration,
 };
-use tokio::io::unix::AsyncFd;
+use tokio::{io::unix::AsyncFd, runtime::Handle};
 
-pub use self::{ready_fut::DriveIo, recv::Recv, send::Send};
+pub use self::{
+    callback::Callback,
+    event_fd::EventFd,
+    globals::{GlobalEvent, Globals},
+    ready_fut::DriveIo,
+    receivers::RegistryConfig,
+    recv::Recv,
+    send::Send,
+};
 
 pub mod recv;
 pub mod send;
 
+mod bind;
+mod callback;
+mod demux;
+mod event_fd;
+mod globals;
 mod obj;
+mod rate_limit;
 mod ready_fut;
+mod receivers;
+mod reconnect;
 mod registry;
+mod roundtrip;
+mod serve;
+
+pub use self::obj::{AnyObject, Object};
+pub(crate) use self::{rate_limit::RateLimit, registry::Registry};
 
-pub use self::obj::Object;
-pub(crate) use self::registry::Registry;
+/// How long [`Connection::disconnect()`] waits for the peer to close its end of the socket.
+pub const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(1);
 
 pub struct Connection<Dir> {
     pub(crate) fd: AsyncFd<UnixStream>,
-    drive_io: Mutex<Io>,
+    rx: Mutex<RxIo>,
+    /// Woken whenever the rx lock is released, see [`Self::poll_lock_rx()`].
+    rx_unlocked: AtomicWaker,
+    tx: Mutex<TxIo>,
     registry: Mutex<Registry<Dir>>,
+    stats: Arc<IoStats>,
+    closed: Arc<CloseNotify>,
+    /// Runtime drop-time cleanup runs in, see [`Self::set_runtime_handle()`].
+    runtime: Mutex<Handle>,
+    /// What the connection was created with, reused by [`Connection::reconnect()`].
+    config: BufConfig,
     // pub(crate) recv: RecvBuf,
 }
 
+/// Whether a message passed to the [`MessageLogger`] was sent or received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Tx,
+    Rx,
+}
+
+/// Callback getting every message sent or received on a connection, see
+/// [`Connection::set_message_logger()`].
+///
+/// Gets called with the interface name of the object, the object id, the opcode and the raw
+/// content of the message (excluding the header).
+pub type MessageLogger = Box<dyn Fn(Direction, &'static str, object, u16, &[u8]) + std::marker::Send>;
+
+/// Snapshot of the traffic a [`Connection`] has seen so far, see [`Connection::stats()`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ConnStats {
+    /// Bytes written to the socket.
+    pub tx_bytes: u64,
+    /// Bytes read from the socket.
+    pub rx_bytes: u64,
+    /// Messages queued for sending.
+    pub tx_msgs: u64,
+    /// Messages handed out by [`Object::recv()`].
+    pub rx_msgs: u64,
+    pub fds_sent: u64,
+    pub fds_recv: u64,
+}
+
+/// Capacities of the ring buffers a [`Connection`] sends and receives messages through, and how
+/// it looks up the receivers of incoming messages.
+///
+/// Each direction gets its own pair of buffers. Messages are written to and read from them in
+/// place, so the data buffer has to hold at least one maximum size message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufConfig {
+    /// Bytes of message data, at least 64 KiB.
+    pub data_capacity: usize,
+    /// Number of fds, at least as many as can arrive with a single `recvmsg` (252).
+    pub fd_capacity: usize,
+    /// How the receivers of incoming messages are looked up.
+    pub registry: RegistryConfig,
+}
+
+impl Default for BufConfig {
+    fn default() -> Self {
+        Self { data_capacity: MAX_DATA, fd_capacity: 1024, registry: RegistryConfig::default() }
+    }
+}
+
+/// Whether `WAYLAND_SOCKET` was taken over already, as only one connection may own it.
+static WAYLAND_SOCKET_TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// Take over the inherited socket `fd`, marking it close-on-exec.
+///
+/// Fails without taking over `fd` if it isn't open or isn't a socket.
+///
+/// # Safety
+///
+/// If `fd` is an open socket, nothing else may use or close it afterwards.
+unsafe fn inherit_socket(fd: RawFd) -> io::Result<UnixStream> {
+    if unsafe { libc::fcntl(fd, libc::F_GETFD) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut stat = unsafe { std::mem::zeroed::<libc::stat>() };
+    if unsafe { libc::fstat(fd, &mut stat) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if stat.st_mode & libc::S_IFMT != libc::S_IFSOCK {
+        let msg = format!("WAYLAND_SOCKET {fd} isn't a socket");
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+    }
+
+    let sock = unsafe { UnixStream::from_raw_fd(fd) };
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(sock)
+}
+
+impl BufConfig {
+    fn validate(&self) -> io::Result<()> {
+        if self.data_capacity < WAYLAND_MAX_MESSAGE_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "data capacity {cap} can't hold a message of {WAYLAND_MAX_MESSAGE_LEN} bytes",
+                    cap = self.data_capacity
+                ),
+            ));
+        }
+        if self.fd_capacity < MAX_FDS as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "fd capacity {cap} can't hold {MAX_FDS} fds",
+                    cap = self.fd_capacity
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
 impl<Dir> Connection<Dir> {
     pub fn new() -> io::Result<Self> {
-        let sock = UnixStream::connect(PathBuf::from_iter([
+        Self::with_config(BufConfig::default())
+    }
+
+    /// Like [`Self::new()`], but with a custom [`BufConfig`].
+    ///
+    /// Takes over the socket passed in `WAYLAND_SOCKET` if the spawning compositor set it.
+    /// Otherwise, or if it was already taken over by an earlier connection, connects to
+    /// `$XDG_RUNTIME_DIR/$WAYLAND_DISPLAY`.
+    ///
+    /// Unlike libwayland, this leaves the variable itself alone, as changing the environment isn't
+    /// thread-safe, see [`Self::with_config_unset_socket()`] for that. The socket is marked
+    /// close-on-exec though, so children inheriting the variable fail to take it over instead of
+    /// sharing the connection.
+    pub fn with_config(config: BufConfig) -> io::Result<Self> {
+        Self::from_stream_with_config(Self::connect()?, config)
+    }
+
+    /// Like [`Self::with_config()`], but removes `WAYLAND_SOCKET` from the environment once it was
+    /// taken over, like libwayland does.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`env::remove_var()`], no other thread may access the environment at the same time.
+    pub unsafe fn with_config_unset_socket(config: BufConfig) -> io::Result<Self> {
+        let sock = Self::connect();
+        // Even if taking it over failed, nobody else may try again.
+        if WAYLAND_SOCKET_TAKEN.load(Ordering::Relaxed) {
+            unsafe { env::remove_var("WAYLAND_SOCKET") };
+        }
+        Self::from_stream_with_config(sock?, config)
+    }
+
+    fn connect() -> io::Result<UnixStream> {
+        if let Some(fd) = env::var_os("WAYLAND_SOCKET")
+            && !WAYLAND_SOCKET_TAKEN.swap(true, Ordering::Relaxed)
+        {
+            let Some(fd) = fd.to_str().and_then(|fd| fd.parse::<RawFd>().ok()) else {
+                let msg = format!("invalid WAYLAND_SOCKET {fd:?}");
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+            };
+            return unsafe { inherit_socket(fd) };
+        }
+
+        UnixStream::connect(PathBuf::from_iter([
             env::var_os("XDG_RUNTIME_DIR").unwrap(),
             env::var_os("WAYLAND_DISPLAY").unwrap(),
-        ]))?;
+        ]))
+    }
 
+    /// Wrap an already connected socket, e.g. one end of a [`UnixStream::pair()`].
+    ///
+    /// Only a unix socket can carry the fds of messages, which are passed as `SCM_RIGHTS` control
+    /// messages, so there is no in-memory transport to use instead. Tests use a socket pair.
+    pub fn from_stream(sock: UnixStream) -> io::Result<Self> {
+        Self::from_stream_with_config(sock, BufConfig::default())
+    }
+
+    /// Like [`Self::from_stream()`], but with a custom [`BufConfig`].
+    ///
+    /// Fails with [`io::ErrorKind::InvalidInput`] if `config` is too small to hold a single
+    /// message.
+    pub fn from_stream_with_config(sock: UnixStream, config: BufConfig) -> io::Result<Self> {
+        Self::from_parts(sock, config)
+    }
+
+    fn from_parts(sock: UnixStream, config: BufConfig) -> io::Result<Self> {
+        config.validate()?;
+
+        let stats = Arc::new(IoStats::default());
+        let logger = Logger::default();
+        let closed = Arc::new(CloseNotify::default());
         Ok(Self {
             fd: AsyncFd::new(sock)?,
-            drive_io: Mutex::new(Io::new()),
-            registry: Mutex::new(Registry::new()),
+            rx: Mutex::new(RxIo::new(
+                config,
+                stats.clone(),
+                logger.clone(),
+                closed.clone(),
+            )),
+            rx_unlocked: AtomicWaker::new(),
+            tx: Mutex::new(TxIo::new(config, stats.clone(), logger, closed.clone())),
+            registry: Mutex::new(Registry::new(config.registry)),
+            stats,
+            closed,
+            runtime: Mutex::new(Handle::current()),
+            config,
             // recv: RecvBuf::new(),
         })
     }
 
+    /// Run drop-time cleanup, like flushing the destructors of [`Object::auto_destroy()`]
+    /// objects, on `runtime` instead of the runtime the connection was created in.
+    ///
+    /// The cleanup is spawned onto the runtime, so objects can be dropped outside of any runtime
+    /// context, e.g. from a plain thread.
+    pub fn set_runtime_handle(&self, runtime: Handle) {
+        *self.runtime.lock().unwrap() = runtime;
+    }
+
+    /// Runtime drop-time cleanup runs on, see [`Self::set_runtime_handle()`].
+    pub fn runtime_handle(&self) -> Handle {
+        self.runtime.lock().unwrap().clone()
+    }
+
+    /// Read the traffic counters.
+    ///
+    /// Doesn't take the io locks, so this can be called at any point, even while holding a
+    /// [`MsgBuf`](recv::MsgBuf).
+    pub fn stats(&self) -> ConnStats {
+        self.stats.snapshot()
+    }
+
+    /// Whether the end of the connection was observed in either direction, see [`Self::closed()`].
+    pub fn is_closed(&self) -> bool {
+        self.closed.is_closed()
+    }
+
+    /// Wait until the end of the connection is observed in either direction, e.g. for a
+    /// supervisor task restarting the client.
+    ///
+    /// The end of the connection is only noticed while it is driven, by receiving, sending or
+    /// [`Self::spawn_driver()`], so this doesn't resolve for a connection nobody uses.
+    pub fn closed(&self) -> impl Future<Output = ()> + '_ {
+        self.closed.wait()
+    }
+
+    /// Readiness the socket has to be polled for to make progress, for driving the connection
+    /// from a custom event loop.
+    ///
+    /// Returns [`None`] once the connection is closed in both directions.
+    /// Takes the rx lock, so it must not be called while holding a [`MsgBuf`](recv::MsgBuf).
+    pub fn current_interest(&self) -> Option<tokio::io::Interest> {
+        let rx = self.lock_rx().query_interest();
+        let tx = self.tx.lock().unwrap().query_interest();
+        match (rx, tx) {
+            (Some(rx), Some(tx)) => Some(rx | tx),
+            (rx, tx) => rx.or(tx),
+        }
+    }
+
+    /// Whether there are buffered messages that [`Self::flush()`] would write to the socket.
+    pub fn wants_flush(&self) -> bool {
+        let tx = self.tx.lock().unwrap();
+        !tx.tx.is_empty() || !self.registry().pending_destructors.is_empty()
+    }
+
+    /// Call `logger` for every message sent or received from now on, replacing the previous
+    /// logger.
+    ///
+    /// Messages are logged while the io lock of their direction is held, so `logger` should be
+    /// cheap and must not call back into the connection.
+    pub fn set_message_logger(&self, logger: MessageLogger) {
+        *self.tx.lock().unwrap().logger.0.lock().unwrap() = Some(logger);
+    }
+
+    /// Gracefully close the connection.
+    ///
+    /// Flushes all buffered messages, shuts down the write half of the socket and then discards
+    /// everything the peer still sends until it closes its end as well.
+    ///
+    /// Waits at most [`DISCONNECT_TIMEOUT`] for the peer to close its end, then fails with
+    /// [`io::ErrorKind::TimedOut`] and closes the socket anyway.
+    pub async fn disconnect(self) -> io::Result<()> {
+        self.flush().await?;
+
+        match self.fd.get_ref().shutdown(Shutdown::Write) {
+            Err(err) if err.kind() != io::ErrorKind::NotConnected => return Err(err),
+            _ => {}
+        }
+        {
+            let mut tx = self.tx.lock().unwrap();
+            tx.interest.remove(Interest::SEND);
+            tx.interest.insert(Interest::SEND_CLOSED);
+        }
+        self.closed.close();
+
+        let eof = async {
+            loop {
+                let mut guard = self.fd.readable().await?;
+                let mut io = self.lock_rx();
+
+                io.discard_rx();
+                if io.interest.contains(Interest::RECV_CLOSED) {
+                    return Ok(());
+                }
+                io.interest.insert(Interest::RECV);
+                io.drive_io(&mut guard)?;
+            }
+        };
+        tokio::time::timeout(DISCONNECT_TIMEOUT, eof)
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "peer didn't close the connection"))?
+    }
+
+    /// Cast `id` to interface `J` like [`object::cast_to()`], but asserting in debug builds that
+    /// the object was created as `J` on this connection.
+    ///
+    /// Unlike [`object::cast_to()`] this also catches casts from the type-erased `object<()>`, like
+    /// the untyped object arguments of messages.
+    #[track_caller]
+    pub fn cast_to<I: Interface, J: Interface>(&self, id: object<I>) -> object<J> {
+        self.registry().debug_assert_interface::<J>(id.cast());
+        id.cast_to()
+    }
+
     fn registry(&self) -> MutexGuard<'_, Registry<Dir>> {
         self.registry.lock().unwrap()
     }
 
-    pub(crate) fn try_lock_io_buf(&self) -> Option<MutexGuard<'_, Io>> {
-        match self.drive_io.try_lock() {
-            Ok(guard) => Some(guard),
-            Err(TryLockError::WouldBlock) => None,
-            Err(poison @ TryLockError::Poisoned(_)) => panic!("{:?}", poison),
+    /// Queue the destructor `opcode` of `id` without waiting for the tx lock, see
+    /// [`Object::auto_destroy()`].
+    ///
+    /// It only reaches the socket with the next flush, see [`ConnectionHandle::flush_destructors()`].
+    pub(crate) fn queue_destructor(&self, id: object, opcode: u16, interface: &'static str) {
+        self.registry().pending_destructors.push_back((id, opcode, interface));
+        if let Some(mut tx) = self.try_lock_tx() {
+            self.write_pending_destructors(&mut tx);
+        }
+    }
+
+    /// Write as many queued destructors to the tx buffer as fit.
+    pub(crate) fn write_pending_destructors(&self, io: &mut TxIo) {
+        let pending = &mut self.registry().pending_destructors;
+        while let Some(&(id, opcode, interface)) = pending.front() {
+            let Some((_, buf)) = io.tx_buf(id, opcode, 0, 0) else { break };
+            unsafe { io.logger.log_msg(Direction::Tx, interface, id, opcode, buf.da) };
+            pending.pop_front();
+        }
+    }
+
+    pub(crate) fn lock_rx(&self) -> RxGuard<'_> {
+        RxGuard { guard: ManuallyDrop::new(self.rx.lock().unwrap()), unlocked: &self.rx_unlocked }
+    }
+
+    pub(crate) fn try_lock_rx(&self) -> Option<RxGuard<'_>> {
+        let guard = try_lock(&self.rx)?;
+        Some(RxGuard { guard: ManuallyDrop::new(guard), unlocked: &self.rx_unlocked })
+    }
+
+    /// Take the rx lock without blocking the thread, waking `cx` once it is released if it is
+    /// held elsewhere.
+    pub(crate) fn poll_lock_rx(&self, cx: &mut Context<'_>) -> Poll<RxGuard<'_>> {
+        if let Some(guard) = self.try_lock_rx() {
+            return Poll::Ready(guard);
+        }
+        self.rx_unlocked.register(cx.waker());
+        // It might have been released before the waker was registered.
+        match self.try_lock_rx() {
+            Some(guard) => Poll::Ready(guard),
+            None => Poll::Pending,
         }
     }
+
+    pub(crate) fn try_lock_tx(&self) -> Option<MutexGuard<'_, TxIo>> {
+        try_lock(&self.tx)
+    }
+}
+
+/// Guard of the rx lock, waking whoever waits for it in [`Connection::poll_lock_rx()`] once it
+/// gets dropped.
+pub(crate) struct RxGuard<'a> {
+    guard: ManuallyDrop<MutexGuard<'a, RxIo>>,
+    unlocked: &'a AtomicWaker,
+}
+
+impl Deref for RxGuard<'_> {
+    type Target = RxIo;
+
+    fn deref(&self) -> &RxIo {
+        &self.guard
+    }
+}
+
+impl DerefMut for RxGuard<'_> {
+    fn deref_mut(&mut self) -> &mut RxIo {
+        &mut self.guard
+    }
+}
+
+impl Drop for RxGuard<'_> {
+    fn drop(&mut self) {
+        // Safety: `guard` isn't used after this.
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        self.unlocked.wake();
+    }
+}
+
+fn try_lock<T>(mutex: &Mutex<T>) -> Option<MutexGuard<'_, T>> {
+    match mutex.try_lock() {
+        Ok(guard) => Some(guard),
+        Err(TryLockError::WouldBlock) => None,
+        Err(poison @ TryLockError::Poisoned(_)) => panic!("{:?}", poison),
+    }
 }
 
 impl<Dir> AsRawFd for Connection<Dir> {
@@ -70,6 +489,12 @@ impl<Dir> AsRawFd for Connection<Dir> {
     }
 }
 
+impl<Dir> AsFd for Connection<Dir> {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
 pub trait ClientHandle: ConnectionHandle<Dir = Client> {
     /// # Panic
     /// Does panic if `id` is `0`.
@@ -77,7 +502,12 @@ pub trait ClientHandle: ConnectionHandle<Dir = Client> {
     where
         I: Interface,
     {
-        Object { conn: self.clone(), id: object { id: NonZero::new(id).unwrap(), _marker: PhantomData } }
+        let id = object { id: NonZero::new(id).unwrap(), _marker: PhantomData };
+        let mut registry = self.conn().registry();
+        registry.record_interface(id);
+        let generation = registry.generation(id.cast());
+        drop(registry);
+        Object { conn: self.clone(), id, version: I::VERSION, auto_destroy: None, generation }
     }
 
     fn new_object<I>(&self) -> (new_id<I>, Object<Self, I>)
@@ -88,6 +518,9 @@ pub trait ClientHandle: ConnectionHandle<Dir = Client> {
         (obj.id.to_new_id(), obj)
     }
 
+    /// Create an object announced by interface name and version, at [`Interface::VERSION`].
+    ///
+    /// Use [`Self::new_object_dyn_versioned()`] if the server may support a lower version only.
     fn new_object_dyn<I>(&self) -> (new_id_dyn<'static>, Object<Self, I>)
     where
         I: Interface,
@@ -106,12 +539,504 @@ pub trait ClientHandle: ConnectionHandle<Dir = Client> {
             obj,
         )
     }
+
+    /// Like [`Self::new_object_dyn()`], but announcing the highest version supported by both the
+    /// bindings and the server, which advertised `server_version`.
+    ///
+    /// The negotiated version is recorded as [`Object::version()`].
+    /// Fails like [`Self::bind_checked()`] if the server is older than [`Interface::MIN_VERSION`].
+    fn new_object_dyn_versioned<I>(
+        &self,
+        server_version: u32,
+    ) -> Result<(new_id_dyn<'static>, Object<Self, I>), WaylandError>
+    where
+        I: Interface,
+    {
+        let version = negotiate_version::<I>(server_version)?;
+        let (mut id, mut obj) = self.new_object_dyn::<I>();
+        obj.version = version;
+        id.version = uint(version);
+        Ok((id, obj))
+    }
+
+    /// Bind the global `name` the server advertised on `registry` at `server_version`.
+    ///
+    /// The object is created at the highest version supported by both sides, see
+    /// [`Object::version()`].
+    /// Fails with [`WaylandError::UnsupportedVersion`] if the server is older than
+    /// [`Interface::MIN_VERSION`].
+    fn bind_checked<I>(
+        &self,
+        registry: &Object<Self, wl_registry::wl_registry>,
+        name: uint,
+        server_version: uint,
+    ) -> impl Future<Output = io::Result<Object<Self, I>>>
+    where
+        I: Interface,
+    {
+        async move {
+            let (id, obj) = self.new_object_dyn_versioned::<I>(server_version.0)?;
+            registry.send(&wl_registry::request::bind_dyn { name, id }).await?;
+            Ok(obj)
+        }
+    }
 }
 
 impl<Conn: ConnectionHandle<Dir = Client>> ClientHandle for Conn {}
 
+pub trait ServerHandle: ConnectionHandle<Dir = Server> {
+    /// Register the object the client created with `id`, e.g. as the `new_id` argument of a
+    /// request, so the requests sent to it get routed.
+    ///
+    /// Fails if `id` is outside of the range of client allocated ids, or if it is already in use.
+    fn register_client_object<I>(&self, id: u32) -> Result<Object<Self, I>, WaylandError>
+    where
+        I: Interface,
+    {
+        let id = object { id: NonZero::new(id).ok_or(WaylandError::InvalidObjectId { id })?, _marker: PhantomData };
+        let mut registry = self.conn().registry();
+        registry.register_client_object(id)?;
+        let generation = registry.generation(id.cast());
+        drop(registry);
+        Ok(Object { conn: self.clone(), id, version: I::VERSION, auto_destroy: None, generation })
+    }
+}
+
+impl<Conn: ConnectionHandle<Dir = Server>> ServerHandle for Conn {}
+
 impl<Dir> AsRef<Connection<Dir>> for &Connection<Dir> {
     fn as_ref(&self) -> Self {
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        connection::{BufConfig, ClientHandle, ConnStats, Connection, Direction, RegistryConfig, ServerHandle},
+        drive_io::{MAX_FDS, WAYLAND_MAX_MESSAGE_LEN},
+        error::WaylandError,
+        handle::{Client, Server},
+        protocols::wayland::{wl_callback, wl_data_offer, wl_data_source, wl_display, wl_shm, wl_shm_pool, wl_surface},
+        test_util,
+    };
+    use ecs_compositor_core::{Message, fd, int, string, uint};
+    use std::{
+        fs::File,
+        io,
+        io::{Read, Write},
+        os::{
+            fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd},
+            unix::net::UnixStream,
+        },
+        sync::{Arc, Mutex},
+    };
+    use tokio::io::Interest;
+
+    #[tokio::test]
+    async fn inherit_wayland_socket() {
+        let (_, pipe) = test_util::pipe();
+        let err = unsafe { super::inherit_socket(pipe.as_raw_fd()) }.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        // The pipe wasn't taken over, so it is still open.
+        assert!(unsafe { libc::fcntl(pipe.as_raw_fd(), libc::F_GETFD) } >= 0);
+        let err = unsafe { super::inherit_socket(-1) }.unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EBADF));
+
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let raw_fd = client.into_raw_fd();
+        let sock = unsafe { super::inherit_socket(raw_fd) }.unwrap();
+        assert_eq!(
+            unsafe { libc::fcntl(raw_fd, libc::F_GETFD) } & libc::FD_CLOEXEC,
+            libc::FD_CLOEXEC
+        );
+
+        let conn = &Connection::<Client>::from_stream(sock).unwrap();
+        assert_eq!(conn.as_raw_fd(), raw_fd);
+
+        let display = conn.new_object_with_id::<wl_display::wl_display>(1);
+        let callback;
+        display
+            .send_and_flush(&wl_display::request::sync { callback: crate::new_id!(conn, callback) })
+            .await
+            .unwrap();
+        let (hdr, content, _) = test_util::read_msg(&mut server);
+        assert_eq!(
+            (hdr.object_id.id().get(), hdr.opcode),
+            (1, wl_display::request::sync::OP)
+        );
+        assert_eq!(content, [callback.id().id.get()]);
+    }
+
+    #[tokio::test]
+    async fn unset_wayland_socket() {
+        let (client, _server) = UnixStream::pair().unwrap();
+        let raw_fd = client.into_raw_fd();
+        // No other test touches the environment.
+        unsafe { std::env::set_var("WAYLAND_SOCKET", raw_fd.to_string()) };
+
+        let conn = unsafe { Connection::<Client>::with_config_unset_socket(BufConfig::default()) }.unwrap();
+        assert_eq!(conn.as_raw_fd(), raw_fd);
+        assert_eq!(std::env::var_os("WAYLAND_SOCKET"), None);
+    }
+
+    #[tokio::test]
+    async fn as_fd() {
+        fn write_raw(sock: BorrowedFd<'_>, buf: &[u8]) -> io::Result<()> {
+            UnixStream::from(sock.try_clone_to_owned()?).write_all(buf)
+        }
+
+        let (conn, mut server) = test_util::pair();
+        assert_eq!(conn.as_fd().as_raw_fd(), conn.as_raw_fd());
+
+        write_raw(conn.as_fd(), b"ping").unwrap();
+        let mut buf = [0; 4];
+        server.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+
+    #[tokio::test]
+    async fn message_logger() {
+        let (conn, mut server) = test_util::pair();
+        let conn = &conn;
+        let surface = conn.new_object_with_id::<wl_surface::wl_surface>(2);
+        let callback = conn.new_object_with_id::<wl_callback::wl_callback>(3);
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+        conn.set_message_logger(Box::new({
+            let log = log.clone();
+            move |direction, interface, object, opcode, data| {
+                log.lock().unwrap().push((
+                    direction,
+                    interface,
+                    object.id().get(),
+                    opcode,
+                    data.to_vec(),
+                ));
+            }
+        }));
+
+        surface
+            .send(&wl_surface::request::offset { x: int(1), y: int(-2) })
+            .await
+            .unwrap();
+        test_util::write_msg(
+            &mut server,
+            3,
+            &wl_callback::event::done { callback_data: uint(42) },
+        );
+        callback.recv().await.unwrap().ignore_message();
+
+        let words = |words: &[u32]| words.iter().flat_map(|word| word.to_ne_bytes()).collect::<Vec<_>>();
+        assert_eq!(
+            *log.lock().unwrap(),
+            [
+                (
+                    Direction::Tx,
+                    "wl_surface",
+                    2,
+                    wl_surface::request::offset::OP,
+                    words(&[1, -2i32 as u32])
+                ),
+                (
+                    Direction::Rx,
+                    "wl_callback",
+                    3,
+                    wl_callback::event::done::OP,
+                    words(&[42])
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn small_buf_config() {
+        let too_small = BufConfig { data_capacity: 1024, ..BufConfig::default() };
+        let Err(err) = Connection::<Client>::from_stream_with_config(UnixStream::pair().unwrap().0, too_small) else {
+            panic!("accepted a buffer too small for a message")
+        };
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let config =
+            BufConfig { data_capacity: WAYLAND_MAX_MESSAGE_LEN, fd_capacity: MAX_FDS as usize, ..BufConfig::default() };
+        let conn = &Connection::<Client>::from_stream_with_config(client, config).unwrap();
+        let source = conn.new_object_with_id::<wl_data_source::wl_data_source>(2);
+        let offer = conn.new_object_with_id::<wl_data_offer::wl_data_offer>(3);
+
+        // header, string length and the padded string add up to the largest possible `datalen`
+        let mut long = vec![b'a'; 0xfffc - 8 - 4];
+        *long.last_mut().unwrap() = 0;
+
+        // the second message only fits after the buffer got rewound
+        for _ in 0..2 {
+            source
+                .send_and_flush(&wl_data_source::request::offer { mime_type: string::from_slice(&long) })
+                .await
+                .unwrap();
+            let (hdr, content, _) = test_util::read_msg(&mut server);
+            assert_eq!(hdr.datalen, 0xfffc);
+            assert_eq!(content[0] as usize, long.len());
+        }
+
+        // the partially received second message has to be moved to the front to fit
+        for mime_type in [&b"text/plain\0"[..], &long] {
+            test_util::write_msg(
+                &mut server,
+                3,
+                &wl_data_offer::event::offer { mime_type: string::from_slice(mime_type) },
+            );
+        }
+        for len in [11, long.len()] {
+            let msg = offer.recv().await.unwrap();
+            let wl_data_offer::event::offer { mime_type } = msg.decode_msg().ok().unwrap();
+            assert_eq!(mime_type.len.get() as usize, len);
+        }
+    }
+
+    #[tokio::test]
+    async fn current_interest() {
+        let (conn, mut server) = test_util::pair();
+        let conn = &conn;
+        assert_eq!(conn.current_interest(), Some(Interest::READABLE));
+        assert!(!conn.wants_flush());
+
+        // dropping queues the destructor without writing it
+        drop(conn.new_object_with_id::<wl_shm_pool::wl_shm_pool>(2).auto_destroy());
+        assert!(conn.current_interest().unwrap().is_writable());
+        assert!(conn.wants_flush());
+
+        conn.flush().await.unwrap();
+        assert_eq!(conn.current_interest(), Some(Interest::READABLE));
+        assert!(!conn.wants_flush());
+
+        let (hdr, _, _) = test_util::read_msg(&mut server);
+        assert_eq!(hdr.opcode, wl_shm_pool::request::destroy::OP);
+    }
+
+    #[tokio::test]
+    async fn stats() {
+        let (conn, mut server) = test_util::pair();
+        let conn = &conn;
+        let display = conn.new_object_with_id::<wl_display::wl_display>(1);
+        let wl_shm = conn.new_object_with_id::<wl_shm::wl_shm>(2);
+        assert_eq!(conn.stats(), ConnStats::default());
+
+        let file = File::from(unsafe { OwnedFd::from_raw_fd(libc::memfd_create(c"test".as_ptr(), 0)) });
+        let _pool;
+        wl_shm
+            .send(&wl_shm::request::create_pool {
+                id: crate::new_id!(conn, _pool),
+                fd: fd(file.as_raw_fd()),
+                size: int(12),
+            })
+            .await
+            .unwrap();
+        let callback;
+        display
+            .send_and_flush(&wl_display::request::sync { callback: crate::new_id!(conn, callback) })
+            .await
+            .unwrap();
+
+        test_util::read_msg(&mut server);
+        let (_, content, _) = test_util::read_msg(&mut server);
+        test_util::write_msg(
+            &mut server,
+            content[0],
+            &wl_callback::event::done { callback_data: uint(0) },
+        );
+        callback.recv().await.unwrap().ignore_message();
+
+        assert_eq!(
+            conn.stats(),
+            ConnStats { tx_bytes: 16 + 12, rx_bytes: 12, tx_msgs: 2, rx_msgs: 1, fds_sent: 1, fds_recv: 0 }
+        );
+    }
+
+    #[tokio::test]
+    async fn indexed_registry() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let config = BufConfig { registry: RegistryConfig::Indexed { capacity: 8 }, ..BufConfig::default() };
+        let conn = Arc::new(Connection::<Client>::from_stream_with_config(client, config).unwrap());
+        let driver = conn.spawn_driver();
+
+        // Both within and beyond the preallocated capacity, the driver routes by looking them up.
+        let callbacks = [3, 20].map(|id| conn.new_object_with_id::<wl_callback::wl_callback>(id));
+        for (id, data) in [(20, 43), (3, 42)] {
+            test_util::write_msg(
+                &mut server,
+                id,
+                &wl_callback::event::done { callback_data: uint(data) },
+            );
+        }
+
+        let recv = async |callback: &crate::connection::Object<Arc<Connection<Client>>, wl_callback::wl_callback>| {
+            let msg = callback.recv().await.unwrap();
+            msg.decode_msg::<wl_callback::event::done>().ok().unwrap().callback_data
+        };
+        let data = tokio::join!(recv(&callbacks[0]), recv(&callbacks[1]));
+        assert_eq!(data, (uint(42), uint(43)));
+
+        drop(server);
+        driver.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn register_client_object() {
+        let (sock, _client) = UnixStream::pair().unwrap();
+        let conn = &Connection::<Server>::from_stream(sock).unwrap();
+
+        let surface = conn.register_client_object::<wl_surface::wl_surface>(3).unwrap();
+        assert_eq!(surface.id().id.get(), 3);
+
+        let Err(err) = conn.register_client_object::<wl_shm::wl_shm>(3) else {
+            panic!("registered the same id twice");
+        };
+        assert_eq!(err, WaylandError::ObjectIdInUse { id: 3 });
+
+        for id in [0, 0xff00_0000] {
+            let Err(err) = conn.register_client_object::<wl_shm::wl_shm>(id) else {
+                panic!("registered id {id} outside of the client range");
+            };
+            assert_eq!(err, WaylandError::InvalidObjectId { id });
+        }
+    }
+
+    #[tokio::test]
+    async fn disconnect_delivers_buffered() {
+        let (conn, mut server) = test_util::pair();
+
+        let server = std::thread::spawn(move || {
+            let received: Vec<_> = (0..3)
+                .map(|_| {
+                    let (hdr, ..) = test_util::read_msg(&mut server);
+                    (hdr.object_id.id().get(), hdr.opcode)
+                })
+                .collect();
+            assert_eq!(
+                server.read(&mut [0; 1]).unwrap(),
+                0,
+                "expected the write half to be shut down"
+            );
+
+            // Events still sent by the server get discarded.
+            test_util::write_msg(
+                &mut server,
+                3,
+                &wl_callback::event::done { callback_data: uint(0) },
+            );
+            received
+        });
+
+        {
+            let conn = &conn;
+            let display = conn.new_object_with_id::<wl_display::wl_display>(1);
+            let surface = conn.new_object_with_id::<wl_surface::wl_surface>(2);
+
+            let _callback;
+            display
+                .send(&wl_display::request::sync { callback: crate::new_id!(conn, _callback) })
+                .await
+                .unwrap();
+            for (x, y) in [(1, 2), (3, 4)] {
+                surface
+                    .send(&wl_surface::request::offset { x: int(x), y: int(y) })
+                    .await
+                    .unwrap();
+            }
+        }
+        conn.disconnect().await.unwrap();
+
+        assert_eq!(
+            server.join().unwrap(),
+            [
+                (1, wl_display::request::sync::OP),
+                (2, wl_surface::request::offset::OP),
+                (2, wl_surface::request::offset::OP)
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn disconnect_times_out() {
+        let (conn, mut server) = test_util::pair();
+
+        // The server never closes its end.
+        let err = conn.disconnect().await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(server.read(&mut [0; 1]).unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn closed() {
+        let (conn, server) = test_util::pair();
+        let conn = Arc::new(conn);
+        let callback = conn.new_object_with_id::<wl_callback::wl_callback>(2);
+
+        let closed = tokio::spawn({
+            let conn = conn.clone();
+            async move { conn.closed().await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!conn.is_closed());
+        assert!(!closed.is_finished());
+
+        drop(server);
+        let err = callback.recv().await.unwrap_err();
+        assert_eq!(
+            err.downcast::<WaylandError>().unwrap(),
+            WaylandError::Closed
+        );
+
+        tokio::time::timeout(std::time::Duration::from_secs(1), closed)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(conn.is_closed());
+        // Resolves right away once the end was observed.
+        conn.closed().await;
+    }
+
+    #[tokio::test]
+    async fn concurrent_recv_send() {
+        const COMMITS: usize = 100;
+
+        let (conn, mut server) = test_util::pair();
+        let conn = Arc::new(conn);
+        let callback = conn.new_object_with_id::<wl_callback::wl_callback>(3);
+        let surface = conn.new_object_with_id::<wl_surface::wl_surface>(2);
+
+        test_util::write_msg(
+            &mut server,
+            3,
+            &wl_callback::event::done { callback_data: uint(7) },
+        );
+
+        let (sent_tx, sent_rx) = tokio::sync::oneshot::channel();
+        let msg = callback.recv().await.unwrap();
+
+        // Receiving keeps holding the message, and with it the rx lock, while the sender runs.
+        let sender = tokio::spawn(async move {
+            for _ in 0..COMMITS {
+                surface.send(&wl_surface::request::commit {}).await.unwrap();
+            }
+            surface.conn().flush().await.unwrap();
+            sent_tx.send(()).unwrap();
+        });
+        sent_rx.await.unwrap();
+
+        let wl_callback::event::done { callback_data } = msg.decode_msg().ok().unwrap();
+        assert_eq!(callback_data.0, 7);
+        drop(msg);
+        sender.await.unwrap();
+
+        for _ in 0..COMMITS {
+            let (hdr, content, _) = test_util::read_msg(&mut server);
+            assert_eq!(
+                (hdr.object_id.id().get(), hdr.opcode),
+                (2, wl_surface::request::commit::OP)
+            );
+            assert!(content.is_empty());
+        }
+    }
+}
@@ -1,6 +1,14 @@
-use crate::handle::{ConnectionHandle, InterfaceDir};
+use crate::{
+    error::WaylandError,
+    handle::{ConnectionHandle, InterfaceDir},
+};
 use ecs_compositor_core::{Interface, object};
-use std::fmt::Display;
+use std::{
+    fmt::Display,
+    hash::{Hash, Hasher},
+    io,
+    num::NonZeroU32,
+};
 
 #[derive(Debug)]
 pub struct Object<Conn, I>
@@ -10,6 +18,14 @@ where
 {
     pub(crate) conn: Conn,
     pub(crate) id: object<I>,
+    pub(crate) version: u32,
+    /// Token of the handle counted by the registry, see [`Object::auto_destroy()`].
+    pub(crate) auto_destroy: Option<NonZeroU32>,
+    /// [`Registry::generation()`] of the id when the object was created, see
+    /// [`Object::check_stale()`].
+    ///
+    /// [`Registry::generation()`]: crate::connection::Registry::generation
+    pub(crate) generation: u32,
 }
 
 impl<Conn, I> Object<Conn, I>
@@ -20,6 +36,83 @@ where
     pub fn id(&self) -> object<I> {
         self.id
     }
+
+    /// Version the object was created with, which may be lower than [`Interface::VERSION`] if it
+    /// was bound to an older global.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Fail with [`WaylandError::StaleObject`] if the id of the object was freed since it was
+    /// created, as the id may belong to another object by now.
+    pub(crate) fn check_stale(&self) -> io::Result<()> {
+        if self.registry().generation(self.id.cast()) != self.generation {
+            return Err(WaylandError::StaleObject { id: self.id.id.get() }.into());
+        }
+        Ok(())
+    }
+
+    /// Send the destructor request of the interface once the last handle to the object gets
+    /// dropped, unless it was sent explicitly before.
+    ///
+    /// Does nothing for interfaces without a destructor, or with one that takes arguments.
+    /// Clones of this handle count as handles to the object, while other handles to it only do
+    /// once `auto_destroy()` is called on them as well.
+    pub fn auto_destroy(mut self) -> Self {
+        if <Conn::Dir as InterfaceDir<I>>::auto_destroy_op().is_none() {
+            return self;
+        }
+        let mut registry = self.conn.conn().registry();
+        if self
+            .auto_destroy
+            .is_none_or(|token| !registry.is_auto_destroy(self.id.cast(), token))
+        {
+            self.auto_destroy = Some(registry.retain_auto_destroy(self.id.cast()));
+        }
+        drop(registry);
+        self
+    }
+
+    /// Reinterpret the object as interface `J`.
+    ///
+    /// Only succeeds if `J` is the same interface as `I` with at most the same version, which
+    /// still supports the version of the object, so `J` can safely be used to talk to the object.
+    /// Otherwise the object is returned unchanged.
+    pub fn downcast_checked<J>(self) -> Result<Object<Conn, J>, Self>
+    where
+        Conn: ConnectionHandle<Dir: InterfaceDir<J>>,
+        J: Interface,
+    {
+        if I::NAME == J::NAME && J::VERSION <= I::VERSION && self.version <= J::VERSION {
+            let mut this = self;
+            Ok(Object {
+                conn: this.conn.clone(),
+                id: this.id.cast_to(),
+                version: this.version,
+                // Handed over, so dropping `this` doesn't release it.
+                auto_destroy: this.auto_destroy.take(),
+                generation: this.generation,
+            })
+        } else {
+            Err(self)
+        }
+    }
+
+    /// Erase the interface, e.g. to keep objects of different interfaces in one collection.
+    pub fn erase(mut self) -> AnyObject<Conn> {
+        AnyObject {
+            conn: self.conn.clone(),
+            id: self.id.cast(),
+            interface: I::NAME,
+            version: self.version,
+            generation: self.generation,
+            // Handed over, so dropping `self` doesn't release it.
+            auto_destroy: self
+                .auto_destroy
+                .take()
+                .and_then(|token| Some((token, <Conn::Dir as InterfaceDir<I>>::auto_destroy_op()?))),
+        }
+    }
 }
 
 impl<Conn, I> Display for Object<Conn, I>
@@ -31,7 +124,7 @@ where
         f.write_fmt(format_args!(
             "{name}:v{version}#{id}",
             name = I::NAME,
-            version = I::VERSION,
+            version = self.version,
             id = self.id.id
         ))
     }
@@ -43,6 +136,404 @@ where
     I: Interface,
 {
     fn clone(&self) -> Self {
-        Self { conn: self.conn.clone(), id: self.id }
+        Self {
+            conn: self.conn.clone(),
+            id: self.id,
+            version: self.version,
+            auto_destroy: self
+                .auto_destroy
+                .filter(|&token| self.registry().retain_auto_destroy_with(self.id.cast(), token)),
+            generation: self.generation,
+        }
+    }
+}
+
+/// Objects are equal if they have the same id.
+///
+/// Ids are only unique within one connection, so this doesn't distinguish objects of different
+/// connections.
+impl<Conn, I> PartialEq for Object<Conn, I>
+where
+    Conn: ConnectionHandle<Dir: InterfaceDir<I>>,
+    I: Interface,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.id.id == other.id.id
+    }
+}
+
+impl<Conn, I> Eq for Object<Conn, I>
+where
+    Conn: ConnectionHandle<Dir: InterfaceDir<I>>,
+    I: Interface,
+{
+}
+
+impl<Conn, I> Hash for Object<Conn, I>
+where
+    Conn: ConnectionHandle<Dir: InterfaceDir<I>>,
+    I: Interface,
+{
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.id.hash(state);
+    }
+}
+
+impl<Conn, I> Drop for Object<Conn, I>
+where
+    Conn: ConnectionHandle<Dir: InterfaceDir<I>>,
+    I: Interface,
+{
+    fn drop(&mut self) {
+        if let Some(token) = self.auto_destroy
+            && self.registry().release_auto_destroy(self.id.cast(), token)
+            && let Some(opcode) = <Conn::Dir as InterfaceDir<I>>::auto_destroy_op()
+        {
+            self.conn().queue_destructor(self.id.cast(), opcode, I::NAME);
+            self.conn.flush_destructors();
+        }
+    }
+}
+
+/// [`Object`] with its interface erased, see [`Object::erase()`].
+///
+/// An [`Object::auto_destroy()`] object still sends its destructor once this gets dropped.
+pub struct AnyObject<Conn>
+where
+    Conn: ConnectionHandle,
+{
+    conn: Conn,
+    id: object,
+    interface: &'static str,
+    version: u32,
+    /// See [`Object::generation`].
+    generation: u32,
+    /// Token of the handle counted by the registry and the destructor opcode to send once it is
+    /// the last one, see [`Object::auto_destroy()`].
+    auto_destroy: Option<(NonZeroU32, u16)>,
+}
+
+impl<Conn> AnyObject<Conn>
+where
+    Conn: ConnectionHandle,
+{
+    pub fn id(&self) -> object {
+        self.id
+    }
+
+    /// [`Interface::NAME`] of the erased interface.
+    pub fn interface(&self) -> &'static str {
+        self.interface
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Turn this back into an [`Object`] of interface `I`.
+    ///
+    /// Only succeeds if `I` is the erased interface and supports the version of the object,
+    /// otherwise the object is returned unchanged.
+    pub fn downcast<I>(self) -> Result<Object<Conn, I>, Self>
+    where
+        Conn: ConnectionHandle<Dir: InterfaceDir<I>>,
+        I: Interface,
+    {
+        if I::NAME == self.interface && self.version <= I::VERSION {
+            let mut this = self;
+            Ok(Object {
+                conn: this.conn.clone(),
+                id: this.conn.conn().cast_to(this.id),
+                version: this.version,
+                // Handed over, so dropping `this` doesn't release it.
+                auto_destroy: this.auto_destroy.take().map(|(token, _)| token),
+                generation: this.generation,
+            })
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl<Conn, I> From<Object<Conn, I>> for AnyObject<Conn>
+where
+    Conn: ConnectionHandle<Dir: InterfaceDir<I>>,
+    I: Interface,
+{
+    fn from(obj: Object<Conn, I>) -> Self {
+        obj.erase()
+    }
+}
+
+impl<Conn> Display for AnyObject<Conn>
+where
+    Conn: ConnectionHandle,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "{name}:v{version}#{id}",
+            name = self.interface,
+            version = self.version,
+            id = self.id.id
+        ))
+    }
+}
+
+impl<Conn> Clone for AnyObject<Conn>
+where
+    Conn: ConnectionHandle,
+{
+    fn clone(&self) -> Self {
+        Self {
+            conn: self.conn.clone(),
+            id: self.id,
+            interface: self.interface,
+            version: self.version,
+            generation: self.generation,
+            auto_destroy: self
+                .auto_destroy
+                .filter(|&(token, _)| self.conn.conn().registry().retain_auto_destroy_with(self.id, token)),
+        }
+    }
+}
+
+impl<Conn> Drop for AnyObject<Conn>
+where
+    Conn: ConnectionHandle,
+{
+    fn drop(&mut self) {
+        let conn = self.conn.conn();
+        if let Some((token, opcode)) = self.auto_destroy
+            && conn.registry().release_auto_destroy(self.id, token)
+        {
+            conn.queue_destructor(self.id, opcode, self.interface);
+            self.conn.flush_destructors();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        connection::{AnyObject, ClientHandle, Connection},
+        handle::{Client, InterfaceDir},
+        protocols::wayland::{
+            wl_buffer, wl_compositor::wl_compositor, wl_output, wl_shm::wl_shm, wl_shm_pool, wl_surface,
+        },
+        test_util,
+    };
+    use ecs_compositor_core::{ArgKind, Interface, Message};
+    use std::{
+        collections::HashSet,
+        io::{ErrorKind, Read},
+        sync::Arc,
+        time::Duration,
+    };
+
+    #[tokio::test]
+    async fn hash_by_id() {
+        let (conn, _server) = test_util::pair();
+        let conn = &conn;
+
+        let pool = conn.new_object_with_id::<wl_shm_pool::wl_shm_pool>(3);
+        let other = conn.new_object_with_id::<wl_shm_pool::wl_shm_pool>(4);
+
+        let mut pools = HashSet::new();
+        assert!(pools.insert(pool.clone()));
+        assert!(!pools.insert(pool.clone()));
+        assert!(pools.contains(&pool));
+        assert!(!pools.contains(&other));
+        assert!(pool != other);
+
+        assert!(pools.remove(&pool));
+        assert!(pools.is_empty());
+    }
+
+    #[tokio::test]
+    async fn downcast_checked() {
+        let (conn, _server) = test_util::pair();
+        let conn = &conn;
+
+        let (new_id, compositor) = conn.new_object_dyn::<wl_compositor>();
+        assert_eq!(new_id.interface_name(), wl_compositor::NAME.as_bytes());
+        let id = compositor.id().id;
+
+        let Err(compositor) = compositor.downcast_checked::<wl_shm>() else {
+            panic!("`wl_compositor` downcast to `wl_shm`");
+        };
+        assert_eq!(compositor.id().id, id);
+
+        let compositor = compositor.downcast_checked::<wl_compositor>().ok().unwrap();
+        assert_eq!(compositor.id().id, id);
+    }
+
+    #[cfg(debug_assertions)]
+    #[tokio::test]
+    #[should_panic = "created as `wl_surface` to `wl_output`"]
+    async fn erased_cast_checked() {
+        let (conn, _server) = test_util::pair();
+        let conn = &conn;
+
+        let (_, surface) = conn.new_object::<wl_surface::wl_surface>();
+        let erased = surface.id().cast::<()>();
+        assert_eq!(
+            conn.cast_to::<(), wl_surface::wl_surface>(erased).id,
+            surface.id().id
+        );
+
+        conn.cast_to::<(), wl_output::wl_output>(erased);
+    }
+
+    #[tokio::test]
+    async fn any_object() {
+        let (conn, mut server) = test_util::pair();
+        let conn = &conn;
+
+        let objects: Vec<AnyObject<&Connection<Client>>> = vec![
+            conn.new_object_with_id::<wl_output::wl_output>(3).erase(),
+            conn.new_object_with_id::<wl_surface::wl_surface>(4).into(),
+        ];
+        assert_eq!(
+            objects
+                .iter()
+                .map(|obj| (obj.id().id.get(), obj.interface()))
+                .collect::<Vec<_>>(),
+            [(3, "wl_output"), (4, "wl_surface")]
+        );
+
+        let [output, surface] = objects.try_into().ok().unwrap();
+        let output = output.downcast::<wl_surface::wl_surface>().err().unwrap();
+        assert_eq!(
+            output.downcast::<wl_output::wl_output>().ok().unwrap().id().id.get(),
+            3
+        );
+        assert_eq!(
+            surface.downcast::<wl_surface::wl_surface>().ok().unwrap().id().id.get(),
+            4
+        );
+
+        // Bindings older than the object can't handle all its messages.
+        let mut newer = conn.new_object_with_id::<wl_output::wl_output>(6).erase();
+        newer.version = wl_output::wl_output::VERSION + 1;
+        let newer = newer.downcast::<wl_output::wl_output>().err().unwrap();
+        assert_eq!(newer.version(), wl_output::wl_output::VERSION + 1);
+
+        // Erased objects keep destroying themselves.
+        drop(conn.new_object_with_id::<wl_shm_pool::wl_shm_pool>(5).auto_destroy().erase());
+        conn.flush().await.unwrap();
+        let (hdr, ..) = test_util::read_msg(&mut server);
+        assert_eq!(
+            (hdr.object_id.id().get(), hdr.opcode),
+            (5, wl_shm_pool::request::destroy::OP)
+        );
+    }
+
+    #[tokio::test]
+    async fn auto_destroy() {
+        let (conn, mut server) = test_util::pair();
+        let conn = &conn;
+        assert_eq!(
+            wl_shm_pool::wl_shm_pool::DESTRUCTOR_OP,
+            Some(wl_shm_pool::request::destroy::OP)
+        );
+        assert_eq!(wl_compositor::DESTRUCTOR_OP, None);
+
+        let pool = conn.new_object_with_id::<wl_shm_pool::wl_shm_pool>(3).auto_destroy();
+        // Clones share the object, so only the last one destroys it.
+        let clone = pool.clone();
+        drop(pool);
+        assert!(!conn.wants_flush());
+        drop(clone);
+        assert!(conn.wants_flush());
+
+        // Explicitly destroyed objects aren't destroyed a second time, even once their id is
+        // reused.
+        let buffer = conn.new_object_with_id::<wl_buffer::wl_buffer>(4).auto_destroy();
+        buffer.send(&wl_buffer::request::destroy {}).await.unwrap();
+        let reused = conn.new_object_with_id::<wl_buffer::wl_buffer>(4).auto_destroy();
+        drop(buffer);
+        drop(conn.new_object_with_id::<wl_compositor>(5).auto_destroy());
+        conn.flush().await.unwrap();
+
+        let (hdr, content, _) = test_util::read_msg(&mut server);
+        assert_eq!(
+            (hdr.object_id.id().get(), hdr.opcode),
+            (3, wl_shm_pool::request::destroy::OP)
+        );
+        assert!(content.is_empty());
+        let (hdr, ..) = test_util::read_msg(&mut server);
+        assert_eq!(
+            (hdr.object_id.id().get(), hdr.opcode),
+            (4, wl_buffer::request::destroy::OP)
+        );
+
+        server.set_nonblocking(true).unwrap();
+        assert_eq!(
+            server.read(&mut [0; 4]).unwrap_err().kind(),
+            ErrorKind::WouldBlock
+        );
+
+        drop(reused);
+        conn.flush().await.unwrap();
+        let (hdr, ..) = test_util::read_msg(&mut server);
+        assert_eq!(
+            (hdr.object_id.id().get(), hdr.opcode),
+            (4, wl_buffer::request::destroy::OP)
+        );
+        assert_eq!(
+            server.read(&mut [0; 4]).unwrap_err().kind(),
+            ErrorKind::WouldBlock
+        );
+    }
+
+    #[test]
+    fn destructor_with_args_not_auto_destroyed() {
+        enum ReleaseWithSerial {}
+        impl Interface for ReleaseWithSerial {
+            const NAME: &str = "release_with_serial";
+            const VERSION: u32 = 1;
+            const DESTRUCTOR_OP: Option<u16> = Some(0);
+            const REQUEST_SIGNATURE: &[&[ArgKind]] = &[&[ArgKind::Uint]];
+
+            type Error = <wl_shm_pool::wl_shm_pool as Interface>::Error;
+            type Request = <wl_shm_pool::wl_shm_pool as Interface>::Request;
+            type Event = <wl_shm_pool::wl_shm_pool as Interface>::Event;
+        }
+
+        assert_eq!(
+            <Client as InterfaceDir<ReleaseWithSerial>>::destructor_op(),
+            Some(0)
+        );
+        assert_eq!(
+            <Client as InterfaceDir<ReleaseWithSerial>>::auto_destroy_op(),
+            None
+        );
+        assert_eq!(
+            <Client as InterfaceDir<wl_shm_pool::wl_shm_pool>>::auto_destroy_op(),
+            Some(wl_shm_pool::request::destroy::OP)
+        );
+    }
+
+    #[test]
+    fn auto_destroy_outside_runtime() {
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_io()
+            .build()
+            .unwrap();
+        let (conn, mut server) = rt.block_on(async { test_util::pair() });
+        let conn = Arc::new(conn);
+        conn.set_runtime_handle(rt.handle().clone());
+
+        let pool = conn.new_object_with_id::<wl_shm_pool::wl_shm_pool>(3).auto_destroy();
+        std::thread::spawn(move || drop(pool)).join().unwrap();
+
+        // Nobody flushes, the flush spawned by the drop gets the destructor out.
+        server.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let (hdr, ..) = test_util::read_msg(&mut server);
+        assert_eq!(
+            (hdr.object_id.id().get(), hdr.opcode),
+            (3, wl_shm_pool::request::destroy::OP)
+        );
     }
 }
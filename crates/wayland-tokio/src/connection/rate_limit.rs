@@ -0,0 +1,187 @@
+use crate::{connection::Connection, drive_io::IoStats, handle::Server};
+use std::{
+    num::NonZeroU32,
+    pin::Pin,
+    sync::atomic::Ordering::Relaxed,
+    task::{Context, Poll, ready},
+    time::Duration,
+};
+use tokio::time::{self, Instant, Sleep};
+use tracing::{debug, trace};
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+impl Connection<Server> {
+    /// Stop reading from the client once it sent more than `msgs_per_sec` messages per second,
+    /// until it is back under the limit, or remove the limit with [`None`].
+    ///
+    /// The limit is a token bucket holding up to a second worth of messages, so short bursts
+    /// still go through at full speed. Messages that were already read from the socket are still
+    /// handed out, only reading more is paused.
+    /// Connections are unthrottled by default.
+    pub fn set_rate_limit(&self, msgs_per_sec: Option<NonZeroU32>) {
+        debug!(?msgs_per_sec, "setting rate limit");
+        self.lock_rx().rate_limit = msgs_per_sec.map(|limit| RateLimit::new(limit, &self.stats));
+    }
+}
+
+/// Token bucket behind [`Connection::set_rate_limit()`].
+///
+/// Counts the received messages through [`IoStats::rx_msgs`], so it only has to be updated
+/// before reading.
+#[derive(Debug)]
+pub(crate) struct RateLimit {
+    msgs_per_sec: NonZeroU32,
+    /// Messages that can still be received, negative if a single read brought in more.
+    tokens: i64,
+    /// [`IoStats::rx_msgs`] at the last update.
+    rx_msgs: u64,
+    /// Point up to which refills were accounted for.
+    refilled: Instant,
+}
+
+impl RateLimit {
+    fn new(msgs_per_sec: NonZeroU32, stats: &IoStats) -> Self {
+        Self {
+            msgs_per_sec,
+            tokens: msgs_per_sec.get().into(),
+            rx_msgs: stats.rx_msgs.load(Relaxed),
+            refilled: Instant::now(),
+        }
+    }
+
+    /// Account for the messages received and the time passed since the last update, returning
+    /// when reading can continue if the bucket is empty.
+    pub(crate) fn throttled_until(&mut self, stats: &IoStats) -> Option<Instant> {
+        let per_sec = u128::from(self.msgs_per_sec.get());
+
+        let refill = (Instant::now() - self.refilled).as_nanos() * per_sec / NANOS_PER_SEC;
+        // Only move forward by the time the whole tokens took, so the fractions add up.
+        self.refilled += nanos(refill * NANOS_PER_SEC / per_sec);
+
+        let rx_msgs = stats.rx_msgs.load(Relaxed);
+        let received = rx_msgs - self.rx_msgs;
+        self.rx_msgs = rx_msgs;
+
+        let tokens = i128::from(self.tokens) + refill as i128 - i128::from(received);
+        self.tokens = tokens.min(per_sec as i128) as i64;
+        if self.tokens > 0 {
+            return None;
+        }
+
+        let missing = (1 - self.tokens) as u128;
+        Some(self.refilled + nanos((missing * NANOS_PER_SEC).div_ceil(per_sec)))
+    }
+
+    /// Sleep until `until` returned by [`RateLimit::throttled_until()`], reusing `sleep` across
+    /// polls and clearing it once the limit is lifted.
+    ///
+    /// The socket stays readable while the rate limit pauses reading, so waiting for readiness
+    /// instead would return right away.
+    pub(crate) fn poll_throttle(sleep: &mut Option<Pin<Box<Sleep>>>, until: Instant, cx: &mut Context<'_>) -> Poll<()> {
+        let throttle = sleep.get_or_insert_with(|| Box::pin(time::sleep_until(until)));
+        if throttle.deadline() != until {
+            throttle.as_mut().reset(until);
+        }
+        ready!(throttle.as_mut().poll(cx));
+        *sleep = None;
+        trace!("rate limit lifted");
+        Poll::Ready(())
+    }
+}
+
+fn nanos(nanos: u128) -> Duration {
+    Duration::from_nanos(nanos.try_into().unwrap_or(u64::MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        connection::{Connection, ServerHandle},
+        handle::Server,
+        protocols::wayland::wl_surface,
+        test_util,
+    };
+    use std::{
+        num::NonZeroU32,
+        os::unix::net::UnixStream,
+        sync::{Arc, mpsc},
+        thread,
+        time::Duration,
+    };
+    use tokio::time::{Instant, timeout};
+
+    #[tokio::test]
+    async fn rate_limit() {
+        let (sock, mut client) = UnixStream::pair().unwrap();
+        let conn = &Connection::<Server>::from_stream(sock).unwrap();
+        conn.set_rate_limit(NonZeroU32::new(50));
+        let surface = conn.register_client_object::<wl_surface::wl_surface>(3).unwrap();
+
+        // A burst beyond the limit is read at once, but leaves the bucket 10 messages short.
+        for _ in 0..60 {
+            test_util::write_msg(&mut client, 3, &wl_surface::request::commit {});
+        }
+        let start = Instant::now();
+        for _ in 0..60 {
+            surface.recv().await.unwrap();
+        }
+
+        test_util::write_msg(&mut client, 3, &wl_surface::request::commit {});
+        let rx_bytes = conn.stats().rx_bytes;
+        assert!(timeout(Duration::from_millis(50), surface.recv()).await.is_err());
+        assert_eq!(conn.stats().rx_bytes, rx_bytes, "read while throttled");
+
+        surface.recv().await.unwrap();
+        assert!(
+            start.elapsed() >= Duration::from_millis(200),
+            "{:?}",
+            start.elapsed()
+        );
+        assert_eq!(conn.stats().rx_bytes, rx_bytes + 8);
+    }
+
+    /// With the time paused, it only advances while the runtime is idle, so a driver spinning on
+    /// the still readable socket instead of sleeping would never see the bucket refill.
+    #[test]
+    fn rate_limit_driver() {
+        let (done_tx, done) = mpsc::channel();
+        thread::spawn(move || {
+            let rt = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .start_paused(true)
+                .build()
+                .unwrap();
+            rt.block_on(async {
+                let (sock, mut client) = UnixStream::pair().unwrap();
+                let conn = Arc::new(Connection::<Server>::from_stream(sock).unwrap());
+                conn.set_rate_limit(NonZeroU32::new(50));
+                let surface = conn.register_client_object::<wl_surface::wl_surface>(3).unwrap();
+                conn.spawn_driver();
+
+                for _ in 0..60 {
+                    test_util::write_msg(&mut client, 3, &wl_surface::request::commit {});
+                }
+                let start = Instant::now();
+                for _ in 0..60 {
+                    surface.recv().await.unwrap();
+                }
+
+                test_util::write_msg(&mut client, 3, &wl_surface::request::commit {});
+                let rx_bytes = conn.stats().rx_bytes;
+                surface.recv().await.unwrap();
+                done_tx.send((start.elapsed(), conn.stats().rx_bytes - rx_bytes)).unwrap();
+            });
+        });
+
+        let (elapsed, read) = done
+            .recv_timeout(Duration::from_secs(5))
+            .expect("the driver spun instead of waiting for the bucket to refill");
+        assert_eq!(
+            elapsed,
+            Duration::from_millis(220),
+            "the 11th token arrives after 220ms"
+        );
+        assert_eq!(read, 8);
+    }
+}
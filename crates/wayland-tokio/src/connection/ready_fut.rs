@@ -1,6 +1,7 @@
 use crate::{
-    connection::Connection,
-    drive_io::{Interest, Io},
+    connection::{Connection, RateLimit},
+    drive_io::{Interest, IoHalf},
+    error::WaylandError,
 };
 use std::{
     future::Future,
@@ -10,7 +11,10 @@ use std::{
     pin::Pin,
     task::{Context, Poll, ready},
 };
-use tokio::io::unix::{AsyncFd, AsyncFdReadyGuard};
+use tokio::{
+    io::unix::{AsyncFd, AsyncFdReadyGuard},
+    time::Sleep,
+};
 use tracing::{debug, error, instrument, trace};
 
 impl<Dir> Connection<Dir> {
@@ -23,6 +27,7 @@ impl<Dir> Connection<Dir> {
                 }
             },
             fut: None,
+            throttle: None,
             _marker: PhantomData,
         }
     }
@@ -32,13 +37,15 @@ impl<Dir> Connection<Dir> {
 pub struct AsyncIo<'a, F, Fut> {
     f: F,
     fut: Option<Fut>,
+    /// Sleeps until a rate limited direction can continue.
+    throttle: Option<Pin<Box<Sleep>>>,
     _marker: PhantomData<&'a AsyncFd<UnixStream>>,
 }
 
-#[allow(private_interfaces)]
+#[allow(private_interfaces, private_bounds)]
 #[must_use = "futures do nothing unless you `.await` or poll them"]
 pub trait DriveIo {
-    fn poll_with_io(self: Pin<&mut Self>, io: &mut Io, cx: &mut Context<'_>) -> Poll<io::Result<()>>;
+    fn poll_with_io(self: Pin<&mut Self>, io: &mut impl IoHalf, cx: &mut Context<'_>) -> Poll<io::Result<()>>;
 }
 
 impl<'a, F, Fut> DriveIo for AsyncIo<'a, F, Fut>
@@ -47,7 +54,7 @@ where
     Fut: Future<Output = io::Result<AsyncFdReadyGuard<'a, UnixStream>>>,
 {
     #[instrument(name = "poll_io", level = "trace", ret, skip_all)]
-    fn poll_with_io(self: Pin<&mut Self>, io: &mut Io, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    fn poll_with_io(self: Pin<&mut Self>, io: &mut impl IoHalf, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
         unsafe {
             let s = self.get_unchecked_mut();
             let f = &mut s.f;
@@ -55,23 +62,21 @@ where
 
             match fut.as_mut().as_pin_mut() {
                 None => {
+                    if let Some(until) = io.throttled_until() {
+                        ready!(RateLimit::poll_throttle(&mut s.throttle, until, cx));
+                        return Poll::Ready(Ok(()));
+                    }
+
                     let Some(interest) = io.query_interest() else {
-                        if !(io.interest & (Interest::RECV_CLOSED | Interest::SEND_CLOSED)).is_empty() {
+                        if !(io.interest() & (Interest::RECV_CLOSED | Interest::SEND_CLOSED)).is_empty() {
                             debug!(
-                                rx_data_len = io.rx.da.data.len(),
-                                rx_ctrl_len = io.rx.fd.data.len(),
-                                tx_data_len = io.tx.da.data.len(),
-                                tx_ctrl_len = io.tx.fd.data.len(),
-                                interest = %io.interest,
-                                "Interest is none and recv and/or send is closed. Broken Pipe"
+                                interest = %io.interest(),
+                                "Interest is none and recv or send is closed. Broken Pipe"
                             );
-                            return Poll::Ready(Err(io::Error::new(
-                                io::ErrorKind::BrokenPipe,
-                                "Connection was closed meanly",
-                            )));
+                            return Poll::Ready(Err(WaylandError::Closed.into()));
                         }
 
-                        error!(interest = %io.interest, "interest should probably **NEVER** be `None` and get polled when interest is not closed");
+                        error!(interest = %io.interest(), "interest should probably **NEVER** be `None` and get polled when interest is not closed");
                         return Poll::Ready(Ok(()));
                     };
 
@@ -0,0 +1,190 @@
+use crate::connection::registry::{RecvEntry, SERVER_ID_START};
+use ecs_compositor_core::object;
+use std::collections::BTreeMap;
+
+/// How a [`Connection`](crate::connection::Connection) looks up the receivers of incoming
+/// messages, see [`BufConfig::registry`](crate::connection::BufConfig::registry).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RegistryConfig {
+    /// Keep all receivers in a [`BTreeMap`], which works well for any id space.
+    #[default]
+    Sorted,
+    /// Index the receivers of client allocated ids by their id, with room for `capacity` of them
+    /// preallocated.
+    ///
+    /// Client ids are allocated sequentially and therefore stay compact, which makes this cheaper
+    /// for clients with many short lived objects.
+    /// Server allocated ids are still kept in a [`BTreeMap`], as they are sparse. So are client ids
+    /// beyond `capacity` that would leave more than half of the table empty, which keeps a peer
+    /// picking huge ids from growing the table.
+    Indexed { capacity: usize },
+}
+
+/// Receivers of incoming messages by object id, see [`RegistryConfig`].
+pub(crate) enum ReceiverMap {
+    Sorted(BTreeMap<object, RecvEntry>),
+    /// Ids below `client.len()` are indexed, all others are kept in `sparse`.
+    Indexed {
+        client: Vec<Option<RecvEntry>>,
+        occupied: usize,
+        sparse: BTreeMap<object, RecvEntry>,
+    },
+}
+
+impl ReceiverMap {
+    pub(crate) fn new(config: RegistryConfig) -> Self {
+        match config {
+            RegistryConfig::Sorted => Self::Sorted(BTreeMap::new()),
+            RegistryConfig::Indexed { capacity } => {
+                Self::Indexed { client: Vec::with_capacity(capacity), occupied: 0, sparse: BTreeMap::new() }
+            }
+        }
+    }
+
+    pub(crate) fn get(&self, id: &object) -> Option<&RecvEntry> {
+        match self {
+            Self::Sorted(map) => map.get(id),
+            Self::Indexed { client, sparse, .. } => match client.get(id.id.get() as usize) {
+                Some(entry) => entry.as_ref(),
+                None => sparse.get(id),
+            },
+        }
+    }
+
+    pub(crate) fn get_mut(&mut self, id: &object) -> Option<&mut RecvEntry> {
+        match self {
+            Self::Sorted(map) => map.get_mut(id),
+            Self::Indexed { client, sparse, .. } => match client.get_mut(id.id.get() as usize) {
+                Some(entry) => entry.as_mut(),
+                None => sparse.get_mut(id),
+            },
+        }
+    }
+
+    /// Insert `entry` for `id`, replacing the previous entry.
+    pub(crate) fn insert(&mut self, id: object, entry: RecvEntry) {
+        match self {
+            Self::Sorted(map) => {
+                map.insert(id, entry);
+            }
+            Self::Indexed { client, occupied, sparse } => {
+                let index = id.id.get() as usize;
+                let dense = index < client.capacity() || index < 2 * (*occupied + 1);
+                if client.len() <= index && id.id.get() < SERVER_ID_START && dense {
+                    let start = client.len();
+                    client.resize_with(index + 1, || None);
+                    // Move over the ids that are indexed now.
+                    while let Some(entry) = sparse.first_entry()
+                        && (entry.key().id.get() as usize) < client.len()
+                    {
+                        let (id, entry) = entry.remove_entry();
+                        debug_assert!(start <= id.id.get() as usize);
+                        client[id.id.get() as usize] = Some(entry);
+                        *occupied += 1;
+                    }
+                }
+
+                match client.get_mut(index) {
+                    Some(slot) => {
+                        if slot.replace(entry).is_none() {
+                            *occupied += 1;
+                        }
+                    }
+                    None => {
+                        sparse.insert(id, entry);
+                    }
+                }
+            }
+        }
+    }
+
+    pub(crate) fn remove(&mut self, id: &object) -> Option<RecvEntry> {
+        match self {
+            Self::Sorted(map) => map.remove(id),
+            Self::Indexed { client, occupied, sparse } => match client.get_mut(id.id.get() as usize) {
+                Some(slot) => {
+                    let entry = slot.take()?;
+                    *occupied -= 1;
+                    Some(entry)
+                }
+                None => sparse.remove(id),
+            },
+        }
+    }
+
+    /// All entries, ordered by id.
+    pub(crate) fn values(&self) -> impl Iterator<Item = &RecvEntry> {
+        let (client, server) = match self {
+            Self::Sorted(map) => (None, map),
+            Self::Indexed { client, sparse, .. } => (Some(client), sparse),
+        };
+        client.into_iter().flatten().flatten().chain(server.values())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::connection::{
+        receivers::{ReceiverMap, RegistryConfig},
+        registry::{RecvEntry, SERVER_ID_START},
+    };
+    use ecs_compositor_core::object;
+    use std::{marker::PhantomData, num::NonZero, task::Waker};
+
+    fn entry(interface: &'static str) -> RecvEntry {
+        RecvEntry { waker: Waker::noop().clone(), waiting: false, queued: false, fd_count: |_| Some(0), interface }
+    }
+
+    fn id(id: u32) -> object {
+        object { id: NonZero::new(id).unwrap(), _marker: PhantomData }
+    }
+
+    #[test]
+    fn indexed_falls_back_for_server_ids() {
+        let mut map = ReceiverMap::new(RegistryConfig::Indexed { capacity: 4 });
+        map.insert(id(SERVER_ID_START + 1), entry("server"));
+        map.insert(id(3), entry("client"));
+        assert!(map.get(&id(2)).is_none());
+        assert!(map.get(&id(7)).is_none());
+        assert_eq!(map.get(&id(3)).unwrap().interface, "client");
+
+        map.get_mut(&id(SERVER_ID_START + 1)).unwrap().interface = "moved";
+        let ReceiverMap::Indexed { client, sparse, .. } = &map else { unreachable!() };
+        assert_eq!(client.len(), 4);
+        assert_eq!(sparse.len(), 1);
+
+        let values: Vec<_> = map.values().map(|entry| entry.interface).collect();
+        assert_eq!(values, ["client", "moved"]);
+    }
+
+    #[test]
+    fn indexed_stays_dense() {
+        let mut map = ReceiverMap::new(RegistryConfig::Indexed { capacity: 4 });
+        let lens = |map: &ReceiverMap| {
+            let ReceiverMap::Indexed { client, sparse, .. } = map else { unreachable!() };
+            (client.len(), sparse.len())
+        };
+
+        // A huge id doesn't grow the table, but is still found.
+        map.insert(id(SERVER_ID_START - 1), entry("huge"));
+        map.insert(id(8), entry("far"));
+        assert_eq!(lens(&map), (0, 2));
+        assert_eq!(map.get(&id(SERVER_ID_START - 1)).unwrap().interface, "huge");
+
+        // Sequential ids grow it, taking over the ids from the sparse map they cover.
+        for raw in 1..=8 {
+            map.insert(id(raw), entry("seq"));
+        }
+        assert_eq!(lens(&map), (9, 1));
+        assert_eq!(map.get(&id(8)).unwrap().interface, "seq");
+
+        assert_eq!(map.remove(&id(8)).unwrap().interface, "seq");
+        assert!(map.remove(&id(8)).is_none());
+        assert_eq!(
+            map.remove(&id(SERVER_ID_START - 1)).unwrap().interface,
+            "huge"
+        );
+        assert_eq!(lens(&map), (9, 0));
+        assert_eq!(map.values().count(), 7);
+    }
+}
@@ -0,0 +1,91 @@
+use crate::{connection::Connection, handle::Client};
+use std::{future::Future, io, os::unix::net::UnixStream, sync::Arc};
+use tracing::debug;
+
+impl Connection<Client> {
+    /// Connect to the server again after it went away, e.g. because it restarted, and run `setup`
+    /// on the new connection to bind the globals and recreate the objects the client needs.
+    ///
+    /// Connects to `$XDG_RUNTIME_DIR/$WAYLAND_DISPLAY` like [`Self::new()`], with the buffer
+    /// capacities, lookup table and runtime handle of `self`. The new connection starts out with
+    /// an empty registry, so ids are allocated from the start again.
+    ///
+    /// Every [`Object`](crate::connection::Object) of `self` stays bound to the old connection and
+    /// is therefore invalidated: once that is closed, sending or receiving on them fails. They
+    /// have to be replaced by the ones `setup` creates.
+    pub async fn reconnect<F, Fut>(&self, setup: F) -> io::Result<Arc<Self>>
+    where
+        F: Fn(&Arc<Self>) -> Fut,
+        Fut: Future<Output = io::Result<()>>,
+    {
+        self.reconnect_with(Self::connect()?, setup).await
+    }
+
+    /// Like [`Self::reconnect()`], but with an already connected socket.
+    pub async fn reconnect_with<F, Fut>(&self, sock: UnixStream, setup: F) -> io::Result<Arc<Self>>
+    where
+        F: Fn(&Arc<Self>) -> Fut,
+        Fut: Future<Output = io::Result<()>>,
+    {
+        debug!(old_closed = self.is_closed(), "reconnecting");
+        let conn = Arc::new(Self::from_parts(sock, self.config)?);
+        conn.set_runtime_handle(self.runtime_handle());
+
+        setup(&conn).await?;
+        Ok(conn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        connection::{BufConfig, ClientHandle, Connection},
+        handle::Client,
+        protocols::wayland::{wl_callback, wl_display},
+        test_util,
+    };
+    use ecs_compositor_core::{Message, uint};
+    use std::{io, os::unix::net::UnixStream, sync::Arc};
+
+    #[tokio::test]
+    async fn reconnect() {
+        let config = BufConfig { data_capacity: 1 << 17, ..BufConfig::default() };
+        let (client, server) = UnixStream::pair().unwrap();
+        let conn = &Connection::<Client>::from_stream_with_config(client, config).unwrap();
+        let old_display = conn.new_object_with_id::<wl_display::wl_display>(1);
+
+        // The server goes away, taking the old objects with it.
+        drop(server);
+        assert!(old_display.roundtrip().await.is_err());
+
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let server = std::thread::spawn(move || {
+            let (hdr, content, _) = test_util::read_msg(&mut server);
+            assert_eq!(
+                (hdr.object_id.id().get(), hdr.opcode),
+                (1, wl_display::request::sync::OP)
+            );
+            test_util::write_msg(
+                &mut server,
+                content[0],
+                &wl_callback::event::done { callback_data: uint(0) },
+            );
+            server
+        });
+
+        let setup = |conn: &Arc<Connection<Client>>| {
+            let display = conn.new_object_with_id::<wl_display::wl_display>(1);
+            async move { display.roundtrip().await }
+        };
+        let new = conn.reconnect_with(client, setup).await.unwrap();
+        assert_eq!(new.config, config);
+        assert!(!new.is_closed());
+        server.join().unwrap();
+
+        // Old objects don't follow the reconnect.
+        let Err(err) = old_display.sync().await else {
+            panic!("sent on the old connection");
+        };
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe, "{err}");
+    }
+}
@@ -1,17 +1,22 @@
 use crate::{
-    connection::{DriveIo, Object},
-    drive_io::Io,
+    connection::{
+        Connection, Direction, DriveIo, Object, RxGuard,
+        demux::{QueuedMsg, close_untaken},
+    },
+    drive_io::{Interest, IoBuf, IoStats, RxIo},
+    error::WaylandError,
     handle::{ConnectionHandle, InterfaceDir},
+    protocols::wayland::wl_display,
 };
-use ecs_compositor_core::{Interface, Message, Opcode, Value, message_header};
+use ecs_compositor_core::{Interface, Message, Opcode, Value, message_header, object};
 use std::{
     fmt::{self, Debug, Display},
     future::Future,
     io,
     marker::PhantomData,
+    ops::Deref,
     os::fd::{AsRawFd, RawFd},
     pin::Pin,
-    sync::MutexGuard,
     task::{Context, Poll, ready},
 };
 use tracing::{debug, instrument, trace};
@@ -21,10 +26,165 @@ where
     Conn: ConnectionHandle<Dir: InterfaceDir<I>>,
     I: Interface,
 {
+    /// Receive the next message addressed to this object.
+    ///
+    /// Cancel safe, so it can be used in `tokio::select!`: a message is only taken out of the rx
+    /// buffer once the future resolves, and the waker of this object is replaced by every poll.
     pub fn recv(&self) -> Recv<'_, Conn, I, impl DriveIo> {
         debug!(object = %self.id());
-        Recv { obj: self, drive_io: self.conn().drive_io() }
+        Recv { obj: self, drive_io: self.conn().drive_io(), flushed: false }
     }
+
+    /// Receive messages until one arrives whose opcode matches `wanted`, ignoring all others.
+    pub async fn recv_filtered(
+        &self,
+        wanted: impl Fn(<Conn::Dir as InterfaceDir<I>>::Recv) -> bool,
+    ) -> io::Result<MsgBuf<'_, Conn::Dir, I>>
+    where
+        <Conn::Dir as InterfaceDir<I>>::Recv: Display,
+    {
+        loop {
+            let msg = self.recv().await?;
+            if wanted(msg.decode_opcode()) {
+                return Ok(msg);
+            }
+            trace!(msg = %MsgKind::<Conn, I>::new(msg.hdr.opcode), "ignoring unwanted message");
+            msg.ignore_message();
+        }
+    }
+
+    /// Receive every message buffered for this object, returning only the most recent one.
+    ///
+    /// Useful for objects where only the latest state matters, like storms of
+    /// `wl_surface.preferred_buffer_scale`.
+    /// Waits only if no message is buffered yet.
+    pub async fn recv_latest(&self) -> io::Result<OwnedMsg<Conn::Dir, I>>
+    where
+        <Conn::Dir as InterfaceDir<I>>::Recv: Display,
+    {
+        let mut latest = match self.try_recv()? {
+            Some(msg) => msg.into_owned(),
+            None => self.recv().await?.into_owned(),
+        };
+        while let Some(msg) = self.try_recv()? {
+            trace!(msg = %MsgKind::<Conn, I>::new(latest.hdr.opcode), "dropping superseded message");
+            // Dropping the superseded message closes its fds.
+            latest = msg.into_owned();
+        }
+        Ok(latest)
+    }
+
+    /// Receive the next message addressed to this object, decoded into the `Decoded` enum of its
+    /// interface, like [`wl_registry::event::Decoded`].
+    ///
+    /// The message borrows the rx buffer like [`Object::recv()`], so it has to be dropped before
+    /// the connection can advance.
+    ///
+    /// [`wl_registry::event::Decoded`]: crate::protocols::wayland::wl_registry::event::Decoded
+    pub async fn recv_decoded(&self) -> io::Result<DecodedMsg<'_, Conn::Dir, I>>
+    where
+        <Conn::Dir as InterfaceDir<I>>::Recv: Display,
+    {
+        let msg = self.recv().await?;
+        let (mut da, mut fd) = (msg.da, msg.fd);
+        // The content stays where it is while `msg` is moved, and lives as long as the returned
+        // `DecodedMsg` holds on to it.
+        let decoded = unsafe { <Conn::Dir as InterfaceDir<I>>::Recv::decode(msg.hdr.opcode, &mut da, &mut fd)? };
+        Ok(DecodedMsg { decoded, msg })
+    }
+
+    /// Free the id if `msg` is a `wl_display.delete_id` event, see [`Registry::free_id()`].
+    ///
+    /// [`Registry::free_id()`]: crate::connection::Registry::free_id
+    fn handle_delete_id(&self, msg: &MsgBuf<'_, Conn::Dir, I>) {
+        if <Conn::Dir as InterfaceDir<I>>::is_delete_id(msg.opcode())
+            && let Ok(wl_display::event::delete_id { id }) = msg.decode_msg()
+        {
+            self.registry().free_id(id.0);
+        }
+    }
+
+    /// Return a message for this object if one can be received without waiting.
+    ///
+    /// Unlike [`Object::recv`] this never registers a waker, so it returns [`None`] if the rx lock
+    /// is held elsewhere, the socket has no data or the next message is addressed to another
+    /// object.
+    pub fn try_recv(&self) -> io::Result<Option<MsgBuf<'_, Conn::Dir, I>>> {
+        self.check_stale()?;
+        let conn = self.conn();
+        let poll = self.registry().demux.poll_pop(self.id.cast());
+        if let Some(poll) = poll {
+            return match poll {
+                Poll::Ready(msg) => {
+                    self.registry().stop_waiting(self.id.cast());
+                    IoStats::add(&conn.stats.rx_msgs, 1);
+                    // A queued `delete_id` already freed its id while being routed.
+                    Ok(Some(MsgBuf::queued(msg?, Some(conn))))
+                }
+                Poll::Pending => Ok(None),
+            };
+        }
+
+        let Some(mut io) = conn.try_lock_rx() else {
+            trace!("rx lock is held elsewhere");
+            return Ok(None);
+        };
+
+        loop {
+            let Some(hdr) = next_hdr(&mut io)? else {
+                if try_recv_more(&mut io, conn.fd.as_raw_fd())? {
+                    continue;
+                }
+                return Ok(None);
+            };
+
+            if self.id.id() != hdr.object_id.id() {
+                let mut registry = self.registry();
+                if let Some(entry) = registry.receiver_map.get(&hdr.object_id) {
+                    entry.waker.wake_by_ref();
+                }
+                registry.wake_readiness(&io.stats);
+                return Ok(None);
+            }
+
+            let fd_count = <Conn::Dir as InterfaceDir<I>>::recv_fd_count(hdr.opcode);
+            let Some((_, buf)) = take_msg(&mut io, hdr, fd_count)? else {
+                if !try_recv_more(&mut io, conn.fd.as_raw_fd())? {
+                    return Ok(None);
+                }
+                continue;
+            };
+            IoStats::add(&io.stats.rx_msgs, 1);
+            unsafe { io.logger.log_msg(Direction::Rx, I::NAME, hdr.object_id, hdr.opcode, buf.da) };
+
+            trace!(id = %self.id(), opcode = hdr.opcode, hdr = ?hdr, "try_recv");
+            let mut registry = self.registry();
+            registry.stop_waiting(self.id.cast());
+            if !io.rx.is_empty() {
+                registry.wake_readiness(&io.stats);
+            }
+            drop(registry);
+            let msg = MsgBuf {
+                _buf: Backing::Io(RxMsg { _guard: io, fd: buf.fd }),
+                conn: Some(conn),
+                hdr,
+                da: buf.da,
+                fd: buf.fd,
+                dir: PhantomData,
+            };
+            self.handle_delete_id(&msg);
+            return Ok(Some(msg));
+        }
+    }
+}
+
+/// Read whatever the socket has available without waiting, returning whether anything arrived.
+fn try_recv_more(io: &mut RxIo, sock: RawFd) -> io::Result<bool> {
+    check_closed(io)?;
+    if !io.interest.contains(Interest::RECV) {
+        return Ok(false);
+    }
+    Ok(io.recv_nonblocking(sock)?.unwrap_or(false))
 }
 
 #[must_use = "futures do nothing unless you `.await` or poll them"]
@@ -36,6 +196,8 @@ where
 {
     obj: &'a Object<Conn, I>,
     drive_io: Fut,
+    /// Whether the tx buffer was flushed already, which happens once per receive.
+    flushed: bool,
 }
 
 impl<'a, Conn, I, Fut> Recv<'a, Conn, I, Fut>
@@ -44,7 +206,7 @@ where
     I: Interface,
     Fut: DriveIo,
 {
-    fn drive_io(self: &mut Pin<&mut Self>, io: &mut Io, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    fn drive_io(self: &mut Pin<&mut Self>, io: &mut RxIo, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
         match unsafe { self.as_mut().map_unchecked_mut(|s| &mut s.drive_io) }.poll_with_io(io, cx) {
             Poll::Ready(ready) => Poll::Ready(ready),
             Poll::Pending => Poll::Pending,
@@ -68,12 +230,29 @@ where
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         unsafe {
             let obj = self.obj;
-            let conn = self.obj.conn();
+            let conn = obj.conn();
+            obj.check_stale()?;
+
+            if !self.flushed {
+                self.as_mut().get_unchecked_mut().flushed = true;
+                if let Err(err) = conn.try_flush() {
+                    // Sending reports the error as well, while receiving can continue.
+                    debug!(%err, "flushing before receiving failed");
+                }
+            }
+
+            if let Some(poll) = obj.registry().poll_queued(obj.id, cx) {
+                let msg = ready!(poll)?;
+                IoStats::add(&conn.stats.rx_msgs, 1);
+                trace!(id = %obj.id(), opcode = msg.hdr.opcode, kind = %MsgKind::<Conn, I>::new(msg.hdr.opcode), "recv queued");
+                // A queued `delete_id` already freed its id while being routed.
+                return Poll::Ready(Ok(MsgBuf::queued(msg, Some(conn))));
+            }
 
-            let mut io = match conn.try_lock_io_buf() {
+            let mut io = match conn.try_lock_rx() {
                 Some(io) => io,
                 None => {
-                    trace!(return_ = ?Poll::<()>::Pending, "waiting on io lock");
+                    trace!(return_ = ?Poll::<()>::Pending, "waiting on rx lock");
 
                     obj.register_recv(cx);
                     return Poll::Pending;
@@ -81,121 +260,147 @@ where
             };
 
             let mut count = 0;
-            let (hdr, (_, buf)) = loop {
+            let (hdr, buf) = loop {
                 trace!(count, "loop");
                 count += 1;
 
-                match io.rx_hdr {
-                    None => {
-                        let Some((_, buf)) = io.rx_msg_buf(message_header::COMBINED_LEN) else {
-                            trace!("drive_io for header");
-                            ready!(self.drive_io(&mut io, cx))?;
-                            continue;
-                        };
-
-                        io.rx_hdr = Some(
-                            message_header::read(&mut buf.da.cast_const(), &mut buf.fd.cast_const())
-                                .ok()
-                                .expect("failed to read header"),
-                        );
-                        trace!(hdr = ?io.rx_hdr, "parsed header");
+                let Some(hdr) = next_hdr(&mut io)? else {
+                    check_closed(&io)?;
+                    trace!("drive_io for header");
+                    ready!(self.drive_io(&mut io, cx))?;
+                    continue;
+                };
+
+                if obj.id.id() == hdr.object_id.id() {
+                    let fd_count = <Conn::Dir as InterfaceDir<I>>::recv_fd_count(hdr.opcode);
+                    let Some((_, buf)) = take_msg(&mut io, hdr, fd_count)? else {
+                        check_closed(&io)?;
+                        trace!("drive_io for ourself");
+                        ready!(self.drive_io(&mut io, cx))?;
                         continue;
-                    }
-                    Some(hdr) => {
-                        if obj.id.id() == hdr.object_id.id() {
-                            let size = (
-                                hdr.content_len(),
-                                <Conn::Dir as InterfaceDir<I>>::Recv::from_u16(hdr.opcode)
-                                    .map_err(|opcode| {
-                                        format!(
-                                            "invalid opcode {opcode} for ({name}@{version}) with id {id}",
-                                            name = I::NAME,
-                                            version = I::VERSION,
-                                            id = hdr.object_id.id(),
-                                        )
-                                    })
-                                    .unwrap()
-                                    .fd_count(),
-                            );
-                            match io.rx_msg_buf(size) {
-                                Some(data) => {
-                                    io.rx_hdr = None;
-
-                                    break (hdr, data);
-                                }
-                                None => {
-                                    trace!("drive_io for ourself");
-                                    ready!(self.drive_io(&mut io, cx))?;
-                                    continue;
-                                }
-                            }
-                        } else if let mut registry = obj.registry()
-                            && let Some(entry) = { registry.receiver_map.get(&hdr.object_id) }
-                        {
-                            let size = (
-                                hdr.content_len(),
-                                (entry.fd_count)(hdr.opcode)
-                                    .ok_or_else(|| {
-                                        format!(
-                                            "invalid opcode {opcode} for {id}",
-                                            opcode = hdr.opcode,
-                                            id = hdr.object_id.id(),
-                                        )
-                                    })
-                                    .unwrap(),
-                            );
-                            match io.rx_msg_buf(size) {
-                                Some((cursor, _)) => {
-                                    tracing::warn!(
-                                        from = %obj.id(),
-                                        to = %hdr.object_id,
-                                        "dispatching to object"
-                                    );
-
-                                    io.rx.restore_cursor(cursor);
-                                    drop(io);
-
-                                    entry.waker.wake_by_ref();
-                                    registry.register_recv(obj.id, cx);
-
-                                    return Poll::Pending;
-                                }
-                                None => {
-                                    trace!(id = hdr.object_id.id().get(), "drive_io for other");
-                                    ready!(self.drive_io(&mut io, cx))?;
-                                    continue;
-                                }
-                            }
-                        } else {
-                            debug!(
-                                return = ?Poll::<()>::Pending,
-                                "`{obj}` received message addressed to unknown ID `{id}`, this *could* indicate a deadlock",
-                                obj = obj,
-                                id = hdr.object_id.id(),
-                            );
-
-                            obj.register_recv(cx);
-                            return Poll::Pending;
-                        }
-                    }
+                    };
+                    IoStats::add(&io.stats.rx_msgs, 1);
+                    io.logger.log_msg(Direction::Rx, I::NAME, hdr.object_id, hdr.opcode, buf.da);
+                    break (hdr, buf);
                 }
+
+                let mut registry = obj.registry();
+                let Some(entry) = registry.receiver_map.get(&hdr.object_id) else {
+                    debug!(
+                        return = ?Poll::<()>::Pending,
+                        "`{obj}` received message addressed to unknown ID `{id}`, this *could* indicate a deadlock",
+                        obj = obj,
+                        id = hdr.object_id.id(),
+                    );
+
+                    drop(registry);
+                    obj.register_recv(cx);
+                    return Poll::Pending;
+                };
+
+                let Some((cursor, _)) = take_msg(&mut io, hdr, (entry.fd_count)(hdr.opcode))? else {
+                    drop(registry);
+                    check_closed(&io)?;
+                    trace!(id = hdr.object_id.id().get(), "drive_io for other");
+                    ready!(self.drive_io(&mut io, cx))?;
+                    continue;
+                };
+                tracing::warn!(from = %obj.id(), to = %hdr.object_id, "dispatching to object");
+
+                // Leave the message for its addressee.
+                io.rx.restore_cursor(cursor);
+                io.rx_hdr = Some(hdr);
+                drop(io);
+
+                entry.waker.wake_by_ref();
+                registry.wake_readiness(&conn.stats);
+                registry.register_recv(obj.id, cx);
+
+                return Poll::Pending;
             };
 
-            obj.register_recv(cx);
-            obj.wake_recver(cx);
+            obj.finish_recv(cx);
 
             trace!(id = %obj.id(), opcode = hdr.opcode, kind = %MsgKind::<Conn, I>::new(hdr.opcode), hdr = ?hdr, "recv");
-            Poll::Ready(Ok(MsgBuf {
-                _io: io,
+            let msg = MsgBuf {
+                _buf: Backing::Io(RxMsg { _guard: io, fd: buf.fd }),
+                conn: Some(conn),
                 hdr,
                 da: buf.da,
                 fd: buf.fd,
                 dir: PhantomData,
-            }))
+            };
+            obj.handle_delete_id(&msg);
+            Poll::Ready(Ok(msg))
+        }
+    }
+}
+
+/// Header of the next message in the rx buffer, parsed into `io.rx_hdr` unless that happened
+/// already.
+///
+/// Returns [`None`] while the header wasn't received completely.
+pub(super) fn next_hdr(io: &mut RxIo) -> io::Result<Option<message_header>> {
+    if io.rx_hdr.is_none() {
+        let Some((_, buf)) = io.rx_msg_buf(message_header::COMBINED_LEN) else {
+            return Ok(None);
+        };
+        let hdr = unsafe { message_header::read(&mut buf.da.cast_const(), &mut buf.fd.cast_const()) }
+            .ok()
+            .filter(|hdr| hdr.datalen >= message_header::DATA_LEN)
+            .ok_or(WaylandError::InvalidHeader)?;
+        trace!(?hdr, "parsed header");
+        io.rx_hdr = Some(hdr);
+    }
+    Ok(io.rx_hdr)
+}
+
+/// Take the content of the message `hdr` parsed by [`next_hdr()`] out of the rx buffer, together
+/// with the cursor to put it back.
+///
+/// `fd_count` is the number of fds the opcode of the message declares, [`None`] for an invalid
+/// opcode. Returns [`None`] while the message wasn't received completely.
+pub(super) fn take_msg(
+    io: &mut RxIo,
+    hdr: message_header,
+    fd_count: Option<usize>,
+) -> io::Result<Option<(IoBuf, IoBuf)>> {
+    let fds = fd_count.ok_or(WaylandError::InvalidOpcode { object: hdr.object_id.id().get(), opcode: hdr.opcode })?;
+    let size = (hdr.content_len(), fds);
+    match io.rx_msg_buf(size) {
+        Some(msg) => {
+            io.rx_hdr = None;
+            Ok(Some(msg))
+        }
+        None => {
+            check_fds(io, hdr, size)?;
+            Ok(None)
         }
     }
 }
 
+/// Fail with [`WaylandError::Closed`] if the peer hung up, so no more data can arrive.
+fn check_closed(io: &RxIo) -> io::Result<()> {
+    if io.interest.contains(Interest::RECV_CLOSED) {
+        return Err(WaylandError::Closed.into());
+    }
+    Ok(())
+}
+
+/// Fail with [`WaylandError::MissingFds`] if the message `hdr` arrived without the fds it declares.
+fn check_fds(io: &RxIo, hdr: message_header, (da, fd): (u16, usize)) -> io::Result<()> {
+    match io.rx_missing_fds((da, fd)) {
+        Some(received) => Err(WaylandError::MissingFds {
+            object: hdr.object_id.id().get(),
+            opcode: hdr.opcode,
+            expected: fd,
+            received,
+        }
+        .into()),
+        None => Ok(()),
+    }
+}
+
 struct MsgKind<Conn, I>(u16, PhantomData<(Conn, I)>)
 where
     Conn: ConnectionHandle<Dir: InterfaceDir<I>>,
@@ -215,25 +420,52 @@ impl<Conn, I> Display for MsgKind<Conn, I>
 where
     Conn: ConnectionHandle<Dir: InterfaceDir<I>>,
     I: Interface,
-    <Conn::Dir as InterfaceDir<I>>::Recv: Display,
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let iface = I::NAME;
-        match <Conn::Dir as InterfaceDir<I>>::Recv::from_u16(self.0) {
-            Ok(msg) => write!(f, "{iface}.{msg}#{opcode}", opcode = self.0,),
-            Err(u16) => write!(f, "{iface}.<unknown>#{u16}"),
+        match <Conn::Dir as InterfaceDir<I>>::Recv::name_of(self.0) {
+            Some(msg) => write!(f, "{iface}.{msg}#{opcode}", opcode = self.0,),
+            None => write!(f, "{iface}.<unknown>#{opcode}", opcode = self.0),
         }
     }
 }
 
+/// Message that no longer borrows the connection, see [`MsgBuf::into_owned()`].
+pub type OwnedMsg<Dir, I> = MsgBuf<'static, Dir, I>;
+
 pub struct MsgBuf<'a, Dir: InterfaceDir<I>, I: Interface> {
-    _io: MutexGuard<'a, Io>,
+    _buf: Backing<'a>,
+    /// [`None`] once the message was turned into an [`OwnedMsg`].
+    conn: Option<&'a Connection<Dir>>,
     hdr: message_header,
     da: *const [u8],
     fd: *const [RawFd],
     dir: PhantomData<(Dir, I)>,
 }
 
+/// Keeps the content of a [`MsgBuf`] alive and closes the fds that weren't taken out of it once
+/// it is dropped.
+enum Backing<'a> {
+    /// The message is still in the rx buffer.
+    Io(RxMsg<'a>),
+    /// The message was routed by [`Connection::spawn_driver()`](crate::connection::Connection::spawn_driver).
+    Queued { _msg: QueuedMsg },
+}
+
+/// Message still in the rx buffer, whose fds it owns.
+///
+/// The rx buffer already moved past the message, so [`RxIo::discard_rx()`] doesn't see them.
+struct RxMsg<'a> {
+    _guard: RxGuard<'a>,
+    fd: *mut [RawFd],
+}
+
+impl Drop for RxMsg<'_> {
+    fn drop(&mut self) {
+        unsafe { close_untaken(self.fd) }
+    }
+}
+
 impl<'a, Dir: InterfaceDir<I>, I: Interface> Debug for MsgBuf<'a, Dir, I> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         Debug::fmt(&self.hdr, f)
@@ -245,10 +477,49 @@ where
     Dir: InterfaceDir<I>,
     I: Interface,
 {
+    fn queued(msg: QueuedMsg, conn: Option<&'a Connection<Dir>>) -> Self {
+        let (da, fd) = msg.data();
+        Self { hdr: msg.hdr, da, fd, _buf: Backing::Queued { _msg: msg }, conn, dir: PhantomData }
+    }
+
+    /// Connection the message was received on, e.g. to send a reply right from the handler of an
+    /// event.
+    ///
+    /// Sending doesn't need the rx lock, so this works even while the message still borrows the
+    /// rx buffer. Returns [`None`] for messages turned into an [`OwnedMsg`], as those no longer
+    /// borrow the connection.
+    pub fn conn(&self) -> Option<&'a Connection<Dir>> {
+        self.conn
+    }
+
     pub fn hdr(&self) -> message_header {
         self.hdr
     }
 
+    pub fn object_id(&self) -> object {
+        self.hdr.object_id
+    }
+
+    pub fn opcode(&self) -> u16 {
+        self.hdr.opcode
+    }
+
+    /// Content of the message as it was received, without the header.
+    ///
+    /// Together with [`Self::raw_fds()`] this allows forwarding a message without decoding it.
+    pub fn raw_data(&self) -> &[u8] {
+        unsafe { &*self.da }
+    }
+
+    /// Fds received with the message.
+    ///
+    /// They are owned by the message and closed once it is dropped, so they have to be duplicated
+    /// to outlive it. Fds already taken out by decoding the message show up as `-1`. Forwarding them with [`Object::send()`] is fine, as the tx buffer sends
+    /// duplicates.
+    pub fn raw_fds(&self) -> &[RawFd] {
+        unsafe { &*self.fd }
+    }
+
     pub fn decode_opcode(&self) -> Dir::Recv {
         Dir::Recv::from_u16(self.hdr.opcode)
             .map_err(|opcode| {
@@ -262,6 +533,14 @@ where
             .unwrap()
     }
 
+    /// Decode the message according to its opcode, see [`Object::recv_decoded()`].
+    pub fn decode(&self) -> ecs_compositor_core::primitives::Result<<Dir::Recv as Opcode>::Decoded<'_>> {
+        debug!(object = %self.hdr.object_id, opcode = self.hdr.opcode, "decode message");
+        let (mut da, mut fd) = (self.da, self.fd);
+
+        unsafe { Dir::Recv::decode(self.hdr.opcode, &mut da, &mut fd) }
+    }
+
     pub fn decode_msg<'data, M: Message<'data>>(&'data self) -> ecs_compositor_core::primitives::Result<M> {
         let obj = self.hdr.object_id;
         debug!(
@@ -275,5 +554,642 @@ where
         unsafe { M::read(&mut da, &mut fd) }
     }
 
+    /// Copy the message out of the rx buffer and release the rx lock, so the connection can
+    /// advance while the message is still in use.
+    ///
+    /// Messages routed by [`Connection::spawn_driver()`](crate::connection::Connection::spawn_driver)
+    /// are already owned and returned without copying.
+    pub fn into_owned(self) -> OwnedMsg<Dir, I> {
+        let msg = match self._buf {
+            Backing::Io(rx) => unsafe {
+                let msg = QueuedMsg::new(self.hdr, IoBuf { da: self.da.cast_mut(), fd: rx.fd });
+                // The copy owns the fds now.
+                (*rx.fd).fill(-1);
+                msg
+            },
+            Backing::Queued { _msg } => _msg,
+        };
+        MsgBuf::queued(msg, None)
+    }
+
+    /// Drop the message without handling it.
+    ///
+    /// Same as dropping it, which closes the fds that weren't taken out of it by decoding.
     pub fn ignore_message(self) {}
 }
+
+/// Message returned by [`Object::recv_decoded()`], whose content is known to decode.
+pub struct DecodedMsg<'a, Dir: InterfaceDir<I>, I: Interface> {
+    /// Borrows the content of `msg`, so it is declared first to be dropped before it.
+    decoded: <Dir::Recv as Opcode>::Decoded<'a>,
+    msg: MsgBuf<'a, Dir, I>,
+}
+
+impl<'a, Dir, I> DecodedMsg<'a, Dir, I>
+where
+    Dir: InterfaceDir<I>,
+    I: Interface,
+{
+    pub fn get(&self) -> &<Dir::Recv as Opcode>::Decoded<'_> {
+        Dir::Recv::shorten(&self.decoded)
+    }
+
+    pub fn into_msg(self) -> MsgBuf<'a, Dir, I> {
+        self.msg
+    }
+}
+
+impl<'a, Dir, I> Deref for DecodedMsg<'a, Dir, I>
+where
+    Dir: InterfaceDir<I>,
+    I: Interface,
+{
+    type Target = MsgBuf<'a, Dir, I>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.msg
+    }
+}
+
+impl<'a, Dir: InterfaceDir<I>, I: Interface> Debug for DecodedMsg<'a, Dir, I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&self.msg, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        connection::{ClientHandle, Object},
+        error::WaylandError,
+        protocols::wayland::{
+            wl_callback, wl_display,
+            wl_keyboard::{self, enumeration::keymap_format},
+            wl_registry,
+            wl_seat::{self, enumeration::capability},
+        },
+        test_util,
+    };
+    use ecs_compositor_core::{Message, fd, string, uint};
+    use std::{
+        fs::File,
+        io::{ErrorKind, Read, Write},
+        os::fd::AsRawFd,
+        sync::{Arc, Mutex},
+        task::{Context, Wake, Waker},
+        time::Duration,
+    };
+
+    /// Waker recording the id of its receiver into `log` when woken.
+    struct WakeLog {
+        id: u32,
+        log: Arc<Mutex<Vec<u32>>>,
+    }
+
+    impl Wake for WakeLog {
+        fn wake(self: Arc<Self>) {
+            self.log.lock().unwrap().push(self.id);
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_fds() {
+        let (conn, mut server) = test_util::pair();
+        let conn = &conn;
+        let keyboard = conn.new_object_with_id::<wl_keyboard::wl_keyboard>(2);
+
+        // `wl_keyboard.keymap { format, fd, size }` (opcode 0, 16 bytes), but without sending the fd.
+        let msg: [u32; 4] = [2, 16 << 16, 1, 4096];
+        server.write_all(&msg.map(u32::to_ne_bytes).concat()).unwrap();
+
+        let err = keyboard.recv().await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(
+            err.downcast::<WaylandError>().unwrap(),
+            WaylandError::MissingFds { object: 2, opcode: 0, expected: 1, received: 0 }
+        );
+    }
+
+    #[tokio::test]
+    async fn dropped_fds_closed() {
+        let (conn, mut server) = test_util::pair();
+        let conn = &conn;
+        let keyboard = conn.new_object_with_id::<wl_keyboard::wl_keyboard>(2);
+
+        let (read, write) = test_util::pipe();
+        let keymap =
+            wl_keyboard::event::keymap { format: keymap_format::xkb_v1, fd: fd(write.as_raw_fd()), size: uint(0) };
+        test_util::write_msg(&mut server, 2, &keymap);
+        test_util::write_msg(&mut server, 2, &keymap);
+        drop(write);
+
+        // Still in the rx buffer.
+        drop(keyboard.recv().await.unwrap());
+        // Copied out of the rx buffer.
+        let owned = keyboard.recv().await.unwrap().into_owned();
+        let Err(err) = (&read).read(&mut [0]) else { panic!("pipe was closed") };
+        assert_eq!(err.kind(), ErrorKind::WouldBlock);
+
+        drop(owned);
+        assert_eq!(
+            (&read).read(&mut [0]).unwrap(),
+            0,
+            "fd of the dropped message leaked"
+        );
+    }
+
+    #[tokio::test]
+    async fn decoded_fds_taken() {
+        let (conn, mut server) = test_util::pair();
+        let conn = &conn;
+        let keyboard = conn.new_object_with_id::<wl_keyboard::wl_keyboard>(2);
+
+        let (read, write) = test_util::pipe();
+        let keymap =
+            wl_keyboard::event::keymap { format: keymap_format::xkb_v1, fd: fd(write.as_raw_fd()), size: uint(0) };
+        test_util::write_msg(&mut server, 2, &keymap);
+        test_util::write_msg(&mut server, 2, &keymap);
+        drop(write);
+
+        let mut taken = Vec::new();
+        for owned in [false, true] {
+            let mut msg = keyboard.recv().await.unwrap();
+            if owned {
+                msg = msg.into_owned();
+            }
+            let wl_keyboard::event::keymap { fd: keymap_fd, .. } = msg.decode_msg().ok().unwrap();
+            taken.push(unsafe { keymap_fd.into_owned() });
+            // The fd was taken, so it can't be decoded a second time.
+            assert!(msg.decode_msg::<wl_keyboard::event::keymap>().is_err());
+            assert_eq!(msg.raw_fds(), [-1]);
+        }
+
+        // Dropping the messages didn't close the fds taken out of them.
+        for keymap_fd in taken {
+            File::from(keymap_fd.0).write_all(b"a").unwrap();
+        }
+        let mut buf = Vec::new();
+        (&read).read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, b"aa");
+    }
+
+    #[tokio::test]
+    async fn invalid_opcode() {
+        let (conn, mut server) = test_util::pair();
+        let conn = &conn;
+        let seat = conn.new_object_with_id::<wl_seat::wl_seat>(2);
+
+        // `wl_seat` has no event with opcode 99.
+        let msg: [u32; 2] = [2, 8 << 16 | 99];
+        server.write_all(&msg.map(u32::to_ne_bytes).concat()).unwrap();
+
+        let err = seat.recv().await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(
+            err.downcast::<WaylandError>().unwrap(),
+            WaylandError::InvalidOpcode { object: 2, opcode: 99 }
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_header() {
+        let (conn, mut server) = test_util::pair();
+        let conn = &conn;
+        let seat = conn.new_object_with_id::<wl_seat::wl_seat>(2);
+
+        // Addressed to the null object.
+        let msg: [u32; 2] = [0, 8 << 16];
+        server.write_all(&msg.map(u32::to_ne_bytes).concat()).unwrap();
+
+        let err = seat.try_recv().unwrap_err();
+        assert_eq!(
+            err.downcast::<WaylandError>().unwrap(),
+            WaylandError::InvalidHeader
+        );
+    }
+
+    #[tokio::test]
+    async fn closed() {
+        let (conn, mut server) = test_util::pair();
+        let conn = &conn;
+        let seat = conn.new_object_with_id::<wl_seat::wl_seat>(2);
+
+        test_util::write_msg(
+            &mut server,
+            2,
+            &wl_seat::event::capabilities { capabilities: capability::pointer | capability::keyboard },
+        );
+        drop(server);
+
+        // Messages sent before hanging up are still delivered.
+        seat.recv().await.unwrap().ignore_message();
+
+        let err = seat.recv().await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::BrokenPipe);
+        assert_eq!(
+            err.downcast::<WaylandError>().unwrap(),
+            WaylandError::Closed
+        );
+    }
+
+    #[tokio::test]
+    async fn raw_data() {
+        let (conn, mut server) = test_util::pair();
+        let conn = &conn;
+        let seat = conn.new_object_with_id::<wl_seat::wl_seat>(2);
+
+        test_util::write_msg(
+            &mut server,
+            2,
+            &wl_seat::event::name { name: string::from_slice(b"seat0\0") },
+        );
+
+        let msg = seat.recv().await.unwrap();
+        assert_eq!(msg.object_id().id().get(), 2);
+        assert_eq!(msg.opcode(), wl_seat::event::name::OP);
+        // length including the terminating null byte, followed by the string padded to 4 bytes
+        let mut expected = 6u32.to_ne_bytes().to_vec();
+        expected.extend(b"seat0\0\0\0");
+        assert_eq!(msg.raw_data(), expected);
+        assert!(msg.raw_fds().is_empty());
+    }
+
+    #[tokio::test]
+    async fn recv_filtered() {
+        let (conn, mut server) = test_util::MockServer::pair();
+        let conn = &conn;
+        let seat = conn.new_object_with_id::<wl_seat::wl_seat>(2);
+
+        server
+            .event(
+                2,
+                &wl_seat::event::capabilities { capabilities: capability::pointer },
+            )
+            .event(
+                2,
+                &wl_seat::event::name { name: string::from_slice(b"seat0\0") },
+            )
+            .event(
+                2,
+                &wl_seat::event::capabilities { capabilities: capability::pointer | capability::keyboard },
+            )
+            .play();
+
+        let msg = seat.recv_filtered(|op| op == wl_seat::event::Opcodes::name).await.unwrap();
+        let wl_seat::event::name { name } = msg.decode_msg().ok().unwrap();
+        assert_eq!(name.as_slice_without_trailing_null(), b"seat0");
+        drop(msg);
+
+        // Messages after the wanted one are left untouched.
+        let msg = seat.recv().await.unwrap();
+        let wl_seat::event::capabilities { capabilities } = msg.decode_msg().ok().unwrap();
+        assert_eq!(capabilities.bits(), 3);
+    }
+
+    #[tokio::test]
+    async fn recv_decoded() {
+        let (conn, mut server) = test_util::MockServer::pair();
+        let conn = &conn;
+        let registry = conn.new_object_with_id::<wl_registry::wl_registry>(2);
+
+        server
+            .event(
+                2,
+                &wl_registry::event::global {
+                    name: uint(1),
+                    interface: string::from_slice(b"wl_seat\0"),
+                    version: uint(7),
+                },
+            )
+            .event(2, &wl_registry::event::global_remove { name: uint(1) })
+            .play();
+
+        let msg = registry.recv_decoded().await.unwrap();
+        match msg.get() {
+            wl_registry::event::Decoded::global(wl_registry::event::global { name, interface, version }) => {
+                assert_eq!(name.0, 1);
+                assert_eq!(interface.as_slice_without_trailing_null(), b"wl_seat");
+                assert_eq!(version.0, 7);
+            }
+            _ => panic!("expected `wl_registry.global`"),
+        }
+        drop(msg);
+
+        let msg = registry.recv_decoded().await.unwrap();
+        match msg.get() {
+            wl_registry::event::Decoded::global_remove(wl_registry::event::global_remove { name }) => {
+                assert_eq!(name.0, 1);
+            }
+            _ => panic!("expected `wl_registry.global_remove`"),
+        }
+    }
+
+    #[tokio::test]
+    async fn reply_from_handler() {
+        let (conn, mut server) = test_util::MockServer::pair();
+        let conn = &conn;
+        let registry = conn.new_object_with_id::<wl_registry::wl_registry>(2);
+
+        server
+            .event(
+                2,
+                &wl_registry::event::global {
+                    name: uint(5),
+                    interface: string::from_slice(b"wl_seat\0"),
+                    version: uint(7),
+                },
+            )
+            .play();
+
+        // Only the message is passed on, the handler finds the connection through it.
+        let msg = registry.recv().await.unwrap();
+        let seat = async {
+            let wl_registry::event::global { name, version, .. } = msg.decode_msg()?;
+            let conn = msg.conn().expect("message borrows the connection");
+            let registry = conn.new_object_with_id::<wl_registry::wl_registry>(msg.object_id().id().get());
+            conn.bind_checked::<wl_seat::wl_seat>(&registry, name, version).await
+        }
+        .await
+        .unwrap();
+        assert!(msg.into_owned().conn().is_none());
+
+        conn.flush().await.unwrap();
+        let (content, _) = server.expect::<wl_registry::request::bind<wl_seat::wl_seat>>(2);
+        assert_eq!(content[0], 5);
+        assert_eq!(content[content.len() - 2..], [7, seat.id().id.get()]);
+    }
+
+    #[tokio::test]
+    async fn try_recv() {
+        let (conn, mut server) = test_util::pair();
+        let conn = &conn;
+        let seat = conn.new_object_with_id::<wl_seat::wl_seat>(2);
+
+        assert!(seat.try_recv().unwrap().is_none());
+
+        test_util::write_msg(
+            &mut server,
+            2,
+            &wl_seat::event::capabilities { capabilities: capability::pointer | capability::keyboard },
+        );
+
+        let msg = seat.try_recv().unwrap().expect("message should be buffered");
+        let wl_seat::event::capabilities { capabilities } = msg.decode_msg().ok().unwrap();
+        assert_eq!(capabilities.bits(), 3);
+        drop(msg);
+
+        assert!(seat.try_recv().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn into_owned() {
+        let (conn, mut server) = test_util::pair();
+        let conn = &conn;
+        let seat = conn.new_object_with_id::<wl_seat::wl_seat>(2);
+        let other = conn.new_object_with_id::<wl_seat::wl_seat>(3);
+
+        test_util::write_msg(
+            &mut server,
+            2,
+            &wl_seat::event::name { name: string::from_slice(b"seat0\0") },
+        );
+        test_util::write_msg(
+            &mut server,
+            3,
+            &wl_seat::event::capabilities { capabilities: capability::keyboard },
+        );
+
+        let owned = seat.recv().await.unwrap().into_owned();
+
+        // The rx lock is released, so other objects can receive meanwhile.
+        let msg = other.recv().await.unwrap();
+        let wl_seat::event::capabilities { capabilities } = msg.decode_msg().ok().unwrap();
+        assert_eq!(capabilities.bits(), 2);
+        drop(msg);
+
+        assert_eq!(owned.object_id().id().get(), 2);
+        let wl_seat::event::name { name } = owned.decode_msg().ok().unwrap();
+        assert_eq!(name.as_slice_without_trailing_null(), b"seat0");
+    }
+
+    #[tokio::test]
+    async fn recv_latest() {
+        let (conn, mut server) = test_util::pair();
+        let conn = &conn;
+        let seat = conn.new_object_with_id::<wl_seat::wl_seat>(2);
+
+        for capabilities in [capability::pointer, capability::keyboard, capability::touch] {
+            test_util::write_msg(
+                &mut server,
+                2,
+                &wl_seat::event::capabilities { capabilities },
+            );
+        }
+
+        let msg = seat.recv_latest().await.unwrap();
+        let wl_seat::event::capabilities { capabilities } = msg.decode_msg().ok().unwrap();
+        assert_eq!(capabilities.bits(), capability::touch.bits());
+
+        assert!(seat.try_recv().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn recv_latest_closes_superseded_fds() {
+        let (conn, mut server) = test_util::pair();
+        let conn = &conn;
+        let keyboard = conn.new_object_with_id::<wl_keyboard::wl_keyboard>(2);
+
+        let (read, write) = test_util::pipe();
+        let (latest_read, latest_write) = test_util::pipe();
+        for (fd_, size) in [(&write, 1), (&write, 2), (&latest_write, 3)] {
+            test_util::write_msg(
+                &mut server,
+                2,
+                &wl_keyboard::event::keymap {
+                    format: keymap_format::xkb_v1,
+                    fd: fd(fd_.as_raw_fd()),
+                    size: uint(size),
+                },
+            );
+        }
+        drop((write, latest_write));
+
+        let msg = keyboard.recv_latest().await.unwrap();
+        let wl_keyboard::event::keymap { size, .. } = msg.decode_msg().ok().unwrap();
+        assert_eq!(size.0, 3);
+
+        assert_eq!(
+            (&read).read(&mut [0]).unwrap(),
+            0,
+            "fds of superseded messages leaked"
+        );
+        let Err(err) = (&latest_read).read(&mut [0]) else { panic!("fd of the latest message was closed") };
+        assert_eq!(err.kind(), ErrorKind::WouldBlock);
+    }
+
+    #[tokio::test]
+    async fn busy_objects_dont_starve() {
+        const BUSY: u32 = 16;
+        const ROUNDS: u32 = 20;
+
+        let (conn, mut server) = test_util::pair();
+        let conn = &conn;
+        let busy: Vec<_> = (2..2 + BUSY)
+            .map(|id| conn.new_object_with_id::<wl_callback::wl_callback>(id))
+            .collect();
+        let quiet = conn.new_object_with_id::<wl_callback::wl_callback>(2 + BUSY);
+
+        let server = std::thread::spawn(move || {
+            for round in 0..ROUNDS {
+                if round == ROUNDS / 2 {
+                    test_util::write_msg(
+                        &mut server,
+                        2 + BUSY,
+                        &wl_callback::event::done { callback_data: uint(0) },
+                    );
+                }
+                for id in 2..2 + BUSY {
+                    test_util::write_msg(
+                        &mut server,
+                        id,
+                        &wl_callback::event::done { callback_data: uint(round) },
+                    );
+                }
+            }
+            server
+        });
+
+        let recv = async |callback: &Object<_, wl_callback::wl_callback>, count: u32| {
+            for expected in 0..count {
+                let msg = callback.recv().await.unwrap();
+                let wl_callback::event::done { callback_data } = msg.decode_msg().ok().unwrap();
+                assert_eq!(callback_data.0, expected);
+            }
+        };
+        let busy_recv = futures::future::join_all(busy.iter().map(|callback| recv(callback, ROUNDS)));
+        tokio::join!(busy_recv, recv(&quiet, 1));
+        let _server = server.join().unwrap();
+
+        // Reading is handed on round-robin, skipping the receiver handing it on and the ones that
+        // stopped waiting.
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let wakers: Vec<_> = (2..6)
+            .map(|id| Waker::from(Arc::new(WakeLog { id, log: log.clone() })))
+            .collect();
+        let mut registry = conn.registry();
+        // Nobody waits anymore, so this only clears the queue.
+        registry.wake_recver(&mut Context::from_waker(Waker::noop()));
+        for (callback, waker) in busy.iter().zip(&wakers) {
+            registry.register_recv(callback.id(), &mut Context::from_waker(waker));
+        }
+        for (finished, stopped) in [(2, None), (3, None), (4, Some(5)), (2, None)] {
+            if let Some(stopped) = stopped {
+                registry.stop_waiting(busy[stopped - 2].id().cast());
+            }
+            registry.wake_recver(&mut Context::from_waker(&wakers[finished - 2]));
+        }
+        assert_eq!(*log.lock().unwrap(), [3, 4, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn cancelled_recv() {
+        const COUNT: u32 = 50;
+
+        let (conn, mut server) = test_util::pair();
+        let conn = &conn;
+        let callback = conn.new_object_with_id::<wl_callback::wl_callback>(2);
+
+        let server = std::thread::spawn(move || {
+            for data in 0..COUNT {
+                test_util::write_msg(
+                    &mut server,
+                    2,
+                    &wl_callback::event::done { callback_data: uint(data) },
+                );
+                std::thread::sleep(Duration::from_micros(300));
+            }
+            server
+        });
+
+        // The timer keeps dropping `recv()` futures that are still waiting for data.
+        let (mut received, mut cancelled) = (Vec::new(), 0);
+        while received.len() < COUNT as usize {
+            tokio::select! {
+                msg = callback.recv() => {
+                    let wl_callback::event::done { callback_data } = msg.unwrap().decode_msg().ok().unwrap();
+                    received.push(callback_data.0);
+                }
+                _ = tokio::time::sleep(Duration::from_micros(100)) => cancelled += 1,
+            }
+        }
+        let _server = server.join().unwrap();
+
+        assert!(cancelled > 0, "no `recv()` got cancelled");
+        assert_eq!(received, (0..COUNT).collect::<Vec<_>>());
+        assert_eq!(conn.registry().receiver_map.values().count(), 1);
+        assert!(callback.try_recv().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn delete_id_frees_id() {
+        let (conn, mut server) = test_util::MockServer::pair();
+        let conn = &conn;
+        let display = conn.new_object_with_id::<wl_display::wl_display>(1);
+
+        let (registry, callback);
+        display
+            .send(&wl_display::request::get_registry { registry: new_id!(conn, registry) })
+            .await
+            .unwrap();
+        display
+            .send_and_flush(&wl_display::request::sync { callback: new_id!(conn, callback) })
+            .await
+            .unwrap();
+        let callback_id = callback.id().id.get();
+        server.expect::<wl_display::request::get_registry>(1);
+        server.expect::<wl_display::request::sync>(1);
+
+        server
+            .event(
+                registry.id().id.get(),
+                &wl_registry::event::global {
+                    name: uint(1),
+                    interface: string::from_slice(b"wl_seat\0"),
+                    version: uint(9),
+                },
+            )
+            .event(
+                callback_id,
+                &wl_callback::event::done { callback_data: uint(0) },
+            )
+            .event(1, &wl_display::event::delete_id { id: uint(callback_id) })
+            .play();
+
+        let msg = registry.recv().await.unwrap();
+        let wl_registry::event::global { interface, .. } = msg.decode_msg().ok().unwrap();
+        assert_eq!(interface.as_slice_without_trailing_null(), b"wl_seat");
+        drop(msg);
+        callback.recv().await.unwrap().ignore_message();
+        assert!(conn.registry().receiver_map.get(&callback.id().cast()).is_some());
+
+        let msg = display.recv().await.unwrap();
+        assert_eq!(msg.opcode(), wl_display::event::delete_id::OP);
+        drop(msg);
+        assert!(conn.registry().receiver_map.get(&callback.id().cast()).is_none());
+
+        // The freed id is handed out again, before any id that was never used.
+        let (_, reused) = conn.new_object::<wl_callback::wl_callback>();
+        assert_eq!(reused.id().id.get(), callback_id);
+        let (_, next) = conn.new_object::<wl_callback::wl_callback>();
+        assert_eq!(next.id().id.get(), callback_id + 1);
+
+        // The old handle doesn't get hold of the messages of the new object.
+        let err = callback.try_recv().err().unwrap();
+        assert_eq!(
+            err.downcast::<WaylandError>().unwrap(),
+            WaylandError::StaleObject { id: callback_id }
+        );
+        assert!(callback.recv().await.is_err());
+        assert!(reused.try_recv().unwrap().is_none());
+    }
+}
@@ -1,37 +1,86 @@
 use crate::{
-    connection::{Client, Connection, Object},
+    connection::{
+        Client, Connection, Object, Server,
+        demux::{Demux, QueuedMsg},
+        receivers::{ReceiverMap, RegistryConfig},
+    },
+    drive_io::IoStats,
+    error::WaylandError,
     handle::{ConnectionHandle, InterfaceDir},
 };
 use ecs_compositor_core::{Interface, object};
 use std::{
-    collections::{BTreeMap, VecDeque, btree_map},
+    collections::{BTreeMap, VecDeque},
+    io,
     marker::PhantomData,
     num::NonZeroU32,
-    sync::MutexGuard,
-    task::{Context, Waker},
+    sync::{MutexGuard, atomic::Ordering::Relaxed},
+    task::{Context, Poll, Waker},
 };
-use tracing::{instrument, trace};
+use tracing::{debug, instrument, trace};
+
+/// First id of the range the server allocates ids from, everything below is up to the client.
+pub(crate) const SERVER_ID_START: u32 = 0xff00_0000;
 
 pub(crate) struct Registry<Dir> {
     next_id: NonZeroU32,
-    pub(crate) receiver_map: BTreeMap<object, RecvEntry>,
+    /// Ids the server acknowledged the destruction of, see [`Self::free_id()`].
+    free_ids: Vec<NonZeroU32>,
+    pub(crate) receiver_map: ReceiverMap,
+    /// Receivers in the order [`Self::wake_recver()`] wakes them, round-robin.
+    ///
+    /// Receivers that stopped waiting are only dropped once they come up, see
+    /// [`RecvEntry::queued`].
+    recv_queue: VecDeque<object>,
     sender_queue: VecDeque<Waker>,
     sender_locked: Option<Waker>,
+    pub(crate) demux: Demux,
+    /// Token and handle count of every [`Object::auto_destroy()`] object, see
+    /// [`Self::retain_auto_destroy()`].
+    auto_destroy: BTreeMap<object, (NonZeroU32, usize)>,
+    next_auto_destroy: NonZeroU32,
+    /// Interface every object was created with, recorded in debug builds only, see
+    /// [`Connection::cast_to()`].
+    interfaces: BTreeMap<object, &'static str>,
+    /// How often each id was freed, to tell handles to the objects that had it before apart, see
+    /// [`Object::check_stale()`].
+    generations: BTreeMap<object, u32>,
+    /// Destructors of dropped objects that couldn't be written yet, see [`Object::auto_destroy()`].
+    pub(crate) pending_destructors: VecDeque<(object, u16, &'static str)>,
+    /// See [`Connection::readiness_waker()`].
+    pub(crate) readiness: Option<Waker>,
+    /// [`IoStats::rx_bytes`] when [`Self::readiness`] was last woken.
+    readiness_rx_bytes: u64,
     dir: PhantomData<Dir>,
 }
 
 pub(crate) struct RecvEntry {
     pub(crate) waker: Waker,
+    /// Whether a receiver is parked on [`Self::waker`], see [`Registry::wake_recver()`].
+    pub(crate) waiting: bool,
+    /// Whether the id is in [`Registry::recv_queue`].
+    pub(crate) queued: bool,
     pub(crate) fd_count: fn(u16) -> Option<usize>,
+    pub(crate) interface: &'static str,
 }
 
 impl<Dir> Registry<Dir> {
-    pub(crate) fn new() -> Self {
+    pub(crate) fn new(config: RegistryConfig) -> Self {
         Self {
-            receiver_map: BTreeMap::new(),
+            receiver_map: ReceiverMap::new(config),
+            recv_queue: VecDeque::new(),
             sender_queue: VecDeque::new(),
             next_id: NonZeroU32::new(2).unwrap(),
+            free_ids: Vec::new(),
             sender_locked: None,
+            demux: Demux::default(),
+            auto_destroy: BTreeMap::new(),
+            next_auto_destroy: NonZeroU32::MIN,
+            interfaces: BTreeMap::new(),
+            generations: BTreeMap::new(),
+            pending_destructors: VecDeque::new(),
+            readiness: None,
+            readiness_rx_bytes: 0,
             dir: PhantomData,
         }
     }
@@ -43,14 +92,48 @@ impl Registry<Client> {
         Conn: ConnectionHandle<Dir: InterfaceDir<I>>,
         I: Interface,
     {
-        Object {
-            conn,
-            id: {
-                let next_id = self.next_id;
-                self.next_id = self.next_id.saturating_add(1);
-                object { id: next_id, _marker: PhantomData }
+        let id = self.free_ids.pop().unwrap_or_else(|| {
+            let next_id = self.next_id;
+            self.next_id = self.next_id.saturating_add(1);
+            next_id
+        });
+        let id = object { id, _marker: PhantomData };
+        self.record_interface(id);
+        Object { conn, id, version: I::VERSION, auto_destroy: None, generation: self.generation(id.cast()) }
+    }
+}
+
+impl Registry<Server> {
+    /// Register an object the client created with `id`, so messages addressed to it get routed.
+    pub(crate) fn register_client_object<I>(&mut self, id: object<I>) -> Result<(), WaylandError>
+    where
+        I: Interface,
+    {
+        let raw = id.id.get();
+        if raw >= SERVER_ID_START {
+            return Err(WaylandError::InvalidObjectId { id: raw });
+        }
+
+        if self.receiver_map.get(&id.cast()).is_some() {
+            return Err(WaylandError::ObjectIdInUse { id: raw });
+        }
+
+        trace!(id = raw, "register client object");
+        self.record_interface(id);
+        self.receiver_map.insert(
+            id.cast(),
+            RecvEntry {
+                waker: Waker::noop().clone(),
+                waiting: false,
+                queued: false,
+                fd_count: <Server as InterfaceDir<I>>::recv_fd_count,
+                interface: I::NAME,
             },
+        );
+        if let Some(waker) = self.demux.waker.take() {
+            waker.wake();
         }
+        Ok(())
     }
 }
 
@@ -61,19 +144,58 @@ impl<Dir> Registry<Dir> {
         I: Interface,
         Dir: InterfaceDir<I>,
     {
-        match self.receiver_map.entry(obj.cast::<()>()) {
-            btree_map::Entry::Vacant(vacant_entry) => {
+        match self.receiver_map.get_mut(&obj.cast()) {
+            None => {
                 trace!(id = obj.id, "register new recv");
-                vacant_entry
-                    .insert(RecvEntry { waker: cx.waker().clone(), fd_count: <Dir as InterfaceDir<I>>::recv_fd_count });
+                self.receiver_map.insert(
+                    obj.cast(),
+                    RecvEntry {
+                        waker: cx.waker().clone(),
+                        waiting: true,
+                        queued: true,
+                        fd_count: <Dir as InterfaceDir<I>>::recv_fd_count,
+                        interface: I::NAME,
+                    },
+                );
+                self.recv_queue.push_back(obj.cast());
+                if let Some(waker) = self.demux.waker.take() {
+                    waker.wake();
+                }
             }
-            btree_map::Entry::Occupied(occupied_entry) => {
+            Some(entry) => {
                 trace!(id = obj.id, "reregister old recv");
-                occupied_entry.into_mut().waker.clone_from(cx.waker());
+                entry.waker.clone_from(cx.waker());
+                entry.waiting = true;
+                if !entry.queued {
+                    entry.queued = true;
+                    self.recv_queue.push_back(obj.cast());
+                }
             }
         }
     }
 
+    /// `id` got its message, so [`Self::wake_recver()`] skips it until it waits again.
+    pub(crate) fn stop_waiting(&mut self, id: object) {
+        if let Some(entry) = self.receiver_map.get_mut(&id) {
+            entry.waiting = false;
+        }
+    }
+
+    /// Pop the next message for `obj` if the connection is driven by
+    /// [`Connection::spawn_driver()`], registering `cx` if there is none yet.
+    pub(crate) fn poll_queued<I>(&mut self, obj: object<I>, cx: &mut Context<'_>) -> Option<Poll<io::Result<QueuedMsg>>>
+    where
+        I: Interface,
+        Dir: InterfaceDir<I>,
+    {
+        let poll = self.demux.poll_pop(obj.cast())?;
+        match poll {
+            Poll::Pending => self.register_recv(obj, cx),
+            Poll::Ready(_) => self.stop_waiting(obj.cast()),
+        }
+        Some(poll)
+    }
+
     #[instrument(level = "trace", skip_all)]
     pub(crate) fn register_send(&mut self, cx: &mut Context<'_>) {
         self.sender_queue.push_back(cx.waker().clone());
@@ -86,6 +208,134 @@ impl<Dir> Registry<Dir> {
         }
     }
 
+    /// Remove one registration of `waker` made by [`Self::register_send()`] or
+    /// [`Self::register_send_locked()`], returning whether it was still registered.
+    pub(crate) fn unpark_sender(&mut self, waker: &Waker) -> bool {
+        if self.sender_locked.as_ref().is_some_and(|locked| locked.will_wake(waker)) {
+            self.sender_locked = None;
+            return true;
+        }
+        match self.sender_queue.iter().position(|parked| parked.will_wake(waker)) {
+            Some(index) => self.sender_queue.remove(index).is_some(),
+            None => false,
+        }
+    }
+
+    /// Forget the receiver of `id` once the server acknowledged its destruction with
+    /// `wl_display.delete_id`, so [`Registry::new_object()`] can hand the id out again.
+    pub(crate) fn free_id(&mut self, id: u32) {
+        let Some(id) = NonZeroU32::new(id).filter(|id| id.get() < SERVER_ID_START) else {
+            debug!(id, "ignoring delete_id for a server id");
+            return;
+        };
+        trace!(id, "free id");
+        self.receiver_map.remove(&object { id, _marker: PhantomData });
+        self.forget_auto_destroy(object { id, _marker: PhantomData });
+        self.interfaces.remove(&object { id, _marker: PhantomData });
+        if id < self.next_id && !self.free_ids.contains(&id) {
+            *self.generations.entry(object { id, _marker: PhantomData }).or_default() += 1;
+            self.free_ids.push(id);
+        }
+    }
+
+    /// How often `id` was freed so far, see [`Object::check_stale()`].
+    pub(crate) fn generation(&self, id: object) -> u32 {
+        self.generations.get(&id).copied().unwrap_or(0)
+    }
+
+    /// Record that `id` was created as an object of `I`, in debug builds only.
+    pub(crate) fn record_interface<I: Interface>(&mut self, id: object<I>) {
+        if cfg!(debug_assertions) {
+            self.interfaces.insert(id.cast(), I::NAME);
+        }
+    }
+
+    /// Assert in debug builds that `id` was created as an object of `J`, if it was recorded.
+    #[track_caller]
+    pub(crate) fn debug_assert_interface<J: Interface>(&self, id: object) {
+        if let Some(&interface) = self.interfaces.get(&id) {
+            debug_assert!(
+                interface == J::NAME,
+                "cast of object {id} created as `{interface}` to `{to}`",
+                id = id.id,
+                to = J::NAME,
+            );
+        }
+    }
+
+    /// Count another handle of the [`Object::auto_destroy()`] object `id`, returning the token the
+    /// handle has to hold.
+    ///
+    /// The token tells handles of the object apart from stale ones, whose object was destroyed
+    /// and whose id was handed out again.
+    pub(crate) fn retain_auto_destroy(&mut self, id: object) -> NonZeroU32 {
+        let next = &mut self.next_auto_destroy;
+        let (token, count) = self.auto_destroy.entry(id).or_insert_with(|| {
+            let token = *next;
+            *next = next.checked_add(1).unwrap_or(NonZeroU32::MIN);
+            (token, 0)
+        });
+        *count += 1;
+        *token
+    }
+
+    /// Whether handles holding `token` are counted for `id`.
+    pub(crate) fn is_auto_destroy(&self, id: object, token: NonZeroU32) -> bool {
+        self.auto_destroy.get(&id).is_some_and(|&(current, _)| current == token)
+    }
+
+    /// Count another handle holding `token`, returning whether the object is still to be
+    /// destroyed.
+    pub(crate) fn retain_auto_destroy_with(&mut self, id: object, token: NonZeroU32) -> bool {
+        match self.auto_destroy.get_mut(&id) {
+            Some((current, count)) if *current == token => {
+                *count += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Release a handle holding `token`, returning whether it was the last one, so the destructor
+    /// has to be sent.
+    pub(crate) fn release_auto_destroy(&mut self, id: object, token: NonZeroU32) -> bool {
+        match self.auto_destroy.get_mut(&id) {
+            Some((current, count)) if *current == token => {
+                *count -= 1;
+                if *count == 0 {
+                    self.auto_destroy.remove(&id);
+                    return true;
+                }
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /// Stop destroying `id` on drop, as it is destroyed already.
+    pub(crate) fn forget_auto_destroy(&mut self, id: object) {
+        self.auto_destroy.remove(&id);
+    }
+
+    /// Wake [`Self::readiness`] for messages left in the rx buffer or queued for their receivers,
+    /// once per read from the socket, so event loops calling
+    /// [`Object::try_recv()`](crate::connection::Object::try_recv) only for some of their objects
+    /// don't keep getting woken for the same messages.
+    pub(crate) fn wake_readiness(&mut self, stats: &IoStats) {
+        let rx_bytes = stats.rx_bytes.load(Relaxed);
+        if rx_bytes == self.readiness_rx_bytes {
+            return;
+        }
+        self.readiness_rx_bytes = rx_bytes;
+        if let Some(waker) = &self.readiness {
+            waker.wake_by_ref();
+        }
+    }
+
+    pub(crate) fn parked_senders(&self) -> usize {
+        self.sender_queue.len()
+    }
+
     pub(crate) fn wake_sender(&mut self) -> bool {
         if let Some(waker) = self.sender_locked.take() {
             waker.wake();
@@ -95,15 +345,24 @@ impl<Dir> Registry<Dir> {
         }
     }
 
-    fn wake_recver(&mut self, cx: &mut Context<'_>) {
+    pub(crate) fn wake_recver(&mut self, cx: &mut Context<'_>) {
         if let Some(waker) = self.sender_locked.take() {
             waker.wake();
         }
 
-        if let Some(waker) = self.receiver_map.first_entry() {
-            let waker = &waker.get().waker;
-            if !waker.will_wake(cx.waker()) {
-                waker.wake_by_ref();
+        // Always waking the same receiver could starve the others while it keeps getting messages,
+        // so go round-robin over the ones still waiting.
+        for _ in 0..self.recv_queue.len() {
+            let Some(id) = self.recv_queue.pop_front() else { break };
+            let Some(entry) = self.receiver_map.get_mut(&id) else { continue };
+            if !entry.waiting {
+                entry.queued = false;
+                continue;
+            }
+            self.recv_queue.push_back(id);
+            if !entry.waker.will_wake(cx.waker()) {
+                entry.waker.wake_by_ref();
+                break;
             }
         }
     }
@@ -138,6 +397,15 @@ where
         self.registry().wake_recver(cx)
     }
 
+    /// This object got its message, so stop waking it and hand reading on to the next waiting
+    /// receiver.
+    pub(crate) fn finish_recv(&self, cx: &mut Context<'_>) {
+        let mut registry = self.registry();
+        registry.register_recv(self.id, cx);
+        registry.stop_waiting(self.id.cast());
+        registry.wake_recver(cx);
+    }
+
     pub(crate) fn wake_sender(&self) -> bool {
         self.registry().wake_sender()
     }
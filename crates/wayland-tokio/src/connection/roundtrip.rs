@@ -0,0 +1,265 @@
+use crate::{
+    connection::{Callback, ClientHandle, Object, recv::MsgBuf},
+    handle::InterfaceDir,
+    protocols::wayland::{wl_callback::wl_callback, wl_display, wl_registry},
+};
+use ecs_compositor_core::{Interface, uint};
+use std::{fmt::Display, io};
+use tracing::trace;
+
+impl<Conn> Object<Conn, wl_display::wl_display>
+where
+    Conn: ClientHandle,
+{
+    /// Send a `wl_display.sync` request and return the `wl_callback` the server will answer on.
+    pub async fn sync(&self) -> io::Result<Object<Conn, wl_callback>> {
+        let callback;
+        self.send(&wl_display::request::sync { callback: new_id!(self.conn, callback) })
+            .await?;
+        Ok(callback)
+    }
+
+    /// Wait until the server processed all requests sent before this call.
+    pub async fn roundtrip(&self) -> io::Result<()> {
+        Callback::new(self.sync().await?).done().await?;
+        Ok(())
+    }
+
+    /// Wait until the server processed the requests sent on `after` so far, e.g. the commits of a
+    /// `wl_surface`.
+    ///
+    /// Unlike [`Self::roundtrip()`] this also receives the events of the display until the
+    /// server answers, so it notices `after` going away: it fails with
+    /// [`WaylandError::StaleObject`] if the id of `after` got deleted before the server got to the
+    /// `wl_display.sync`, and with the protocol error if the server raised one. The events of
+    /// `after` and every other object are left to their own receivers.
+    ///
+    /// [`WaylandError::StaleObject`]: crate::error::WaylandError::StaleObject
+    pub async fn sync_scoped<I>(&self, after: &Object<Conn, I>) -> io::Result<()>
+    where
+        I: Interface,
+    {
+        trace!(interface = I::NAME, id = after.id.id, "scoped sync");
+        after.check_stale()?;
+
+        let callback = self.sync().await?;
+        loop {
+            tokio::select! {
+                biased;
+                done = callback.recv() => {
+                    done?.ignore_message();
+                    break;
+                }
+                // `delete_id` already freed its id while being received.
+                msg = self.recv() => match msg?.decode()? {
+                    wl_display::event::Decoded::error(wl_display::event::error { object_id, code, message }) => {
+                        return Err(io::Error::other(format!(
+                            "protocol error {code} on {object_id}: {message}",
+                            code = code.0,
+                            object_id = object_id.id(),
+                            message = String::from_utf8_lossy(message.as_slice_without_trailing_null()),
+                        )));
+                    }
+                    wl_display::event::Decoded::delete_id(_) => {}
+                },
+            }
+        }
+        after.check_stale()
+    }
+
+    /// Like [`Self::roundtrip()`], but hands every event received on `obj` in the meantime to `f`.
+    ///
+    /// Because the server answers the `wl_display.sync` in order, this sees every event `obj`
+    /// was sent before the roundtrip started.
+    pub async fn roundtrip_with<I>(
+        &self,
+        obj: &Object<Conn, I>,
+        mut f: impl FnMut(MsgBuf<'_, Conn::Dir, I>) -> io::Result<()>,
+    ) -> io::Result<()>
+    where
+        I: Interface,
+        <Conn::Dir as InterfaceDir<I>>::Recv: Display,
+    {
+        let callback = self.sync().await?;
+        loop {
+            tokio::select! {
+                biased;
+                msg = obj.recv() => f(msg?)?,
+                done = callback.recv() => {
+                    done?.ignore_message();
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+impl<Conn> Object<Conn, wl_registry::wl_registry>
+where
+    Conn: ClientHandle,
+{
+    /// Collect all globals advertised by the server as `(name, interface, version)`.
+    ///
+    /// Returns once a roundtrip on `display` completes, so the list contains every global the
+    /// server knew about at that point.
+    /// Globals that got removed again in the meantime are not part of the list.
+    pub async fn collect_globals(
+        &self,
+        display: &Object<Conn, wl_display::wl_display>,
+    ) -> io::Result<Vec<(uint, String, uint)>> {
+        let mut globals = Vec::new();
+        display
+            .roundtrip_with(self, |msg| {
+                match msg.decode_opcode() {
+                    wl_registry::event::Opcodes::global => {
+                        let wl_registry::event::global { name, interface, version } = msg.decode_msg()?;
+                        let interface = interface
+                            .as_utf8()
+                            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+                        globals.push((name, interface.to_owned(), version));
+                    }
+                    wl_registry::event::Opcodes::global_remove => {
+                        let wl_registry::event::global_remove { name } = msg.decode_msg()?;
+                        globals.retain(|(global, _, _)| global.0 != name.0);
+                    }
+                }
+                Ok(())
+            })
+            .await?;
+        Ok(globals)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        connection::ClientHandle,
+        error::WaylandError,
+        protocols::wayland::{wl_callback, wl_display, wl_registry, wl_seat, wl_surface},
+        test_util,
+    };
+    use ecs_compositor_core::{string, uint};
+
+    #[tokio::test]
+    async fn collect_globals() {
+        let (conn, mut server) = test_util::pair();
+
+        let server = std::thread::spawn(move || {
+            let (hdr, ..) = test_util::read_msg(&mut server);
+            assert_eq!(
+                (hdr.object_id.id().get(), hdr.opcode),
+                (1, 1),
+                "expected `wl_display.get_registry`"
+            );
+            let (hdr, ..) = test_util::read_msg(&mut server);
+            assert_eq!(
+                (hdr.object_id.id().get(), hdr.opcode),
+                (1, 0),
+                "expected `wl_display.sync`"
+            );
+
+            for (name, interface, version) in [(1, "wl_compositor\0", 6), (2, "wl_shm\0", 2), (3, "wl_seat\0", 9)] {
+                test_util::write_msg(
+                    &mut server,
+                    2,
+                    &wl_registry::event::global {
+                        name: uint(name),
+                        interface: string::from_slice(interface.as_bytes()),
+                        version: uint(version),
+                    },
+                );
+            }
+            test_util::write_msg(
+                &mut server,
+                3,
+                &wl_callback::event::done { callback_data: uint(0) },
+            );
+            server
+        });
+
+        let conn = &conn;
+        let display = conn.new_object_with_id::<wl_display::wl_display>(1);
+        let registry;
+        display
+            .send(&wl_display::request::get_registry { registry: crate::new_id!(conn, registry) })
+            .await
+            .unwrap();
+
+        let globals = registry.collect_globals(&display).await.unwrap();
+        assert_eq!(
+            globals
+                .iter()
+                .map(|(name, interface, version)| (name.0, interface.as_str(), version.0))
+                .collect::<Vec<_>>(),
+            [(1, "wl_compositor", 6), (2, "wl_shm", 2), (3, "wl_seat", 9)]
+        );
+
+        server.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn sync_scoped() {
+        let (conn, mut server) = test_util::MockServer::pair();
+        let conn = &conn;
+        let display = conn.new_object_with_id::<wl_display::wl_display>(1);
+        let seat = conn.new_object_with_id::<wl_seat::wl_seat>(2);
+        let surface = conn.new_object_with_id::<wl_surface::wl_surface>(3);
+
+        let server = std::thread::spawn(move || {
+            server.expect::<wl_surface::request::commit>(3);
+            let (content, _) = server.expect::<wl_display::request::sync>(1);
+            server
+                .event(
+                    2,
+                    &wl_seat::event::name { name: string::from_slice(b"seat0\0") },
+                )
+                .event(
+                    content[0],
+                    &wl_callback::event::done { callback_data: uint(0) },
+                )
+                .play();
+        });
+
+        surface.send(&wl_surface::request::commit {}).await.unwrap();
+        // The received message holds the rx buffer, so it has to be dropped for the sync to go on.
+        let (synced, name) = tokio::join!(display.sync_scoped(&surface), async {
+            let msg = seat.recv().await.unwrap();
+            let wl_seat::event::name { name } = msg.decode_msg().ok().unwrap();
+            name.as_slice_without_trailing_null().to_vec()
+        });
+        synced.unwrap();
+        assert_eq!(name, b"seat0");
+
+        server.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn sync_scoped_destroyed() {
+        let (conn, mut server) = test_util::MockServer::pair();
+        let conn = &conn;
+        let display = conn.new_object_with_id::<wl_display::wl_display>(1);
+        let (_, surface) = conn.new_object::<wl_surface::wl_surface>();
+        let id = surface.id().id.get();
+
+        let server = std::thread::spawn(move || {
+            server.expect::<wl_surface::request::destroy>(id);
+            let (content, _) = server.expect::<wl_display::request::sync>(1);
+            server
+                .event(1, &wl_display::event::delete_id { id: uint(id) })
+                .event(
+                    content[0],
+                    &wl_callback::event::done { callback_data: uint(0) },
+                )
+                .play();
+        });
+
+        surface.send(&wl_surface::request::destroy {}).await.unwrap();
+        let err = display.sync_scoped(&surface).await.err().unwrap();
+        assert_eq!(
+            err.downcast::<WaylandError>().unwrap(),
+            WaylandError::StaleObject { id }
+        );
+
+        server.join().unwrap();
+    }
+}
@@ -1,24 +1,34 @@
 use crate::{
-    connection::{Connection, DriveIo, Object},
-    drive_io::{Interest, Io},
+    connection::{ClientHandle, Connection, Direction, DriveIo, Object},
+    drive_io::{Interest, TxIo},
+    error::WaylandError,
     handle::{ConnectionHandle, InterfaceDir},
 };
-use ecs_compositor_core::{Interface, Message};
+use ecs_compositor_core::{Interface, Message, new_id};
 use std::{
     fmt::Display,
     future::Future,
     io,
     os::fd::{AsRawFd, RawFd},
-    pin::Pin,
-    task::{Context, Poll, ready},
+    pin::{Pin, pin},
+    sync::{Arc, MutexGuard},
+    task::{Context, Poll, Waker, ready},
 };
 use tracing::{debug, instrument, trace};
 
+/// How often [`Send`] tries to reserve room in the tx buffer within a single poll, driving io in
+/// between, before it parks until a flush makes room.
+const SEND_ATTEMPTS: usize = 2;
+
 impl<Conn, I> Object<Conn, I>
 where
     Conn: ConnectionHandle<Dir: InterfaceDir<I>>,
     I: Interface,
 {
+    /// Write `msg` into the tx buffer, flushing it as needed to make room.
+    ///
+    /// Cancel safe: if the returned future is dropped before it resolves, `msg` was either written
+    /// completely and still gets sent, or not at all.
     #[instrument(level = "trace", skip(self, msg), fields(%msg))]
     pub fn send<'a, Msg>(&'a self, msg: &'a Msg) -> Send<'a, Conn, I, Msg, impl DriveIo>
     where
@@ -26,7 +36,71 @@ where
     {
         debug!(msg = %msg, object = %self.id());
 
-        Send { obj: self, msg, ready_fut: self.conn().drive_io(), did_send: false }
+        Send { obj: self, msg, make_io: Connection::drive_io, ready_fut: None, did_send: false, parked: None }
+    }
+
+    /// Send `msg` and flush the connection, returning only once the message left the socket.
+    ///
+    /// [`Self::send()`] may resolve while the message still sits in the tx buffer. Either way the
+    /// tx buffer holds duplicates of the fds of `msg`, so the caller may close them right away.
+    pub async fn send_and_flush<'a, Msg>(&'a self, msg: &'a Msg) -> io::Result<()>
+    where
+        Msg: Message<'a, Opcode = <Conn::Dir as InterfaceDir<I>>::Send, Interface = I> + Display,
+    {
+        self.send(msg).await?;
+        self.conn().flush().await
+    }
+
+    /// Like [`Self::send()`], but fails with [`WaylandError::UnsupportedVersion`] instead of
+    /// sending `msg` if the object was created at a version older than [`Message::MIN_VERSION`].
+    ///
+    /// Sending such a message is a protocol error, so this is for requests that only exist in
+    /// newer versions of an interface bound at whatever version the server advertised.
+    pub async fn send_versioned<'a, Msg>(&'a self, msg: &'a Msg) -> io::Result<()>
+    where
+        Msg: Message<'a, Opcode = <Conn::Dir as InterfaceDir<I>>::Send, Interface = I> + Display,
+    {
+        if self.version < Msg::MIN_VERSION {
+            return Err(WaylandError::UnsupportedVersion {
+                interface: I::NAME,
+                required: Msg::MIN_VERSION,
+                advertised: self.version,
+            }
+            .into());
+        }
+        self.send(msg).await
+    }
+}
+
+impl<Conn, I> Object<Conn, I>
+where
+    Conn: ClientHandle,
+    I: Interface,
+{
+    /// Create an object of interface `J` and send the request `make_msg` builds from its id, e.g.
+    /// `wl_compositor.create_surface`.
+    ///
+    /// Unlike creating the object with [`new_id!`](crate::new_id) and sending the request
+    /// separately, the id is released again if sending fails before the request made it into the
+    /// tx buffer, so it doesn't leak.
+    /// Once buffered, the server may have created the object, so the id stays taken.
+    pub async fn send_creating<J, Msg>(&self, make_msg: impl FnOnce(new_id<J>) -> Msg) -> io::Result<Object<Conn, J>>
+    where
+        J: Interface,
+        Msg: for<'a> Message<'a, Opcode = I::Request, Interface = I> + Display,
+    {
+        let (id, obj) = self.conn.new_object::<J>();
+        let msg = make_msg(id);
+        let mut send = pin!(self.send(&msg));
+        match send.as_mut().await {
+            Ok(()) => Ok(obj),
+            Err(err) if send.buffered() => Err(err),
+            Err(err) => {
+                debug!(id = %obj.id(), %err, "releasing id of the object that wasn't created");
+                self.conn().registry().free_id(obj.id().id.get());
+                Err(err)
+            }
+        }
     }
 }
 
@@ -40,8 +114,13 @@ where
 {
     obj: &'a Object<Conn, I>,
     msg: &'a Msg,
-    ready_fut: Fut,
+    make_io: fn(&'a Connection<Conn::Dir>) -> Fut,
+    /// Only built once the io has to be driven, which the fast path skips.
+    ready_fut: Option<Fut>,
     did_send: bool,
+    /// Waker registered while waiting for the tx lock or room in the tx buffer, removed again on
+    /// the next poll or on drop so cancelled sends don't pile up in the sender queue.
+    parked: Option<Waker>,
 }
 
 impl<'a, Conn, I, Msg, Fut> Send<'a, Conn, I, Msg, Fut>
@@ -52,16 +131,73 @@ where
     Fut: DriveIo,
 {
     fn ready_fut<'pin>(self: &'pin mut Pin<&mut Self>) -> Pin<&'pin mut Fut> {
-        unsafe { self.as_mut().map_unchecked_mut(|s| &mut s.ready_fut) }
+        unsafe {
+            let s = self.as_mut().get_unchecked_mut();
+            let (obj, make_io) = (s.obj, s.make_io);
+            // Never moved out again once built, as `Self` is pinned.
+            Pin::new_unchecked(s.ready_fut.get_or_insert_with(|| make_io(obj.conn())))
+        }
     }
 
-    fn drive_io(self: &mut Pin<&mut Self>, io: &mut Io, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    fn drive_io(self: &mut Pin<&mut Self>, io: &mut TxIo, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
         self.ready_fut().poll_with_io(io, cx)
     }
 
     fn fd(&self) -> RawFd {
         self.obj.conn().fd.as_raw_fd()
     }
+
+    /// Whether the message made it into the tx buffer, so it may reach the peer even if the send
+    /// fails afterwards.
+    pub(crate) fn buffered(&self) -> bool {
+        self.did_send
+    }
+
+    fn park(self: &mut Pin<&mut Self>, cx: &mut Context<'_>, locked: bool) {
+        match locked {
+            true => self.obj.register_send_locked(cx),
+            false => self.obj.register_send(cx),
+        }
+        unsafe { self.as_mut().get_unchecked_mut() }.parked = Some(cx.waker().clone());
+    }
+
+    /// Drive the io until the tx buffer is empty, for the last sender.
+    fn drain(self: &mut Pin<&mut Self>, mut io: MutexGuard<'a, TxIo>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !io.tx.is_empty() {
+            ready!(self.drive_io(&mut io, cx))?;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn lock_tx(self: &mut Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<MutexGuard<'a, TxIo>> {
+        match self.obj.conn().try_lock_tx() {
+            Some(io) => Poll::Ready(io),
+            None => {
+                self.park(cx, true);
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<'a, Conn, I, Msg, Fut> Drop for Send<'a, Conn, I, Msg, Fut>
+where
+    Conn: ConnectionHandle<Dir: InterfaceDir<I>>,
+    I: Interface,
+    Msg: Message<'a, Opcode = <Conn::Dir as InterfaceDir<I>>::Send, Interface = I>,
+    Fut: DriveIo,
+{
+    fn drop(&mut self) {
+        let Some(waker) = self.parked.take() else {
+            return;
+        };
+        let unparked = self.obj.registry().unpark_sender(&waker);
+        if !unparked {
+            // We were already woken, so pass the wakeup on instead of losing it.
+            trace!("cancelled woken sender");
+            self.obj.wake_sender();
+        }
+    }
 }
 
 impl<'a, Conn, I, Msg, Fut> Future for Send<'a, Conn, I, Msg, Fut>
@@ -79,54 +215,89 @@ where
             let conn = self.obj.conn();
             let msg = self.msg;
 
-            let lock_io = |cx: &mut Context<'_>| match conn.try_lock_io_buf() {
-                Some(io) => Poll::Ready(io),
-                None => {
-                    obj.register_send_locked(cx);
-                    Poll::Pending
-                }
-            };
+            // Being polled again, whether woken or not, so drop the old registration.
+            if let Some(waker) = self.as_mut().get_unchecked_mut().parked.take() {
+                obj.registry().unpark_sender(&waker);
+            }
 
             if !self.did_send {
-                let mut io = ready!(lock_io(cx));
+                obj.check_stale()?;
+                let mut io = ready!(self.lock_tx(cx));
+                conn.write_pending_destructors(&mut io);
 
-                // The wayland connection was closed, so just hang to make sure error events have
-                // the time to get handled.
+                // Error events the peer sent before hanging up stay in the rx buffer, so they can
+                // still be received.
                 if io.interest.contains(Interest::SEND_CLOSED) {
                     trace!("send closed");
-                    self.as_mut().get_unchecked_mut().did_send = true;
                     drop(io);
                     obj.wake_sender();
-                    return Poll::Pending;
+                    return Poll::Ready(Err(WaylandError::Closed.into()));
                 }
 
-                let (_, mut buf) = 'ret: {
-                    if let Some(out) = io.tx_msg_buf(obj.id, msg) {
-                        break 'ret out;
-                    }
-
-                    ready!(self.drive_io(&mut io, cx))?;
-                    if let Some(out) = io.tx_msg_buf(obj.id, msg) {
-                        break 'ret out;
+                let (cursor, mut buf) = 'ret: {
+                    for attempt in 1..=SEND_ATTEMPTS {
+                        match io.tx_msg_buf(obj.id, msg) {
+                            Ok(Some(out)) => break 'ret out,
+                            Ok(None) => {}
+                            Err(err) => {
+                                drop(io);
+                                obj.wake_sender();
+                                return Poll::Ready(Err(err.into()));
+                            }
+                        }
+                        if attempt < SEND_ATTEMPTS {
+                            ready!(self.drive_io(&mut io, cx))?;
+                        }
                     }
 
-                    obj.register_send(cx);
+                    // Retrying right away would just spin on the full buffer, so wait for whoever
+                    // flushes it to wake us.
+                    trace!("tx buffer full, parking sender");
+                    self.park(cx, false);
                     return Poll::Pending;
                 };
 
+                let (content, fds) = (buf.da, buf.fd);
                 msg.write(&mut buf.da, &mut buf.fd).ok().expect("serialization error");
+                // The buffer was sized by `len()` and `FDS`, anything left over would be sent as
+                // garbage and corrupt the stream.
+                debug_assert!(
+                    buf.da.len() == 0 && buf.fd.len() == 0,
+                    "`{}.{}` wrote {} bytes and {} fds less than it announced",
+                    I::NAME,
+                    Msg::NAME,
+                    buf.da.len(),
+                    buf.fd.len(),
+                );
+                if let Err(err) = io.own_fds(cursor, fds) {
+                    drop(io);
+                    obj.wake_sender();
+                    return Poll::Ready(Err(err));
+                }
+                io.logger.log_msg(Direction::Tx, I::NAME, obj.id.cast(), Msg::OP, content);
+                if <Conn::Dir as InterfaceDir<I>>::destructor_op() == Some(Msg::OP) {
+                    obj.registry().forget_auto_destroy(obj.id.cast());
+                }
                 self.as_mut().get_unchecked_mut().did_send = true;
+
+                // Fast path: the message fit and the next sender takes care of the buffer, so
+                // leave the io alone and return after the single buffer write.
+                if obj.wake_sender() {
+                    trace!("deferring write to the next sender");
+                    obj.wake_recver(cx);
+                    return Poll::Ready(Ok(()));
+                }
+                // Drain right away with the lock we already hold.
+                return self.drain(io, cx);
             }
 
-            // if we are the last sender we have to drive the io until it is empty
+            // if we are the last sender we have to drive the io until it is empty, as receiving
+            // doesn't wait for the socket to become writable
             if !obj.wake_sender() {
-                let mut io = ready!(lock_io(cx));
-                if !io.tx.is_empty() {
-                    ready!(self.drive_io(&mut io, cx))?;
-                }
-            } else {
-                obj.wake_recver(cx);
+                let io = ready!(self.lock_tx(cx));
+                return self.drain(io, cx);
             }
+            obj.wake_recver(cx);
 
             Poll::Ready(Ok(()))
         }
@@ -137,6 +308,42 @@ impl<Dir> Connection<Dir> {
     pub fn flush(&self) -> Flush<'_, Dir, impl DriveIo> {
         Flush { conn: self, io_cb: self.drive_io() }
     }
+
+    /// Flush in a task on [`Self::runtime_handle()`], which works from outside of any runtime.
+    pub(crate) fn spawn_flush(self: &Arc<Self>)
+    where
+        Dir: std::marker::Send + Sync + 'static,
+    {
+        let conn = self.clone();
+        self.runtime_handle().spawn(async move {
+            if let Err(err) = conn.flush().await {
+                debug!(%err, "flushing in the background failed");
+            }
+        });
+    }
+
+    /// Number of senders parked until a flush makes room in the full tx buffer.
+    pub fn send_backlog(&self) -> usize {
+        self.registry().parked_senders()
+    }
+
+    /// Write as much of the tx buffer as the socket takes right away, unless the tx lock is held
+    /// elsewhere.
+    ///
+    /// Each receive calls this once, so queued destructors go out even if nothing else gets sent.
+    pub(crate) fn try_flush(&self) -> io::Result<()> {
+        let Some(mut io) = self.try_lock_tx() else {
+            return Ok(());
+        };
+        self.write_pending_destructors(&mut io);
+
+        let pending = io.tx.da.data.len();
+        while let Some(true) = io.send_nonblocking(self.fd.as_raw_fd())? {}
+        if io.tx.da.data.len() < pending {
+            self.registry().wake_sender();
+        }
+        Ok(())
+    }
 }
 
 pub struct Flush<'a, Dir, Fut> {
@@ -157,22 +364,469 @@ where
             let conn = s.conn;
             let mut iocb = Pin::new_unchecked(&mut s.io_cb);
 
-            let Some(mut io) = conn.try_lock_io_buf() else {
+            let Some(mut io) = conn.try_lock_tx() else {
                 s.conn.registry().register_send_locked(cx);
                 return Poll::Pending;
             };
 
-            while !io.tx.is_empty() {
+            loop {
+                conn.write_pending_destructors(&mut io);
+                if io.tx.is_empty() {
+                    break;
+                }
+
                 if io.interest.contains(Interest::SEND_CLOSED) {
                     trace!("sending was closed");
                     conn.registry().wake_sender();
-                    return Poll::Pending;
+                    return Poll::Ready(Err(WaylandError::Closed.into()));
                 }
 
-                ready!(iocb.as_mut().poll_with_io(&mut io, cx))?;
+                ready!(iocb.as_mut().poll_with_io(&mut *io, cx))?;
+                // Some of the buffer was written, so let a parked sender try again.
+                conn.registry().wake_sender();
             }
 
             Poll::Ready(Ok(()))
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        connection::{ClientHandle, Connection, ServerHandle},
+        error::WaylandError,
+        handle::Server,
+        protocols::wayland::{
+            wl_callback, wl_compositor, wl_data_source, wl_display, wl_keyboard, wl_output::enumeration::transform,
+            wl_shm, wl_surface,
+        },
+        test_util,
+    };
+    use ecs_compositor_core::{Message, Value, array, fd, int, object, string, uint};
+    use std::{
+        fs::File,
+        future::Future,
+        io::{self, Read, Seek, SeekFrom, Write},
+        marker::PhantomData,
+        num::NonZero,
+        os::{
+            fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+            unix::net::UnixStream,
+        },
+        ptr::NonNull,
+        task::{Context, Waker},
+    };
+
+    /// Define a `wl_surface.commit` that declares `FDS` fds and `len` bytes of arguments, but
+    /// writes them with `write`, to send messages the generated ones can't represent.
+    macro_rules! fake_commit {
+        (
+            $(#[$attr:meta])*
+            struct $name:ident$(($field:ty))?;
+            FDS = $fds:expr,
+            len = $len:expr,
+            write = |$this:pat_param, $data:pat_param, $fd:pat_param| $write:block $(,)?
+        ) => {
+            $(#[$attr])*
+            struct $name$(($field))?;
+
+            impl Value<'_> for $name {
+                const FDS: usize = $fds;
+                fn len(&self) -> u32 {
+                    $len
+                }
+                unsafe fn read(
+                    _: &mut *const [u8],
+                    _: &mut *const [RawFd],
+                ) -> ecs_compositor_core::primitives::Result<Self> {
+                    unreachable!()
+                }
+                unsafe fn write(
+                    &self,
+                    $data: &mut *mut [u8],
+                    $fd: &mut *mut [RawFd],
+                ) -> ecs_compositor_core::primitives::Result<()> {
+                    let $this = self;
+                    $write
+                }
+            }
+
+            impl std::fmt::Display for $name {
+                fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                    f.write_str("commit()")
+                }
+            }
+
+            impl Message<'_> for $name {
+                type Interface = wl_surface::wl_surface;
+                const VERSION: u32 = 1;
+                const NAME: &'static str = "commit";
+                type Opcode = wl_surface::request::Opcodes;
+                const OPCODE: Self::Opcode = wl_surface::request::Opcodes::commit;
+                const OP: u16 = wl_surface::request::commit::OP;
+            }
+        };
+    }
+
+    #[tokio::test]
+    async fn send_and_flush_delivers_fd() {
+        let (conn, mut server) = test_util::MockServer::pair();
+        let conn = &conn;
+        let wl_shm = conn.new_object_with_id::<wl_shm::wl_shm>(2);
+
+        let mut file = File::from(unsafe { OwnedFd::from_raw_fd(libc::memfd_create(c"test".as_ptr(), 0)) });
+        file.write_all(b"fd contents\0").unwrap();
+
+        let pool;
+        wl_shm
+            .send_and_flush(&wl_shm::request::create_pool {
+                id: new_id!(conn, pool),
+                fd: fd(file.as_raw_fd()),
+                size: int(12),
+            })
+            .await
+            .unwrap();
+        // Closing our end right away must not lose the fd that is in flight.
+        drop(file);
+
+        // Everything has to be in the socket already, so this must not block.
+        server.sock().set_nonblocking(true).unwrap();
+        let (content, fds) = server.expect::<wl_shm::request::create_pool>(2);
+        assert_eq!(content, [pool.id().id.get(), 12]);
+
+        let mut file = File::from(fds.into_iter().next().expect("fd was not sent"));
+        let mut data = Vec::new();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"fd contents\0");
+    }
+
+    #[tokio::test]
+    async fn queued_fds_are_duplicated() {
+        let (conn, mut server) = test_util::MockServer::pair();
+        let conn = &conn;
+        let wl_shm = conn.new_object_with_id::<wl_shm::wl_shm>(2);
+
+        let mut file = File::from(unsafe { OwnedFd::from_raw_fd(libc::memfd_create(c"test".as_ptr(), 0)) });
+        file.write_all(b"queued\0").unwrap();
+
+        // Queue the message by hand, as sending flushes eagerly.
+        let pool;
+        {
+            let msg = wl_shm::request::create_pool { id: new_id!(conn, pool), fd: fd(file.as_raw_fd()), size: int(7) };
+            let mut io = conn.try_lock_tx().unwrap();
+            let (cursor, mut buf) = io.tx_msg_buf(wl_shm.id(), &msg).unwrap().unwrap();
+            let fds = buf.fd;
+            unsafe { msg.write(&mut buf.da, &mut buf.fd) }.ok().unwrap();
+            io.own_fds(cursor, fds).unwrap();
+        }
+        // The fd of the message is closed long before it gets sent.
+        drop(file);
+        conn.flush().await.unwrap();
+
+        let (content, fds) = server.expect::<wl_shm::request::create_pool>(2);
+        assert_eq!(content, [pool.id().id.get(), 7]);
+        let mut file = File::from(fds.into_iter().next().expect("fd was not sent"));
+        let mut data = Vec::new();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"queued\0");
+    }
+
+    #[tokio::test]
+    async fn full_tx_parks_sender() {
+        let (conn, mut server) = test_util::pair();
+        let conn = &conn;
+        let source = conn.new_object_with_id::<wl_data_source::wl_data_source>(2);
+
+        let mut long = vec![b'a'; 0xfffc - 8 - 4];
+        *long.last_mut().unwrap() = 0;
+        let msg = wl_data_source::request::offer { mime_type: string::from_slice(&long) };
+
+        // Fill the socket and the tx buffer until a message doesn't fit anymore.
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut send = loop {
+            let tx_msgs = conn.stats().tx_msgs;
+            let mut send = Box::pin(source.send(&msg));
+            if send.as_mut().poll(&mut cx).is_pending() && conn.stats().tx_msgs == tx_msgs {
+                break send;
+            }
+        };
+        assert_eq!(conn.send_backlog(), 0);
+
+        // Incoming data lets io make progress without making room, so the sender has to park.
+        test_util::write_msg(
+            &mut server,
+            3,
+            &wl_callback::event::done { callback_data: uint(0) },
+        );
+        tokio::task::yield_now().await;
+        assert!(send.as_mut().poll(&mut cx).is_pending());
+        assert_eq!(conn.send_backlog(), 1);
+
+        std::thread::spawn(move || std::io::copy(&mut server, &mut std::io::sink()));
+        conn.flush().await.unwrap();
+        assert_eq!(conn.send_backlog(), 0);
+
+        send.await.unwrap();
+        conn.flush().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn send_defers_to_next_sender() {
+        let (conn, mut server) = test_util::pair();
+        let conn = &conn;
+        let surface = conn.new_object_with_id::<wl_surface::wl_surface>(3);
+
+        // Another sender is waiting, so it gets the buffer handed over and this one returns after
+        // writing to the buffer without calling `sendmsg`.
+        let mut cx = Context::from_waker(Waker::noop());
+        conn.registry().register_send(&mut cx);
+        let mut send = Box::pin(surface.send(&wl_surface::request::commit {}));
+        assert!(send.as_mut().poll(&mut cx).is_ready());
+        assert!(
+            send.ready_fut.is_none(),
+            "the io was set up for the fast path"
+        );
+        assert_eq!(conn.send_backlog(), 0);
+        assert_eq!((conn.stats().tx_msgs, conn.stats().tx_bytes), (1, 0));
+        assert!(conn.wants_flush());
+
+        conn.flush().await.unwrap();
+        assert_eq!(conn.stats().tx_bytes, 8);
+        let (hdr, ..) = test_util::read_msg(&mut server);
+        assert_eq!(
+            (hdr.object_id.id().get(), hdr.opcode),
+            (3, wl_surface::request::commit::OP)
+        );
+    }
+
+    #[tokio::test]
+    #[cfg(debug_assertions)]
+    #[should_panic = "`wl_surface.commit` wrote 4 bytes and 0 fds less than it announced"]
+    async fn missized_message() {
+        fake_commit! {
+            /// A `wl_surface.commit` announcing one argument more than it writes.
+            struct Missized;
+            FDS = 0,
+            len = 8,
+            write = |_, data, fds| { unsafe { uint(0).write(data, fds) } },
+        }
+
+        let (conn, _server) = test_util::pair();
+        let conn = &conn;
+        let surface = conn.new_object_with_id::<wl_surface::wl_surface>(3);
+        surface.send(&Missized).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn cancelled_send() {
+        let (conn, mut server) = test_util::pair();
+        let conn = &conn;
+        let source = conn.new_object_with_id::<wl_data_source::wl_data_source>(2);
+
+        let mut long = vec![b'a'; 0xfffc - 8 - 4];
+        *long.last_mut().unwrap() = 0;
+        let msg = wl_data_source::request::offer { mime_type: string::from_slice(&long) };
+
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut send = loop {
+            let tx_msgs = conn.stats().tx_msgs;
+            let mut send = Box::pin(source.send(&msg));
+            if send.as_mut().poll(&mut cx).is_pending() && conn.stats().tx_msgs == tx_msgs {
+                break send;
+            }
+        };
+
+        // Incoming data lets io make progress without making room, so the sender has to park, and
+        // polling it again must not register it twice.
+        for attempt in 0..5 {
+            test_util::write_msg(
+                &mut server,
+                3,
+                &wl_callback::event::done { callback_data: uint(0) },
+            );
+            tokio::task::yield_now().await;
+            assert!(send.as_mut().poll(&mut cx).is_pending());
+            match attempt {
+                0 => assert_eq!(conn.send_backlog(), 1),
+                _ => assert!(conn.send_backlog() <= 1),
+            }
+        }
+        // Dropping it removes the registration.
+        drop(send);
+        assert_eq!(conn.send_backlog(), 0);
+
+        let tx_msgs = conn.stats().tx_msgs;
+        for _ in 0..3 {
+            tokio::select! {
+                _ = source.send(&msg) => panic!("tx buffer should be full"),
+                _ = tokio::task::yield_now() => {}
+            }
+        }
+        assert_eq!(conn.send_backlog(), 0);
+        assert_eq!(conn.stats().tx_msgs, tx_msgs);
+
+        std::thread::spawn(move || std::io::copy(&mut server, &mut std::io::sink()));
+        source.send(&msg).await.unwrap();
+        conn.flush().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn message_too_long() {
+        let (sock, mut client) = UnixStream::pair().unwrap();
+        let conn = &Connection::<Server>::from_stream(sock).unwrap();
+        let keyboard = conn.register_client_object::<wl_keyboard::wl_keyboard>(3).unwrap();
+
+        // together with the header and the other arguments this no longer fits into `datalen`
+        let mut keys = vec![0u8; 0x10000];
+        let enter = wl_keyboard::event::enter {
+            serial: uint(1),
+            surface: object::from_id(NonZero::new(4).unwrap()),
+            keys: array { ptr: NonNull::new(keys.as_mut_ptr()), len: keys.len() as u32, _marker: PhantomData },
+        };
+        let err = keyboard.send(&enter).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(
+            err.downcast::<WaylandError>().unwrap(),
+            WaylandError::MessageTooLong { interface: "wl_keyboard", message: "enter", len: 8 + 4 + 4 + 4 + 0x10000 }
+        );
+        assert!(!conn.wants_flush());
+
+        // nothing was written, so the connection is still usable
+        keyboard
+            .send_and_flush(&wl_keyboard::event::repeat_info { rate: int(25), delay: int(600) })
+            .await
+            .unwrap();
+        let (hdr, content, _) = test_util::read_msg(&mut client);
+        assert_eq!(hdr.opcode, wl_keyboard::event::repeat_info::OP);
+        assert_eq!(content, [25, 600]);
+    }
+
+    #[tokio::test]
+    async fn too_many_fds() {
+        fake_commit! {
+            /// A `wl_surface.commit` declaring more fds than a message can carry.
+            struct ManyFds;
+            FDS = 300,
+            len = 0,
+            write = |_, _, _| { unreachable!() },
+        }
+
+        let (conn, _server) = test_util::pair();
+        let conn = &conn;
+        let surface = conn.new_object_with_id::<wl_surface::wl_surface>(3);
+        let err = surface.send(&ManyFds).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(
+            err.downcast::<WaylandError>().unwrap(),
+            WaylandError::TooManyFds { interface: "wl_surface", message: "commit", fds: 300 }
+        );
+        assert!(!conn.wants_flush());
+    }
+
+    #[tokio::test]
+    async fn fds_stay_with_their_message() {
+        fake_commit! {
+            /// A `wl_surface.commit` passing the same fd 200 times, so two of them don't fit into
+            /// a single `sendmsg`.
+            struct ManyFds(RawFd);
+            FDS = 200,
+            len = 0,
+            write = |this, data, fds| {
+                for _ in 0..ManyFds::FDS {
+                    unsafe { fd(this.0).write(data, fds)? }
+                }
+                Ok(())
+            },
+        }
+
+        let (conn, mut server) = test_util::pair();
+        let file = File::from(unsafe { OwnedFd::from_raw_fd(libc::memfd_create(c"test".as_ptr(), 0)) });
+
+        // Sending flushes eagerly, so queue both messages by hand to have them in the buffer at
+        // the same time.
+        {
+            let mut io = conn.try_lock_tx().unwrap();
+            let msg = ManyFds(file.as_raw_fd());
+            for id in [3, 4] {
+                let surface = object::<wl_surface::wl_surface>::from_id(NonZero::new(id).unwrap());
+                let (cursor, mut buf) = io.tx_msg_buf(surface, &msg).unwrap().unwrap();
+                let fds = buf.fd;
+                unsafe { msg.write(&mut buf.da, &mut buf.fd) }.ok().unwrap();
+                io.own_fds(cursor, fds).unwrap();
+            }
+        }
+        conn.flush().await.unwrap();
+        assert_eq!(conn.stats().fds_sent, 400);
+
+        for id in [3, 4] {
+            let (hdr, _, fds) = test_util::read_msg(&mut server);
+            assert_eq!(hdr.object_id.id().get(), id);
+            assert_eq!(fds.len(), 200);
+        }
+    }
+
+    #[tokio::test]
+    async fn send_creating() {
+        let (conn, mut server) = test_util::pair();
+        let conn = &conn;
+        let display = conn.new_object_with_id::<wl_display::wl_display>(1);
+
+        let callback = display
+            .send_creating(|callback| wl_display::request::sync { callback })
+            .await
+            .unwrap();
+        let (_, content, _) = test_util::read_msg(&mut server);
+        assert_eq!(content, [callback.id().id.get()]);
+
+        // Sending on a destroyed object fails before the request gets buffered, so the id of the
+        // surface is handed out again.
+        let (_, compositor) = conn.new_object::<wl_compositor::wl_compositor>();
+        let stale = compositor.id().id.get();
+        conn.registry().free_id(stale);
+        let Err(err) = compositor
+            .send_creating(|id| wl_compositor::request::create_surface { id })
+            .await
+        else {
+            panic!("sent on a destroyed object");
+        };
+        assert_eq!(
+            err.downcast::<WaylandError>().unwrap(),
+            WaylandError::StaleObject { id: stale }
+        );
+        let (_, reused) = conn.new_object::<wl_surface::wl_surface>();
+        assert_eq!(reused.id().id.get(), stale);
+
+        // Here the request was buffered before writing failed, so its id stays taken.
+        drop(server);
+        let Err(err) = display.send_creating(|callback| wl_display::request::sync { callback }).await else {
+            panic!("sent to a closed socket");
+        };
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe, "{err}");
+        let (_, next) = conn.new_object::<wl_callback::wl_callback>();
+        assert_eq!(next.id().id.get(), stale + 2);
+    }
+
+    #[tokio::test]
+    async fn send_versioned() {
+        let (conn, mut server) = test_util::MockServer::pair();
+        let conn = &conn;
+        let mut surface = conn.new_object_with_id::<wl_surface::wl_surface>(2);
+        surface.version = 1;
+
+        let msg = wl_surface::request::set_buffer_transform { transform: transform::flipped };
+        let err = surface.send_versioned(&msg).await.unwrap_err();
+        assert_eq!(
+            err.downcast::<WaylandError>().unwrap(),
+            WaylandError::UnsupportedVersion { interface: "wl_surface", required: 2, advertised: 1 }
+        );
+        assert!(!conn.wants_flush());
+
+        surface.version = 2;
+        surface.send_versioned(&msg).await.unwrap();
+        conn.flush().await.unwrap();
+        server.expect::<wl_surface::request::set_buffer_transform>(2);
+    }
+}
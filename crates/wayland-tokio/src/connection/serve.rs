@@ -0,0 +1,94 @@
+use crate::{connection::Connection, handle::Server};
+use std::{future::Future, io, os::fd::AsRawFd, sync::Arc};
+use tokio::{net::UnixListener, task::JoinSet};
+use tracing::{debug, warn};
+
+impl Server {
+    /// Accept clients on `listener` and run `handler` for each of them in its own task.
+    ///
+    /// Runs until accepting fails. It then stops accepting, waits for the handlers of the clients
+    /// that are still connected to finish and returns the error.
+    pub async fn serve<F, Fut>(listener: UnixListener, handler: F) -> io::Error
+    where
+        F: Fn(Arc<Connection<Server>>) -> Fut,
+        Fut: Future<Output = ()> + std::marker::Send + 'static,
+    {
+        let mut clients = JoinSet::new();
+
+        let err = loop {
+            let sock = tokio::select! {
+                res = listener.accept() => match res {
+                    Ok((sock, _)) => sock,
+                    Err(err) => break err,
+                },
+                Some(res) = clients.join_next() => {
+                    if let Err(err) = res {
+                        warn!(%err, "client handler failed");
+                    }
+                    continue;
+                }
+            };
+
+            match sock.into_std().and_then(Connection::from_stream) {
+                Ok(conn) => {
+                    debug!(fd = conn.as_raw_fd(), "accepted client");
+                    clients.spawn(handler(Arc::new(conn)));
+                }
+                Err(err) => warn!(%err, "failed to set up client connection"),
+            }
+        };
+
+        drop(listener);
+        debug!(%err, clients = clients.len(), "accepting failed, waiting for clients");
+        while let Some(res) = clients.join_next().await {
+            if let Err(err) = res {
+                warn!(%err, "client handler failed");
+            }
+        }
+        err
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::handle::Server;
+    use std::{
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering::Relaxed},
+        },
+        time::Duration,
+    };
+    use tokio::{
+        net::{UnixListener, UnixStream},
+        sync::mpsc,
+    };
+
+    #[tokio::test]
+    async fn serve_clients() {
+        let path = std::env::temp_dir().join(format!("wayland-serve-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let count = Arc::new(AtomicUsize::new(0));
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let server = tokio::spawn({
+            let count = count.clone();
+            Server::serve(listener, move |conn| {
+                count.fetch_add(1, Relaxed);
+                let tx = tx.clone();
+                async move { tx.send(conn).unwrap() }
+            })
+        });
+
+        let (a, b) = tokio::join!(UnixStream::connect(&path), UnixStream::connect(&path));
+        let (_a, _b) = (a.unwrap(), b.unwrap());
+        for _ in 0..2 {
+            tokio::time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap().unwrap();
+        }
+        assert_eq!(count.load(Relaxed), 2);
+
+        server.abort();
+        std::fs::remove_file(&path).unwrap();
+    }
+}
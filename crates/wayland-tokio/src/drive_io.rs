@@ -1,30 +1,102 @@
-use crate::msg_io::{Msg, cmsg_cursor::CmsgCursor};
+use crate::{
+    connection::{BufConfig, ConnStats, Direction, MessageLogger, RateLimit},
+    error::WaylandError,
+    msg_io::{
+        Msg,
+        cmsg_cursor::{CmsgBuf, CmsgCursor},
+    },
+};
 use bitflags::bitflags;
-use ecs_compositor_core::{Message, RawSliceExt, Value, message_header, object};
-use libc::{CMSG_SPACE, EWOULDBLOCK, MSG_DONTWAIT, SCM_RIGHTS, SOL_SOCKET, cmsghdr};
+use ecs_compositor_core::{Interface, Message, RawSliceExt, Value, message_header, object};
+use libc::{CMSG_SPACE, EWOULDBLOCK, F_DUPFD_CLOEXEC, MSG_CTRUNC, MSG_DONTWAIT, SCM_RIGHTS, SOL_SOCKET, cmsghdr};
 use std::{
     alloc::{self, Layout},
     cmp,
+    collections::VecDeque,
     fmt::{self, Debug, Display, Formatter},
     io,
     os::{
         fd::{AsRawFd, RawFd},
         unix::net::UnixStream,
     },
+    pin::pin,
     ptr::{null_mut, slice_from_raw_parts_mut},
+    sync::{
+        Arc, Mutex,
+        atomic::{
+            AtomicBool, AtomicU64,
+            Ordering::{Acquire, Relaxed, Release},
+        },
+    },
+    task::Waker,
+};
+use tokio::{
+    io::{Ready, unix::AsyncFdReadyGuard},
+    sync::Notify,
+    time::Instant,
 };
-use tokio::io::{Ready, unix::AsyncFdReadyGuard};
 use tracing::{instrument, trace, warn};
 
+/// Receiving half of the connection, locked independently of [`TxIo`] so sending and receiving
+/// never wait for each other.
 #[derive(Debug)]
-pub(crate) struct Io {
-    pub(crate) tx: BufDir,
+pub(crate) struct RxIo {
     pub(crate) rx: BufDir,
 
+    /// Only ever contains [`Interest::RECV`] and [`Interest::RECV_CLOSED`].
     pub(crate) interest: Interest,
     pub(crate) rx_hdr: Option<message_header>,
+    pub(crate) stats: Arc<IoStats>,
+    pub(crate) logger: Logger,
+    /// Woken whenever data or the end of the stream is received, see
+    /// [`Connection::spawn_driver()`](crate::connection::Connection::spawn_driver).
+    pub(crate) rx_waker: Option<Waker>,
+    pub(crate) closed: Arc<CloseNotify>,
+    /// Pauses reading while set and exceeded, see
+    /// [`Connection::set_rate_limit()`](crate::connection::Connection::set_rate_limit).
+    pub(crate) rate_limit: Option<RateLimit>,
+
+    cmsg_buf: CmsgBuf<{ unsafe { CMSG_SPACE(4 * MAX_FDS) as usize } }>,
+}
 
-    cmsg_buf: [u8; unsafe { CMSG_SPACE(4 * MAX_FDS) as usize }],
+/// Sending half of the connection, see [`RxIo`].
+#[derive(Debug)]
+pub(crate) struct TxIo {
+    pub(crate) tx: BufDir,
+
+    /// Only ever contains [`Interest::SEND`] and [`Interest::SEND_CLOSED`].
+    pub(crate) interest: Interest,
+    pub(crate) stats: Arc<IoStats>,
+    pub(crate) logger: Logger,
+    pub(crate) closed: Arc<CloseNotify>,
+
+    /// Queued messages with fds whose fds weren't sent yet, in the order they are in `tx`.
+    fd_msgs: VecDeque<FdMsg>,
+    cmsg_buf: CmsgBuf<{ unsafe { CMSG_SPACE(4 * MAX_FDS) as usize } }>,
+}
+
+/// Message in the tx buffer that carries fds.
+#[derive(Debug)]
+struct FdMsg {
+    /// Offset of the first byte of the message into the tx data buffer.
+    offset: usize,
+    fds: usize,
+}
+
+/// One direction of the connection, driven by [`DriveIo`](crate::connection::DriveIo).
+pub(crate) trait IoHalf {
+    fn interest(&self) -> Interest;
+
+    /// Readiness the socket has to be polled for to make progress in this direction.
+    fn query_interest(&mut self) -> Option<tokio::io::Interest>;
+
+    fn drive_io(&mut self, guard: &mut AsyncFdReadyGuard<UnixStream>) -> io::Result<()>;
+
+    /// When to try again if this direction is paused by a rate limit, instead of waiting for
+    /// readiness.
+    fn throttled_until(&mut self) -> Option<Instant> {
+        None
+    }
 }
 
 bitflags! {
@@ -72,69 +144,156 @@ fn io_ready(guard: &AsyncFdReadyGuard<UnixStream>) -> Interest {
     out
 }
 
-impl Io {
-    pub fn new() -> Self {
-        Io { tx: BufDir::new(), rx: BufDir::new(), rx_hdr: None, cmsg_buf: [0; _], interest: Interest::RECV }
-    }
+/// Whether either direction of the connection got closed, shared by both halves and woken by the
+/// first one that observes it, see [`Connection::closed()`].
+///
+/// [`Connection::closed()`]: crate::connection::Connection::closed
+#[derive(Debug, Default)]
+pub(crate) struct CloseNotify {
+    closed: AtomicBool,
+    notify: Notify,
+}
 
-    pub fn query_interest(&mut self) -> Option<tokio::io::Interest> {
-        match self.interest {
-            interest if interest.contains(Interest::RECV | Interest::SEND) => {
-                Some(tokio::io::Interest::READABLE | tokio::io::Interest::WRITABLE)
-            }
-            interest if interest.contains(Interest::RECV) => Some(tokio::io::Interest::READABLE),
-            interest if interest.contains(Interest::SEND) => Some(tokio::io::Interest::WRITABLE),
-            _ => None,
+impl CloseNotify {
+    pub(crate) fn close(&self) {
+        if !self.closed.swap(true, Release) {
+            self.notify.notify_waiters();
         }
     }
 
-    #[instrument(name = "drive_io", level = "trace", fields(interest = %self.interest, ready = %io_ready(guard)), ret, skip_all)]
-    pub fn drive_io(&mut self, guard: &mut AsyncFdReadyGuard<UnixStream>) -> io::Result<()> {
-        let ready = guard.ready();
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed.load(Acquire)
+    }
 
-        if ready.is_read_closed() {
-            self.interest |= Interest::RECV_CLOSED;
+    pub(crate) async fn wait(&self) {
+        let mut notified = pin!(self.notify.notified());
+        notified.as_mut().enable();
+        if !self.is_closed() {
+            notified.await;
         }
+    }
+}
 
-        if ready.is_write_closed() {
-            self.interest.insert(Interest::SEND_CLOSED);
-            self.interest.remove(Interest::SEND);
+/// The [`MessageLogger`], shared by both halves of the connection.
+#[derive(Clone, Default)]
+pub(crate) struct Logger(pub(crate) Arc<Mutex<Option<MessageLogger>>>);
+
+impl Logger {
+    /// Pass a message to the logger set by [`Connection::set_message_logger()`].
+    ///
+    /// # Safety
+    ///
+    /// `data` has to point to the initialized content of the message.
+    ///
+    /// [`Connection::set_message_logger()`]: crate::connection::Connection::set_message_logger
+    pub(crate) unsafe fn log_msg(
+        &self,
+        direction: Direction,
+        interface: &'static str,
+        object: object,
+        opcode: u16,
+        data: *const [u8],
+    ) {
+        if let Some(logger) = &*self.0.lock().unwrap() {
+            logger(direction, interface, object, opcode, unsafe { &*data });
         }
+    }
+}
 
-        let mut reading = self.interest.contains(Interest::RECV) && ready.is_readable();
-        let mut writing = self.interest.contains(Interest::SEND) && ready.is_writable();
+impl Debug for Logger {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.0.try_lock().as_deref() {
+            Ok(Some(_)) => f.write_str("Some(<logger>)"),
+            Ok(None) => f.write_str("None"),
+            Err(_) => f.write_str("<locked>"),
+        }
+    }
+}
 
-        let mut count = 0;
-        loop {
-            if !reading && !writing {
-                break;
-            }
+/// Traffic counters, kept outside of the [`RxIo`] and [`TxIo`] locks so they can be read at any
+/// time.
+#[derive(Debug, Default)]
+pub(crate) struct IoStats {
+    pub(crate) tx_bytes: AtomicU64,
+    pub(crate) rx_bytes: AtomicU64,
+    pub(crate) tx_msgs: AtomicU64,
+    pub(crate) rx_msgs: AtomicU64,
+    pub(crate) fds_sent: AtomicU64,
+    pub(crate) fds_recv: AtomicU64,
+}
 
-            if writing {
-                writing = self.send(guard)?;
-            }
+impl IoStats {
+    pub(crate) fn add(counter: &AtomicU64, count: usize) {
+        counter.fetch_add(count as u64, Relaxed);
+    }
 
-            if reading {
-                reading = self.recv(guard)?;
-            }
+    pub(crate) fn snapshot(&self) -> ConnStats {
+        ConnStats {
+            tx_bytes: self.tx_bytes.load(Relaxed),
+            rx_bytes: self.rx_bytes.load(Relaxed),
+            tx_msgs: self.tx_msgs.load(Relaxed),
+            rx_msgs: self.rx_msgs.load(Relaxed),
+            fds_sent: self.fds_sent.load(Relaxed),
+            fds_recv: self.fds_recv.load(Relaxed),
+        }
+    }
+}
 
-            count += 1;
-            trace!(reading, writing, count)
+impl RxIo {
+    pub fn new(config: BufConfig, stats: Arc<IoStats>, logger: Logger, closed: Arc<CloseNotify>) -> Self {
+        RxIo {
+            rx: BufDir::new(config),
+            rx_hdr: None,
+            cmsg_buf: CmsgBuf::new(),
+            interest: Interest::RECV,
+            stats,
+            logger,
+            rx_waker: None,
+            closed,
+            rate_limit: None,
         }
+    }
 
-        Ok(())
+    /// Drop all received messages that were not handled yet, closing the fds that came with them.
+    pub fn discard_rx(&mut self) {
+        unsafe {
+            for fd in &*self.rx.fd.data {
+                libc::close(*fd);
+            }
+            self.rx.da.data = slice_from_raw_parts_mut(self.rx.da.buf.start(), 0);
+            self.rx.fd.data = slice_from_raw_parts_mut(self.rx.fd.buf.start(), 0);
+            self.rx_hdr = None;
+        }
     }
 
-    #[instrument(name = "client rx", level = "trace", fields(fd = guard.get_inner().as_raw_fd()), ret, skip_all)]
     fn recv(&mut self, guard: &mut AsyncFdReadyGuard<UnixStream>) -> io::Result<bool> {
+        match self.recv_nonblocking(guard.get_inner().as_raw_fd())? {
+            Some(received) => Ok(received),
+            None => {
+                guard.clear_ready_matching(Ready::READABLE);
+                Ok(false)
+            }
+        }
+    }
+
+    /// Receive from `sock` without waiting for readiness.
+    ///
+    /// Returns whether reading should continue, or [`None`] if the socket would block.
+    #[instrument(name = "client rx", level = "trace", fields(fd = sock), ret, skip_all)]
+    pub(crate) fn recv_nonblocking(&mut self, sock: RawFd) -> io::Result<Option<bool>> {
         unsafe {
             let da = &mut self.rx.da;
             let fd = &mut self.rx.fd;
-            let mut ctrl = &mut self.cmsg_buf as *mut [u8];
+            let mut ctrl = &mut self.cmsg_buf.0 as *mut [u8];
 
             if self.interest.contains(Interest::RECV_CLOSED) {
                 self.interest.remove(Interest::RECV);
-                return Ok(false);
+                return Ok(Some(false));
+            }
+            if let Some(until) = self.rate_limit.as_mut().and_then(|limit| limit.throttled_until(&self.stats)) {
+                trace!(?until, "rate limited");
+                self.interest.remove(Interest::RECV);
+                return Ok(Some(false));
             }
 
             let data = 'data: {
@@ -143,37 +302,39 @@ impl Io {
                     da.data = slice_from_raw_parts_mut(da.buf.start(), 0);
 
                     let mut data = da.buf;
-                    data.set_len(WAYLAND_MAX_MESSAGE_LEN * 3);
+                    data.set_len(cmp::min(data.len(), WAYLAND_MAX_MESSAGE_LEN * 3));
 
                     break 'data data;
                 }
 
                 const HDR_LEN: usize = 8;
                 let mut unused = da.unused_end();
-                if unused.len() < WAYLAND_MAX_MESSAGE_LEN * 2 {
-                    match self.rx_hdr {
+                if unused.len() < da.buf.len() / 2 {
+                    let len = match self.rx_hdr {
                         None if HDR_LEN <= da.data.len() => {
                             self.interest.remove(Interest::RECV);
-                            return Ok(false);
-                        }
-                        None => {
-                            let len = da.data.len() - HDR_LEN;
-                            unused.set_len(len);
-                            unused
+                            return Ok(Some(false));
                         }
+                        None => HDR_LEN - da.data.len(),
 
                         Some(hdr) if hdr.content_len() as usize <= da.data.len() => {
                             self.interest.remove(Interest::RECV);
-                            return Ok(false);
-                        }
-                        Some(hdr) => {
-                            let len = hdr.content_len() as usize - da.data.len();
-                            unused.set_len(len);
-                            unused
+                            return Ok(Some(false));
                         }
+                        Some(hdr) => hdr.content_len() as usize - da.data.len(),
+                    };
+
+                    // move the partial message to the front, so the rest of it fits behind it
+                    if unused.len() < len {
+                        let data = da.data;
+                        da.buf.start().copy_from(data.start(), data.len());
+                        da.data = slice_from_raw_parts_mut(da.buf.start(), data.len());
+                        unused = da.unused_end();
                     }
+                    unused.set_len(len);
+                    unused
                 } else {
-                    unused.set_len(WAYLAND_MAX_MESSAGE_LEN * 3);
+                    unused.set_len(cmp::min(unused.len(), WAYLAND_MAX_MESSAGE_LEN * 3));
                     unused
                 }
             };
@@ -202,24 +363,29 @@ impl Io {
 
             let mut msg = Msg { data, ctrl, flags: 0 };
 
-            match msg.recv(guard.get_inner().as_raw_fd(), MSG_DONTWAIT) {
+            match msg.recv(sock, MSG_DONTWAIT) {
                 // fd closed on the other side
                 Ok(None) => {
-                    trace!(fd = ?guard.get_inner(), "closed");
+                    trace!(fd = sock, "closed");
                     self.interest.remove(Interest::RECV);
                     self.interest.insert(Interest::RECV_CLOSED);
+                    self.closed.close();
+                    if let Some(waker) = self.rx_waker.take() {
+                        waker.wake();
+                    }
 
-                    Ok(false)
+                    Ok(Some(false))
                 }
                 Ok(Some(msg)) => {
                     trace!(
-                        fd = guard.get_inner().as_raw_fd(),
+                        fd = sock,
                         data_len = msg.data.len(),
                         ctrl_len = msg.ctrl.len(),
                         "received data"
                     );
 
                     da.data.set_len(da.data.len() + msg.data.len());
+                    IoStats::add(&self.stats.rx_bytes, msg.data.len());
 
                     let mut cursor = CmsgCursor::from_ctrl_buf(msg.ctrl);
 
@@ -233,16 +399,24 @@ impl Io {
 
                                 ctrl_dst.start().copy_from(fds.start(), fds.len());
                                 fd.data.set_len(fd.data.len() + fds.len());
+                                IoStats::add(&self.stats.fds_recv, fds.len());
 
                                 ctrl_dst = slice_from_raw_parts_mut(null_mut(), 0);
                             }
-                            Some((cmsghdr { cmsg_type: SOL_SOCKET, cmsg_level: SCM_RIGHTS, .. }, _ctrl_data)) => {
-                                warn!("duplicate SCM_RIGHTS control message");
+                            Some((cmsghdr { cmsg_type: SOL_SOCKET, cmsg_level: SCM_RIGHTS, .. }, ctrl_data)) => {
+                                let fds = &*ctrl_data.read_as::<RawFd>();
+                                warn!(
+                                    fds = fds.len(),
+                                    "duplicate SCM_RIGHTS control message, closing its fds"
+                                );
+                                for fd in fds {
+                                    libc::close(*fd);
+                                }
                             }
 
                             Some((cmsghdr { cmsg_type, cmsg_level, cmsg_len }, _ctrl_data)) => {
                                 trace!(
-                                    fd = guard.get_inner().as_raw_fd(),
+                                    fd = sock,
                                     cmsg_type, cmsg_level, cmsg_len, "unknown cmsg type, discarding"
                                 );
                             }
@@ -251,21 +425,137 @@ impl Io {
                             }
                         }
                     }
+                    if let Some(waker) = self.rx_waker.take() {
+                        waker.wake();
+                    }
 
-                    Ok(true)
-                }
-                Err(code) if code == EWOULDBLOCK => {
-                    guard.clear_ready_matching(Ready::READABLE);
+                    // The fds that didn't fit were closed by the kernel, so the ones of later
+                    // messages would be handed to the wrong message.
+                    if msg.flags & MSG_CTRUNC != 0 {
+                        warn!(fd = sock, "control data truncated, fd limit exceeded");
+                        return Err(WaylandError::FdLimitExceeded { limit: fd.buf.len() }.into());
+                    }
 
-                    Ok(false)
+                    Ok(Some(true))
                 }
+                Err(code) if code == EWOULDBLOCK => Ok(None),
                 Err(code) => Err(io::Error::from_raw_os_error(code)),
             }
         }
     }
 
-    #[instrument(name = "client tx", level = "trace", fields(fd = guard.get_inner().as_raw_fd()), ret, skip_all)]
+    #[instrument(level = "trace", fields(data_len = da, ctrl_len = fd), ret, skip_all)]
+    pub fn rx_msg_buf(&mut self, (da, fd): (u16, usize)) -> Option<(IoBuf, IoBuf)> {
+        unsafe {
+            let rx = &mut self.rx;
+            let cursor = rx.save_cursor();
+
+            trace!(
+                expected_data = da,
+                expected_ctrl = fd,
+                actual_data = self.rx.da.data.len(),
+                actual_ctrl = self.rx.fd.data.len(),
+                "recv buf read"
+            );
+
+            let data_len = da as usize;
+            let ctrl_len = fd;
+
+            match (
+                self.rx.da.data.split_at(data_len),
+                self.rx.fd.data.split_at(ctrl_len),
+            ) {
+                (Some(da), Some(fd)) => Some((cursor, IoBuf { da, fd })),
+                _ => {
+                    if !self.interest.contains(Interest::RECV_CLOSED) {
+                        self.interest.insert(Interest::RECV)
+                    }
+
+                    self.rx.restore_cursor(cursor);
+                    None
+                }
+            }
+        }
+    }
+
+    /// Number of received fds, if the data of a `(da, fd)` sized message was fully received, but
+    /// fewer than the `fd` fds it declares.
+    ///
+    /// As fds arrive together with the first byte of the `sendmsg` they were sent with, they can't
+    /// show up after the data anymore, so the message is malformed.
+    pub fn rx_missing_fds(&self, (da, fd): (u16, usize)) -> Option<usize> {
+        let (data_len, fd_len) = (self.rx.da.data.len(), self.rx.fd.data.len());
+        (data_len >= da as usize && fd_len < fd).then_some(fd_len)
+    }
+}
+
+/// Close the fds of messages nobody received, e.g. because they are addressed to an unknown
+/// object, as those are still owned by the rx buffer.
+impl Drop for RxIo {
+    fn drop(&mut self) {
+        self.discard_rx();
+    }
+}
+
+impl IoHalf for RxIo {
+    fn interest(&self) -> Interest {
+        self.interest
+    }
+
+    fn query_interest(&mut self) -> Option<tokio::io::Interest> {
+        self.interest.contains(Interest::RECV).then_some(tokio::io::Interest::READABLE)
+    }
+
+    #[instrument(name = "drive_rx", level = "trace", fields(interest = %self.interest, ready = %io_ready(guard)), ret, skip_all)]
+    fn drive_io(&mut self, guard: &mut AsyncFdReadyGuard<UnixStream>) -> io::Result<()> {
+        // `RECV_CLOSED` is only set once `recv` reaches the end of the stream, as the peer might
+        // have sent more data before hanging up.
+        let mut reading = self.interest.contains(Interest::RECV) && guard.ready().is_readable();
+        while reading {
+            reading = self.recv(guard)?;
+        }
+
+        Ok(())
+    }
+
+    fn throttled_until(&mut self) -> Option<Instant> {
+        self.rate_limit.as_mut()?.throttled_until(&self.stats)
+    }
+}
+
+impl TxIo {
+    pub fn new(config: BufConfig, stats: Arc<IoStats>, logger: Logger, closed: Arc<CloseNotify>) -> Self {
+        TxIo {
+            tx: BufDir::new(config),
+            fd_msgs: VecDeque::new(),
+            cmsg_buf: CmsgBuf::new(),
+            interest: Interest::empty(),
+            stats,
+            logger,
+            closed,
+        }
+    }
+
     fn send(&mut self, guard: &mut AsyncFdReadyGuard<UnixStream>) -> io::Result<bool> {
+        match self.send_nonblocking(guard.get_inner().as_raw_fd())? {
+            Some(sent) => Ok(sent),
+            None => {
+                guard.clear_ready_matching(Ready::WRITABLE);
+                Ok(false)
+            }
+        }
+    }
+
+    /// Send to `sock` without waiting for readiness.
+    ///
+    /// The fds of a `sendmsg` arrive together with its first byte, so every message has to be sent
+    /// with or after its fds. A single `sendmsg` can only carry [`MAX_FDS`] fds though, so the
+    /// data is cut off before the first message whose fds don't fit anymore, which is then sent
+    /// with the next one.
+    ///
+    /// Returns whether sending should continue, or [`None`] if the socket would block.
+    #[instrument(name = "client tx", level = "trace", fields(fd = sock), ret, skip_all)]
+    pub(crate) fn send_nonblocking(&mut self, sock: RawFd) -> io::Result<Option<bool>> {
         unsafe {
             let da = &mut self.tx.da;
             let fd = &mut self.tx.fd;
@@ -274,20 +564,31 @@ impl Io {
                 trace!("data empty");
 
                 self.interest.remove(Interest::SEND);
-                return Ok(false);
+                return Ok(Some(false));
             }
 
-            let data = da.data;
+            let mut data = da.data;
+            let (mut fds, mut fd_msgs) = (0, 0);
+            for msg in &self.fd_msgs {
+                if fds + msg.fds > MAX_FDS as usize {
+                    data.set_len(msg.offset - data.start().offset_from_unsigned(da.buf.start()));
+                    break;
+                }
+                fds += msg.fds;
+                fd_msgs += 1;
+            }
+            debug_assert!(fds <= fd.data.len(), "tx buffer lost track of its fds");
+
             let ctrl = 'ctrl: {
-                if fd.data.is_empty() {
-                    trace!("fd.data is empty");
+                if fds == 0 {
+                    trace!("no fds to send");
                     break 'ctrl slice_from_raw_parts_mut(null_mut(), 0);
                 }
 
                 let mut ctrl = fd.data;
-                ctrl.set_len(cmp::min(ctrl.len(), MAX_FDS as usize));
+                ctrl.set_len(fds);
 
-                let mut cursor = CmsgCursor::from_ctrl_buf(&mut self.cmsg_buf);
+                let mut cursor = CmsgCursor::from_ctrl_buf(&mut self.cmsg_buf.0);
                 cursor
                     .write_cursor(SOL_SOCKET, SCM_RIGHTS)
                     .expect("failed to create tx cmsg buffer")
@@ -299,15 +600,16 @@ impl Io {
 
             let mut msg = Msg { data, ctrl, flags: 0 };
 
-            match msg.send(guard.get_inner().as_raw_fd(), MSG_DONTWAIT) {
+            match msg.send(sock, MSG_DONTWAIT) {
                 // fd closed on the other side
                 Ok(None) => {
                     trace!("closed");
 
                     self.interest.remove(Interest::SEND);
                     self.interest.insert(Interest::SEND_CLOSED);
+                    self.closed.close();
 
-                    Ok(false)
+                    Ok(Some(false))
                 }
                 Ok(Some(msg)) => {
                     trace!(
@@ -317,36 +619,75 @@ impl Io {
                     );
 
                     da.data.split_at(msg.data.len()).unwrap();
-                    fd.data.split_at(cmp::min(fd.data.len(), MAX_FDS as usize)).unwrap();
+                    // The fds are duplicates owned by the tx buffer, see `Self::own_fds()`.
+                    for fd in &*fd.data.split_at(fds).unwrap() {
+                        libc::close(*fd);
+                    }
+                    self.fd_msgs.drain(..fd_msgs);
+                    IoStats::add(&self.stats.tx_bytes, msg.data.len());
+                    IoStats::add(&self.stats.fds_sent, fds);
 
                     if da.data.is_empty() {
                         self.interest.remove(Interest::SEND);
-                        return Ok(false);
+                        return Ok(Some(false));
                     }
 
-                    Ok(true)
-                }
-                Err(code) if code == EWOULDBLOCK => {
-                    guard.clear_ready_matching(Ready::WRITABLE);
-
-                    Ok(false)
+                    Ok(Some(true))
                 }
+                Err(code) if code == EWOULDBLOCK => Ok(None),
                 Err(code) => Err(io::Error::from_raw_os_error(code)),
             }
         }
     }
 
+    /// Reserve room for `msg` in the tx buffer and write its header, returning the cursor to
+    /// restore on failure and the room for its content.
+    ///
+    /// Returns [`None`] if the buffer is too full and [`WaylandError::MessageTooLong`] or
+    /// [`WaylandError::TooManyFds`] if `msg` can't be sent at all.
     #[instrument(level = "trace", ret, skip_all)]
-    pub fn tx_msg_buf<'a, M>(&mut self, object_id: object<M::Interface>, msg: &M) -> Option<(IoBuf, IoBuf)>
+    pub fn tx_msg_buf<'a, M>(
+        &mut self,
+        object_id: object<M::Interface>,
+        msg: &M,
+    ) -> Result<Option<(IoBuf, IoBuf)>, WaylandError>
     where
         M: Message<'a>,
     {
+        if M::FDS > message_header::MAX_FDS {
+            return Err(WaylandError::TooManyFds {
+                interface: <M::Interface as Interface>::NAME,
+                message: M::NAME,
+                fds: M::FDS,
+            });
+        }
+        let content_len = msg.len() as usize;
+        if message_header::with_content_len(object_id.cast(), M::OP, content_len).is_none() {
+            return Err(WaylandError::MessageTooLong {
+                interface: <M::Interface as Interface>::NAME,
+                message: M::NAME,
+                len: message_header::DATA_LEN as usize + content_len,
+            });
+        }
+        Ok(self.tx_buf(object_id.cast(), M::OP, content_len, M::FDS))
+    }
+
+    /// Reserve room for a message with `content_len` bytes of content and `fds` fds in the tx
+    /// buffer and write its header, see [`Self::tx_msg_buf()`].
+    ///
+    /// Panics if the message exceeds [`message_header::MAX_LEN`].
+    pub fn tx_buf(&mut self, object_id: object, opcode: u16, content_len: usize, fds: usize) -> Option<(IoBuf, IoBuf)> {
         unsafe {
             let tx = &mut self.tx;
+            // everything was sent, start over at the front
+            if tx.da.data.is_empty() && tx.fd.data.is_empty() {
+                tx.da.data = slice_from_raw_parts_mut(tx.da.buf.start(), 0);
+                tx.fd.data = slice_from_raw_parts_mut(tx.fd.buf.start(), 0);
+            }
             let cursor = tx.save_cursor();
 
-            let data_len = message_header::DATA_LEN as usize + msg.len() as usize;
-            let ctrl_len = message_header::CTRL_LEN + M::FDS;
+            let data_len = message_header::DATA_LEN as usize + content_len;
+            let ctrl_len = message_header::CTRL_LEN + fds;
 
             trace!(
                 expected_data = data_len,
@@ -365,13 +706,19 @@ impl Io {
                 tx.fd.unused_end().split_at(ctrl_len),
             ) {
                 (Some(mut da), Some(mut fd)) => {
+                    if fds > 0 {
+                        let offset = da.start().offset_from_unsigned(tx.da.buf.start());
+                        self.fd_msgs.push_back(FdMsg { offset, fds });
+                    }
                     tx.da.data.set_len(tx.da.data.len() + data_len);
                     tx.fd.data.set_len(tx.fd.data.len() + ctrl_len);
 
-                    message_header { object_id: object_id.cast(), datalen: da.len() as u16, opcode: M::OP }
+                    message_header::with_content_len(object_id, opcode, content_len)
+                        .expect("message too long for `datalen`")
                         .write(&mut da, &mut fd)
                         .ok()
                         .expect("failed writing message_header");
+                    IoStats::add(&self.stats.tx_msgs, 1);
 
                     Some((cursor, IoBuf { da, fd }))
                 }
@@ -382,39 +729,69 @@ impl Io {
             }
         }
     }
+}
 
-    #[instrument(level = "trace", fields(data_len = da, ctrl_len = fd), ret, skip_all)]
-    pub fn rx_msg_buf(&mut self, (da, fd): (u16, usize)) -> Option<(IoBuf, IoBuf)> {
+impl TxIo {
+    /// Replace the `fds` a message just wrote with duplicates owned by the tx buffer, which are
+    /// closed once sent, so the fds of the message may be closed right after writing it.
+    ///
+    /// If duplicating fails, the message is removed from the buffer again by restoring `cursor`.
+    pub fn own_fds(&mut self, cursor: IoBuf, fds: *mut [RawFd]) -> io::Result<()> {
         unsafe {
-            let rx = &mut self.rx;
-            let cursor = rx.save_cursor();
+            for index in 0..fds.len() {
+                let fd = fds.cast::<RawFd>().add(index);
+                let dup = libc::fcntl(*fd, F_DUPFD_CLOEXEC, 0);
+                if dup < 0 {
+                    let err = io::Error::last_os_error();
+                    for index in 0..index {
+                        libc::close(*fds.cast::<RawFd>().add(index));
+                    }
+                    self.tx.restore_cursor(cursor);
+                    self.fd_msgs.pop_back();
+                    return Err(err);
+                }
+                *fd = dup;
+            }
+        }
+        Ok(())
+    }
+}
 
-            trace!(
-                expected_data = da,
-                expected_ctrl = fd,
-                actual_data = self.rx.da.data.len(),
-                actual_ctrl = self.rx.fd.data.len(),
-                "recv buf read"
-            );
+/// Close the duplicated fds of messages that were never sent.
+impl Drop for TxIo {
+    fn drop(&mut self) {
+        unsafe {
+            for fd in &*self.tx.fd.data {
+                libc::close(*fd);
+            }
+        }
+    }
+}
 
-            let data_len = da as usize;
-            let ctrl_len = fd;
+impl IoHalf for TxIo {
+    fn interest(&self) -> Interest {
+        self.interest
+    }
 
-            match (
-                self.rx.da.data.split_at(data_len),
-                self.rx.fd.data.split_at(ctrl_len),
-            ) {
-                (Some(da), Some(fd)) => Some((cursor, IoBuf { da, fd })),
-                _ => {
-                    if !self.interest.contains(Interest::RECV_CLOSED) {
-                        self.interest.insert(Interest::RECV)
-                    }
+    fn query_interest(&mut self) -> Option<tokio::io::Interest> {
+        self.interest.contains(Interest::SEND).then_some(tokio::io::Interest::WRITABLE)
+    }
 
-                    self.rx.restore_cursor(cursor);
-                    None
-                }
-            }
+    #[instrument(name = "drive_tx", level = "trace", fields(interest = %self.interest, ready = %io_ready(guard)), ret, skip_all)]
+    fn drive_io(&mut self, guard: &mut AsyncFdReadyGuard<UnixStream>) -> io::Result<()> {
+        let ready = guard.ready();
+        if ready.is_write_closed() {
+            self.interest.insert(Interest::SEND_CLOSED);
+            self.interest.remove(Interest::SEND);
+            self.closed.close();
         }
+
+        let mut writing = self.interest.contains(Interest::SEND) && ready.is_writable();
+        while writing {
+            writing = self.send(guard)?;
+        }
+
+        Ok(())
     }
 }
 
@@ -425,10 +802,13 @@ pub(crate) struct BufDir {
 }
 
 impl BufDir {
-    pub fn new() -> Self {
+    pub fn new(BufConfig { data_capacity, fd_capacity, .. }: BufConfig) -> Self {
         unsafe {
-            let da = RingBuf::new(Layout::from_size_align_unchecked(MAX_DATA, 1), MAX_DATA);
-            let fd = RingBuf::new(Layout::new::<RawFd>(), 1024);
+            let da = RingBuf::new(
+                Layout::from_size_align_unchecked(data_capacity, 1),
+                data_capacity,
+            );
+            let fd = RingBuf::new(Layout::array::<RawFd>(fd_capacity).unwrap(), fd_capacity);
 
             Self { da, fd }
         }
@@ -525,4 +905,4 @@ impl<T> RingBuf<T> {
 
 pub const WAYLAND_MAX_MESSAGE_LEN: usize = 1 << 16;
 pub const MAX_DATA: usize = WAYLAND_MAX_MESSAGE_LEN * 4;
-pub const MAX_FDS: u32 = 252;
+pub const MAX_FDS: u32 = message_header::MAX_FDS as u32;
@@ -0,0 +1,105 @@
+use ecs_compositor_core::message_header;
+use std::{error::Error, fmt, io};
+
+/// Protocol violations of the peer, or the peer being incompatible with us.
+///
+/// These get surfaced as [`io::Error`] of kind [`io::ErrorKind::InvalidData`] wrapping this enum,
+/// so they can be recovered using [`io::Error::downcast()`]. The only exceptions are
+/// [`WaylandError::Closed`], which is of kind [`io::ErrorKind::BrokenPipe`], and
+/// [`WaylandError::MessageTooLong`], [`WaylandError::TooManyFds`] and
+/// [`WaylandError::StaleObject`], which are of kind [`io::ErrorKind::InvalidInput`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WaylandError {
+    /// The data of a message was received, but it came with fewer fds than its opcode declares.
+    MissingFds { object: u32, opcode: u16, expected: usize, received: usize },
+    /// The server advertised a global at a version older than [`Interface::MIN_VERSION`], or a
+    /// message was sent on an object older than its [`Message::MIN_VERSION`].
+    ///
+    /// [`Interface::MIN_VERSION`]: ecs_compositor_core::Interface::MIN_VERSION
+    /// [`Message::MIN_VERSION`]: ecs_compositor_core::Message::MIN_VERSION
+    UnsupportedVersion { interface: &'static str, required: u32, advertised: u32 },
+    /// The client created an object with an id outside of the range reserved for client
+    /// allocated ids.
+    InvalidObjectId { id: u32 },
+    /// The client created an object with an id that is already in use.
+    ObjectIdInUse { id: u32 },
+    /// A message was too long to be sent, as its length including the header has to fit into the
+    /// 16 bit `datalen` of the header, see [`message_header::MAX_LEN`].
+    ///
+    /// [`message_header::MAX_LEN`]: ecs_compositor_core::message_header::MAX_LEN
+    MessageTooLong { interface: &'static str, message: &'static str, len: usize },
+    /// A message declares more fds than can be sent with it, see [`message_header::MAX_FDS`].
+    ///
+    /// [`message_header::MAX_FDS`]: ecs_compositor_core::message_header::MAX_FDS
+    TooManyFds { interface: &'static str, message: &'static str, fds: usize },
+    /// The peer sent more fds than the rx buffer has room for, see [`BufConfig::fd_capacity`].
+    ///
+    /// The kernel closes the fds that don't fit, so the remaining ones can no longer be matched
+    /// to their messages.
+    ///
+    /// [`BufConfig::fd_capacity`]: crate::connection::BufConfig::fd_capacity
+    FdLimitExceeded { limit: usize },
+    /// A message header names the null object, or is shorter than the header itself.
+    InvalidHeader,
+    /// A message arrived with an opcode its object doesn't have.
+    InvalidOpcode { object: u32, opcode: u16 },
+    /// The peer closed the connection and every message it sent before was already received.
+    Closed,
+    /// The object was used after the server acknowledged its destruction with
+    /// `wl_display.delete_id`, so its id may belong to a new object already.
+    StaleObject { id: u32 },
+}
+
+impl fmt::Display for WaylandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WaylandError::MissingFds { object, opcode, expected, received } => write!(
+                f,
+                "message {opcode} for object {object} declares {expected} fds, but only {received} were received"
+            ),
+            WaylandError::UnsupportedVersion { interface, required, advertised } => write!(
+                f,
+                "server only supports {interface} v{advertised}, but at least v{required} is required"
+            ),
+            WaylandError::InvalidObjectId { id } => write!(f, "object id {id} is not a client allocated id"),
+            WaylandError::ObjectIdInUse { id } => write!(f, "object id {id} is already in use"),
+            WaylandError::MessageTooLong { interface, message, len } => write!(
+                f,
+                "{interface}.{message} is {len} bytes long, but messages are limited to {max} bytes",
+                max = message_header::MAX_LEN,
+            ),
+            WaylandError::TooManyFds { interface, message, fds } => write!(
+                f,
+                "{interface}.{message} declares {fds} fds, but messages are limited to {max} fds",
+                max = message_header::MAX_FDS,
+            ),
+            WaylandError::FdLimitExceeded { limit } => {
+                write!(f, "peer sent more than the {limit} fds the rx buffer holds")
+            }
+            WaylandError::InvalidHeader => write!(f, "received an invalid message header"),
+            WaylandError::InvalidOpcode { object, opcode } => {
+                write!(
+                    f,
+                    "object {object} received message with invalid opcode {opcode}"
+                )
+            }
+            WaylandError::Closed => write!(f, "connection was closed by the peer"),
+            WaylandError::StaleObject { id } => write!(f, "object {id} was already destroyed"),
+        }
+    }
+}
+
+impl Error for WaylandError {}
+
+impl From<WaylandError> for io::Error {
+    fn from(err: WaylandError) -> Self {
+        let kind = match err {
+            WaylandError::Closed => io::ErrorKind::BrokenPipe,
+            WaylandError::MessageTooLong { .. }
+            | WaylandError::TooManyFds { .. }
+            | WaylandError::StaleObject { .. } => io::ErrorKind::InvalidInput,
+            _ => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, err)
+    }
+}
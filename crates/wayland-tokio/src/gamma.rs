@@ -0,0 +1,81 @@
+use libc::MFD_CLOEXEC;
+use std::{
+    fs::File,
+    io::{self, Write},
+    os::fd::{FromRawFd, IntoRawFd, RawFd},
+};
+
+/// Ramp of `size` entries going linearly from `0` towards `brightness` on all three channels.
+///
+/// Entry `i` is `brightness * i / size`, so the last entry stays just below `brightness`.
+pub fn linear_ramp(size: u32, brightness: u16) -> Vec<[u16; 3]> {
+    (0..size)
+        .map(|i| {
+            let val = (brightness as u64 * i as u64 / size as u64) as u16;
+            [val; 3]
+        })
+        .collect()
+}
+
+/// Write `ramp` into a new memfd and return it, the caller is responsible for closing it.
+///
+/// The table is stored as `size` red values, followed by `size` green and `size` blue values,
+/// each in native endianness.
+pub fn write_ramp_to_fd(size: u32, ramp: &[[u16; 3]]) -> io::Result<RawFd> {
+    if ramp.len() != size as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("gamma ramp has {} entries, expected {size}", ramp.len()),
+        ));
+    }
+
+    let mut file = unsafe {
+        let fd = libc::memfd_create(c"gamma_ramp".as_ptr(), MFD_CLOEXEC);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        File::from_raw_fd(fd)
+    };
+
+    let table: Vec<u8> = (0..3)
+        .flat_map(|channel| ramp.iter().map(move |entry| entry[channel]))
+        .flat_map(u16::to_ne_bytes)
+        .collect();
+    file.write_all(&table)?;
+
+    Ok(file.into_raw_fd())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gamma::{linear_ramp, write_ramp_to_fd};
+    use std::{
+        fs::File,
+        io::{Read, Seek, SeekFrom},
+        os::fd::FromRawFd,
+    };
+
+    #[test]
+    fn ramp_bounds() {
+        let ramp = linear_ramp(256, u16::MAX);
+        assert_eq!(ramp.len(), 256);
+        assert_eq!(ramp[0], [0; 3]);
+        assert_eq!(ramp[255], [(u16::MAX as u32 * 255 / 256) as u16; 3]);
+
+        assert_eq!(linear_ramp(4, 400), [[0; 3], [100; 3], [200; 3], [300; 3]]);
+    }
+
+    #[test]
+    fn ramp_to_fd() {
+        let ramp = [[1, 2, 3], [4, 5, 6]];
+        assert!(write_ramp_to_fd(3, &ramp).is_err());
+
+        let mut file = unsafe { File::from_raw_fd(write_ramp_to_fd(2, &ramp).unwrap()) };
+        file.seek(SeekFrom::Start(0)).unwrap();
+        let mut data = Vec::new();
+        file.read_to_end(&mut data).unwrap();
+
+        let table: Vec<u16> = data.chunks_exact(2).map(|b| u16::from_ne_bytes([b[0], b[1]])).collect();
+        assert_eq!(table, [1, 4, 2, 5, 3, 6]);
+    }
+}
@@ -1,10 +1,19 @@
-use crate::connection::Connection;
-use ecs_compositor_core::{Interface, Opcode};
+use crate::{connection::Connection, protocols::wayland::wl_display};
+use ecs_compositor_core::{Interface, Message, Opcode};
 use std::sync::Arc;
 
 pub trait ConnectionHandle: Clone {
     type Dir;
     fn conn(&self) -> &Connection<Self::Dir>;
+
+    /// Get the destructors [`Object::auto_destroy()`] objects queued on drop onto the socket.
+    ///
+    /// Handles owning the connection spawn a flush on its
+    /// [runtime](Connection::runtime_handle()). Borrowed ones can't outlive the drop, so they
+    /// leave the destructors to the next send, flush or receive.
+    ///
+    /// [`Object::auto_destroy()`]: crate::connection::Object::auto_destroy
+    fn flush_destructors(&self) {}
 }
 
 impl<Dir> ConnectionHandle for &Connection<Dir> {
@@ -14,20 +23,40 @@ impl<Dir> ConnectionHandle for &Connection<Dir> {
     }
 }
 
-impl<Dir> ConnectionHandle for Arc<Connection<Dir>> {
+impl<Dir: Send + Sync + 'static> ConnectionHandle for Arc<Connection<Dir>> {
     type Dir = Dir;
     fn conn(&self) -> &Connection<Self::Dir> {
         self
     }
+
+    fn flush_destructors(&self) {
+        self.spawn_flush();
+    }
 }
 
-pub trait InterfaceDir<I: Interface> {
+pub trait InterfaceDir<I: Interface>: 'static {
     type Recv: Opcode;
     type Send: Opcode;
 
     fn recv_fd_count(i: u16) -> Option<usize> {
         Self::Recv::from_u16(i).ok().as_ref().map(Opcode::fd_count)
     }
+
+    /// Opcode of the destructor this side can send, see [`Interface::DESTRUCTOR_OP`].
+    fn destructor_op() -> Option<u16> {
+        None
+    }
+
+    /// Opcode of the destructor this side can send without filling in any arguments, so it can be
+    /// sent on drop, see [`Object::auto_destroy()`](crate::connection::Object::auto_destroy).
+    fn auto_destroy_op() -> Option<u16> {
+        None
+    }
+
+    /// Whether messages with `opcode` are `wl_display.delete_id` events, which free client ids.
+    fn is_delete_id(_opcode: u16) -> bool {
+        false
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -38,6 +67,18 @@ pub struct Server;
 impl<I: Interface> InterfaceDir<I> for Client {
     type Recv = I::Event;
     type Send = I::Request;
+
+    fn destructor_op() -> Option<u16> {
+        I::DESTRUCTOR_OP
+    }
+
+    fn auto_destroy_op() -> Option<u16> {
+        I::DESTRUCTOR_OP.filter(|&op| I::request_signature(op).is_some_and(<[_]>::is_empty))
+    }
+
+    fn is_delete_id(opcode: u16) -> bool {
+        I::NAME == wl_display::wl_display::NAME && opcode == wl_display::event::delete_id::OP
+    }
 }
 
 impl<I: Interface> InterfaceDir<I> for Server {
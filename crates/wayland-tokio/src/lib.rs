@@ -11,5 +11,14 @@ macro_rules! new_id {
 pub mod buf;
 pub mod connection;
 mod drive_io;
+pub mod error;
+#[cfg(feature = "gamma")]
+pub mod gamma;
 pub mod handle;
 pub mod msg_io;
+pub mod protocols;
+#[cfg(feature = "shm")]
+pub mod shm;
+
+#[cfg(test)]
+mod test_util;
@@ -2,6 +2,26 @@ use ecs_compositor_core::RawSliceExt;
 use libc::{CMSG_DATA, CMSG_FIRSTHDR, CMSG_LEN, CMSG_NXTHDR, c_int, cmsghdr, msghdr};
 use std::ptr::{null_mut, slice_from_raw_parts_mut};
 
+/// Control message buffer aligned for [`cmsghdr`], which [`CmsgCursor`] requires to access the
+/// headers and the data following them in place.
+#[derive(Debug, Clone, Copy)]
+#[repr(C, align(8))]
+pub struct CmsgBuf<const LEN: usize>(pub [u8; LEN]);
+
+const _: () = assert!(align_of::<cmsghdr>() <= align_of::<CmsgBuf<0>>());
+
+impl<const LEN: usize> CmsgBuf<LEN> {
+    pub const fn new() -> Self {
+        Self([0; LEN])
+    }
+}
+
+impl<const LEN: usize> Default for CmsgBuf<LEN> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Debug)]
 pub struct CmsgCursor {
     msg: msghdr,
@@ -21,8 +41,11 @@ impl CmsgCursor {
     }
 
     /// # Safety
-    /// TODO
+    ///
+    /// `ctrl_buf` has to be valid for reads and writes and aligned for [`cmsghdr`], e.g. by being
+    /// backed by a [`CmsgBuf`].
     pub unsafe fn from_ctrl_buf(ctrl_buf: *mut [u8]) -> Self {
+        debug_assert!(ctrl_buf.cast::<cmsghdr>().is_aligned());
         unsafe {
             let msg = msghdr {
                 msg_name: null_mut(),
@@ -48,9 +71,12 @@ impl CmsgCursor {
                 (*self.hdr).cmsg_type = cmsg_type;
                 (*self.hdr).cmsg_level = cmsg_level;
 
+                // `msg_controllen` is in bytes, so the end has to be computed before casting to `T`.
                 let data = RawSliceExt::from_range(
                     CMSG_DATA(self.hdr).cast(),
-                    slice_from_raw_parts_mut(self.msg.msg_control.cast(), self.msg.msg_controllen).end(),
+                    slice_from_raw_parts_mut(self.msg.msg_control.cast::<u8>(), self.msg.msg_controllen)
+                        .end()
+                        .cast(),
                 );
 
                 Ok(CmsgCursorWriteData { cursor: self, data, len: 0 })
@@ -89,7 +115,14 @@ pub struct ReadData {
 }
 
 impl ReadData {
+    /// Reinterpret the data of the control message as `[T]`, in place.
+    ///
+    /// The data directly follows its [`cmsghdr`] and is padded to the alignment of `size_t`, so
+    /// this is aligned for any `T` like [`RawFd`](std::os::fd::RawFd) that isn't aligned stricter
+    /// than [`cmsghdr`], as long as the buffer of the [`CmsgCursor`] was aligned.
     pub fn read_as<T>(self) -> *mut [T] {
+        debug_assert!(align_of::<T>() <= align_of::<cmsghdr>());
+        debug_assert!(self.data.cast::<T>().is_aligned());
         unsafe { <_>::from_range(self.data.start().cast(), self.data.end().cast()) }
     }
 }
@@ -103,8 +136,10 @@ pub struct CmsgCursorWriteData<'a, T> {
 impl<'a, T: Copy> CmsgCursorWriteData<'a, T> {
     pub fn write(&mut self, val: T) -> &mut Self {
         unsafe {
-            if let Some(buf) = self.data.split_at(1)
-                && !self.data.is_null()
+            // Check for null *before* splitting, so that writes after a failed write don't end
+            // up as a hole in the data.
+            if !self.data.is_null()
+                && let Some(buf) = self.data.split_at(1)
             {
                 debug_assert!(buf.start().is_aligned());
                 buf.start().write(val);
@@ -118,8 +153,8 @@ impl<'a, T: Copy> CmsgCursorWriteData<'a, T> {
 
     pub fn write_unaligned(&mut self, val: T) -> &mut Self {
         unsafe {
-            if let Some(buf) = self.data.split_at(1)
-                && !self.data.is_null()
+            if !self.data.is_null()
+                && let Some(buf) = self.data.split_at(1)
             {
                 buf.start().write_unaligned(val);
                 self.len += 1;
@@ -130,10 +165,13 @@ impl<'a, T: Copy> CmsgCursorWriteData<'a, T> {
         }
     }
 
+    /// Write all of `val`, which may fill the remaining space exactly.
+    ///
+    /// If there isn't enough space left, nothing is written and [`Self::commit()`] fails.
     pub fn write_slice(&mut self, val: &[T]) -> &mut Self {
         unsafe {
-            if let Some(buf) = self.data.split_at(val.len())
-                && !self.data.is_null()
+            if !self.data.is_null()
+                && let Some(buf) = self.data.split_at(val.len())
             {
                 buf.start().copy_from(val.as_ptr(), val.len());
                 self.len += val.len();
@@ -0,0 +1,95 @@
+mod interfaces {
+    pub use super::wayland::*;
+}
+
+pub use ecs_compositor_core as proto;
+
+include!(concat!(env!("OUT_DIR"), "/wayland-protocols/wayland.rs"));
+
+#[cfg(test)]
+mod tests {
+    use super::wayland::{
+        wl_compositor::wl_compositor,
+        wl_registry,
+        wl_shm::{enumeration::format, wl_shm},
+    };
+    use ecs_compositor_core::{Interface, Value, new_id, uint};
+    use std::{marker::PhantomData, num::NonZero, os::fd::RawFd};
+
+    #[test]
+    fn bind_round_trip() {
+        let bind = wl_registry::request::bind::<wl_compositor> {
+            name: uint(7),
+            id: new_id { id: NonZero::new(3).unwrap(), _marker: PhantomData },
+        };
+        assert_eq!(bind.len(), 4 + 4 + 16 + 4 + 4);
+
+        let mut buf = [0u32; 8];
+        unsafe {
+            let (mut data, mut fds): (*mut [u8], *mut [RawFd]) = (
+                std::ptr::slice_from_raw_parts_mut(buf.as_mut_ptr().cast::<u8>(), 32),
+                &mut [],
+            );
+            bind.write(&mut data, &mut fds).ok().unwrap();
+            assert_eq!(data.len(), 0);
+        }
+
+        let mut name = [0u8; 16];
+        name[..13].copy_from_slice(b"wl_compositor");
+        let mut expected = vec![7, 14];
+        expected.extend(name.chunks(4).map(|chunk| u32::from_ne_bytes(chunk.try_into().unwrap())));
+        expected.extend([wl_compositor::VERSION, 3]);
+        assert_eq!(buf.as_slice(), expected);
+
+        unsafe {
+            let (mut data, mut fds): (*const [u8], *const [RawFd]) = (
+                std::ptr::slice_from_raw_parts(buf.as_ptr().cast::<u8>(), 32),
+                &[],
+            );
+            let wl_registry::request::bind { name, id } =
+                wl_registry::request::bind::<wl_compositor>::read(&mut data, &mut fds)
+                    .ok()
+                    .unwrap();
+            assert_eq!((name.0, id.id.get()), (7, 3));
+
+            let (mut data, mut fds): (*const [u8], *const [RawFd]) = (
+                std::ptr::slice_from_raw_parts(buf.as_ptr().cast::<u8>(), 32),
+                &[],
+            );
+            assert!(wl_registry::request::bind::<wl_shm>::read(&mut data, &mut fds).is_err());
+        }
+    }
+
+    #[test]
+    fn enum_conversions() {
+        assert!(matches!(format::try_from(0u32), Ok(format::argb8888)));
+        assert!(matches!(format::try_from(0x34324258), Ok(format::xbgr8888)));
+        assert!(matches!(format::try_from(7u32), Err(7)));
+        assert_eq!(u32::from(format::argb8888), 0);
+        assert_eq!(u32::from(format::xrgb8888), 1);
+    }
+
+    #[test]
+    fn unknown_enum_value() {
+        let mut buf = [7u32];
+        let format = unsafe {
+            let (mut data, mut fds): (*const [u8], *const [RawFd]) = (
+                std::ptr::slice_from_raw_parts(buf.as_ptr().cast::<u8>(), 4),
+                &[],
+            );
+            format::read(&mut data, &mut fds).ok().unwrap()
+        };
+        assert!(matches!(format, format::Unknown(7)));
+        assert_eq!(format.to_string(), "unknown(7)");
+
+        buf = [0];
+        unsafe {
+            let (mut data, mut fds): (*mut [u8], *mut [RawFd]) = (
+                std::ptr::slice_from_raw_parts_mut(buf.as_mut_ptr().cast::<u8>(), 4),
+                &mut [],
+            );
+            format.write(&mut data, &mut fds).ok().unwrap();
+        }
+        assert_eq!(buf, [7]);
+    }
+}
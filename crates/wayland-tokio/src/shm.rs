@@ -0,0 +1,247 @@
+use crate::{
+    connection::{ClientHandle, Object},
+    protocols::wayland::{wl_buffer::wl_buffer, wl_shm, wl_shm_pool},
+};
+use ecs_compositor_core::int;
+use libc::{MAP_FAILED, MAP_SHARED, MFD_CLOEXEC, PROT_READ, PROT_WRITE};
+use std::{
+    io,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    ptr::{self, NonNull},
+};
+use tracing::debug;
+
+/// `wl_buffer` backed by a memfd, which stays mapped into memory for as long as the buffer lives.
+///
+/// The `wl_buffer` and its `wl_shm_pool` get destroyed once the `ShmBuffer` and all clones of
+/// [`Self::buffer()`] and [`Self::pool()`] are dropped, see [`Object::auto_destroy()`].
+pub struct ShmBuffer<Conn: ClientHandle> {
+    // Dropped in declaration order, so the buffer is destroyed before its pool.
+    buffer: Object<Conn, wl_buffer>,
+    pool: Object<Conn, wl_shm_pool::wl_shm_pool>,
+    map: Mapping,
+    fd: OwnedFd,
+}
+
+impl<Conn: ClientHandle> ShmBuffer<Conn> {
+    /// Create a memfd of `stride * height` bytes and share it with the server as `wl_shm_pool`
+    /// containing a single `wl_buffer`.
+    ///
+    /// Fails with [`io::ErrorKind::InvalidInput`] if the size isn't positive or `stride` is too
+    /// short to fit `width` 4 byte pixels.
+    pub async fn new(
+        conn: &Conn,
+        wl_shm: &Object<Conn, wl_shm::wl_shm>,
+        width: i32,
+        height: i32,
+        stride: i32,
+        format: wl_shm::enumeration::format,
+    ) -> io::Result<Self> {
+        if width <= 0 || width.checked_mul(4).is_none_or(|min| stride < min) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid shm buffer stride",
+            ));
+        }
+        let size = stride
+            .checked_mul(height)
+            .filter(|size| *size > 0)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid shm buffer size"))?;
+
+        let fd = unsafe {
+            let fd = libc::memfd_create(c"wl_shm".as_ptr(), MFD_CLOEXEC);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            OwnedFd::from_raw_fd(fd)
+        };
+        if unsafe { libc::ftruncate(fd.as_raw_fd(), size as i64) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let map = Mapping::new(&fd, size as usize)?;
+
+        let pool;
+        wl_shm
+            .send(&wl_shm::request::create_pool {
+                id: new_id!(conn, pool),
+                fd: ecs_compositor_core::fd(fd.as_raw_fd()),
+                size: int(size),
+            })
+            .await?;
+        let pool = pool.auto_destroy();
+
+        let buffer;
+        pool.send(&wl_shm_pool::request::create_buffer {
+            id: new_id!(conn, buffer),
+            offset: int(0),
+            width: int(width),
+            height: int(height),
+            stride: int(stride),
+            format,
+        })
+        .await?;
+        let buffer = buffer.auto_destroy();
+
+        Ok(Self { buffer, pool, map, fd })
+    }
+
+    pub fn pool(&self) -> &Object<Conn, wl_shm_pool::wl_shm_pool> {
+        &self.pool
+    }
+
+    pub fn buffer(&self) -> &Object<Conn, wl_buffer> {
+        &self.buffer
+    }
+
+    /// The memfd shared with the server.
+    pub fn fd(&self) -> &OwnedFd {
+        &self.fd
+    }
+
+    pub fn as_mut_pixels(&mut self) -> &mut [u32] {
+        unsafe { self.map.0.as_mut() }
+    }
+}
+
+struct Mapping(NonNull<[u32]>);
+
+impl Mapping {
+    fn new(fd: &OwnedFd, len: usize) -> io::Result<Self> {
+        unsafe {
+            let addr = libc::mmap(
+                ptr::null_mut(),
+                len,
+                PROT_READ | PROT_WRITE,
+                MAP_SHARED,
+                fd.as_raw_fd(),
+                0,
+            );
+            if addr == MAP_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+
+            let map = NonNull::slice_from_raw_parts(NonNull::new_unchecked(addr).cast::<u32>(), len / 4);
+            debug!(?map, "mapped shm buffer");
+            Ok(Self(map))
+        }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe {
+            if libc::munmap(self.0.as_ptr().cast(), self.0.len() * 4) < 0 {
+                debug!(err = %io::Error::last_os_error(), map = ?self.0, "failed to unmap shm buffer");
+            }
+        }
+    }
+}
+
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        connection::ClientHandle,
+        protocols::wayland::{wl_buffer, wl_shm, wl_shm_pool},
+        shm::ShmBuffer,
+        test_util,
+    };
+    use ecs_compositor_core::Message;
+    use std::{
+        fs::File,
+        io::{ErrorKind, Read, Seek, SeekFrom, Write},
+    };
+
+    #[tokio::test]
+    async fn create_buffer() {
+        let (conn, mut server) = test_util::pair();
+        let conn = &conn;
+        let wl_shm = conn.new_object_with_id::<wl_shm::wl_shm>(2);
+
+        let mut buffer = ShmBuffer::new(
+            &conn,
+            &wl_shm,
+            4,
+            2,
+            16,
+            wl_shm::enumeration::format::argb8888,
+        )
+        .await
+        .unwrap();
+        buffer.as_mut_pixels().fill(0xff_00_ff_00);
+
+        let (hdr, content, pool_fds) = test_util::read_msg(&mut server);
+        assert_eq!(
+            (hdr.object_id.id().get(), hdr.opcode),
+            (2, wl_shm::request::create_pool::OP)
+        );
+        // `id` and `size`, the fd itself is only sent out of band.
+        assert_eq!(content, [buffer.pool().id().id.get(), 32]);
+        assert_eq!(pool_fds.len(), 1);
+
+        let (hdr, content, fds) = test_util::read_msg(&mut server);
+        assert_eq!(
+            (hdr.object_id.id().get(), hdr.opcode),
+            (
+                buffer.pool().id().id.get(),
+                wl_shm_pool::request::create_buffer::OP
+            )
+        );
+        assert_eq!(content, [buffer.buffer().id().id.get(), 0, 4, 2, 16, 0]);
+        assert!(fds.is_empty());
+
+        // Both ends see the same memory.
+        let mut file = File::from(pool_fds.into_iter().next().unwrap());
+        let mut data = [0; 32];
+        file.read_exact(&mut data).unwrap();
+        assert_eq!(data, [0x00, 0xff, 0x00, 0xff].repeat(8).as_slice());
+
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.write_all(&[0x12, 0x34, 0x56, 0x78]).unwrap();
+        assert_eq!(buffer.as_mut_pixels()[..2], [0x78_56_34_12, 0xff_00_ff_00]);
+
+        let (pool, wl_buffer) = (buffer.pool().id().id.get(), buffer.buffer().id().id.get());
+        drop(buffer);
+        conn.flush().await.unwrap();
+        let (hdr, ..) = test_util::read_msg(&mut server);
+        assert_eq!(
+            (hdr.object_id.id().get(), hdr.opcode),
+            (wl_buffer, wl_buffer::request::destroy::OP)
+        );
+        let (hdr, ..) = test_util::read_msg(&mut server);
+        assert_eq!(
+            (hdr.object_id.id().get(), hdr.opcode),
+            (pool, wl_shm_pool::request::destroy::OP)
+        );
+    }
+
+    #[tokio::test]
+    async fn stride_too_short() {
+        let (conn, mut server) = test_util::pair();
+        let conn = &conn;
+        let wl_shm = conn.new_object_with_id::<wl_shm::wl_shm>(2);
+
+        let err = ShmBuffer::new(
+            &conn,
+            &wl_shm,
+            4,
+            2,
+            15,
+            wl_shm::enumeration::format::argb8888,
+        )
+        .await
+        .err()
+        .unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        // Nothing was sent to the server.
+        conn.flush().await.unwrap();
+        server.set_nonblocking(true).unwrap();
+        assert_eq!(
+            server.read(&mut [0; 4]).unwrap_err().kind(),
+            ErrorKind::WouldBlock
+        );
+    }
+}
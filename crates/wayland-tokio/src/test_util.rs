@@ -0,0 +1,167 @@
+use crate::{
+    connection::Connection,
+    drive_io::MAX_FDS,
+    handle::Client,
+    msg_io::{
+        Msg,
+        cmsg_cursor::{CmsgBuf, CmsgCursor},
+    },
+};
+use ecs_compositor_core::{Message, Value, message_header, object};
+use libc::{CMSG_SPACE, SCM_RIGHTS, SOL_SOCKET};
+use std::{
+    fs::File,
+    io::Write,
+    num::NonZero,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::net::UnixStream,
+    },
+    ptr,
+};
+
+/// Create a client [`Connection`] together with the raw server end of the socket.
+pub(crate) fn pair() -> (Connection<Client>, UnixStream) {
+    let (client, server) = UnixStream::pair().unwrap();
+    (Connection::from_stream(client).unwrap(), server)
+}
+
+/// Server end of a [`pair()`], which writes scripted events and checks the requests it receives.
+pub(crate) struct MockServer {
+    sock: UnixStream,
+    script: Vec<u32>,
+}
+
+impl MockServer {
+    pub(crate) fn pair() -> (Connection<Client>, Self) {
+        let (conn, sock) = pair();
+        (conn, Self { sock, script: Vec::new() })
+    }
+
+    /// Queue `msg` addressed to `id`, to be written by the next [`Self::play()`].
+    pub(crate) fn event<'a, M: Message<'a>>(&mut self, id: u32, msg: &M) -> &mut Self {
+        let (data, fds) = encode_msg(id, msg);
+        assert!(
+            fds.is_empty(),
+            "scripted events can't carry fds, use `write_msg()`"
+        );
+        self.script.extend(data);
+        self
+    }
+
+    /// Write all queued events at once.
+    pub(crate) fn play(&mut self) {
+        let script = std::mem::take(&mut self.script);
+        self.sock.write_all(as_bytes(&script)).unwrap();
+    }
+
+    /// Read the next request, asserting it is `M` addressed to `id`, and return its content and
+    /// the fds received with it.
+    pub(crate) fn expect<'a, M: Message<'a>>(&mut self, id: u32) -> (Vec<u32>, Vec<OwnedFd>) {
+        let (hdr, content, fds) = read_msg(&mut self.sock);
+        assert_eq!(
+            (hdr.object_id.id().get(), hdr.opcode),
+            (id, M::OP),
+            "expected {name}",
+            name = M::NAME
+        );
+        (content, fds)
+    }
+
+    pub(crate) fn sock(&mut self) -> &mut UnixStream {
+        &mut self.sock
+    }
+}
+
+/// Create a non-blocking pipe, to tell whether all copies of the write end were closed.
+pub(crate) fn pipe() -> (File, OwnedFd) {
+    unsafe {
+        let mut pipe = [0; 2];
+        assert_eq!(libc::pipe2(pipe.as_mut_ptr(), libc::O_NONBLOCK), 0);
+        (
+            File::from(OwnedFd::from_raw_fd(pipe[0])),
+            OwnedFd::from_raw_fd(pipe[1]),
+        )
+    }
+}
+
+/// Serialize `msg` addressed to `id` and write it to `sock`, together with its fds.
+pub(crate) fn write_msg<'a, M: Message<'a>>(sock: &mut UnixStream, id: u32, msg: &M) {
+    let (data, fds) = encode_msg(id, msg);
+    if fds.is_empty() {
+        sock.write_all(as_bytes(&data)).unwrap();
+        return;
+    }
+
+    let mut ctrl = CmsgBuf::<{ unsafe { CMSG_SPACE(size_of::<[RawFd; MAX_FDS as usize]>() as u32) as usize } }>::new();
+    let mut cursor = unsafe { CmsgCursor::from_ctrl_buf(&mut ctrl.0) };
+    cursor
+        .write_cursor::<RawFd>(SOL_SOCKET, SCM_RIGHTS)
+        .unwrap()
+        .write_slice(&fds)
+        .commit()
+        .unwrap();
+    let mut data = as_bytes(&data).to_vec();
+    let mut msg = Msg { data: &mut data[..], ctrl: cursor.as_slice(), flags: 0 };
+    msg.send(sock.as_raw_fd(), 0).unwrap().expect("socket closed");
+    assert!(msg.data.is_empty(), "short write");
+}
+
+/// Serialize `msg` addressed to `id`, using `u32` backing storage to keep it 4 byte aligned, and
+/// return it with the fds it carries.
+fn encode_msg<'a, M: Message<'a>>(id: u32, msg: &M) -> (Vec<u32>, Vec<RawFd>) {
+    let len = message_header::DATA_LEN as u32 + msg.len();
+    let mut buf = vec![0u32; len as usize / 4];
+    let bytes = unsafe { std::slice::from_raw_parts_mut(buf.as_mut_ptr().cast::<u8>(), len as usize) };
+
+    let hdr =
+        message_header { object_id: object::from_id(NonZero::new(id).unwrap()), datalen: len as u16, opcode: M::OP };
+    let mut fd_buf = vec![-1; M::FDS];
+    let (mut data, mut fds): (*mut [u8], *mut [RawFd]) = (bytes, &mut fd_buf[..]);
+    unsafe {
+        hdr.write(&mut data, &mut fds).ok().unwrap();
+        msg.write(&mut data, &mut fds).ok().unwrap();
+    }
+    (buf, fd_buf)
+}
+
+fn as_bytes(buf: &[u32]) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(buf.as_ptr().cast::<u8>(), size_of_val(buf)) }
+}
+
+/// Read a single message from `sock`, returning its header, content and the fds received with it.
+pub(crate) fn read_msg(sock: &mut UnixStream) -> (message_header, Vec<u32>, Vec<OwnedFd>) {
+    let mut fds = Vec::new();
+
+    let mut hdr = [0u32; 2];
+    recv_exact(sock, &mut hdr, &mut fds);
+    let hdr = unsafe {
+        let (mut data, mut fds): (*const [u8], *const [RawFd]) =
+            (ptr::slice_from_raw_parts(hdr.as_ptr().cast::<u8>(), 8), &[]);
+        message_header::read(&mut data, &mut fds).ok().unwrap()
+    };
+
+    let mut content = vec![0u32; hdr.content_len() as usize / 4];
+    recv_exact(sock, &mut content, &mut fds);
+    (hdr, content, fds)
+}
+
+fn recv_exact(sock: &mut UnixStream, buf: &mut [u32], fds: &mut Vec<OwnedFd>) {
+    let mut data = ptr::slice_from_raw_parts_mut(buf.as_mut_ptr().cast::<u8>(), size_of_val(buf));
+    while !data.is_empty() {
+        let mut ctrl =
+            CmsgBuf::<{ unsafe { CMSG_SPACE(size_of::<[RawFd; MAX_FDS as usize]>() as u32) as usize } }>::new();
+        let mut msg = Msg { data, ctrl: &mut ctrl.0, flags: 0 };
+        let recv = msg.recv(sock.as_raw_fd(), 0).unwrap().expect("socket closed");
+
+        let mut cursor = unsafe { CmsgCursor::from_ctrl_buf(recv.ctrl) };
+        while let Some((_, cmsg)) = cursor.read_cmsg() {
+            fds.extend(
+                unsafe { &*cmsg.read_as::<RawFd>() }
+                    .iter()
+                    .map(|fd| unsafe { OwnedFd::from_raw_fd(*fd) }),
+            );
+        }
+        data = msg.data;
+    }
+}
@@ -2,17 +2,10 @@ use ecs_compositor_codegen::builder::{Dir, Wayland};
 
 fn main() {
     let out_dir = &std::env::var("OUT_DIR").unwrap();
-    Wayland::protocols(
+    if let Err(err) = Wayland::protocols(
         Dir::with("../../wayland-protocols", out_dir).dir(
             Dir::with("", "wayland-protocols")
-                .dir(
-                    Dir::new()
-                        .protocol("wayland/protocol/wayland.xml", "wayland.rs")
-                        .dir(
-                            Dir::with("wayland-protocols/stable", "xdg")
-                                .protocol("xdg-shell/xdg-shell.xml", "xdg-shell.rs"),
-                        ),
-                )
+                .dir(Dir::with("wayland-protocols/stable", "xdg").protocol("xdg-shell/xdg-shell.xml", "xdg-shell.rs"))
                 .dir(
                     Dir::with("wlr-protocols/unstable", "wlr")
                         .protocol(
@@ -29,5 +22,7 @@ fn main() {
                         .protocol("./resources/brightness.xml", "brightness.rs"),
                 ),
         ),
-    );
+    ) {
+        println!("cargo::error={err}");
+    }
 }
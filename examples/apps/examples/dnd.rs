@@ -1,26 +1,26 @@
 use apps::{
-    bind::bind,
+    bind::GlobalExt,
     protocols::{
         wayland::{
             wl_buffer, wl_compositor, wl_data_device_manager, wl_display,
             wl_registry::{self, event::global},
             wl_seat,
             wl_shm::{self, enumeration::format},
-            wl_shm_pool, wl_surface,
+            wl_surface,
         },
         wlr::wlr_layer_shell_unstable_v1::{zwlr_layer_shell_v1, zwlr_layer_surface_v1},
     },
 };
-use ecs_compositor_core::{Interface, RawSliceExt, enumeration, int, uint};
+use ecs_compositor_core::{Interface, int, uint};
 use ecs_compositor_tokio::{
     connection::{ClientHandle, Connection, Object},
     handle::Client,
     new_id,
+    shm::ShmBuffer,
 };
 use itertools::Itertools;
-use libc::{MAP_SHARED, MFD_CLOEXEC, PROT_READ, PROT_WRITE};
 // use libc::copy_file_range;
-use std::{convert::Infallible, fs::File, io, os::fd::RawFd, ptr, sync::Arc, time::Duration};
+use std::{convert::Infallible, fs::File, io, sync::Arc, time::Duration};
 use tracing::{debug, error, info, instrument, trace};
 
 fn main() {
@@ -105,18 +105,16 @@ async fn wayland_client(_data: &[(String, File)]) -> io::Result<()> {
             conn: &Conn,
             registry: &Object<Conn, wl_registry::wl_registry>,
             (name, version): (uint, uint),
-        ) -> Object<Conn, I> {
-            let (id, obj) = conn.new_object();
-            let bind = bind { name, id };
+        ) -> io::Result<Object<Conn, I>> {
             info!(
-                bind = %bind,
+                name = name.0,
                 version = version.0,
                 expected_version = I::VERSION,
                 "binding global"
             );
-            registry.send(&bind).await.ok().unwrap();
-            info!("bound global");
-            obj
+            let obj = conn.bind_checked(registry, name, version).await?;
+            info!(obj = %obj, "bound global");
+            Ok(obj)
         }
     }
 
@@ -176,9 +174,9 @@ async fn wayland_client(_data: &[(String, File)]) -> io::Result<()> {
             zwlr_layer_surface_v1::request as wlr_layer_surface,
         };
 
-        let compositor = Globals::do_bind(&conn, &registry, globals.compositor).await;
-        let layer_shell = Globals::do_bind(&conn, &registry, globals.layer_shell).await;
-        let wl_shm = Globals::do_bind(&conn, &registry, globals.wl_shm).await;
+        let compositor = Globals::do_bind(&conn, &registry, globals.compositor).await?;
+        let layer_shell = Globals::do_bind(&conn, &registry, globals.layer_shell).await?;
+        let wl_shm = Globals::do_bind(&conn, &registry, globals.wl_shm).await?;
         let h4 = spawn(handle_wl_shm(wl_shm.clone()), "wl_shm");
 
         let surface;
@@ -193,21 +191,20 @@ async fn wayland_client(_data: &[(String, File)]) -> io::Result<()> {
                 id: new_id!(conn, layer_surface),
                 surface: surface.id(),
                 output: None,
-                layer: zwlr_layer_shell_v1::enumeration::layer::overlay.to_uint(),
+                layer: zwlr_layer_shell_v1::enumeration::layer::overlay,
                 namespace: ecs_compositor_core::string::from_slice(b"drag-and-drop\0"),
             })
             .await?;
 
         layer_surface
             .send(&wlr_layer_surface::set_anchor {
-                anchor: (anchor::top | anchor::left | anchor::bottom | anchor::right).to_uint(),
+                anchor: anchor::top | anchor::left | anchor::bottom | anchor::right,
             })
             .await?;
 
         layer_surface
             .send(&wlr_layer_surface::set_keyboard_interactivity {
-                keyboard_interactivity:
-                    zwlr_layer_surface_v1::enumeration::keyboard_interactivity::exclusive.to_uint(),
+                keyboard_interactivity: zwlr_layer_surface_v1::enumeration::keyboard_interactivity::exclusive,
             })
             .await?;
 
@@ -230,25 +227,29 @@ async fn wayland_client(_data: &[(String, File)]) -> io::Result<()> {
             }
         };
 
-        let buf = memfd_buffer::new(
+        let size = BufSize { width: configure.width.0, height: configure.height.0, scale: 2 };
+        let mut buf = ShmBuffer::new(
             &conn,
             &wl_shm,
-            BufSize { width: configure.width.0, height: configure.height.0, scale: 2 },
+            size.actual_width(),
+            size.actual_height(),
+            size.actual_width() * (size_of::<u32>() as i32),
+            format::argb8888,
         )
         .await?;
-        buf.render_to_fd(0x80_ff_00_00)?;
-        let h6 = spawn(handle_wl_buffer(buf.buffer.clone()), "wl_buffer");
-        info!(size = ?buf.size, "buffer");
+        buf.as_mut_pixels().fill(0x80_ff_00_00);
+        let h6 = spawn(handle_wl_buffer(buf.buffer().clone()), "wl_buffer");
+        info!(size = ?size, "buffer");
 
         surface
-            .send(&wl_surface::attach { buffer: Some(buf.buffer.id()), x: int(0), y: int(0) })
+            .send(&wl_surface::attach { buffer: Some(buf.buffer().id()), x: int(0), y: int(0) })
             .await?;
         surface
             .send(&wl_surface::damage_buffer {
                 x: int(0),
                 y: int(0),
-                width: int(buf.size.actual_width()),
-                height: int(buf.size.actual_height()),
+                width: int(size.actual_width()),
+                height: int(size.actual_height()),
             })
             .await?;
         layer_surface
@@ -381,9 +382,7 @@ async fn handle_wl_shm<Conn: ClientHandle>(wl_shm: Object<Conn, wl_shm::wl_shm>)
             match event.decode_opcode() {
                 format => {
                     let event = event.decode_msg::<wl_shm::event::format>().ok().unwrap();
-                    let pixel_format =
-                        wl_shm::enumeration::format::from_u32(event.format.0).unwrap();
-                    info!(pixel_format = ?pixel_format, %event);
+                    info!(pixel_format = ?event.format, %event);
                 }
             }
         }
@@ -392,14 +391,6 @@ async fn handle_wl_shm<Conn: ClientHandle>(wl_shm: Object<Conn, wl_shm::wl_shm>)
     error!(%err, "wl_shm errored");
 }
 
-#[allow(non_camel_case_types)]
-struct memfd_buffer<Conn: ClientHandle> {
-    fd: RawFd,
-    pool: Object<Conn, wl_shm_pool::wl_shm_pool>,
-    buffer: Object<Conn, wl_buffer::wl_buffer>,
-    size: BufSize,
-}
-
 #[derive(Debug, Clone, Copy)]
 struct BufSize {
     width: u32,
@@ -408,14 +399,6 @@ struct BufSize {
 }
 
 impl BufSize {
-    fn in_pixels(self) -> u32 {
-        let BufSize { width, height, scale } = self;
-        width * scale * height * scale
-    }
-    fn in_bytes(self) -> u32 {
-        self.in_pixels() * (size_of::<u32>() as u32)
-    }
-
     fn actual_width(self) -> i32 {
         (self.width * self.scale) as i32
     }
@@ -425,99 +408,6 @@ impl BufSize {
     }
 }
 
-impl<Conn: ClientHandle> memfd_buffer<Conn> {
-    async fn new(
-        conn: &Conn,
-        wl_shm: &Object<Conn, wl_shm::wl_shm>,
-        size: BufSize,
-    ) -> io::Result<memfd_buffer<Conn>> {
-        use {wl_shm::request as wl_shm, wl_shm_pool::request as wl_shm_pool};
-
-        let fd = unsafe {
-            let fd = libc::memfd_create(c"".as_ptr(), MFD_CLOEXEC);
-            if fd < 0 {
-                return Err(io::Error::last_os_error());
-            }
-            if libc::ftruncate(fd, size.in_bytes() as i64) < 0 {
-                return Err(io::Error::last_os_error());
-            }
-            fd
-        };
-
-        let pool;
-        wl_shm
-            .send(&wl_shm::create_pool {
-                id: new_id!(conn, pool),
-                fd: ecs_compositor_core::fd(fd),
-                size: int(size.in_bytes() as i32),
-            })
-            .await?;
-        let buffer;
-        pool.send(&wl_shm_pool::create_buffer {
-            id: new_id!(conn, buffer),
-            offset: int(0),
-            width: int(size.actual_width()),
-            height: int(size.actual_height()),
-            stride: int(size.actual_width() * (size_of::<u32>() as i32)),
-            format: format::argb8888.to_uint(),
-        })
-        .await?;
-
-        Ok(memfd_buffer { fd, pool, buffer, size })
-    }
-
-    fn map_buf(&self) -> io::Result<*mut [u32]> {
-        unsafe {
-            let len = self.size.in_bytes() as usize;
-            let addr = libc::mmap(
-                ptr::null_mut(),
-                len,
-                PROT_READ | PROT_WRITE,
-                MAP_SHARED,
-                self.fd,
-                0,
-            );
-
-            if addr.is_null() {
-                return Err(io::Error::last_os_error());
-            }
-            let buf =
-                ptr::slice_from_raw_parts_mut(addr.cast::<u32>(), self.size.in_pixels() as usize);
-            info!(?buf, "mapped buf");
-
-            Ok(buf)
-        }
-    }
-
-    fn unmap_buf(&self, buf: *mut [u32]) -> io::Result<()> {
-        unsafe {
-            let len = self.size.in_bytes() as usize;
-            if libc::munmap(buf.cast(), len) < 0 {
-                return Err(io::Error::last_os_error());
-            }
-            info!(?buf, "unmapped buf");
-
-            Ok(())
-        }
-    }
-
-    fn render_to_fd(&self, pixel_value: u32) -> io::Result<()> {
-        unsafe {
-            let buf = self.map_buf()?;
-            let data = buf.start();
-
-            info!("start writing buffer");
-            for offset in 0..buf.len() {
-                data.add(offset).write(pixel_value);
-            }
-            info!("finished writing buffer");
-
-            self.unmap_buf(buf)?;
-            Ok(())
-        }
-    }
-}
-
 #[instrument(level = "debug", fields(wl_buffer = %wl_buffer.id()), skip_all)]
 async fn handle_wl_buffer<Conn: ClientHandle>(wl_buffer: Object<Conn, wl_buffer::wl_buffer>) {
     debug!("start handling wl_buffer");
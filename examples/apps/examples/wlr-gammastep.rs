@@ -3,34 +3,30 @@ use apps::{
     bind::str_with_nul,
     protocols::{
         brightness,
-        wayland::{wl_display, wl_output, wl_registry},
+        wayland::{wl_display, wl_output},
         wlr::wlr_gamma_control_unstable_v1::{
             zwlr_gamma_control_manager_v1::{self as gamma_manager, zwlr_gamma_control_manager_v1},
             zwlr_gamma_control_v1 as gamma_control,
         },
     },
 };
-use ecs_compositor_core::{
-    Interface, Message, Opcode, RawSliceExt, Value, fd, message_header, new_id, object, primitives::align, string, uint,
-};
+use ecs_compositor_core::{Interface, Message, Opcode, Value, fd, fd_owned, message_header, object, string, uint};
 use ecs_compositor_tokio::{
-    connection::{ClientHandle, Connection, Object},
+    connection::{ClientHandle, Connection, GlobalEvent, Object},
+    gamma,
     handle::Client,
     new_id,
 };
 use futures::{Stream, StreamExt};
-use libc::{MAP_SHARED, MFD_CLOEXEC, PROT_READ, PROT_WRITE};
 use std::{
     borrow::Cow,
     collections::BTreeMap,
     env::VarError,
     error::Error,
-    fmt::Display,
     io,
     num::NonZero,
     os::fd::RawFd,
     pin::{Pin, pin},
-    ptr::null_mut,
     sync::{Arc, LazyLock, Mutex},
     task::{Context, Poll, ready},
 };
@@ -423,92 +419,55 @@ async fn wayland_client() -> anyhow::Result<()> {
         }
     });
 
-    let registry;
-    display
-        .send(&wl_display::request::get_registry { registry: new_id!(conn, registry) })
-        .await?;
-
-    let mut brightness_map = BTreeMap::<u32, usize>::new();
+    let mut globals = conn.globals().await?;
+    let mut brightness_map = BTreeMap::<uint, usize>::new();
     match async {
-        enum Interface {
-            Gamma,
-            Output,
-        }
-
         let mut gamma_manager = None;
-        loop {
-            match loop {
-                let event = registry.recv().await?;
-                match event.decode_opcode() {
-                    wl_registry::event::Opcodes::global => {
-                        let e: wl_registry::event::global = event.decode_msg().ok().unwrap();
-                        match e.interface.as_utf8().map_err(io::Error::other)? {
-                            gamma_manager::zwlr_gamma_control_manager_v1::NAME => {
-                                break (e.name, e.version, Interface::Gamma);
-                            }
-                            wl_output::wl_output::NAME => {
-                                break (e.name, e.version, Interface::Output);
-                            }
-                            unused => {
-                                debug!(interface = unused, "unused global");
-                                continue;
-                            }
-                        }
+        while let Some(event) = globals.next().await {
+            match event? {
+                GlobalEvent::Added { name, interface, version } => match interface.as_str() {
+                    gamma_manager::zwlr_gamma_control_manager_v1::NAME => {
+                        gamma_manager = Some(
+                            conn.bind_checked::<zwlr_gamma_control_manager_v1>(globals.registry(), name, version)
+                                .await?,
+                        );
                     }
-                    wl_registry::event::Opcodes::global_remove => {
-                        let wl_registry::event::global_remove { name: uint(name) } = event.decode_msg().ok().unwrap();
-                        let id = brightness_map
-                            .get(&name)
-                            .expect("expected there to be an extry in brightness_map");
-                        STATE.lock().unwrap().remove_output(*id);
-                        continue;
+                    wl_output::wl_output::NAME => {
+                        let output = conn
+                            .bind_checked::<wl_output::wl_output>(globals.registry(), name, version)
+                            .await?;
+
+                        let gamma_control;
+                        gamma_manager
+                            .as_ref()
+                            .ok_or_else(|| io::Error::other("failed to bind to gamma manager"))?
+                            .send(&gamma_manager::request::get_gamma_control {
+                                id: new_id!(conn, gamma_control),
+                                output: output.id(),
+                            })
+                            .await?;
+
+                        let (id, brightness) = STATE.lock().unwrap().new_output();
+                        brightness_map.insert(name, id);
+                        tokio::spawn(handle_output(gamma_control, output, brightness));
+                    }
+                    unused => debug!(interface = unused, "unused global"),
+                },
+                GlobalEvent::Removed { name } => {
+                    if let Some(id) = brightness_map.remove(&name) {
+                        STATE.lock().unwrap().remove_output(id);
                     }
-                }
-            } {
-                (name, version, Interface::Gamma) => {
-                    assert!(zwlr_gamma_control_manager_v1::VERSION <= version.0);
-                    let gamma;
-                    registry.send(&bind { name, id: new_id!(conn, gamma) }).await?;
-                    gamma_manager = Some(gamma);
-                }
-                (name, version, Interface::Output) => {
-                    assert!(wl_output::wl_output::VERSION <= version.0);
-
-                    let output;
-                    registry.send(&bind { name, id: new_id!(conn, output) }).await?;
-
-                    let gamma_control;
-                    gamma_manager
-                        .as_ref()
-                        .ok_or_else(|| io::Error::other("failed to bind to gamma manager"))?
-                        .send(&gamma_manager::request::get_gamma_control {
-                            id: new_id!(conn, gamma_control),
-                            output: output.id(),
-                        })
-                        .await?;
-
-                    let (id, brightness) = STATE.lock().unwrap().new_output();
-                    brightness_map.insert(name.0, id);
-                    tokio::spawn(handle_output(gamma_control, output, brightness));
                 }
             }
         }
+        io::Result::Ok(())
     }
     .await
     {
         Ok(()) => Ok(()),
         Err(err) if io::Error::kind(&err) == io::ErrorKind::BrokenPipe => {
             info!("pipe was broken");
-            loop {
-                info!("receiving event");
-                match registry.recv().await {
-                    Ok(ok) => ok.ignore_message(),
-                    Err(err) if err.kind() == io::ErrorKind::BrokenPipe => {
-                        break Ok(());
-                    }
-                    Err(err) => break Err(err.into()),
-                };
-            }
+            Ok(())
         }
         Err(err) => Err(err.into()),
     }
@@ -551,22 +510,12 @@ async fn handle_output(
         size: u32,
     ) -> io::Result<()> {
         let gamma_fd = create_gamma_table(size, brightness)?;
-        info!(fd = gamma_fd, "gamma_fd");
+        info!(fd = %gamma_fd, "gamma_fd");
+        // ensure the file descriptor was actually sent before it gets closed on drop
         gamma_control
-            .send(&gamma_control::request::set_gamma { fd: fd(gamma_fd) })
+            .send_and_flush(&gamma_control::request::set_gamma { fd: gamma_fd.as_raw() })
             .await?;
 
-        // ensure the file descriptor was actually sent
-        gamma_control.conn().flush().await?;
-
-        unsafe {
-            let ret = libc::close(gamma_fd);
-            info!(ret = ret, "closed");
-            if ret < 0 {
-                return Err(io::Error::last_os_error());
-            }
-        }
-
         Ok(())
     }
 
@@ -614,140 +563,12 @@ async fn handle_output(
     }
 }
 
-fn create_gamma_table(size: u32, [r, g, b]: [u16; 3]) -> io::Result<RawFd> {
-    unsafe {
-        let table_size = size as usize * size_of::<[u16; 3]>();
-
-        let gamma_fd = libc::memfd_create(c"".as_ptr(), MFD_CLOEXEC);
-        if gamma_fd < 0 {
-            error!("gamma fd error");
-            return Err(io::Error::last_os_error());
-        }
-
-        let ret = libc::ftruncate(gamma_fd, table_size as i64);
-        if ret < 0 {
-            error!("failed truncate");
-            return Err(io::Error::last_os_error());
-        }
-
-        let data = libc::mmap(
-            null_mut(),
-            table_size,
-            PROT_READ | PROT_WRITE,
-            MAP_SHARED,
-            gamma_fd,
-            0,
-        );
-        if data.is_null() {
-            return Err(io::Error::last_os_error());
-        }
-
-        let data = data.cast::<u16>();
-
-        unsafe fn write_brightness(data: *mut u16, offset: u32, brightness: u16, size: u32) {
-            unsafe {
-                for i in 0..size {
-                    let brightness = brightness as u32;
-
-                    let val = brightness * i / size;
-                    let val: u16 = std::cmp::min(val, u16::MAX as u32) as u16;
-
-                    data.add((offset + i) as usize).write(val);
-                }
-            }
-        }
-
-        write_brightness(data, size * 0, r, size);
-        write_brightness(data, size * 1, g, size);
-        write_brightness(data, size * 2, b, size);
-
-        Ok(gamma_fd)
-    }
-}
-
-#[allow(non_camel_case_types)]
-struct bind<I: Interface> {
-    name: uint,
-    id: new_id<I>,
-}
-
-impl<'data, I: Interface> Value<'data> for bind<I> {
-    const FDS: usize = 0;
-
-    fn len(&self) -> u32 {
-        4 // self.name
-        + 4 + align::<4>(I::NAME.len() as u32 + 1) // Interface::NAME
-        + 4 // Interface::VERSION
-        + 4 // self.id
-    }
-
-    unsafe fn read(
-        _data: &mut *const [u8],
-        _fds: &mut *const [RawFd],
-    ) -> ecs_compositor_core::primitives::Result<Self> {
-        unimplemented!()
-    }
-
-    unsafe fn write(
-        &self,
-        data: &mut *mut [u8],
-        fds: &mut *mut [RawFd],
-    ) -> ecs_compositor_core::primitives::Result<()> {
-        unsafe {
-            self.name.write(data, fds)?;
-
-            {
-                // Write the interface string to the buffer.
-                // Because `Interface::NAME` lacks the expected null terminator,
-                // we just pretend we write a string with len+1 to the buffer and then set the
-                // padding (which is there *anyways*) to zero, which makes sure we the string data
-                // is followed by a null byte. (Which has effectively the same impact as if we
-                // wrote a full null terminated string)
-                let str_len = I::NAME.len() as u32 + 1;
-                uint(str_len).write(data, fds)?;
-                let (padding, data) = {
-                    let mut padding = data
-                        .split_at(align::<4>(str_len) as usize)
-                        .expect("not enough space for string");
-                    let data = padding.split_at(I::NAME.len()).unwrap();
-                    (padding, data)
-                };
-
-                data.start().copy_from_nonoverlapping(I::NAME.as_ptr(), I::NAME.len());
-                padding.start().write_bytes(0, padding.len());
-            }
-
-            uint(I::VERSION).write(data, fds)?;
-
-            self.id.write(data, fds)?;
-            Ok(())
-        }
-    }
-}
-
-impl<I: Interface> Display for bind<I> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "new_id_dyn{{ name: {}, id: {}, version: {}}}",
-            self.name,
-            self.id,
-            I::VERSION
-        )?;
-        Ok(())
-    }
-}
-
-impl<'data, I: Interface> Message<'data> for bind<I> {
-    type Interface = wl_registry::wl_registry;
-
-    const VERSION: u32 = wl_registry::request::bind::VERSION;
-    const NAME: &'static str = wl_registry::request::bind::NAME;
-
-    type Opcode = <wl_registry::request::bind<'data> as Message<'data>>::Opcode;
+fn create_gamma_table(size: u32, brightness: [u16; 3]) -> io::Result<fd_owned> {
+    let [r, g, b] = brightness.map(|brightness| gamma::linear_ramp(size, brightness));
+    let ramp: Vec<_> = (0..size as usize).map(|i| [r[i][0], g[i][1], b[i][2]]).collect();
 
-    const OPCODE: Self::Opcode = <wl_registry::request::bind<'data> as Message<'data>>::OPCODE;
-    const OP: u16 = <wl_registry::request::bind<'data> as Message<'data>>::OP;
+    let gamma_fd = gamma::write_ramp_to_fd(size, &ramp).inspect_err(|_| error!("failed to write gamma table"))?;
+    Ok(unsafe { fd(gamma_fd).into_owned() })
 }
 
 async fn handle_output_event(output: &Object<Conn, wl_output::wl_output>) -> io::Result<()> {
@@ -7,8 +7,7 @@ mod interfaces {
 }
 
 pub use ecs_compositor_core as proto;
-
-include!(concat!(env!("OUT_DIR"), "/wayland-protocols/wayland.rs"));
+pub use ecs_compositor_tokio::protocols::wayland;
 
 pub mod xdg {
     use super::*;